@@ -0,0 +1,496 @@
+use crate::{
+    auth::{
+        password,
+        tokens::{self, Claims, TokenKey, ACCESS_TOKEN_LIFETIME, REFRESH_TOKEN_LIFETIME},
+        AuthUser,
+    },
+    database::{
+        sessions::{self, Rotation},
+        users::{self, User},
+        Database,
+    },
+    dav::Logins,
+    error::{ApiError, FieldError},
+    limiter::Limiter,
+    util::now,
+};
+use actix_web::{
+    cookie::{time::Duration, Cookie, SameSite},
+    get, post,
+    rt::time::Instant,
+    web, HttpRequest, HttpResponse, ResponseError,
+};
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+const REFRESH_COOKIE: &str = "refresh_token";
+
+#[derive(Deserialize, ToSchema)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct UserResponse {
+    user: User,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Session {
+    user: User,
+    /// Goes in `Authorization: Bearer <access_token>`
+    access_token: String,
+    /// Seconds until the access token expires
+    expires_in: i64,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(register)
+        .service(login)
+        .service(refresh)
+        .service(logout)
+        .service(me);
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(register, login, refresh, logout, me))]
+pub(super) struct Docs;
+
+fn refresh_cookie(token: String) -> Cookie<'static> {
+    Cookie::build(REFRESH_COOKIE, token)
+        .path("/api")
+        .http_only(true)
+        .same_site(SameSite::Strict)
+        .secure(cfg!(not(debug_assertions)))
+        .max_age(Duration::seconds(REFRESH_TOKEN_LIFETIME))
+        .finish()
+}
+
+fn removal_cookie() -> Cookie<'static> {
+    let mut cookie = refresh_cookie(String::new());
+    cookie.make_removal();
+    cookie
+}
+
+/// Responds with a fresh access token in the body and the refresh token as a cookie.
+fn session_response(
+    key: &TokenKey,
+    user: &User,
+    family: String,
+    refresh_token: String,
+) -> HttpResponse {
+    let access_token = key.sign(&Claims {
+        sub: user.id,
+        fam: family,
+        exp: now() + ACCESS_TOKEN_LIFETIME,
+    });
+
+    HttpResponse::Ok()
+        .cookie(refresh_cookie(refresh_token))
+        .json(Session {
+            user: user.clone(),
+            access_token,
+            expires_in: ACCESS_TOKEN_LIFETIME,
+        })
+}
+
+fn validate(credentials: &Credentials) -> Result<(), ApiError> {
+    let username = &credentials.username;
+    let mut errors = Vec::new();
+
+    if username.len() < 3 || username.len() > 32 {
+        errors.push(FieldError::new(
+            "username",
+            "Username must be between 3 and 32 characters long.",
+        ));
+    } else if !username
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        errors.push(FieldError::new(
+            "username",
+            "Username may only contain letters, numbers, underscores and dashes.",
+        ));
+    }
+
+    if credentials.password.chars().count() < 8 {
+        errors.push(FieldError::new(
+            "password",
+            "Password must be at least 8 characters long.",
+        ));
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(ApiError::Invalid(errors)),
+    }
+}
+
+#[utoipa::path(responses((status = CREATED, body = UserResponse)), security(()))]
+#[post("/register")]
+async fn register(
+    db: web::Data<Database>,
+    body: web::Json<Credentials>,
+) -> Result<HttpResponse, ApiError> {
+    let credentials = body.into_inner();
+    validate(&credentials)?;
+
+    let hash = web::block(move || password::hash(&credentials.password))
+        .await?
+        .map_err(|_| ApiError::Internal("Failed to hash password.".to_string()))?;
+
+    let user = users::create(&db.lock(), &credentials.username, &hash, now())?
+        .ok_or_else(|| ApiError::Conflict("Username is already taken.".to_string()))?;
+
+    Ok(HttpResponse::Created().json(UserResponse { user }))
+}
+
+/// Responds with an access token, and sets the refresh token as an HTTP-only cookie.
+#[utoipa::path(responses((status = OK, body = Session)), security(()))]
+#[post("/login")]
+async fn login(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    key: web::Data<TokenKey>,
+    limiter: web::Data<Limiter>,
+    body: web::Json<Credentials>,
+) -> Result<HttpResponse, ApiError> {
+    let credentials = body.into_inner();
+    let ip = limiter.client_ip(&req);
+    let username = credentials.username.clone();
+    limiter.login(ip, &username, Instant::now())?;
+
+    let user = users::find_by_username(&db.lock(), &credentials.username)?;
+
+    let (user, valid) = web::block(move || {
+        let hash = match &user {
+            Some(user) => &user.password,
+            None => password::dummy_hash(),
+        };
+        let valid = password::verify(&credentials.password, hash);
+        (user, valid)
+    })
+    .await?;
+
+    match user {
+        Some(user) if valid => {
+            limiter.login_succeeded(ip, &username);
+            let family = tokens::random_token();
+            let refresh_token = tokens::random_token();
+            let now = now();
+
+            sessions::create(
+                &db.lock(),
+                user.id,
+                &family,
+                &tokens::hash_token(&refresh_token),
+                now,
+                now + REFRESH_TOKEN_LIFETIME,
+            )?;
+
+            Ok(session_response(&key, &user, family, refresh_token))
+        }
+        _ => {
+            limiter.login_failed(ip, &username, Instant::now());
+            Err(ApiError::Unauthorized(
+                "Invalid username or password.".to_string(),
+            ))
+        }
+    }
+}
+
+/// Trades the refresh token cookie for a new access token, and a new refresh token.
+#[utoipa::path(responses((status = OK, body = Session)), security(()))]
+#[get("/refresh")]
+async fn refresh(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    key: web::Data<TokenKey>,
+    logins: web::Data<Logins>,
+) -> Result<HttpResponse, ApiError> {
+    let old_token = req
+        .cookie(REFRESH_COOKIE)
+        .ok_or_else(|| ApiError::Unauthorized("Missing refresh token.".to_string()))?;
+    let new_token = tokens::random_token();
+    let now = now();
+
+    let conn = db.lock();
+    let rotation = sessions::rotate(
+        &conn,
+        &tokens::hash_token(old_token.value()),
+        &tokens::hash_token(&new_token),
+        now,
+        now + REFRESH_TOKEN_LIFETIME,
+    )?;
+
+    if let Rotation::Reused { user_id } = rotation {
+        logins.forget(user_id);
+    }
+
+    match rotation {
+        Rotation::Rotated { user_id, family } => {
+            let user = users::find_by_id(&conn, user_id)?
+                .ok_or_else(|| ApiError::Unauthorized("Account no longer exists.".to_string()))?;
+            Ok(session_response(&key, &user, family, new_token))
+        }
+        Rotation::Reused { .. } | Rotation::Invalid => {
+            let mut response =
+                ApiError::Unauthorized("Invalid or expired refresh token.".to_string())
+                    .error_response();
+            response
+                .add_cookie(&removal_cookie())
+                .map_err(|_| ApiError::Internal("Failed to set cookie.".to_string()))?;
+            Ok(response)
+        }
+    }
+}
+
+#[utoipa::path(responses((status = NO_CONTENT)), security(()))]
+#[post("/logout")]
+async fn logout(
+    req: HttpRequest,
+    db: web::Data<Database>,
+    logins: web::Data<Logins>,
+) -> Result<HttpResponse, ApiError> {
+    if let Some(token) = req.cookie(REFRESH_COOKIE) {
+        let user_id = sessions::revoke_by_token(&db.lock(), &tokens::hash_token(token.value()))?;
+        if let Some(user_id) = user_id {
+            logins.forget(user_id);
+        }
+    }
+
+    Ok(HttpResponse::NoContent().cookie(removal_cookie()).finish())
+}
+
+#[utoipa::path(responses((status = OK, body = UserResponse)))]
+#[get("/me")]
+async fn me(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let user = users::find_by_id(&db.lock(), user.id)?
+        .ok_or_else(|| ApiError::Unauthorized("Account no longer exists.".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(UserResponse { user }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::RateLimits,
+        testing::{app, call, request, sign_up},
+    };
+    use actix_web::{
+        dev::{Service, ServiceResponse},
+        http::{header, Method, StatusCode},
+        test,
+    };
+    use serde_json::{json, Value};
+
+    async fn post(
+        app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = actix_web::Error>,
+        uri: &str,
+        body: Value,
+    ) -> (StatusCode, Value) {
+        call(app, test::TestRequest::post().uri(uri).set_json(body)).await
+    }
+
+    #[actix_web::test]
+    async fn register_then_login() {
+        let app = test::init_service(app()).await;
+        let credentials = json!({ "username": "steven", "password": "correct horse" });
+
+        let (status, body) = post(&app, "/api/register", credentials.clone()).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["user"]["username"], "steven");
+        assert!(body["user"].get("password").is_none());
+
+        let (status, body) = post(&app, "/api/register", credentials.clone()).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        assert_eq!(body["code"], "conflict");
+        assert_eq!(body["message"], "Username is already taken.");
+
+        let (status, body) = post(&app, "/api/login", credentials).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["access_token"].is_string());
+
+        let wrong = json!({ "username": "steven", "password": "wrong horse" });
+        let (status, body) = post(&app, "/api/login", wrong).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], "Invalid username or password.");
+
+        let missing = json!({ "username": "nobody", "password": "correct horse" });
+        let (status, _) = post(&app, "/api/login", missing).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn repeated_failures_lock_out() {
+        let app = test::init_service(app()).await;
+        sign_up(&app, "steven").await;
+        let wrong = json!({ "username": "steven", "password": "wrong horse" });
+
+        for _ in 0..RateLimits::default().lockout_threshold {
+            let (status, _) = post(&app, "/api/login", wrong.clone()).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED);
+        }
+
+        // Even the right password doesn't work until the lockout is over
+        let req = test::TestRequest::post()
+            .uri("/api/login")
+            .set_json(json!({ "username": "steven", "password": "correct horse" }));
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get(header::RETRY_AFTER).unwrap(), "30");
+        let body: Value = test::read_body_json(res).await;
+        assert_eq!(
+            body["message"],
+            "Too many failed logins, try again in 30 seconds."
+        );
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_credentials() {
+        let app = test::init_service(app()).await;
+
+        let (status, _) = post(
+            &app,
+            "/api/register",
+            json!({ "username": "a", "password": "correct horse" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = post(
+            &app,
+            "/api/register",
+            json!({ "username": "steven", "password": "short" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // Everything that's wrong at once, field by field
+        let (status, body) = post(
+            &app,
+            "/api/register",
+            json!({ "username": "a b", "password": "short" }),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid");
+        assert_eq!(body["fields"][0]["field"], "username");
+        assert_eq!(body["fields"][1]["field"], "password");
+
+        // Bodies that don't even parse get the same envelope
+        let req = test::TestRequest::post()
+            .uri("/api/register")
+            .insert_header((header::CONTENT_TYPE, "application/json"))
+            .set_payload("{ \"username\": ");
+        let (status, body) = call(&app, req).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "bad_request");
+        assert!(body["message"].as_str().unwrap().contains("Json"));
+    }
+
+    #[actix_web::test]
+    async fn access_token_guards_me() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let (status, _) = call(&app, test::TestRequest::get().uri("/api/me")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let req = test::TestRequest::get()
+            .uri("/api/me")
+            .insert_header((header::AUTHORIZATION, format!("Bearer {token}")));
+        let (status, body) = call(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["user"]["username"], "steven");
+    }
+
+    #[actix_web::test]
+    async fn refresh_rotates_and_detects_reuse() {
+        let app = test::init_service(app()).await;
+        let credentials = json!({ "username": "steven", "password": "correct horse" });
+        post(&app, "/api/register", credentials.clone()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/login")
+            .set_json(credentials);
+        let res = test::call_service(&app, req.to_request()).await;
+        let first = res.response().cookies().next().unwrap().into_owned();
+        assert!(first.http_only().unwrap());
+        let body: Value = test::read_body_json(res).await;
+        let access_token = body["access_token"].as_str().unwrap().to_string();
+
+        let refresh_with = |cookie: Cookie<'static>| {
+            test::TestRequest::get()
+                .uri("/api/refresh")
+                .cookie(cookie)
+                .to_request()
+        };
+
+        let res = test::call_service(&app, refresh_with(first.clone())).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let second = res.response().cookies().next().unwrap().into_owned();
+        assert_ne!(first.value(), second.value());
+
+        // Replaying the first token kills the family, including the second token
+        let (status, _) = call(&app, request(Method::GET, "/api/me", &access_token)).await;
+        assert_eq!(status, StatusCode::OK);
+        let res = test::call_service(&app, refresh_with(first)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let res = test::call_service(&app, refresh_with(second)).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        // and its access tokens, even though they haven't expired yet
+        let (status, _) = call(&app, request(Method::GET, "/api/me", &access_token)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn logout_revokes_refresh_token() {
+        let app = test::init_service(app()).await;
+        let credentials = json!({ "username": "steven", "password": "correct horse" });
+        post(&app, "/api/register", credentials.clone()).await;
+
+        let req = test::TestRequest::post()
+            .uri("/api/login")
+            .set_json(credentials);
+        let res = test::call_service(&app, req.to_request()).await;
+        let cookie = res.response().cookies().next().unwrap().into_owned();
+        let body: Value = test::read_body_json(res).await;
+        let access_token = body["access_token"].as_str().unwrap().to_string();
+
+        let req = test::TestRequest::post()
+            .uri("/api/logout")
+            .cookie(cookie.clone());
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(res.response().cookies().next().unwrap().value(), "");
+
+        let req = test::TestRequest::get().uri("/api/refresh").cookie(cookie);
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let (status, body) = call(&app, request(Method::GET, "/api/me", &access_token)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], "Invalid or expired access token.");
+    }
+
+    #[actix_web::test]
+    async fn rotating_forgets_expired_tokens() {
+        let db = Database::open_in_memory().unwrap();
+        let conn = db.lock();
+        let user = users::create(&conn, "steven", "hash", 0).unwrap().unwrap();
+        sessions::create(&conn, user.id, "old", "old token", 0, 100).unwrap();
+        sessions::create(&conn, user.id, "new", "new token", 0, 300).unwrap();
+
+        let rotation = sessions::rotate(&conn, "new token", "newer token", 200, 500).unwrap();
+        assert!(matches!(rotation, Rotation::Rotated { .. }));
+
+        let tokens: i64 = conn
+            .query_row("SELECT COUNT(*) FROM RefreshTokens", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tokens, 2);
+        assert!(!sessions::is_active(&conn, "old").unwrap());
+        assert!(sessions::is_active(&conn, "new").unwrap());
+    }
+}
@@ -0,0 +1,534 @@
+use super::tasks::validate;
+use crate::{
+    auth::AuthUser,
+    backup::{self, Backup},
+    database::{
+        dependencies, occurrences,
+        revisions::{self, State},
+        tasks::{self, from_timestamp, Task, TaskFields},
+        views, Database,
+    },
+    error::ApiError,
+    filter,
+    gateway::Gateway,
+    plan,
+    util::now,
+};
+use actix_web::{http::header, web, HttpResponse};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+/// Backups carry the whole history, so they can get a lot bigger than other requests.
+const MAX_BACKUP_SIZE: usize = 32 * 1024 * 1024;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ImportQuery {
+    /// Only report what would change
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+struct Changes {
+    created: Vec<String>,
+    updated: Vec<String>,
+    unchanged: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Skipped {
+    uid: String,
+    reason: String,
+}
+
+#[derive(Default, Serialize, ToSchema)]
+struct Report {
+    dry_run: bool,
+    /// UIDs of the tasks
+    tasks: Changes,
+    skipped: Vec<Skipped>,
+    /// Names of the views
+    views: Changes,
+    /// How many revisions were brought over
+    revisions: usize,
+    /// The ID each task from the backup has here
+    ids: BTreeMap<i64, i64>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/backup")
+            .app_data(web::PayloadConfig::new(MAX_BACKUP_SIZE))
+            .route(web::get().to(export))
+            .route(web::post().to(import)),
+    );
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(export, import))]
+pub(super) struct Docs;
+
+/// Everything in the user's account, outside of shared lists.
+#[utoipa::path(get, path = "/backup", responses((status = OK, body = Backup)))]
+async fn export(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let own: Vec<_> = tasks::list(&conn, user.id)?
+        .into_iter()
+        .filter(|task| task.list_id.is_none())
+        .collect();
+
+    let mut entries = Vec::new();
+    for task in &own {
+        // Blockers can be in lists the backup doesn't have
+        let blocked_by = dependencies::blockers(&conn, user.id, task.id)?
+            .into_iter()
+            .filter(|id| own.iter().any(|task| task.id == *id))
+            .collect();
+        let overrides = occurrences::list(&conn, task.id)?;
+        entries.push(backup::Task::of(task.clone(), blocked_by, overrides));
+    }
+
+    let history = revisions::personal(&conn, user.id)?
+        .into_iter()
+        .map(backup::Entry::from)
+        .collect();
+    let backup = Backup::new(
+        from_timestamp(now()),
+        entries,
+        history,
+        views::list(&conn, user.id)?,
+    );
+
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"ztasks-backup.json\"",
+        ))
+        .json(backup))
+}
+
+/// Takes a backup as the body. Tasks are matched by UID, so importing the same backup again only updates
+/// what changed since. Either everything is imported or nothing is.
+#[utoipa::path(
+    post,
+    path = "/backup",
+    params(ImportQuery),
+    request_body = Backup,
+    responses((status = OK, body = Report))
+)]
+async fn import(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    query: web::Query<ImportQuery>,
+    body: web::Bytes,
+) -> Result<HttpResponse, ApiError> {
+    let backup = Backup::read(&body).map_err(ApiError::BadRequest)?;
+
+    let mut fields = Vec::new();
+    for task in &backup.tasks {
+        let checked = validate(task.fields())
+            .map_err(|error| ApiError::BadRequest(format!("Task {}: {error}", task.id)))?;
+        fields.push(checked);
+    }
+    for view in &backup.settings.views {
+        if view.name.trim().is_empty() || view.name.chars().count() > 50 {
+            return Err(ApiError::BadRequest(format!(
+                "View \"{}\" needs a name between 1 and 50 characters.",
+                view.name
+            )));
+        }
+        filter::parse(&view.query).map_err(|error| {
+            ApiError::BadRequest(format!(
+                "View \"{}\" has an invalid filter: {error}",
+                view.name
+            ))
+        })?;
+    }
+
+    let conn = db.lock();
+    let tx = conn.unchecked_transaction()?;
+    let mut report = Report {
+        dry_run: query.dry_run,
+        ..Report::default()
+    };
+    let (created, updated) = import_tasks(&tx, user.id, &backup, fields, &mut report)?;
+    import_history(&tx, user.id, &backup, &created, &mut report)?;
+
+    let timestamp = now();
+    for view in &backup.settings.views {
+        let name = view.name.trim();
+        let query = view.query.trim();
+        match views::get(&tx, user.id, name)? {
+            Some(existing) if existing.query == query => {
+                report.views.unchanged.push(name.to_string());
+                continue;
+            }
+            Some(_) => report.views.updated.push(name.to_string()),
+            None => report.views.created.push(name.to_string()),
+        }
+        views::save(&tx, user.id, name, query, timestamp)?;
+    }
+
+    // Rolling back leaves everything the way it was
+    if query.dry_run {
+        return Ok(HttpResponse::Ok().json(report));
+    }
+    tx.commit()?;
+
+    for task in &created {
+        gateway.task_created(&[user.id], task);
+    }
+    for task in &updated {
+        gateway.task_updated(&[user.id], task);
+    }
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// Creates or updates every task in the backup, and returns the ones that were created and updated.
+fn import_tasks(
+    conn: &Connection,
+    user_id: i64,
+    backup: &Backup,
+    fields: Vec<TaskFields>,
+    report: &mut Report,
+) -> Result<(Vec<Task>, Vec<Task>), ApiError> {
+    let timestamp = now();
+    // Every task that's imported, and whether it's new
+    let mut imported: Vec<(&backup::Task, i64, bool)> = Vec::new();
+    let mut changed = Vec::new();
+
+    for (task, fields) in backup.tasks.iter().zip(fields) {
+        let id = match tasks::find_by_uid(conn, user_id, &task.uid)? {
+            Some(existing) if existing.list_id.is_some() => {
+                report.skipped.push(Skipped {
+                    uid: task.uid.clone(),
+                    reason: "A task in a shared list already has this UID.".to_string(),
+                });
+                continue;
+            }
+            Some(existing) => {
+                if existing.fields() != fields {
+                    tasks::update(conn, user_id, existing.id, &fields, timestamp)?;
+                    changed.push(existing.id);
+                }
+                imported.push((task, existing.id, false));
+                existing.id
+            }
+            None => {
+                let created = tasks::insert_with_uid(
+                    conn,
+                    user_id,
+                    &task.uid,
+                    &fields,
+                    task.created.timestamp(),
+                )?;
+                imported.push((task, created.id, true));
+                created.id
+            }
+        };
+        report.ids.insert(task.id, id);
+    }
+
+    // Now that every task has an ID here, link them up
+    for &(task, id, new) in &imported {
+        // New tasks keep the time they were last changed in the backup
+        let timestamp = if new {
+            task.updated.timestamp()
+        } else {
+            timestamp
+        };
+        let Some(current) = tasks::get(conn, user_id, id)? else {
+            continue;
+        };
+
+        let completed = task.completed.map(|time| time.timestamp());
+        if new || current.completed.map(|time| time.timestamp()) != completed {
+            tasks::set_completed(conn, user_id, id, completed, timestamp)?;
+            changed.push(id);
+        }
+
+        let parent_id = task
+            .parent_id
+            .and_then(|parent_id| report.ids.get(&parent_id).copied());
+        if current.parent_id != parent_id {
+            if let Some(parent_id) = parent_id {
+                check_cycle(conn, user_id, parent_id, id)?;
+            }
+            tasks::set_parent(conn, user_id, id, parent_id, timestamp)?;
+            changed.push(id);
+        }
+
+        let overrides = occurrences::list(conn, id)?;
+        if overrides != task.occurrences {
+            occurrences::clear(conn, id)?;
+            for changes in &task.occurrences {
+                occurrences::save(conn, id, changes)?;
+            }
+            changed.push(id);
+        }
+    }
+
+    // After the parents, so blockers are checked against every subtask
+    for &(task, id, _) in &imported {
+        let mut blocked_by: Vec<_> = task
+            .blocked_by
+            .iter()
+            .filter_map(|blocker_id| report.ids.get(blocker_id).copied())
+            .collect();
+        blocked_by.sort();
+        let current = dependencies::blockers(conn, user_id, id)?;
+        if current == blocked_by {
+            continue;
+        }
+
+        for blocker_id in current {
+            dependencies::remove(conn, id, blocker_id)?;
+        }
+        for &blocker_id in &blocked_by {
+            check_cycle(conn, user_id, id, blocker_id)?;
+            dependencies::add(conn, id, blocker_id)?;
+        }
+        changed.push(id);
+    }
+
+    let mut created = Vec::new();
+    let mut updated = Vec::new();
+    for (task, id, new) in imported {
+        let Some(current) = tasks::get(conn, user_id, id)? else {
+            continue;
+        };
+
+        if new {
+            report.tasks.created.push(task.uid.clone());
+            created.push(current);
+        } else if changed.contains(&id) {
+            report.tasks.updated.push(task.uid.clone());
+            updated.push(current);
+        } else {
+            report.tasks.unchanged.push(task.uid.clone());
+        }
+    }
+
+    Ok((created, updated))
+}
+
+/// Tasks here might already wait on the ones in the backup in ways the backup doesn't know about.
+fn check_cycle(conn: &Connection, user_id: i64, waiting: i64, on: i64) -> Result<(), ApiError> {
+    if plan::creates_cycle(&dependencies::edges(conn, user_id)?, waiting, on) {
+        return Err(ApiError::Conflict(format!(
+            "Importing would make task {waiting} wait on itself."
+        )));
+    }
+    Ok(())
+}
+
+/// Brings over the history of the tasks that were just created, and of the deleted tasks that have never
+/// been here. Tasks that were already here keep their own history.
+fn import_history(
+    conn: &Connection,
+    user_id: i64,
+    backup: &Backup,
+    created: &[Task],
+    report: &mut Report,
+) -> Result<(), ApiError> {
+    let mut by_task: BTreeMap<i64, Vec<&backup::Entry>> = BTreeMap::new();
+    for entry in &backup.history {
+        by_task.entry(entry.task_id).or_default().push(entry);
+    }
+
+    // Deleted tasks get IDs of their own, so they can be restored later
+    let mut ids: HashMap<i64, i64> = HashMap::new();
+    let mut deleted = Vec::new();
+    for (&task_id, entries) in &by_task {
+        if let Some(&id) = report.ids.get(&task_id) {
+            if created.iter().any(|task| task.id == id) {
+                ids.insert(task_id, id);
+            }
+        } else if !backup.tasks.iter().any(|task| task.id == task_id) {
+            let uid = &entries[entries.len() - 1].state.uid;
+            if !revisions::has_uid(conn, user_id, uid)? {
+                deleted.push(task_id);
+            }
+        }
+    }
+    if !deleted.is_empty() {
+        let first = tasks::reserve_ids(conn, deleted.len() as i64)?;
+        ids.extend(deleted.into_iter().zip(first..));
+    }
+
+    for (task_id, id) in &ids {
+        let history: Vec<_> = by_task[task_id]
+            .iter()
+            .map(|entry| {
+                let state = State {
+                    parent_id: entry
+                        .state
+                        .parent_id
+                        .and_then(|parent_id| report.ids.get(&parent_id).or(ids.get(&parent_id)))
+                        .copied(),
+                    list_id: None,
+                    ..entry.state.clone()
+                };
+                (entry.kind, state, entry.time.timestamp())
+            })
+            .collect();
+
+        revisions::replace(conn, user_id, *id, &history)?;
+        report.revisions += history.len();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn moving_accounts() {
+        let app = test::init_service(app()).await;
+        let old = sign_up(&app, "steven").await;
+        let new = sign_up(&app, "emma").await;
+
+        // Something for the new account to already have, so IDs don't line up
+        call(
+            &app,
+            request(Method::POST, "/api/tasks", &new).set_json(json!({ "title": "Water plants" })),
+        )
+        .await;
+
+        let mut ids = Vec::new();
+        for task in [
+            json!({ "title": "Move", "category": "home" }),
+            json!({ "title": "Pack", "category": "Home", "due": "2023-03-01T09:00:00Z" }),
+            json!({ "title": "Throw out", "category": "home" }),
+        ] {
+            let (_, body) = call(
+                &app,
+                request(Method::POST, "/api/tasks", &old).set_json(task),
+            )
+            .await;
+            ids.push(body["task"]["id"].as_i64().unwrap());
+        }
+        let [moving, packing, trash] = ids[..] else {
+            unreachable!()
+        };
+        call(
+            &app,
+            request(Method::PATCH, &format!("/api/tasks/{packing}"), &old)
+                .set_json(json!({ "parent_id": moving, "title": "Pack boxes" })),
+        )
+        .await;
+        call(
+            &app,
+            request(
+                Method::PUT,
+                &format!("/api/tasks/{moving}/blockers/{trash}"),
+                &old,
+            ),
+        )
+        .await;
+        call(
+            &app,
+            request(Method::DELETE, &format!("/api/tasks/{trash}"), &old),
+        )
+        .await;
+        call(
+            &app,
+            request(Method::PUT, "/api/views/home", &old)
+                .set_json(json!({ "query": "category:home" })),
+        )
+        .await;
+
+        let res =
+            test::call_service(&app, request(Method::GET, "/api/backup", &old).to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let backup: Value = test::read_body_json(res).await;
+        assert_eq!(backup["version"], 1);
+        assert_eq!(backup["categories"], json!(["home"]));
+        assert_eq!(backup["tasks"].as_array().unwrap().len(), 2);
+        let revisions = backup["history"].as_array().unwrap().len();
+
+        let import = |dry_run: bool| {
+            request(
+                Method::POST,
+                &format!("/api/backup?dry_run={dry_run}"),
+                &new,
+            )
+            .set_json(backup.clone())
+        };
+        let (status, body) = call(&app, import(true)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["dry_run"], true);
+        assert_eq!(body["tasks"]["created"].as_array().unwrap().len(), 2);
+        assert_eq!(body["views"]["created"], json!(["home"]));
+        let (_, tasks) = call(&app, request(Method::GET, "/api/tasks", &new)).await;
+        assert_eq!(tasks["tasks"].as_array().unwrap().len(), 1);
+
+        let (status, body) = call(&app, import(false)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["revisions"], revisions);
+        let moved = body["ids"][moving.to_string()].as_i64().unwrap();
+        let packed = body["ids"][packing.to_string()].as_i64().unwrap();
+        assert_ne!(moved, moving);
+
+        let (_, task) = call(
+            &app,
+            request(Method::GET, &format!("/api/tasks/{packed}"), &new),
+        )
+        .await;
+        assert_eq!(task["task"]["parent_id"], moved);
+        assert_eq!(task["task"]["title"], "Pack boxes");
+        let history =
+            |id: i64, token: &str| request(Method::GET, &format!("/api/tasks/{id}/history"), token);
+        let (_, before) = call(&app, history(packing, &old)).await;
+        let (_, after) = call(&app, history(packed, &new)).await;
+        assert_eq!(
+            before["revisions"].as_array().unwrap().len(),
+            after["revisions"].as_array().unwrap().len()
+        );
+        assert_eq!(after["revisions"][0]["changes"]["parent_id"]["to"], moved);
+
+        // The deleted task can still be brought back
+        let (_, deleted) = call(&app, request(Method::GET, "/api/tasks/deleted", &new)).await;
+        assert_eq!(deleted["tasks"][0]["title"], "Throw out");
+        let (status, _) = call(
+            &app,
+            request(
+                Method::POST,
+                &format!("/api/tasks/{}/undo", deleted["tasks"][0]["id"]),
+                &new,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Nothing changed since, so importing again doesn't do anything
+        let (_, body) = call(&app, import(false)).await;
+        assert_eq!(body["tasks"]["unchanged"].as_array().unwrap().len(), 2);
+        assert_eq!(body["views"]["unchanged"], json!(["home"]));
+        assert_eq!(body["revisions"], 0);
+
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/backup", &new)
+                .set_json(json!({ "format": "ztasks-backup", "version": 9 })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["message"],
+            "Backups from version 9 can't be read, only up to version 1."
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{
+    auth::AuthUser,
+    calendar::{self, Bucket, Grouping, Occurrence},
+    database::{occurrences, tasks, Database},
+    error::ApiError,
+};
+use actix_web::{get, web, HttpResponse};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+// Enough for a year of days, or a few decades of months
+const MAX_BUCKETS: usize = 400;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct CalendarQuery {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    /// IANA time zone the buckets are in, `UTC` by default
+    tz: Option<String>,
+    /// `day` by default
+    group: Option<Grouping>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CalendarResponse {
+    /// The range widened to whole buckets
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tz: String,
+    group: Grouping,
+    buckets: Vec<Bucket>,
+    occurrences: Vec<Occurrence>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_calendar);
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(get_calendar))]
+pub(super) struct Docs;
+
+/// The `tz` query parameter, which defaults to UTC.
+pub(super) fn time_zone(tz: Option<&str>) -> Result<Tz, ApiError> {
+    match tz {
+        Some(tz) => tz
+            .parse()
+            .map_err(|_| ApiError::field("tz", format!("Unknown time zone \"{tz}\"."))),
+        None => Ok(Tz::UTC),
+    }
+}
+
+/// Splits `[from, to)` up like `calendar::buckets()`, as long as that doesn't make too many of them.
+pub(super) fn split(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tz: Tz,
+    group: Grouping,
+) -> Result<Vec<Bucket>, ApiError> {
+    if from >= to {
+        return Err(ApiError::field("from", "\"from\" must be before \"to\"."));
+    }
+
+    // Cheap check first, so absurd ranges don't get split up at all
+    let too_long = to - from > Duration::days(MAX_BUCKETS as i64 * 31);
+    let buckets = match too_long {
+        true => Vec::new(),
+        false => calendar::buckets(from, to, tz, group),
+    };
+
+    if too_long || buckets.len() > MAX_BUCKETS {
+        return Err(ApiError::BadRequest(
+            "Range is too large for this grouping.".to_string(),
+        ));
+    }
+    Ok(buckets)
+}
+
+/// The tasks in `[from, to)`, split up by day, week or month.
+#[utoipa::path(params(CalendarQuery), responses((status = OK, body = CalendarResponse)))]
+#[get("/calendar")]
+async fn get_calendar(
+    user: AuthUser,
+    db: web::Data<Database>,
+    query: web::Query<CalendarQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let group = query.group.unwrap_or(Grouping::Day);
+    let tz = time_zone(query.tz.as_deref())?;
+    let mut buckets = split(query.from, query.to, tz, group)?;
+
+    // Widen the query to whole buckets, so the first and last ones aren't missing anything
+    let from = buckets
+        .first()
+        .map_or(query.from, |bucket| bucket.start.with_timezone(&Utc));
+    let to = buckets
+        .last()
+        .map_or(query.to, |bucket| bucket.end.with_timezone(&Utc));
+
+    let conn = db.lock();
+    let mut found = Vec::new();
+
+    for task in tasks::list_between(&conn, user.id, from, to)? {
+        let overrides = match task.recurrence {
+            Some(_) => occurrences::list(&conn, task.id)?,
+            None => Vec::new(),
+        };
+        found.extend(calendar::occurrences(&task, &overrides, from, to));
+    }
+
+    found.sort_by_key(|occurrence| occurrence.start.or(occurrence.due));
+
+    for bucket in &mut buckets {
+        bucket.occurrences = found
+            .iter()
+            .filter(|occurrence| occurrence.span().is_some_and(|span| bucket.contains(span)))
+            .map(|occurrence| occurrence.key.clone())
+            .collect();
+    }
+
+    Ok(HttpResponse::Ok().json(CalendarResponse {
+        from,
+        to,
+        tz: tz.name().to_string(),
+        group,
+        buckets,
+        occurrences: found,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn groups_tasks_by_local_day() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let tasks = [
+            // 11 PM on the 1st in New York, but already the 2nd in UTC
+            json!({ "title": "Late", "due": "2023-03-02T04:00:00Z" }),
+            json!({ "title": "Trip", "start": "2023-03-02T15:00:00Z", "due": "2023-03-03T15:00:00Z" }),
+            json!({ "title": "Way later", "due": "2023-04-01T00:00:00Z" }),
+            json!({ "title": "Whenever" }),
+        ];
+        for task in tasks {
+            call(
+                &app,
+                request(Method::POST, "/api/tasks", &token).set_json(task),
+            )
+            .await;
+        }
+
+        let uri =
+            "/api/calendar?from=2023-03-01T05:00:00Z&to=2023-03-04T05:00:00Z&tz=America/New_York";
+        let (status, body) = call(&app, request(Method::GET, uri, &token)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let titles = |keys: &Value| -> Vec<String> {
+            keys.as_array()
+                .unwrap()
+                .iter()
+                .map(|key| {
+                    let occurrence = body["occurrences"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .find(|occurrence| occurrence["key"] == *key)
+                        .unwrap();
+                    occurrence["title"].as_str().unwrap().to_string()
+                })
+                .collect()
+        };
+
+        let buckets = body["buckets"].as_array().unwrap();
+        assert_eq!(buckets.len(), 3);
+        assert_eq!(buckets[0]["label"], "2023-03-01");
+        assert_eq!(titles(&buckets[0]["occurrences"]), ["Late"]);
+        assert_eq!(titles(&buckets[1]["occurrences"]), ["Trip"]);
+        assert_eq!(titles(&buckets[2]["occurrences"]), ["Trip"]);
+        assert_eq!(body["occurrences"].as_array().unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn rejects_bad_queries() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        for uri in [
+            "/api/calendar?from=2023-03-01T00:00:00Z&to=2023-03-02T00:00:00Z&tz=Mars/Olympus",
+            "/api/calendar?from=2023-03-02T00:00:00Z&to=2023-03-01T00:00:00Z",
+            "/api/calendar?from=2023-01-01T00:00:00Z&to=2025-01-01T00:00:00Z&group=day",
+            "/api/calendar?from=2023-01-01T00:00:00Z&to=2023-02-01T00:00:00Z&group=year",
+        ] {
+            let (status, _) = call(&app, request(Method::GET, uri, &token)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST, "{uri}");
+        }
+    }
+}
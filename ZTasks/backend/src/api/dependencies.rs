@@ -0,0 +1,305 @@
+use super::tasks::check_editable;
+use crate::{
+    auth::AuthUser,
+    database::{
+        dependencies, lists,
+        tasks::{self, Task},
+        Database,
+    },
+    error::ApiError,
+    gateway::Gateway,
+    plan::{self, Step},
+};
+use actix_web::{delete, get, put, web, HttpResponse};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+#[derive(Serialize, ToSchema)]
+struct StepsResponse {
+    tasks: Vec<Step>,
+}
+
+/// How many of the subtasks below a task, at any depth, are done.
+#[derive(Serialize, ToSchema)]
+struct Progress {
+    completed: i64,
+    total: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SubtasksResponse {
+    /// Only the direct ones
+    subtasks: Vec<Task>,
+    progress: Progress,
+}
+
+#[derive(Serialize, ToSchema)]
+struct BlockersResponse {
+    /// IDs of the tasks this one waits on
+    blocked_by: Vec<i64>,
+    /// IDs of the tasks waiting on this one
+    blocking: Vec<i64>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(next_tasks)
+        .service(list_subtasks)
+        .service(list_blockers)
+        .service(add_blocker)
+        .service(remove_blocker);
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(next_tasks, list_subtasks, list_blockers, add_blocker, remove_blocker))]
+pub(super) struct Docs;
+
+fn not_found() -> ApiError {
+    ApiError::NotFound("Task not found.".to_string())
+}
+
+/// Every unfinished task, in an order that never puts a task before something it waits on.
+/// The ones marked `ready` can be worked on right now.
+#[utoipa::path(responses((status = OK, body = StepsResponse)))]
+#[get("/tasks/next")]
+async fn next_tasks(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let steps = plan::order(
+        tasks::list(&conn, user.id)?,
+        &dependencies::edges(&conn, user.id)?,
+    );
+
+    Ok(HttpResponse::Ok().json(StepsResponse { tasks: steps }))
+}
+
+#[utoipa::path(responses((status = OK, body = SubtasksResponse)))]
+#[get("/tasks/{id}/subtasks")]
+async fn list_subtasks(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    tasks::get(&conn, user.id, id)?.ok_or_else(not_found)?;
+
+    let subtasks = tasks::subtasks(&conn, user.id, id)?;
+    let (completed, total) = dependencies::progress(&conn, id)?;
+
+    Ok(HttpResponse::Ok().json(SubtasksResponse {
+        subtasks,
+        progress: Progress { completed, total },
+    }))
+}
+
+#[utoipa::path(responses((status = OK, body = BlockersResponse)))]
+#[get("/tasks/{id}/blockers")]
+async fn list_blockers(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    tasks::get(&conn, user.id, id)?.ok_or_else(not_found)?;
+
+    Ok(HttpResponse::Ok().json(BlockersResponse {
+        blocked_by: dependencies::blockers(&conn, user.id, id)?,
+        blocking: dependencies::blocking(&conn, user.id, id)?,
+    }))
+}
+
+/// Makes the task wait on `blocker_id`.
+#[utoipa::path(responses((status = NO_CONTENT)))]
+#[put("/tasks/{id}/blockers/{blocker_id}")]
+async fn add_blocker(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<(i64, i64)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, blocker_id) = path.into_inner();
+    let conn = db.lock();
+    let task = tasks::get(&conn, user.id, id)?.ok_or_else(not_found)?;
+    check_editable(&conn, user.id, task.list_id)?;
+    tasks::get(&conn, user.id, blocker_id)?
+        .ok_or_else(|| ApiError::NotFound("Blocking task not found.".to_string()))?;
+
+    if plan::creates_cycle(&dependencies::edges(&conn, user.id)?, id, blocker_id) {
+        return Err(ApiError::Conflict(
+            "Tasks can't block themselves, or anything they're waiting on.".to_string(),
+        ));
+    }
+
+    if dependencies::add(&conn, id, blocker_id)? {
+        gateway.task_updated(&lists::audience(&conn, user.id, task.list_id)?, &task);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[utoipa::path(responses((status = NO_CONTENT)))]
+#[delete("/tasks/{id}/blockers/{blocker_id}")]
+async fn remove_blocker(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<(i64, i64)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, blocker_id) = path.into_inner();
+    let conn = db.lock();
+    let task = tasks::get(&conn, user.id, id)?.ok_or_else(not_found)?;
+    check_editable(&conn, user.id, task.list_id)?;
+
+    match dependencies::remove(&conn, id, blocker_id)? {
+        true => {
+            gateway.task_updated(&lists::audience(&conn, user.id, task.list_id)?, &task);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        false => Err(ApiError::NotFound(
+            "Task isn't blocked by that.".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn subtasks_and_blockers() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let create = |body: Value| {
+            let req = request(Method::POST, "/api/tasks", &token).set_json(body);
+            let app = &app;
+            async move { call(app, req).await.1["task"]["id"].as_i64().unwrap() }
+        };
+        let move_out = create(json!({ "title": "Move out", "due": "2023-03-31T00:00:00Z" })).await;
+        let pack = create(json!({ "title": "Pack", "parent_id": move_out })).await;
+        let boxes = create(json!({ "title": "Buy boxes", "parent_id": pack })).await;
+        let van = create(json!({ "title": "Rent a van", "due": "2023-03-20T00:00:00Z" })).await;
+        let other = create(json!({ "title": "Something else" })).await;
+
+        let uri = format!("/api/tasks/{move_out}/subtasks");
+        let (status, body) = call(&app, request(Method::GET, &uri, &token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["subtasks"].as_array().unwrap().len(), 1);
+        assert_eq!(body["progress"], json!({ "completed": 0, "total": 2 }));
+
+        let uri = format!("/api/tasks/{boxes}/complete");
+        call(&app, request(Method::POST, &uri, &token)).await;
+        let uri = format!("/api/tasks/{move_out}/subtasks");
+        let (_, body) = call(&app, request(Method::GET, &uri, &token)).await;
+        assert_eq!(body["progress"], json!({ "completed": 1, "total": 2 }));
+
+        // Packing waits on the van, which can't wait on moving out (or anything under it) in turn
+        let uri = format!("/api/tasks/{pack}/blockers/{van}");
+        let (status, _) = call(&app, request(Method::PUT, &uri, &token)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        for blocker in [move_out, pack, van] {
+            let uri = format!("/api/tasks/{van}/blockers/{blocker}");
+            let (status, body) = call(&app, request(Method::PUT, &uri, &token)).await;
+            assert_eq!(status, StatusCode::CONFLICT);
+            assert_eq!(
+                body["message"],
+                "Tasks can't block themselves, or anything they're waiting on."
+            );
+        }
+
+        // Same goes for subtasks
+        let uri = format!("/api/tasks/{move_out}");
+        let (status, _) = call(
+            &app,
+            request(Method::PATCH, &uri, &token).set_json(json!({ "parent_id": boxes })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let uri = format!("/api/tasks/{pack}");
+        let (status, _) = call(
+            &app,
+            request(Method::PATCH, &uri, &token).set_json(json!({ "parent_id": van })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let (status, body) = call(&app, request(Method::GET, "/api/tasks/next", &token)).await;
+        assert_eq!(status, StatusCode::OK);
+        let order: Vec<_> = body["tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|step| {
+                (
+                    step["id"].as_i64().unwrap(),
+                    step["ready"].as_bool().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            order,
+            [(van, true), (pack, false), (move_out, false), (other, true)]
+        );
+
+        let uri = format!("/api/tasks/{pack}/blockers");
+        let (_, body) = call(&app, request(Method::GET, &uri, &token)).await;
+        assert_eq!(body, json!({ "blocked_by": [van], "blocking": [] }));
+
+        let uri = format!("/api/tasks/{pack}/blockers/{van}");
+        let (status, _) = call(&app, request(Method::DELETE, &uri, &token)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&app, request(Method::DELETE, &uri, &token)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Deleting a task takes its subtasks with it
+        let uri = format!("/api/tasks/{move_out}");
+        call(&app, request(Method::DELETE, &uri, &token)).await;
+        let uri = format!("/api/tasks/{boxes}");
+        let (status, _) = call(&app, request(Method::GET, &uri, &token)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn blockers_only_show_visible_tasks() {
+        let app = test::init_service(app()).await;
+        let owner = sign_up(&app, "steven").await;
+        let member = sign_up(&app, "emma").await;
+
+        let (_, body) = call(
+            &app,
+            request(Method::POST, "/api/lists", &owner).set_json(json!({ "name": "Household" })),
+        )
+        .await;
+        let list = body["list"]["id"].as_i64().unwrap();
+        let uri = format!("/api/lists/{list}/invites");
+        let (_, body) = call(
+            &app,
+            request(Method::POST, &uri, &owner).set_json(json!({ "role": "editor" })),
+        )
+        .await;
+        let link = format!("/api/invites/{}", body["token"].as_str().unwrap());
+        call(&app, request(Method::POST, &link, &member)).await;
+
+        let create = |token: &str, body: Value| {
+            let req = request(Method::POST, "/api/tasks", token).set_json(body);
+            let app = &app;
+            async move { call(app, req).await.1["task"]["id"].as_i64().unwrap() }
+        };
+        let shop = create(&member, json!({ "title": "Shop", "list_id": list })).await;
+        let cook = create(&owner, json!({ "title": "Cook" })).await;
+        let uri = format!("/api/tasks/{cook}/blockers/{shop}");
+        let (status, _) = call(&app, request(Method::PUT, &uri, &owner)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        // Only the owner knows what's waiting on the shopping
+        let uri = format!("/api/tasks/{shop}/blockers");
+        let (_, body) = call(&app, request(Method::GET, &uri, &owner)).await;
+        assert_eq!(body, json!({ "blocked_by": [], "blocking": [cook] }));
+        let (_, body) = call(&app, request(Method::GET, &uri, &member)).await;
+        assert_eq!(body, json!({ "blocked_by": [], "blocking": [] }));
+    }
+}
@@ -0,0 +1,510 @@
+use super::tasks::{check_editable, rescheduled, TaskResponse};
+use crate::{
+    auth::AuthUser,
+    database::{
+        clocks, dependencies, lists, occurrences,
+        revisions::{self, Kind, Revision, State},
+        tasks::{self, from_timestamp, Task},
+        Database,
+    },
+    error::ApiError,
+    gateway::Gateway,
+    plan,
+    util::now,
+};
+use actix_web::{get, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use utoipa::{OpenApi, ToSchema};
+
+const MAX_DELETED: i64 = 100;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(deleted_tasks)
+        .service(task_history)
+        .service(restore_revision)
+        .service(undo);
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(deleted_tasks, task_history, restore_revision, undo))]
+pub(super) struct Docs;
+
+#[derive(Serialize, ToSchema)]
+struct DeletedTask {
+    id: i64,
+    title: String,
+    deleted: DateTime<Utc>,
+    /// The revision to restore to bring it back
+    revision: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DeletedResponse {
+    tasks: Vec<DeletedTask>,
+}
+
+/// One field of a task changing.
+#[derive(Serialize, ToSchema)]
+struct Change {
+    from: Value,
+    to: Value,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HistoryEntry {
+    id: i64,
+    kind: Kind,
+    time: DateTime<Utc>,
+    /// By the name of the field
+    changes: BTreeMap<String, Change>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct HistoryResponse {
+    /// Whether the task is gone, in which case it can be restored
+    deleted: bool,
+    revisions: Vec<HistoryEntry>,
+}
+
+fn not_found() -> ApiError {
+    ApiError::NotFound("Task not found.".to_string())
+}
+
+/// The parts of a task that revisions can change, the way the API shows them.
+fn fields(state: &State) -> Map<String, Value> {
+    let time = |timestamp: Option<i64>| json!(timestamp.map(from_timestamp));
+    let fields = json!({
+        "title": state.title,
+        "notes": state.notes,
+        "category": state.category,
+        "start": time(state.start),
+        "due": time(state.due),
+        "completed": time(state.completed),
+        "recurrence": state.recurrence,
+        "time_zone": state.time_zone,
+        "parent_id": state.parent_id,
+    });
+
+    match fields {
+        Value::Object(fields) => fields,
+        _ => unreachable!(),
+    }
+}
+
+/// The fields that differ between two versions of a task. A new task only lists the fields that were
+/// filled in.
+fn diff(before: Option<&State>, after: &State) -> BTreeMap<String, Change> {
+    let created = before.is_none();
+    let before = before.map(fields).unwrap_or_default();
+
+    fields(after)
+        .into_iter()
+        .filter(|(_, to)| !(created && (to.is_null() || *to == "")))
+        .filter_map(|(name, to)| {
+            let from = before.get(&name).cloned().unwrap_or(Value::Null);
+            (from != to).then_some((name, Change { from, to }))
+        })
+        .collect()
+}
+
+/// Puts a task back the way it was at `revision`. Deleted tasks come back under the same ID, along with
+/// the subtasks that were deleted with them. Returns every task that changed, with the revision it's at.
+fn restore(
+    conn: &Connection,
+    user_id: i64,
+    revision: &Revision,
+    now: i64,
+) -> Result<Vec<(Task, i64)>, ApiError> {
+    let id = revision.task_id;
+    let history = revisions::list(conn, user_id, id)?;
+    let latest = history.last().ok_or_else(not_found)?;
+    let current = tasks::get(conn, user_id, id)?;
+    let mut state = revision.state.clone();
+
+    // Restoring never moves a task to another list, it stays where it is (or was deleted from)
+    state.list_id = latest.state.list_id;
+    check_editable(conn, user_id, state.list_id)?;
+
+    if current
+        .as_ref()
+        .is_some_and(|task| State::of(task) == state)
+    {
+        return Ok(Vec::new());
+    }
+
+    // Whatever the task was under may have gone, or moved below it or to another list since
+    if let Some(parent_id) = state.parent_id {
+        let moved = match &current {
+            Some(_) => plan::creates_cycle(&dependencies::edges(conn, user_id)?, parent_id, id),
+            None => false,
+        };
+        let parent = tasks::get(conn, user_id, parent_id)?;
+        if moved || parent.is_none_or(|parent| parent.list_id != state.list_id) {
+            state.parent_id = None;
+        }
+    }
+    // An import may have reused the UID in the meantime
+    if tasks::find_by_uid(conn, user_id, &state.uid)?.is_some_and(|task| task.id != id) {
+        state.uid = tasks::new_uid();
+    }
+
+    // A deleted task is still the way it was when it went, overrides and all
+    let (before, deletion) = match &current {
+        Some(task) => (task.fields(), None),
+        None => (latest.state.fields(), tasks::deletion(conn, id)?),
+    };
+    if rescheduled(&before, &state.fields()) {
+        occurrences::clear(conn, id)?;
+    }
+
+    let task =
+        tasks::restore(conn, user_id, latest.user_id, id, &state, now)?.ok_or_else(not_found)?;
+    revisions::mark_restored(conn, id)?;
+    // Sync can bring it back too, now that it's not deleted
+    clocks::unbury(conn, user_id, &task.uid)?;
+    let mut restored = vec![(task, revision.id)];
+
+    if let Some(deletion) = deletion {
+        for subtask in revisions::deleted_with(conn, user_id, id, deletion)? {
+            restored.extend(restore(conn, user_id, &subtask, now)?);
+        }
+    }
+
+    Ok(restored)
+}
+
+/// Restores the revisions in a transaction, then lets everyone know.
+fn restore_and_publish(
+    conn: &Connection,
+    gateway: &Gateway,
+    user_id: i64,
+    revision: &Revision,
+) -> Result<HttpResponse, ApiError> {
+    let tx = conn.unchecked_transaction()?;
+    let restored = restore(&tx, user_id, revision, now())?;
+    let task = tasks::get(&tx, user_id, revision.task_id)?.ok_or_else(not_found)?;
+    tx.commit()?;
+
+    let audience = lists::audience(conn, user_id, task.list_id)?;
+    for (task, revision) in &restored {
+        gateway.task_restored(&audience, task, *revision);
+    }
+    Ok(HttpResponse::Ok().json(TaskResponse { task }))
+}
+
+/// Tasks that were deleted and can still be restored, most recently deleted first.
+#[utoipa::path(responses((status = OK, body = DeletedResponse)))]
+#[get("/tasks/deleted")]
+async fn deleted_tasks(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let tasks: Vec<_> = revisions::deleted(&db.lock(), user.id, MAX_DELETED)?
+        .into_iter()
+        .map(|revision| DeletedTask {
+            id: revision.task_id,
+            title: revision.state.title,
+            deleted: from_timestamp(revision.time),
+            revision: revision.id,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(DeletedResponse { tasks }))
+}
+
+/// Every change to a task, newest first. Works for deleted tasks too.
+#[utoipa::path(responses((status = OK, body = HistoryResponse)))]
+#[get("/tasks/{id}/history")]
+async fn task_history(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    let history = revisions::list(&conn, user.id, id)?;
+    if history.is_empty() {
+        return Err(not_found());
+    }
+
+    let mut previous = None;
+    let mut revisions = Vec::new();
+    for revision in &history {
+        revisions.push(HistoryEntry {
+            id: revision.id,
+            kind: revision.kind,
+            time: from_timestamp(revision.time),
+            changes: diff(previous, &revision.state),
+        });
+        previous = Some(&revision.state);
+    }
+    revisions.reverse();
+
+    Ok(HttpResponse::Ok().json(HistoryResponse {
+        deleted: tasks::get(&conn, user.id, id)?.is_none(),
+        revisions,
+    }))
+}
+
+/// Puts the task back the way it was at the revision, even if it was deleted since.
+#[utoipa::path(responses((status = OK, body = TaskResponse)))]
+#[post("/tasks/{id}/history/{revision}/restore")]
+async fn restore_revision(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<(i64, i64)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, revision_id) = path.into_inner();
+    let conn = db.lock();
+    let revision = revisions::get(&conn, user.id, id, revision_id)?
+        .ok_or_else(|| ApiError::NotFound("Revision not found.".to_string()))?;
+
+    restore_and_publish(&conn, &gateway, user.id, &revision)
+}
+
+/// Reverts the latest change to a task, including deleting it. An undo is a change too,
+/// so undoing twice in a row redoes.
+#[utoipa::path(responses((status = OK, body = TaskResponse)))]
+#[post("/tasks/{id}/undo")]
+async fn undo(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    let history = revisions::list(&conn, user.id, id)?;
+
+    // A delete's revision holds the task from right before it
+    let revision = match history.as_slice() {
+        [] => return Err(not_found()),
+        [.., latest] if latest.kind == Kind::Deleted => latest,
+        [.., previous, _] => previous,
+        [_] => return Err(ApiError::Conflict("Nothing to undo.".to_string())),
+    };
+
+    restore_and_publish(&conn, &gateway, user.id, revision)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        gateway::{
+            broker::{Broker, Connect, Subscribe},
+            protocol::{Event, ServerMessage, Topic},
+        },
+        testing::{app_with, call, events, received, request, sign_up, Collector},
+    };
+    use actix::Actor;
+    use actix_web::http::{Method, StatusCode};
+
+    fn state(title: &str, due: Option<i64>) -> State {
+        State {
+            title: title.to_string(),
+            notes: String::new(),
+            category: None,
+            start: None,
+            due,
+            completed: None,
+            created: 0,
+            recurrence: None,
+            time_zone: "UTC".to_string(),
+            uid: "1@ztasks".to_string(),
+            parent_id: None,
+            list_id: None,
+        }
+    }
+
+    #[test]
+    fn diffs() {
+        let created = state("Pay rent", None);
+        assert_eq!(
+            json!(diff(None, &created)),
+            json!({
+                "title": { "from": null, "to": "Pay rent" },
+                "time_zone": { "from": null, "to": "UTC" },
+            })
+        );
+
+        let updated = state("Pay the rent", Some(0));
+        assert_eq!(
+            json!(diff(Some(&created), &updated)),
+            json!({
+                "title": { "from": "Pay rent", "to": "Pay the rent" },
+                "due": { "from": null, "to": "1970-01-01T00:00:00Z" },
+            })
+        );
+        assert!(diff(Some(&updated), &updated).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn undo_and_restore() {
+        let broker = Broker::default().start();
+        let app = actix_web::test::init_service(app_with(broker.clone())).await;
+        let token = sign_up(&app, "steven").await;
+
+        let collector = Collector::default().start();
+        broker.do_send(Connect {
+            session_id: "watcher".to_string(),
+            user_id: 1,
+            recipient: collector.clone().recipient(),
+            resume_from: None,
+        });
+        broker.do_send(Subscribe {
+            session_id: "watcher".to_string(),
+            topics: vec![Topic::Tasks],
+            enabled: true,
+        });
+
+        let create = |body: Value| {
+            let req = request(Method::POST, "/api/tasks", &token).set_json(body);
+            let app = &app;
+            async move { call(app, req).await.1["task"]["id"].as_i64().unwrap() }
+        };
+        let id = create(json!({ "title": "Pay rent" })).await;
+        let subtask = create(json!({ "title": "Find checkbook", "parent_id": id })).await;
+        let uri = format!("/api/tasks/{id}");
+
+        // A subtask is created under its parent, not moved there afterwards
+        let (_, body) = call(
+            &app,
+            request(
+                Method::GET,
+                &format!("/api/tasks/{subtask}/history"),
+                &token,
+            ),
+        )
+        .await;
+        assert_eq!(body["revisions"].as_array().unwrap().len(), 1);
+        assert_eq!(body["revisions"][0]["kind"], "created");
+        assert_eq!(
+            body["revisions"][0]["changes"]["parent_id"],
+            json!({ "from": null, "to": id })
+        );
+
+        let (status, _) = call(&app, request(Method::POST, &format!("{uri}/undo"), &token)).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        let changes = json!({ "title": "Pay the rent", "notes": "Before the 1st" });
+        call(&app, request(Method::PATCH, &uri, &token).set_json(changes)).await;
+        call(
+            &app,
+            request(Method::POST, &format!("{uri}/complete"), &token),
+        )
+        .await;
+
+        let (status, body) = call(
+            &app,
+            request(Method::GET, &format!("{uri}/history"), &token),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["deleted"], false);
+        let kinds: Vec<_> = body["revisions"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|revision| revision["kind"].as_str().unwrap())
+            .collect();
+        assert_eq!(kinds, ["updated", "updated", "created"]);
+        assert_eq!(
+            body["revisions"][1]["changes"],
+            json!({
+                "title": { "from": "Pay rent", "to": "Pay the rent" },
+                "notes": { "from": "", "to": "Before the 1st" },
+            })
+        );
+        let first = body["revisions"][2]["id"].as_i64().unwrap();
+
+        // Undoing takes back the completion, restoring goes back any number of changes
+        let (_, body) = call(&app, request(Method::POST, &format!("{uri}/undo"), &token)).await;
+        assert_eq!(body["task"]["completed"], json!(null));
+        assert_eq!(body["task"]["title"], "Pay the rent");
+        let restore = format!("{uri}/history/{first}/restore");
+        let (status, body) = call(&app, request(Method::POST, &restore, &token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["task"]["title"], "Pay rent");
+        assert_eq!(body["task"]["notes"], "");
+
+        let (_, body) = call(
+            &app,
+            request(Method::GET, &format!("{uri}/history"), &token),
+        )
+        .await;
+        assert_eq!(body["revisions"][0]["kind"], "restored");
+        assert_eq!(body["revisions"].as_array().unwrap().len(), 5);
+
+        // Deleted tasks come back with their subtasks, and with what hangs off them
+        let other = create(json!({ "title": "Mail it" })).await;
+        let blockers = format!("/api/tasks/{other}/blockers");
+        call(
+            &app,
+            request(Method::PUT, &format!("{blockers}/{subtask}"), &token),
+        )
+        .await;
+        let time = format!("/api/tasks/{subtask}/time");
+        let entry = json!({ "start": "2024-03-01T09:00:00Z", "end": "2024-03-01T10:00:00Z" });
+        call(&app, request(Method::POST, &time, &token).set_json(entry)).await;
+
+        call(&app, request(Method::DELETE, &uri, &token)).await;
+        let (_, body) = call(&app, request(Method::GET, &blockers, &token)).await;
+        assert_eq!(body["blocked_by"], json!([]));
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks/deleted", &token)).await;
+        assert_eq!(body["tasks"].as_array().unwrap().len(), 2);
+        let (_, body) = call(
+            &app,
+            request(Method::GET, &format!("{uri}/history"), &token),
+        )
+        .await;
+        assert_eq!(body["deleted"], true);
+        assert_eq!(body["revisions"][0]["kind"], "deleted");
+
+        received(&broker, &collector).await;
+        let (status, body) =
+            call(&app, request(Method::POST, &format!("{uri}/undo"), &token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["task"]["id"], id);
+        assert_eq!(body["task"]["title"], "Pay rent");
+
+        let (status, body) = call(
+            &app,
+            request(Method::GET, &format!("/api/tasks/{subtask}"), &token),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["task"]["parent_id"], id);
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks/deleted", &token)).await;
+        assert_eq!(body["tasks"], json!([]));
+        let (_, body) = call(&app, request(Method::GET, &blockers, &token)).await;
+        assert_eq!(body["blocked_by"], json!([subtask]));
+        let (_, body) = call(&app, request(Method::GET, &time, &token)).await;
+        assert_eq!(body["seconds"], 3600);
+
+        let restored: Vec<_> = events(received(&broker, &collector).await)
+            .into_iter()
+            .map(|message| match message {
+                ServerMessage::Event {
+                    event: Event::TaskRestored { task, .. },
+                    ..
+                } => task.id,
+                message => panic!("Expected a restore, got {message:?}"),
+            })
+            .collect();
+        assert_eq!(restored, [id, subtask]);
+
+        // History is private like everything else
+        let other = sign_up(&app, "someone").await;
+        let (status, _) = call(
+            &app,
+            request(Method::GET, &format!("{uri}/history"), &other),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&app, request(Method::POST, &restore, &other)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
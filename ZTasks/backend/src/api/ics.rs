@@ -0,0 +1,215 @@
+use super::tasks::validate;
+use crate::{
+    auth::AuthUser,
+    calendar::ics,
+    database::{lists, occurrences, tasks, Database},
+    error::ApiError,
+    gateway::Gateway,
+    util::now,
+};
+use actix_web::{get, http::header, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+
+#[derive(Serialize, ToSchema)]
+struct ImportResponse {
+    created: usize,
+    updated: usize,
+    skipped: Vec<ics::Skipped>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(export).service(import);
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(export, import))]
+pub(super) struct Docs;
+
+/// Every task as an iCalendar file.
+#[utoipa::path(responses((status = OK, body = String, content_type = "text/calendar")))]
+#[get("/export.ics")]
+async fn export(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let mut entries = Vec::new();
+
+    for task in tasks::list(&conn, user.id)? {
+        let overrides = occurrences::list(&conn, task.id)?;
+        entries.push((task, overrides));
+    }
+
+    let now = DateTime::from_timestamp(now(), 0).unwrap_or_default();
+    Ok(HttpResponse::Ok()
+        .content_type("text/calendar; charset=utf-8")
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"ztasks.ics\"",
+        ))
+        .body(ics::export(&entries, now)))
+}
+
+/// Takes the raw .ics file as the body. Tasks are matched by UID, so importing the same file again updates them.
+#[utoipa::path(
+    request_body(content = String, content_type = "text/calendar"),
+    responses((status = OK, body = ImportResponse))
+)]
+#[post("/import")]
+async fn import(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    body: String,
+) -> Result<HttpResponse, ApiError> {
+    let timestamp = now();
+    let now: DateTime<Utc> = DateTime::from_timestamp(timestamp, 0).unwrap_or_default();
+    let (imported, mut skipped) = ics::import(&body, now).map_err(ApiError::BadRequest)?;
+
+    let conn = db.lock();
+    let tx = conn.unchecked_transaction()?;
+    let mut created = Vec::new();
+    let mut updated = Vec::new();
+
+    for entry in imported {
+        let fields = match validate(entry.fields) {
+            Ok(fields) => fields,
+            Err(error) => {
+                skipped.push(ics::Skipped {
+                    uid: Some(entry.uid),
+                    reason: error.to_string(),
+                });
+                continue;
+            }
+        };
+
+        let existing = tasks::find_by_uid(&tx, user.id, &entry.uid)?;
+        let task = match &existing {
+            Some(task) => tasks::update(&tx, user.id, task.id, &fields, timestamp)?,
+            None => Some(tasks::insert_with_uid(
+                &tx, user.id, &entry.uid, &fields, timestamp,
+            )?),
+        };
+        let Some(task) = task else {
+            skipped.push(ics::Skipped {
+                uid: Some(entry.uid),
+                reason: "Task is in a list you can only view.".to_string(),
+            });
+            continue;
+        };
+
+        let task = tasks::set_completed(
+            &tx,
+            user.id,
+            task.id,
+            entry.completed.map(|time| time.timestamp()),
+            timestamp,
+        )?
+        .unwrap_or(task);
+
+        // The file is the source of truth for which occurrences were changed
+        occurrences::clear(&tx, task.id)?;
+        for changes in &entry.overrides {
+            occurrences::save(&tx, task.id, changes)?;
+        }
+
+        match existing {
+            Some(_) => updated.push(task),
+            None => created.push(task),
+        }
+    }
+
+    tx.commit()?;
+
+    for task in &created {
+        gateway.task_created(&[user.id], task);
+    }
+    for task in &updated {
+        gateway.task_updated(&lists::audience(&conn, user.id, task.list_id)?, task);
+    }
+
+    Ok(HttpResponse::Ok().json(ImportResponse {
+        created: created.len(),
+        updated: updated.len(),
+        skipped,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn export_then_reimport() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let task =
+            json!({ "title": "Pay rent", "due": "2023-03-01T09:00:00Z", "category": "home" });
+        call(
+            &app,
+            request(Method::POST, "/api/tasks", &token).set_json(task),
+        )
+        .await;
+
+        let res = test::call_service(
+            &app,
+            request(Method::GET, "/api/export.ics", &token).to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/calendar; charset=utf-8"
+        );
+        let ics = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert!(ics.contains("SUMMARY:Pay rent\r\n"));
+
+        // Same UID, so this updates instead of duplicating
+        let ics = ics.replace("SUMMARY:Pay rent", "SUMMARY:Pay the rent");
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/import", &token).set_payload(ics),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["created"], 0);
+        assert_eq!(body["updated"], 1);
+
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &token)).await;
+        let tasks = body["tasks"].as_array().unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0]["title"], "Pay the rent");
+    }
+
+    #[actix_web::test]
+    async fn imports_new_tasks() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let ics = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:new@example.com\r\nSUMMARY:Water plants\r\nDUE:20230301T090000Z\r\nSTATUS:COMPLETED\r\nEND:VTODO\r\nBEGIN:VTODO\r\nUID:empty@example.com\r\nSUMMARY:Bad\r\nDTSTART:20230302T000000Z\r\nDUE:20230301T000000Z\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/import", &token).set_payload(ics),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["created"], 1);
+        assert_eq!(body["skipped"][0]["uid"], "empty@example.com");
+
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &token)).await;
+        assert_eq!(body["tasks"][0]["uid"], "new@example.com");
+        assert!(body["tasks"][0]["completed"].is_string());
+
+        let (status, _) = call(
+            &app,
+            request(Method::POST, "/api/import", &token).set_payload("not a calendar"),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,546 @@
+use crate::{
+    auth::{tokens, AuthUser},
+    database::{
+        lists::{self, List, Member, Role},
+        tasks::{self, from_timestamp},
+        Database,
+    },
+    error::ApiError,
+    gateway::{protocol::Topic, Gateway},
+    util::now,
+};
+use actix_web::{delete, get, patch, post, put, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+const DEFAULT_INVITE_DAYS: i64 = 7;
+const MAX_INVITE_DAYS: i64 = 30;
+
+#[derive(Deserialize, ToSchema)]
+struct ListBody {
+    name: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RoleBody {
+    role: Role,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct NewInvite {
+    role: Role,
+    /// How long the link works for, a week if not given
+    days: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ListsResponse {
+    lists: Vec<List>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ListResponse {
+    list: List,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ListDetails {
+    list: List,
+    members: Vec<Member>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MembersResponse {
+    members: Vec<Member>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Invite {
+    /// Goes in `POST /invites/{token}`, and can't be looked up again
+    token: String,
+    role: Role,
+    expires: DateTime<Utc>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_lists)
+        .service(create_list)
+        .service(get_list)
+        .service(rename_list)
+        .service(delete_list)
+        .service(set_role)
+        .service(remove_member)
+        .service(create_invite)
+        .service(revoke_invites)
+        .service(accept_invite);
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_lists,
+    create_list,
+    get_list,
+    rename_list,
+    delete_list,
+    set_role,
+    remove_member,
+    create_invite,
+    revoke_invites,
+    accept_invite
+))]
+pub(super) struct Docs;
+
+fn validate_name(name: &str) -> Result<String, ApiError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > 50 {
+        return Err(ApiError::field(
+            "name",
+            "List names must be between 1 and 50 characters.",
+        ));
+    }
+    Ok(name.to_string())
+}
+
+/// The list, as long as the user is a member of it, and an owner if `owner` is set.
+fn find(conn: &Connection, user_id: i64, id: i64, owner: bool) -> Result<List, ApiError> {
+    let list = lists::get(conn, user_id, id)?
+        .ok_or_else(|| ApiError::NotFound("List not found.".to_string()))?;
+
+    if owner && list.role != Role::Owner {
+        return Err(ApiError::Forbidden(
+            "Only owners can manage a list.".to_string(),
+        ));
+    }
+    Ok(list)
+}
+
+/// Lists need an owner, so the last one can't leave or step down.
+fn check_last_owner(conn: &Connection, id: i64, user_id: i64) -> Result<(), ApiError> {
+    if lists::role(conn, user_id, id)? == Some(Role::Owner) && lists::count_owners(conn, id)? == 1 {
+        return Err(ApiError::Conflict(
+            "Lists need at least one owner, make someone else one first.".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+#[utoipa::path(responses((status = OK, body = ListsResponse)))]
+#[get("/lists")]
+async fn list_lists(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let lists = lists::list(&db.lock(), user.id)?;
+    Ok(HttpResponse::Ok().json(ListsResponse { lists }))
+}
+
+#[utoipa::path(responses((status = CREATED, body = ListResponse)))]
+#[post("/lists")]
+async fn create_list(
+    user: AuthUser,
+    db: web::Data<Database>,
+    body: web::Json<ListBody>,
+) -> Result<HttpResponse, ApiError> {
+    let name = validate_name(&body.name)?;
+    let list = lists::create(&db.lock(), user.id, &name, now())?;
+    Ok(HttpResponse::Created().json(ListResponse { list }))
+}
+
+#[utoipa::path(responses((status = OK, body = ListDetails)))]
+#[get("/lists/{id}")]
+async fn get_list(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let list = find(&conn, user.id, path.into_inner(), false)?;
+    let members = lists::members(&conn, list.id)?;
+
+    Ok(HttpResponse::Ok().json(ListDetails { list, members }))
+}
+
+/// Renames the list.
+#[utoipa::path(responses((status = OK, body = ListResponse)))]
+#[patch("/lists/{id}")]
+async fn rename_list(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+    body: web::Json<ListBody>,
+) -> Result<HttpResponse, ApiError> {
+    let name = validate_name(&body.name)?;
+    let conn = db.lock();
+    let list = find(&conn, user.id, path.into_inner(), true)?;
+
+    lists::rename(&conn, list.id, &name)?;
+    let list = find(&conn, user.id, list.id, false)?;
+    Ok(HttpResponse::Ok().json(ListResponse { list }))
+}
+
+/// Deletes the list and every task in it, for everyone.
+#[utoipa::path(responses((status = NO_CONTENT)))]
+#[delete("/lists/{id}")]
+async fn delete_list(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let list = find(&conn, user.id, path.into_inner(), true)?;
+    let members = lists::members(&conn, list.id)?;
+    let audience = lists::audience(&conn, user.id, Some(list.id))?;
+    let gone: Vec<_> = tasks::list(&conn, user.id)?
+        .into_iter()
+        .filter(|task| task.list_id == Some(list.id))
+        .collect();
+
+    // The tasks go through ON DELETE CASCADE, so everyone is told about them before they lose the list
+    lists::delete(&conn, list.id)?;
+    for task in &gone {
+        gateway.task_deleted(&audience, task);
+    }
+    for member in members {
+        gateway.revoke(member.user_id, vec![Topic::List(list.id)]);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Changes a member's role.
+#[utoipa::path(responses((status = OK, body = MembersResponse)))]
+#[put("/lists/{id}/members/{user_id}")]
+async fn set_role(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<(i64, i64)>,
+    body: web::Json<RoleBody>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, member_id) = path.into_inner();
+    let conn = db.lock();
+    find(&conn, user.id, id, true)?;
+
+    if body.role != Role::Owner {
+        check_last_owner(&conn, id, member_id)?;
+    }
+    if !lists::set_role(&conn, id, member_id, body.role)? {
+        return Err(ApiError::NotFound("Member not found.".to_string()));
+    }
+
+    let members = lists::members(&conn, id)?;
+    Ok(HttpResponse::Ok().json(MembersResponse { members }))
+}
+
+/// Owners can remove anyone, everyone else can only leave. Their sockets stop getting the list's events
+/// right away.
+#[utoipa::path(responses((status = NO_CONTENT)))]
+#[delete("/lists/{id}/members/{user_id}")]
+async fn remove_member(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<(i64, i64)>,
+) -> Result<HttpResponse, ApiError> {
+    let (id, member_id) = path.into_inner();
+    let conn = db.lock();
+    find(&conn, user.id, id, member_id != user.id)?;
+    check_last_owner(&conn, id, member_id)?;
+
+    if !lists::remove_member(&conn, id, member_id)? {
+        return Err(ApiError::NotFound("Member not found.".to_string()));
+    }
+    gateway.revoke(member_id, vec![Topic::List(id)]);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Makes a link anyone can use to join the list until it expires. Only the response has the token.
+#[utoipa::path(responses((status = CREATED, body = Invite)))]
+#[post("/lists/{id}/invites")]
+async fn create_invite(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+    body: web::Json<NewInvite>,
+) -> Result<HttpResponse, ApiError> {
+    let days = body.days.unwrap_or(DEFAULT_INVITE_DAYS);
+    if !(1..=MAX_INVITE_DAYS).contains(&days) {
+        return Err(ApiError::field(
+            "days",
+            format!("Invites can last between 1 and {MAX_INVITE_DAYS} days."),
+        ));
+    }
+    if body.role == Role::Owner {
+        return Err(ApiError::field(
+            "role",
+            "Invites are for editors and viewers, make owners from members.",
+        ));
+    }
+
+    let conn = db.lock();
+    let list = find(&conn, user.id, path.into_inner(), true)?;
+    let token = tokens::random_token();
+    let now = now();
+    let expires = now + days * 24 * 60 * 60;
+    lists::create_invite(
+        &conn,
+        list.id,
+        &tokens::hash_token(&token),
+        body.role,
+        user.id,
+        (now, expires),
+    )?;
+
+    Ok(HttpResponse::Created().json(Invite {
+        token,
+        role: body.role,
+        expires: from_timestamp(expires),
+    }))
+}
+
+/// Stops every invite link to the list from working.
+#[utoipa::path(responses((status = NO_CONTENT)))]
+#[delete("/lists/{id}/invites")]
+async fn revoke_invites(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let list = find(&conn, user.id, path.into_inner(), true)?;
+
+    lists::revoke_invites(&conn, list.id, now())?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Joins the list an invite is for. Members who already are one keep their role.
+#[utoipa::path(responses((status = OK, body = ListResponse)))]
+#[post("/invites/{token}")]
+async fn accept_invite(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let now = now();
+    let (id, role) = lists::find_invite(&conn, &tokens::hash_token(&path.into_inner()), now)?
+        .ok_or_else(|| ApiError::NotFound("Invite not found or expired.".to_string()))?;
+
+    lists::add_member(&conn, id, user.id, role, now)?;
+    let list = find(&conn, user.id, id, false)?;
+    Ok(HttpResponse::Ok().json(ListResponse { list }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gateway::{
+            broker::{Broker, Connect, Subscribe},
+            protocol::{Event, ServerMessage, Topic},
+        },
+        testing::{app_with, call, events, received, request, sign_up, Collector},
+    };
+    use actix::Actor;
+    use actix_web::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn sharing() {
+        let broker = Broker::default().start();
+        let app = actix_web::test::init_service(app_with(broker.clone())).await;
+        let owner = sign_up(&app, "steven").await;
+        let editor = sign_up(&app, "emma").await;
+        let viewer = sign_up(&app, "vera").await;
+        let outsider = sign_up(&app, "oscar").await;
+
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/lists", &owner).set_json(json!({ "name": " Household " })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["list"]["name"], "Household");
+        assert_eq!(body["list"]["role"], "owner");
+        let list = body["list"]["id"].as_i64().unwrap();
+
+        // Only owners make invites, and anyone with the link can join
+        let uri = format!("/api/lists/{list}/invites");
+        let invite = |token: &str, role: &str| {
+            request(Method::POST, &uri, token).set_json(json!({ "role": role }))
+        };
+        let (status, _) = call(&app, invite(&owner, "owner")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let mut links = Vec::new();
+        for role in ["editor", "viewer"] {
+            let (status, body) = call(&app, invite(&owner, role)).await;
+            assert_eq!(status, StatusCode::CREATED);
+            links.push(format!("/api/invites/{}", body["token"].as_str().unwrap()));
+        }
+        for (token, link) in [(&editor, &links[0]), (&viewer, &links[1])] {
+            let (status, body) = call(&app, request(Method::POST, link, token)).await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body["list"]["id"], list);
+        }
+        let (status, _) = call(&app, invite(&editor, "viewer")).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (_, body) = call(
+            &app,
+            request(Method::GET, &format!("/api/lists/{list}"), &viewer),
+        )
+        .await;
+        let roles: Vec<_> = body["members"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|member| member["role"].as_str().unwrap())
+            .collect();
+        assert_eq!(roles, ["owner", "editor", "viewer"]);
+        let editor_id = body["members"][1]["user_id"].as_i64().unwrap();
+        let viewer_id = body["members"][2]["user_id"].as_i64().unwrap();
+
+        // The viewer watches the list
+        let collector = Collector::default().start();
+        broker.do_send(Connect {
+            session_id: "vera".to_string(),
+            user_id: viewer_id,
+            recipient: collector.clone().recipient(),
+            resume_from: None,
+        });
+        broker.do_send(Subscribe {
+            session_id: "vera".to_string(),
+            topics: vec![Topic::List(list)],
+            enabled: true,
+        });
+
+        // Editors add and change tasks, viewers only see them, everyone else doesn't
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &editor)
+                .set_json(json!({ "title": "Buy milk", "list_id": list })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let uri = format!("/api/tasks/{}", body["task"]["id"]);
+        let (status, _) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &viewer)
+                .set_json(json!({ "title": "Buy cake", "list_id": list })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let titles = |body: &Value| -> Vec<String> {
+            body["tasks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|task| task["title"].as_str().unwrap().to_string())
+                .collect()
+        };
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &owner)).await;
+        assert_eq!(titles(&body), ["Buy milk"]);
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &outsider)).await;
+        assert!(titles(&body).is_empty());
+        let (status, _) = call(&app, request(Method::GET, &uri, &outsider)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let rename = json!({ "title": "Buy oat milk" });
+        let (status, _) = call(
+            &app,
+            request(Method::PATCH, &uri, &viewer).set_json(rename.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(
+            &app,
+            request(Method::POST, &format!("{uri}/complete"), &viewer),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(&app, request(Method::DELETE, &uri, &viewer)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(&app, request(Method::PATCH, &uri, &owner).set_json(rename)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let messages = events(received(&broker, &collector).await);
+        assert_eq!(messages.len(), 2);
+
+        // The last owner can't leave, but can remove the viewer, whose socket stops hearing about the list
+        let (status, _) = call(
+            &app,
+            request(
+                Method::DELETE,
+                &format!("/api/lists/{list}/members/1"),
+                &owner,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = call(
+            &app,
+            request(
+                Method::DELETE,
+                &format!("/api/lists/{list}/members/{viewer_id}"),
+                &owner,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let messages = received(&broker, &collector).await;
+        assert!(matches!(
+            &messages[..],
+            [ServerMessage::Unsubscribed { topics }] if topics == &[Topic::List(list)]
+        ));
+        call(&app, request(Method::DELETE, &uri, &editor)).await;
+        assert!(received(&broker, &collector).await.is_empty());
+        let (status, _) = call(
+            &app,
+            request(Method::GET, &format!("/api/lists/{list}"), &viewer),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Deleting the list takes its tasks along, which everyone still in it hears about
+        let watcher = Collector::default().start();
+        broker.do_send(Connect {
+            session_id: "emma".to_string(),
+            user_id: editor_id,
+            recipient: watcher.clone().recipient(),
+            resume_from: None,
+        });
+        broker.do_send(Subscribe {
+            session_id: "emma".to_string(),
+            topics: vec![Topic::List(list)],
+            enabled: true,
+        });
+        let (_, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &owner)
+                .set_json(json!({ "title": "Buy bread", "list_id": list })),
+        )
+        .await;
+        let id = body["task"]["id"].as_i64().unwrap();
+        received(&broker, &watcher).await;
+
+        let (status, _) = call(
+            &app,
+            request(Method::DELETE, &format!("/api/lists/{list}"), &editor),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = call(
+            &app,
+            request(Method::DELETE, &format!("/api/lists/{list}"), &owner),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let messages = received(&broker, &watcher).await;
+        assert!(matches!(
+            &messages[..],
+            [
+                ServerMessage::Event { event: Event::TaskDeleted { id: deleted, list_id: Some(from) }, .. },
+                ServerMessage::Unsubscribed { topics },
+            ] if *deleted == id && *from == list && topics == &[Topic::List(list)]
+        ));
+    }
+}
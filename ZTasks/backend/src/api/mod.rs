@@ -0,0 +1,53 @@
+mod auth;
+mod backup;
+mod calendar;
+mod dependencies;
+mod history;
+mod ics;
+mod lists;
+mod occurrences;
+mod openapi;
+mod search;
+mod sync;
+mod tasks;
+mod time;
+mod views;
+
+use crate::{error, limiter};
+
+use actix_web::{middleware, web};
+use serde::{Deserialize, Deserializer};
+pub(crate) use tasks::validate;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/api")
+            .wrap(middleware::from_fn(limiter::middleware))
+            .wrap(middleware::ErrorHandlers::new().default_handler(error::envelope))
+            .configure(auth::config)
+            .configure(backup::config)
+            .configure(calendar::config)
+            // Before tasks, so /tasks/next and /tasks/deleted aren't taken for /tasks/{id}
+            .configure(dependencies::config)
+            .configure(history::config)
+            .configure(ics::config)
+            .configure(lists::config)
+            .configure(occurrences::config)
+            .configure(openapi::config)
+            .configure(search::config)
+            .configure(sync::config)
+            .configure(tasks::config)
+            .configure(time::config)
+            .configure(views::config),
+    );
+}
+
+/// Lets PATCH bodies tell a missing field (`None`) apart from an explicit `null` (`Some(None)`).
+/// Use together with `#[serde(default)]`.
+fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
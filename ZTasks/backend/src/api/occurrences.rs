@@ -0,0 +1,336 @@
+use super::tasks::check_editable;
+use crate::{
+    auth::AuthUser,
+    calendar::{self, recurrence::Rule, Occurrence},
+    database::{
+        lists,
+        occurrences::{self, Override},
+        tasks::{self, Task},
+        Database,
+    },
+    error::ApiError,
+    gateway::Gateway,
+    util::now,
+};
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+/// Fields that are left out stay the same, and `null` goes back to what the series has.
+#[derive(Deserialize, ToSchema)]
+struct OccurrenceChanges {
+    #[serde(default, deserialize_with = "super::nullable")]
+    title: Option<Option<String>>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    notes: Option<Option<String>>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    start: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    due: Option<Option<DateTime<Utc>>>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct OccurrenceResponse {
+    occurrence: Occurrence,
+    cancelled: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+struct OverridesResponse {
+    overrides: Vec<Override>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_overrides)
+        .service(update_occurrence)
+        .service(cancel_occurrence)
+        .service(complete_occurrence)
+        .service(uncomplete_occurrence);
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_overrides,
+    update_occurrence,
+    cancel_occurrence,
+    complete_occurrence,
+    uncomplete_occurrence
+))]
+pub(super) struct Docs;
+
+/// Occurrences are addressed by their recurrence ID, either as a UNIX timestamp (like in occurrence keys) or RFC 3339.
+fn parse_recurrence_id(value: &str) -> Option<DateTime<Utc>> {
+    match value.parse::<i64>() {
+        Ok(timestamp) => DateTime::from_timestamp(timestamp, 0),
+        Err(_) => value.parse().ok(),
+    }
+}
+
+/// Looks up a recurring task and makes sure `recurrence_id` really is one of its occurrences.
+fn find_series(
+    conn: &Connection,
+    user_id: i64,
+    id: i64,
+    recurrence_id: &str,
+) -> Result<(Task, DateTime<Utc>, DateTime<Utc>), ApiError> {
+    let not_found = || ApiError::NotFound("Occurrence not found.".to_string());
+    let task = tasks::get(conn, user_id, id)?
+        .ok_or_else(|| ApiError::NotFound("Task not found.".to_string()))?;
+
+    let rule: Rule = task
+        .recurrence
+        .as_deref()
+        .ok_or_else(|| ApiError::BadRequest("Task doesn't repeat.".to_string()))?
+        .parse()
+        .map_err(ApiError::BadRequest)?;
+    let anchor = calendar::anchor(&task).ok_or_else(not_found)?;
+    let recurrence_id = parse_recurrence_id(recurrence_id).ok_or_else(not_found)?;
+    let tz: Tz = task.time_zone.parse().unwrap_or(Tz::UTC);
+
+    match rule.includes(anchor, tz, recurrence_id) {
+        true => Ok((task, anchor, recurrence_id)),
+        false => Err(not_found()),
+    }
+}
+
+/// Applies `change` to the override for an occurrence and responds with the resulting occurrence.
+fn modify(
+    db: &Database,
+    gateway: &Gateway,
+    user_id: i64,
+    (id, recurrence_id): (i64, String),
+    change: impl FnOnce(&mut Override),
+) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let (task, anchor, recurrence_id) = find_series(&conn, user_id, id, &recurrence_id)?;
+    check_editable(&conn, user_id, task.list_id)?;
+
+    let mut changes =
+        occurrences::get(&conn, id, recurrence_id)?.unwrap_or_else(|| Override::new(recurrence_id));
+    change(&mut changes);
+    occurrences::save(&conn, id, &changes)?;
+    // The series itself didn't change, but clients showing its occurrences need to refresh them
+    gateway.task_updated(&lists::audience(&conn, user_id, task.list_id)?, &task);
+
+    let occurrence = calendar::repeat(&task, anchor, recurrence_id, Some(&changes));
+    Ok(HttpResponse::Ok().json(OccurrenceResponse {
+        occurrence,
+        cancelled: changes.cancelled,
+    }))
+}
+
+/// The changes made to single occurrences of a recurring task.
+#[utoipa::path(responses((status = OK, body = OverridesResponse)))]
+#[get("/tasks/{id}/occurrences")]
+async fn list_overrides(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    tasks::get(&conn, user.id, id)?
+        .ok_or_else(|| ApiError::NotFound("Task not found.".to_string()))?;
+
+    let overrides = occurrences::list(&conn, id)?;
+    Ok(HttpResponse::Ok().json(OverridesResponse { overrides }))
+}
+
+#[utoipa::path(params(("recurrence_id", description = "UNIX timestamp or RFC 3339")), responses((status = OK, body = OccurrenceResponse)))]
+#[patch("/tasks/{id}/occurrences/{recurrence_id}")]
+async fn update_occurrence(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<(i64, String)>,
+    body: web::Json<OccurrenceChanges>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+
+    if let Some(Some(title)) = &body.title {
+        if title.trim().is_empty() {
+            return Err(ApiError::field("title", "Title cannot be empty."));
+        }
+    }
+
+    modify(&db, &gateway, user.id, path.into_inner(), |changes| {
+        if let Some(title) = body.title {
+            changes.title = title.map(|title| title.trim().to_string());
+        }
+        if let Some(notes) = body.notes {
+            changes.notes = notes;
+        }
+        if let Some(start) = body.start {
+            changes.start = start;
+        }
+        if let Some(due) = body.due {
+            changes.due = due;
+        }
+        changes.cancelled = false;
+    })
+}
+
+/// Skips a single occurrence, like an EXDATE.
+#[utoipa::path(params(("recurrence_id", description = "UNIX timestamp or RFC 3339")), responses((status = OK, body = OccurrenceResponse)))]
+#[delete("/tasks/{id}/occurrences/{recurrence_id}")]
+async fn cancel_occurrence(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<(i64, String)>,
+) -> Result<HttpResponse, ApiError> {
+    modify(&db, &gateway, user.id, path.into_inner(), |changes| {
+        changes.cancelled = true
+    })
+}
+
+#[utoipa::path(params(("recurrence_id", description = "UNIX timestamp or RFC 3339")), responses((status = OK, body = OccurrenceResponse)))]
+#[post("/tasks/{id}/occurrences/{recurrence_id}/complete")]
+async fn complete_occurrence(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<(i64, String)>,
+) -> Result<HttpResponse, ApiError> {
+    let now = DateTime::from_timestamp(now(), 0);
+    modify(&db, &gateway, user.id, path.into_inner(), |changes| {
+        changes.completed = now
+    })
+}
+
+#[utoipa::path(params(("recurrence_id", description = "UNIX timestamp or RFC 3339")), responses((status = OK, body = OccurrenceResponse)))]
+#[delete("/tasks/{id}/occurrences/{recurrence_id}/complete")]
+async fn uncomplete_occurrence(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<(i64, String)>,
+) -> Result<HttpResponse, ApiError> {
+    modify(&db, &gateway, user.id, path.into_inner(), |changes| {
+        changes.completed = None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn edit_single_occurrences() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let standup = json!({
+            "title": "Standup",
+            "start": "2023-03-06T14:00:00Z",
+            "due": "2023-03-06T14:15:00Z",
+            "recurrence": "FREQ=WEEKLY;BYDAY=MO,WE,FR",
+            "time_zone": "America/New_York",
+        });
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &token).set_json(standup),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        let id = body["task"]["id"].as_i64().unwrap();
+
+        // Wednesday's standup runs long, Friday's is skipped, Monday's is done
+        let uri = format!("/api/tasks/{id}/occurrences/2023-03-08T14:00:00Z");
+        let changes = json!({ "title": "Long standup", "due": "2023-03-08T15:00:00Z" });
+        let (status, body) =
+            call(&app, request(Method::PATCH, &uri, &token).set_json(changes)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["occurrence"]["title"], "Long standup");
+        assert_eq!(body["occurrence"]["start"], "2023-03-08T14:00:00Z");
+
+        let uri = format!("/api/tasks/{id}/occurrences/1678456800");
+        let (status, _) = call(&app, request(Method::DELETE, &uri, &token)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let uri = format!("/api/tasks/{id}/occurrences/2023-03-06T14:00:00Z/complete");
+        let (status, _) = call(&app, request(Method::POST, &uri, &token)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Not an occurrence (Tuesday)
+        let uri = format!("/api/tasks/{id}/occurrences/2023-03-07T14:00:00Z/complete");
+        let (status, _) = call(&app, request(Method::POST, &uri, &token)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let uri = "/api/calendar?from=2023-03-06T05:00:00Z&to=2023-03-20T04:00:00Z&tz=America/New_York&group=week";
+        let (_, body) = call(&app, request(Method::GET, uri, &token)).await;
+        let occurrences = body["occurrences"].as_array().unwrap();
+        let summary: Vec<_> = occurrences
+            .iter()
+            .map(|occurrence| {
+                format!(
+                    "{} {} {}",
+                    occurrence["start"].as_str().unwrap(),
+                    occurrence["title"].as_str().unwrap(),
+                    occurrence["completed"].is_string()
+                )
+            })
+            .collect();
+
+        // DST starts on the 12th, so the second week is an hour earlier in UTC
+        assert_eq!(
+            summary,
+            [
+                "2023-03-06T14:00:00Z Standup true",
+                "2023-03-08T14:00:00Z Long standup false",
+                "2023-03-13T13:00:00Z Standup false",
+                "2023-03-15T13:00:00Z Standup false",
+                "2023-03-17T13:00:00Z Standup false",
+            ]
+        );
+        assert_eq!(
+            body["buckets"][0]["occurrences"].as_array().unwrap().len(),
+            2
+        );
+        assert_eq!(
+            body["buckets"][1]["occurrences"].as_array().unwrap().len(),
+            3
+        );
+
+        // Moving the whole series forgets the single changes
+        let changes = json!({ "recurrence": "FREQ=DAILY;COUNT=2" });
+        call(
+            &app,
+            request(Method::PATCH, &format!("/api/tasks/{id}"), &token).set_json(changes),
+        )
+        .await;
+        let (_, body) = call(
+            &app,
+            request(Method::GET, &format!("/api/tasks/{id}/occurrences"), &token),
+        )
+        .await;
+        assert!(body["overrides"].as_array().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn rejects_bad_rules() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        for task in [
+            json!({ "title": "No anchor", "recurrence": "FREQ=DAILY" }),
+            json!({ "title": "Bad rule", "due": "2023-03-06T14:00:00Z", "recurrence": "FREQ=HOURLY" }),
+            json!({ "title": "Bad zone", "due": "2023-03-06T14:00:00Z", "time_zone": "Nowhere" }),
+        ] {
+            let (status, _) = call(
+                &app,
+                request(Method::POST, "/api/tasks", &token).set_json(task),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! The OpenAPI document at `/api/openapi.json`. Each module lists its own handlers in a `Docs` struct,
+//! and request bodies and parameters are read off the handlers' arguments.
+
+use super::{
+    auth, backup, calendar, dependencies, history, ics, lists, occurrences, search, sync, tasks,
+    time, views,
+};
+use crate::error::ErrorBody;
+use actix_web::{get, web, HttpResponse};
+use std::sync::OnceLock;
+use utoipa::{
+    openapi::{
+        path::Operation,
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        PathItem, Ref, RefOr, Response,
+    },
+    OpenApi,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "ZTasks",
+        description = "Errors always come back as an `ErrorBody`, whatever the status."
+    ),
+    servers((url = "/api")),
+    paths(get_openapi),
+    components(schemas(ErrorBody)),
+    security(("access_token" = []))
+)]
+struct ApiDoc;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_openapi);
+}
+
+/// Every module's handlers, each under a tag named after it.
+pub fn document() -> utoipa::openapi::OpenApi {
+    let mut document = ApiDoc::openapi();
+    for (tag, docs) in [
+        ("auth", auth::Docs::openapi()),
+        ("backup", backup::Docs::openapi()),
+        ("calendar", calendar::Docs::openapi()),
+        ("dependencies", dependencies::Docs::openapi()),
+        ("history", history::Docs::openapi()),
+        ("ics", ics::Docs::openapi()),
+        ("lists", lists::Docs::openapi()),
+        ("occurrences", occurrences::Docs::openapi()),
+        ("search", search::Docs::openapi()),
+        ("sync", sync::Docs::openapi()),
+        ("tasks", tasks::Docs::openapi()),
+        ("time", time::Docs::openapi()),
+        ("views", views::Docs::openapi()),
+    ] {
+        let mut docs = docs;
+        for item in docs.paths.paths.values_mut() {
+            for operation in operations(item) {
+                operation.tags = Some(vec![tag.to_string()]);
+            }
+        }
+        document.merge(docs);
+    }
+
+    let error = Response::builder()
+        .description("Something went wrong, see `code`")
+        .content(
+            "application/json",
+            utoipa::openapi::Content::new(Some(Ref::from_schema_name("ErrorBody"))),
+        )
+        .build();
+    for item in document.paths.paths.values_mut() {
+        for operation in operations(item) {
+            operation
+                .responses
+                .responses
+                .insert("default".to_string(), RefOr::T(error.clone()));
+        }
+    }
+
+    if let Some(components) = &mut document.components {
+        components.add_security_scheme(
+            "access_token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+    document
+}
+
+fn operations(item: &mut PathItem) -> impl Iterator<Item = &mut Operation> {
+    [
+        &mut item.get,
+        &mut item.put,
+        &mut item.post,
+        &mut item.delete,
+        &mut item.patch,
+    ]
+    .into_iter()
+    .flatten()
+}
+
+/// This document.
+#[utoipa::path(responses((status = OK, description = "OpenAPI 3.1")), security(()))]
+#[get("/openapi.json")]
+async fn get_openapi() -> HttpResponse {
+    static DOCUMENT: OnceLock<String> = OnceLock::new();
+    let document = DOCUMENT.get_or_init(|| document().to_json().unwrap_or_default());
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(document.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    #[actix_web::test]
+    async fn document() {
+        let app = test::init_service(app()).await;
+        let req = test::TestRequest::get().uri("/api/openapi.json");
+        let (status, body) = call(&app, req).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let paths = body["paths"].as_object().unwrap();
+        for path in [
+            "/tasks",
+            "/tasks/{id}",
+            "/lists/{id}/members/{user_id}",
+            "/sync",
+            "/time/report",
+        ] {
+            assert!(paths.contains_key(path), "{path}");
+        }
+        let create = &paths["/tasks"]["post"];
+        assert_eq!(
+            create["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/NewTask"
+        );
+        assert_eq!(
+            create["responses"]["default"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/ErrorBody"
+        );
+        assert_eq!(
+            paths["/login"]["post"]["security"][0],
+            serde_json::json!({})
+        );
+
+        let schemas = body["components"]["schemas"].as_object().unwrap();
+        for schema in [
+            "Task",
+            "TaskResponse",
+            "ErrorBody",
+            "FieldError",
+            "ReportResponse",
+        ] {
+            assert!(schemas.contains_key(schema), "{schema}");
+        }
+    }
+
+    #[actix_web::test]
+    async fn errors_share_an_envelope() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let (status, body) = call(&app, request(Method::GET, "/api/nowhere", &token)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["code"], "not_found");
+        assert_eq!(body["fields"], serde_json::json!([]));
+
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &token)
+                .set_json(serde_json::json!({ "title": " ", "time_zone": "Mars/Base" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["code"], "invalid");
+        assert_eq!(body["fields"][0]["field"], "title");
+        assert_eq!(body["fields"][1]["field"], "time_zone");
+
+        let (status, body) = call(&app, test::TestRequest::get().uri("/api/tasks")).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["code"], "unauthorized");
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    auth::AuthUser,
+    database::{
+        tasks::{self, MATCH_END, MATCH_START},
+        Database,
+    },
+    error::ApiError,
+};
+use actix_web::{get, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+const DEFAULT_LIMIT: i64 = 20;
+const MAX_LIMIT: i64 = 100;
+const MAX_QUERY_LENGTH: usize = 200;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct SearchQuery {
+    q: String,
+    limit: Option<i64>,
+}
+
+/// A matching task. `title` and `snippet` are HTML with the matches wrapped in `<mark>`.
+#[derive(Serialize, ToSchema)]
+struct SearchResult {
+    task: tasks::Task,
+    title: String,
+    snippet: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SearchResponse {
+    results: Vec<SearchResult>,
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(search))]
+pub(super) struct Docs;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(search);
+}
+
+/// Turns what the user typed into an FTS5 query that matches tasks containing words starting with every
+/// word in it. Each word is quoted, so nothing in it is taken as FTS5 syntax.
+fn fts_query(input: &str) -> Option<String> {
+    let words: Vec<_> = input
+        .split_whitespace()
+        .map(|word| word.replace('"', ""))
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{word}\"*"))
+        .collect();
+
+    match words.is_empty() {
+        true => None,
+        false => Some(words.join(" ")),
+    }
+}
+
+/// Escapes `text` for HTML, then wraps the matches in `<mark>`.
+fn highlight(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// Full-text search over titles and notes, best matches first. `title` and `snippet` are HTML.
+#[utoipa::path(params(SearchQuery), responses((status = OK, body = SearchResponse)))]
+#[get("/search")]
+async fn search(
+    user: AuthUser,
+    db: web::Data<Database>,
+    query: web::Query<SearchQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+
+    if query.q.len() > MAX_QUERY_LENGTH {
+        return Err(ApiError::field(
+            "q",
+            format!("Searches can't be longer than {MAX_QUERY_LENGTH} characters."),
+        ));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT);
+    if !(1..=MAX_LIMIT).contains(&limit) {
+        return Err(ApiError::field(
+            "limit",
+            format!("Limit must be between 1 and {MAX_LIMIT}."),
+        ));
+    }
+    let fts =
+        fts_query(&query.q).ok_or_else(|| ApiError::field("q", "Search for at least one word."))?;
+
+    let results: Vec<_> = tasks::search(&db.lock(), user.id, &fts, limit)?
+        .into_iter()
+        .map(|hit| SearchResult {
+            task: hit.task,
+            title: highlight(&hit.title),
+            snippet: highlight(&hit.snippet),
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(SearchResponse { results }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::http::{Method, StatusCode};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn queries() {
+        assert_eq!(
+            fts_query("pay  ren\"t NEAR(x)"),
+            Some("\"pay\"* \"rent\"* \"NEAR(x)\"*".to_string())
+        );
+        assert_eq!(fts_query(" - \"\" "), None);
+    }
+
+    fn titles(body: &Value) -> Vec<&str> {
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["task"]["title"].as_str().unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn search_stays_in_sync() {
+        let app = actix_web::test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+        let other = sign_up(&app, "someone").await;
+
+        for (token, title, notes) in [
+            (&token, "Call the landlord", "About the <rent> increase"),
+            (&token, "Pay rent", "Transfer before the 1st"),
+            (&token, "Renew passport", ""),
+            (&other, "Pay rent", ""),
+        ] {
+            let req = request(Method::POST, "/api/tasks", token)
+                .set_json(json!({ "title": title, "notes": notes }));
+            call(&app, req).await;
+        }
+
+        let find = |q: &str| {
+            let req = request(Method::GET, &format!("/api/search?q={q}"), &token);
+            let app = &app;
+            async move { call(app, req).await }
+        };
+
+        // Titles rank above notes
+        let (status, body) = find("rent").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(titles(&body), ["Pay rent", "Call the landlord"]);
+        assert_eq!(body["results"][0]["title"], "Pay <mark>rent</mark>");
+        assert_eq!(
+            body["results"][1]["snippet"],
+            "About the &lt;<mark>rent</mark>&gt; increase"
+        );
+
+        // Words match by their start
+        let (_, body) = find("REN").await;
+        assert_eq!(body["results"].as_array().unwrap().len(), 3);
+
+        let (_, body) = find("pay%20rent").await;
+        assert_eq!(titles(&body), ["Pay rent"]);
+
+        // Edits and deletes show up right away
+        let id = body["results"][0]["task"]["id"].as_i64().unwrap();
+        let req = request(Method::PATCH, &format!("/api/tasks/{id}"), &token)
+            .set_json(json!({ "title": "Pay the mortgage" }));
+        call(&app, req).await;
+        let (_, body) = find("mortg").await;
+        assert_eq!(titles(&body), ["Pay the mortgage"]);
+        let (_, body) = find("rent").await;
+        assert_eq!(titles(&body), ["Call the landlord"]);
+
+        call(
+            &app,
+            request(Method::DELETE, &format!("/api/tasks/{id}"), &token),
+        )
+        .await;
+        let (_, body) = find("mortgage").await;
+        assert_eq!(titles(&body), Vec::<&str>::new());
+
+        let (status, _) = find("%22%22").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,506 @@
+use super::tasks::{normalize, rescheduled, validate};
+use crate::{
+    auth::AuthUser,
+    database::{clocks, occurrences, tasks, Database},
+    error::ApiError,
+    gateway::{protocol::Event, Gateway},
+    sync::{self, Field, Snapshot, Stamp},
+    util::now,
+};
+use actix_web::{post, web, HttpResponse};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use utoipa::{OpenApi, ToSchema};
+
+const MAX_OPERATIONS: usize = 1000;
+
+#[derive(Deserialize, ToSchema)]
+struct Batch {
+    /// Identifies the device, and breaks ties between writes with the same clock
+    client_id: String,
+    ops: Vec<Operation>,
+}
+
+/// Tasks are addressed by UID, since devices create them before the server has given them an ID.
+#[derive(Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Operation {
+    /// Writes every field given, creating the task if it doesn't exist yet.
+    Create {
+        uid: String,
+        clock: i64,
+        #[schema(value_type = Object)]
+        fields: Map<String, Value>,
+    },
+    Set {
+        uid: String,
+        clock: i64,
+        field: Field,
+        value: Value,
+    },
+    /// Deletes always win, no matter the clock of other writes.
+    Delete { uid: String, clock: i64 },
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Outcome {
+    Applied,
+    /// Every write was older than what's already there
+    Stale,
+    /// The task was deleted
+    Deleted,
+    Rejected {
+        error: String,
+    },
+}
+
+fn rejected(error: impl Into<String>) -> Outcome {
+    Outcome::Rejected {
+        error: error.into(),
+    }
+}
+
+/// One outcome per operation, in order, along with the state of every task they touched.
+#[derive(Serialize, ToSchema)]
+struct SyncResponse {
+    results: Vec<Outcome>,
+    tasks: Vec<tasks::Task>,
+    /// UIDs of touched tasks that are gone
+    deleted: Vec<String>,
+    /// The highest clock the server has seen, for the next batch to build on
+    clock: i64,
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(sync_tasks))]
+pub(super) struct Docs;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(sync_tasks);
+}
+
+/// Applies a batch of operations from a device that may have been offline, and responds with how
+/// each one went along with the resulting state of every task they touched.
+#[utoipa::path(responses((status = OK, body = SyncResponse)))]
+#[post("/sync")]
+async fn sync_tasks(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    body: web::Json<Batch>,
+) -> Result<HttpResponse, ApiError> {
+    let batch = body.into_inner();
+    let client_id = batch.client_id.trim();
+
+    if client_id.is_empty() || client_id.chars().count() > 100 {
+        return Err(ApiError::field(
+            "client_id",
+            "Client IDs must be between 1 and 100 characters.",
+        ));
+    }
+
+    if batch.ops.len() > MAX_OPERATIONS {
+        return Err(ApiError::field(
+            "ops",
+            format!("Send at most {MAX_OPERATIONS} operations at a time."),
+        ));
+    }
+
+    let timestamp = now();
+    let conn = db.lock();
+    let tx = conn.unchecked_transaction()?;
+    let mut results = Vec::new();
+    let mut touched: Vec<String> = Vec::new();
+    let mut events = Vec::new();
+
+    for op in batch.ops {
+        let uid = match &op {
+            Operation::Create { uid, .. }
+            | Operation::Set { uid, .. }
+            | Operation::Delete { uid, .. } => uid.clone(),
+        };
+        if !touched.contains(&uid) {
+            touched.push(uid);
+        }
+
+        results.push(apply(&tx, user.id, client_id, op, timestamp, &mut events)?);
+    }
+
+    let mut current = Vec::new();
+    let mut deleted = Vec::new();
+    for uid in touched {
+        match tasks::find_by_uid(&tx, user.id, &uid)? {
+            Some(task) => current.push(task),
+            None if clocks::tombstone(&tx, user.id, &uid)?.is_some() => deleted.push(uid),
+            None => (),
+        }
+    }
+    let clock = clocks::latest(&tx, user.id)?;
+
+    tx.commit()?;
+
+    for event in events {
+        gateway.publish(user.id, event);
+    }
+
+    Ok(HttpResponse::Ok().json(SyncResponse {
+        results,
+        tasks: current,
+        deleted,
+        clock,
+    }))
+}
+
+fn apply(
+    conn: &Connection,
+    user_id: i64,
+    client_id: &str,
+    op: Operation,
+    now: i64,
+    events: &mut Vec<Event>,
+) -> Result<Outcome, ApiError> {
+    let deleting = matches!(op, Operation::Delete { .. });
+    let (uid, clock, writes, create) = match op {
+        Operation::Create { uid, clock, fields } => {
+            let mut writes = Vec::new();
+            for (name, value) in fields {
+                match Field::from_name(&name) {
+                    Some(field) => writes.push((field, value)),
+                    None => return Ok(rejected(format!("Unknown field \"{name}\"."))),
+                }
+            }
+            (uid, clock, writes, true)
+        }
+        Operation::Set {
+            uid,
+            clock,
+            field,
+            value,
+        } => (uid, clock, vec![(field, value)], false),
+        Operation::Delete { uid, clock } => (uid, clock, Vec::new(), false),
+    };
+
+    if clock < 0 {
+        return Ok(rejected("Clocks can't be negative."));
+    }
+    if uid.is_empty() || uid.len() > 255 {
+        return Ok(rejected("UIDs must be between 1 and 255 bytes."));
+    }
+
+    let stamp = Stamp {
+        clock,
+        client_id: client_id.to_string(),
+    };
+    let existing = tasks::find_by_uid(conn, user_id, &uid)?;
+    if existing.as_ref().is_some_and(|task| task.list_id.is_some()) {
+        return Ok(rejected("Tasks in shared lists can't be synced."));
+    }
+    if deleting {
+        return delete(conn, user_id, &uid, &stamp, now, events);
+    }
+    if clocks::tombstone(conn, user_id, &uid)?.is_some() {
+        return Ok(Outcome::Deleted);
+    }
+
+    let created = existing.is_none();
+    let (mut snapshot, mut stamps) = match &existing {
+        Some(task) => {
+            let stamps = clocks::stamps(conn, task.id)?;
+            let written = clocks::written(conn, task.id)?;
+            (Snapshot::of(task).recall(&stamps, &written), stamps)
+        }
+        None if create => (Snapshot::blank(), HashMap::new()),
+        None => return Ok(rejected("Unknown task.")),
+    };
+
+    let won = match sync::merge(&mut snapshot, &mut stamps, &writes, &stamp) {
+        Ok(won) if won.is_empty() && existing.is_some() => return Ok(Outcome::Stale),
+        Ok(won) => won,
+        Err(error) => return Ok(rejected(error)),
+    };
+
+    // Each value has to make sense on its own, which doesn't depend on what else was written
+    let (fields, errors) = normalize(snapshot.fields);
+    if !errors.is_empty() {
+        return Ok(rejected(ApiError::Invalid(errors).to_string()));
+    }
+    snapshot.fields = fields;
+
+    // Values that clash with each other, like a start moved past the due date, are settled by stamp
+    // instead of turning away whichever write happened to come in last
+    let fields = match validate(sync::resolve(&snapshot, &stamps).fields) {
+        Ok(fields) => fields,
+        Err(error) => return Ok(rejected(error.to_string())),
+    };
+
+    let task = match existing {
+        Some(task) => {
+            if rescheduled(&task.fields(), &fields) {
+                occurrences::clear(conn, task.id)?;
+            }
+            tasks::update(conn, user_id, task.id, &fields, now)?
+        }
+        None => Some(tasks::insert_with_uid(conn, user_id, &uid, &fields, now)?),
+    };
+    let Some(mut task) = task else {
+        return Ok(rejected("Unknown task."));
+    };
+
+    if won.contains(&Field::Completed) {
+        let completed = snapshot.completed.map(|time| time.timestamp());
+        if let Some(updated) = tasks::set_completed(conn, user_id, task.id, completed, now)? {
+            task = updated;
+        }
+    }
+
+    for field in &won {
+        clocks::save(conn, task.id, *field, &stamp, &snapshot.get(*field))?;
+    }
+
+    events.push(match created {
+        true => Event::TaskCreated {
+            task: Box::new(task),
+        },
+        false => Event::TaskUpdated {
+            task: Box::new(task),
+        },
+    });
+
+    Ok(Outcome::Applied)
+}
+
+fn delete(
+    conn: &Connection,
+    user_id: i64,
+    uid: &str,
+    stamp: &Stamp,
+    now: i64,
+    events: &mut Vec<Event>,
+) -> Result<Outcome, ApiError> {
+    if clocks::tombstone(conn, user_id, uid)?.is_some() {
+        return Ok(Outcome::Stale);
+    }
+
+    clocks::bury(conn, user_id, uid, stamp, now)?;
+
+    if let Some(task) = tasks::find_by_uid(conn, user_id, uid)? {
+        for deleted in tasks::delete(conn, user_id, task.id, now)? {
+            // Subtasks go with their parent, and shouldn't come back either
+            clocks::bury(conn, user_id, &deleted.uid, stamp, now)?;
+            events.push(Event::TaskDeleted {
+                id: deleted.id,
+                list_id: None,
+            });
+        }
+    }
+
+    Ok(Outcome::Applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::{json, Value};
+
+    fn statuses(body: &Value) -> Vec<&str> {
+        body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_str().unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn offline_edits_converge() {
+        let laptop_edits = json!({
+            "client_id": "laptop",
+            "ops": [
+                { "type": "set", "uid": "rent", "clock": 3, "field": "title", "value": "Pay rent (laptop)" },
+                { "type": "set", "uid": "rent", "clock": 3, "field": "notes", "value": "Landlord takes checks" },
+            ],
+        });
+        let phone_edits = json!({
+            "client_id": "phone",
+            "ops": [
+                // Same clock as the laptop's title, "phone" wins the tie
+                { "type": "set", "uid": "rent", "clock": 3, "field": "title", "value": "Pay rent (phone)" },
+                { "type": "set", "uid": "rent", "clock": 2, "field": "notes", "value": "Old notes" },
+                { "type": "set", "uid": "rent", "clock": 4, "field": "completed", "value": "2023-03-01T10:00:00Z" },
+            ],
+        });
+
+        let mut outcomes = Vec::new();
+        for order in [[&laptop_edits, &phone_edits], [&phone_edits, &laptop_edits]] {
+            let app = test::init_service(app()).await;
+            let token = sign_up(&app, "steven").await;
+
+            let create = json!({
+                "client_id": "laptop",
+                "ops": [{
+                    "type": "create",
+                    "uid": "rent",
+                    "clock": 1,
+                    "fields": { "title": "Pay rent", "due": "2023-03-01T09:00:00Z" },
+                }],
+            });
+            let (status, body) = call(
+                &app,
+                request(Method::POST, "/api/sync", &token).set_json(create),
+            )
+            .await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(statuses(&body), ["applied"]);
+
+            let mut body = Value::Null;
+            for batch in order {
+                (_, body) = call(
+                    &app,
+                    request(Method::POST, "/api/sync", &token).set_json(batch),
+                )
+                .await;
+            }
+            assert_eq!(body["clock"], 4);
+
+            let task = &body["tasks"][0];
+            outcomes.push(json!({
+                "title": task["title"],
+                "notes": task["notes"],
+                "due": task["due"],
+                "completed": task["completed"],
+            }));
+        }
+
+        assert_eq!(outcomes[0], outcomes[1]);
+        assert_eq!(
+            outcomes[0],
+            json!({
+                "title": "Pay rent (phone)",
+                "notes": "Landlord takes checks",
+                "due": "2023-03-01T09:00:00Z",
+                "completed": "2023-03-01T10:00:00Z",
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn deletes_and_rejections() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        // Tasks made over REST can be synced too, by UID
+        let (_, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &token).set_json(json!({ "title": "Gym" })),
+        )
+        .await;
+        let uid = body["task"]["uid"].as_str().unwrap();
+
+        let batch = json!({
+            "client_id": "laptop",
+            "ops": [
+                { "type": "set", "uid": uid, "clock": 1, "field": "title", "value": "Gym!" },
+                { "type": "set", "uid": uid, "clock": 1, "field": "start", "value": "2023-03-02T00:00:00Z" },
+                // Before the start, which gives way to it since it was written earlier
+                { "type": "set", "uid": uid, "clock": 2, "field": "due", "value": "2023-03-01T00:00:00Z" },
+                { "type": "set", "uid": uid, "clock": 3, "field": "due", "value": 5 },
+                { "type": "set", "uid": "nope", "clock": 1, "field": "title", "value": "Nope" },
+                { "type": "create", "uid": "new", "clock": 1, "fields": { "priority": 1 } },
+                { "type": "delete", "uid": uid, "clock": 0 },
+                // Deletes win even over newer writes
+                { "type": "set", "uid": uid, "clock": 9, "field": "title", "value": "Gym?" },
+                { "type": "create", "uid": uid, "clock": 9, "fields": { "title": "Gym?" } },
+            ],
+        });
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/sync", &token).set_json(batch),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            statuses(&body),
+            [
+                "applied", "applied", "applied", "rejected", "rejected", "rejected", "applied",
+                "deleted", "deleted"
+            ]
+        );
+        assert_eq!(body["results"][3]["error"], "Invalid value for due.");
+        assert_eq!(body["deleted"], json!([uid]));
+        assert!(body["tasks"].as_array().unwrap().is_empty());
+
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &token)).await;
+        assert!(body["tasks"].as_array().unwrap().is_empty());
+
+        let (status, _) = call(
+            &app,
+            request(Method::POST, "/api/sync", &token)
+                .set_json(json!({ "client_id": " ", "ops": [] })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn clashing_writes_converge() {
+        let start = json!({ "type": "set", "uid": "trip", "clock": 1, "field": "start", "value": "2023-03-10T00:00:00Z" });
+        let due = json!({ "type": "set", "uid": "trip", "clock": 2, "field": "due", "value": "2023-03-05T00:00:00Z" });
+        let later = json!({ "type": "set", "uid": "trip", "clock": 3, "field": "due", "value": "2023-03-20T00:00:00Z" });
+
+        let mut outcomes = Vec::new();
+        for order in [
+            [&start, &due, &later],
+            [&due, &start, &later],
+            [&start, &later, &due],
+            [&later, &due, &start],
+        ] {
+            let app = test::init_service(app()).await;
+            let token = sign_up(&app, "steven").await;
+            let create = json!({
+                "client_id": "laptop",
+                "ops": [{ "type": "create", "uid": "trip", "clock": 0, "fields": { "title": "Trip" } }],
+            });
+            call(
+                &app,
+                request(Method::POST, "/api/sync", &token).set_json(create),
+            )
+            .await;
+
+            // One batch each, so every write goes through the database
+            let mut states = Vec::new();
+            for op in order {
+                let (_, body) = call(
+                    &app,
+                    request(Method::POST, "/api/sync", &token)
+                        .set_json(json!({ "client_id": "phone", "ops": [op] })),
+                )
+                .await;
+                // Older than what's there is fine, but nothing gets turned away
+                assert_ne!(body["results"][0]["status"], "rejected");
+                let task = &body["tasks"][0];
+                states.push(json!({ "start": task["start"], "due": task["due"] }));
+            }
+            outcomes.push(states);
+        }
+
+        // Whichever of the first two came in last, the start gives way to the due date written after it
+        let clashed = json!({ "start": "2023-03-05T00:00:00Z", "due": "2023-03-05T00:00:00Z" });
+        assert_eq!(outcomes[0][1], clashed);
+        assert_eq!(outcomes[1][1], clashed);
+
+        // and is back to what was written once the due date moves past it
+        for states in &outcomes {
+            assert_eq!(
+                states[2],
+                json!({ "start": "2023-03-10T00:00:00Z", "due": "2023-03-20T00:00:00Z" })
+            );
+        }
+    }
+}
@@ -0,0 +1,703 @@
+use crate::{
+    auth::AuthUser,
+    calendar::recurrence::Rule,
+    database::{
+        dependencies, lists, occurrences,
+        tasks::{self, Task, TaskFields},
+        views, Database,
+    },
+    error::{ApiError, FieldError},
+    filter,
+    gateway::Gateway,
+    plan, quick,
+    util::now,
+};
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+#[derive(Deserialize, ToSchema)]
+struct NewTask {
+    title: String,
+    #[serde(default)]
+    notes: String,
+    category: Option<String>,
+    start: Option<DateTime<Utc>>,
+    due: Option<DateTime<Utc>>,
+    recurrence: Option<String>,
+    time_zone: Option<String>,
+    /// Makes this a subtask
+    parent_id: Option<i64>,
+    /// Puts it in a shared list
+    list_id: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct QuickTask {
+    text: String,
+    /// IANA time zone the dates in `text` are in
+    time_zone: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ListQuery {
+    /// See "Filters" in the README
+    filter: Option<String>,
+    /// Name of a saved view to use as the filter
+    view: Option<String>,
+    /// Only the tasks in this list
+    list: Option<i64>,
+}
+
+/// Fields that are left out stay the same, and nullable ones are cleared with `null`.
+#[derive(Deserialize, ToSchema)]
+struct TaskChanges {
+    title: Option<String>,
+    notes: Option<String>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    category: Option<Option<String>>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    start: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    due: Option<Option<DateTime<Utc>>>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    recurrence: Option<Option<String>>,
+    time_zone: Option<String>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    parent_id: Option<Option<i64>>,
+    #[serde(default, deserialize_with = "super::nullable")]
+    list_id: Option<Option<i64>>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct TaskResponse {
+    pub task: Task,
+}
+
+#[derive(Serialize, ToSchema)]
+pub(super) struct TasksResponse {
+    pub tasks: Vec<Task>,
+}
+
+/// A task as it would be created, minus what quick add doesn't read.
+#[derive(Serialize, ToSchema)]
+struct QuickFields {
+    title: String,
+    category: Option<String>,
+    due: Option<DateTime<Utc>>,
+    recurrence: Option<String>,
+    time_zone: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct QuickResponse {
+    task: QuickFields,
+    /// Not stored, since tasks don't have a priority
+    priority: Option<quick::Priority>,
+}
+
+impl TaskChanges {
+    fn apply(self, fields: &mut TaskFields) {
+        if let Some(title) = self.title {
+            fields.title = title;
+        }
+        if let Some(notes) = self.notes {
+            fields.notes = notes;
+        }
+        if let Some(category) = self.category {
+            fields.category = category;
+        }
+        if let Some(start) = self.start {
+            fields.start = start;
+        }
+        if let Some(due) = self.due {
+            fields.due = due;
+        }
+        if let Some(recurrence) = self.recurrence {
+            fields.recurrence = recurrence;
+        }
+        if let Some(time_zone) = self.time_zone {
+            fields.time_zone = time_zone;
+        }
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_tasks)
+        .service(create_task)
+        .service(quick_task)
+        .service(get_task)
+        .service(update_task)
+        .service(delete_task)
+        .service(complete_task)
+        .service(uncomplete_task);
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    list_tasks,
+    create_task,
+    quick_task,
+    get_task,
+    update_task,
+    delete_task,
+    complete_task,
+    uncomplete_task
+))]
+pub(super) struct Docs;
+
+/// Trims the fields and makes sure they make sense together.
+pub(crate) fn validate(fields: TaskFields) -> Result<TaskFields, ApiError> {
+    let (fields, mut errors) = normalize(fields);
+
+    if let (Some(start), Some(due)) = (fields.start, fields.due) {
+        if start > due {
+            errors.push(FieldError::new(
+                "start",
+                "Start cannot be after the due date.",
+            ));
+        }
+    }
+
+    if fields.recurrence.is_some() && fields.start.is_none() && fields.due.is_none() {
+        errors.push(FieldError::new(
+            "recurrence",
+            "Recurring tasks need a start or due date.",
+        ));
+    }
+
+    if !errors.is_empty() {
+        return Err(ApiError::Invalid(errors));
+    }
+    Ok(fields)
+}
+
+/// Trims the fields and checks each one on its own, without looking at how they fit together.
+pub(crate) fn normalize(mut fields: TaskFields) -> (TaskFields, Vec<FieldError>) {
+    fields.title = fields.title.trim().to_string();
+    fields.category = fields
+        .category
+        .map(|category| filter::normalize_category(&category))
+        .filter(|category| !category.is_empty());
+
+    let mut errors = Vec::new();
+
+    if fields.title.is_empty() {
+        errors.push(FieldError::new("title", "Title cannot be empty."));
+    } else if fields.title.chars().count() > 200 {
+        errors.push(FieldError::new(
+            "title",
+            "Title cannot be longer than 200 characters.",
+        ));
+    }
+
+    if let Some(category) = &fields.category {
+        if category.chars().count() > 50 {
+            errors.push(FieldError::new(
+                "category",
+                "Category cannot be longer than 50 characters.",
+            ));
+        }
+    }
+
+    if fields.time_zone.parse::<Tz>().is_err() {
+        errors.push(FieldError::new(
+            "time_zone",
+            format!("Unknown time zone \"{}\".", fields.time_zone),
+        ));
+    }
+
+    if let Some(recurrence) = &fields.recurrence {
+        match recurrence.parse::<Rule>() {
+            Err(error) => errors.push(FieldError::new(
+                "recurrence",
+                format!("Invalid recurrence: {error}"),
+            )),
+            // Stored normalized, so comparing rules is just comparing strings
+            Ok(rule) => fields.recurrence = Some(rule.to_string()),
+        }
+    }
+
+    (fields, errors)
+}
+
+/// Changes to single occurrences don't line up anymore once the series moves.
+pub(super) fn rescheduled(before: &TaskFields, after: &TaskFields) -> bool {
+    after.recurrence != before.recurrence
+        || after.start != before.start
+        || after.due != before.due
+        || after.time_zone != before.time_zone
+}
+
+/// Makes sure the user can add and change tasks in `list_id`. Tasks outside of lists are only ever
+/// seen by whoever made them, so they can always change them.
+pub(super) fn check_editable(
+    conn: &Connection,
+    user_id: i64,
+    list_id: Option<i64>,
+) -> Result<(), ApiError> {
+    let Some(list_id) = list_id else {
+        return Ok(());
+    };
+
+    match lists::role(conn, user_id, list_id)? {
+        Some(role) if role.can_edit() => Ok(()),
+        Some(_) => Err(ApiError::Forbidden(
+            "Viewers can't change the tasks in a list.".to_string(),
+        )),
+        None => Err(ApiError::NotFound("List not found.".to_string())),
+    }
+}
+
+/// Makes sure `id` can be moved under `parent_id`, in `list_id`, without ending up waiting on itself.
+fn check_parent(
+    conn: &Connection,
+    user_id: i64,
+    id: Option<i64>,
+    parent_id: Option<i64>,
+    list_id: Option<i64>,
+) -> Result<(), ApiError> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+
+    let parent = tasks::get(conn, user_id, parent_id)?
+        .ok_or_else(|| ApiError::field("parent_id", "Parent task not found."))?;
+    if parent.list_id != list_id {
+        return Err(ApiError::field(
+            "parent_id",
+            "Subtasks have to be in the same list as their parent.",
+        ));
+    }
+
+    // Tasks wait on their subtasks, so the parent will wait on this one
+    if let Some(id) = id {
+        let edges = dependencies::edges(conn, user_id)?;
+        if plan::creates_cycle(&edges, parent_id, id) {
+            return Err(ApiError::Conflict(
+                "A task can't be a subtask of itself, or of anything waiting on it.".to_string(),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+fn not_found() -> ApiError {
+    ApiError::NotFound("Task not found.".to_string())
+}
+
+fn task_response(task: Task) -> HttpResponse {
+    HttpResponse::Ok().json(TaskResponse { task })
+}
+
+/// Every task the user can see, or the ones matching a filter or saved view.
+#[utoipa::path(params(ListQuery), responses((status = OK, body = TasksResponse)))]
+#[get("/tasks")]
+async fn list_tasks(
+    user: AuthUser,
+    db: web::Data<Database>,
+    query: web::Query<ListQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let query = query.into_inner();
+    let list = query.list;
+    let query = match query {
+        ListQuery {
+            filter: Some(_),
+            view: Some(_),
+            ..
+        } => {
+            return Err(ApiError::BadRequest(
+                "Use either a filter or a view, not both.".to_string(),
+            ))
+        }
+        ListQuery {
+            filter: Some(filter),
+            ..
+        } => Some(filter),
+        ListQuery {
+            view: Some(name), ..
+        } => {
+            let view = views::get(&conn, user.id, &name)?
+                .ok_or_else(|| ApiError::NotFound("View not found.".to_string()))?;
+            Some(view.query)
+        }
+        _ => None,
+    };
+
+    let mut tasks = match query {
+        Some(query) => {
+            let filter = filter::parse(&query)
+                .map_err(|error| ApiError::BadRequest(format!("Invalid filter: {error}")))?;
+            let now = DateTime::from_timestamp(now(), 0).unwrap_or_default();
+            tasks::list_matching(&conn, user.id, &filter, now)?
+        }
+        None => tasks::list(&conn, user.id)?,
+    };
+    if let Some(list) = list {
+        tasks.retain(|task| task.list_id == Some(list));
+    }
+
+    Ok(HttpResponse::Ok().json(TasksResponse { tasks }))
+}
+
+#[utoipa::path(responses((status = CREATED, body = TaskResponse)))]
+#[post("/tasks")]
+async fn create_task(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    body: web::Json<NewTask>,
+) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let fields = validate(TaskFields {
+        title: body.title,
+        notes: body.notes,
+        category: body.category,
+        start: body.start,
+        due: body.due,
+        recurrence: body.recurrence,
+        time_zone: body.time_zone.unwrap_or_else(|| "UTC".to_string()),
+    })?;
+
+    let conn = db.lock();
+    check_editable(&conn, user.id, body.list_id)?;
+    check_parent(&conn, user.id, None, body.parent_id, body.list_id)?;
+
+    let task = tasks::insert(&conn, user.id, &fields, body.list_id, body.parent_id, now())?;
+    gateway.task_created(&lists::audience(&conn, user.id, task.list_id)?, &task);
+    Ok(HttpResponse::Created().json(TaskResponse { task }))
+}
+
+/// Reads a task out of a line like "pay rent tomorrow 9am #home", see `quick`. Doesn't create it, so the
+/// user can check it first.
+#[utoipa::path(responses((status = OK, body = QuickResponse)))]
+#[post("/tasks/quick")]
+async fn quick_task(_: AuthUser, body: web::Json<QuickTask>) -> Result<HttpResponse, ApiError> {
+    let body = body.into_inner();
+    let time_zone = body.time_zone.unwrap_or_else(|| "UTC".to_string());
+    let tz: Tz = time_zone
+        .parse()
+        .map_err(|_| ApiError::field("time_zone", format!("Unknown time zone \"{time_zone}\".")))?;
+
+    let parsed = quick::parse(&body.text, tasks::from_timestamp(now()).with_timezone(&tz));
+    let fields = validate(TaskFields {
+        title: parsed.title,
+        notes: String::new(),
+        category: parsed.category,
+        start: None,
+        due: parsed.due,
+        recurrence: parsed.recurrence,
+        time_zone,
+    })?;
+
+    Ok(HttpResponse::Ok().json(QuickResponse {
+        task: QuickFields {
+            title: fields.title,
+            category: fields.category,
+            due: fields.due,
+            recurrence: fields.recurrence,
+            time_zone: fields.time_zone,
+        },
+        priority: parsed.priority,
+    }))
+}
+
+#[utoipa::path(responses((status = OK, body = TaskResponse)))]
+#[get("/tasks/{id}")]
+async fn get_task(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let task = tasks::get(&db.lock(), user.id, path.into_inner())?.ok_or_else(not_found)?;
+    Ok(task_response(task))
+}
+
+#[utoipa::path(responses((status = OK, body = TaskResponse)))]
+#[patch("/tasks/{id}")]
+async fn update_task(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<i64>,
+    body: web::Json<TaskChanges>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    let task = tasks::get(&conn, user.id, id)?.ok_or_else(not_found)?;
+    check_editable(&conn, user.id, task.list_id)?;
+
+    let mut changes = body.into_inner();
+    let list_id = changes
+        .list_id
+        .take()
+        .filter(|list_id| *list_id != task.list_id);
+    let parent_id = match changes.parent_id.take() {
+        Some(parent_id) => Some(parent_id),
+        // Moving to another list leaves the parent behind
+        None if list_id.is_some() && task.parent_id.is_some() => Some(None),
+        None => None,
+    };
+    let mut fields = task.fields();
+    changes.apply(&mut fields);
+    let fields = validate(fields)?;
+
+    if let Some(list_id) = list_id {
+        check_editable(&conn, user.id, list_id)?;
+    }
+    if let Some(parent_id) = parent_id {
+        check_parent(
+            &conn,
+            user.id,
+            Some(id),
+            parent_id,
+            list_id.unwrap_or(task.list_id),
+        )?;
+    }
+
+    if rescheduled(&task.fields(), &fields) {
+        occurrences::clear(&conn, id)?;
+    }
+
+    let before = lists::audience(&conn, user.id, task.list_id)?;
+    let mut task = tasks::update(&conn, user.id, id, &fields, now())?.ok_or_else(not_found)?;
+    if let Some(parent_id) = parent_id.filter(|parent_id| *parent_id != task.parent_id) {
+        task = tasks::set_parent(&conn, user.id, id, parent_id, now())?.ok_or_else(not_found)?;
+    }
+    let Some(list_id) = list_id else {
+        gateway.task_updated(&before, &task);
+        return Ok(task_response(task));
+    };
+
+    // Subtasks move along, and whoever can't see them anymore is told they're gone
+    task = tasks::set_list(&conn, user.id, id, list_id, now())?.ok_or_else(not_found)?;
+    let after = lists::audience(&conn, user.id, list_id)?;
+    let gone: Vec<_> = before
+        .into_iter()
+        .filter(|user_id| !after.contains(user_id))
+        .collect();
+    let mut moved = tasks::descendants(&conn, user.id, id)?;
+    moved.insert(0, task.clone());
+    for task in &moved {
+        gateway.task_deleted(&gone, task);
+        gateway.task_updated(&after, task);
+    }
+    Ok(task_response(task))
+}
+
+/// Deletes the task along with its subtasks.
+#[utoipa::path(responses((status = NO_CONTENT)))]
+#[delete("/tasks/{id}")]
+async fn delete_task(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    let task = tasks::get(&conn, user.id, id)?.ok_or_else(not_found)?;
+    check_editable(&conn, user.id, task.list_id)?;
+
+    let audience = lists::audience(&conn, user.id, task.list_id)?;
+    for task in tasks::delete(&conn, user.id, id, now())? {
+        gateway.task_deleted(&audience, &task);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn set_completed(
+    db: &Database,
+    gateway: &Gateway,
+    user_id: i64,
+    id: i64,
+    completed: Option<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let task = tasks::get(&conn, user_id, id)?.ok_or_else(not_found)?;
+    check_editable(&conn, user_id, task.list_id)?;
+
+    let task = tasks::set_completed(&conn, user_id, id, completed, now())?.ok_or_else(not_found)?;
+    gateway.task_updated(&lists::audience(&conn, user_id, task.list_id)?, &task);
+    Ok(task_response(task))
+}
+
+#[utoipa::path(responses((status = OK, body = TaskResponse)))]
+#[post("/tasks/{id}/complete")]
+async fn complete_task(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    set_completed(&db, &gateway, user.id, path.into_inner(), Some(now()))
+}
+
+#[utoipa::path(responses((status = OK, body = TaskResponse)))]
+#[delete("/tasks/{id}/complete")]
+async fn uncomplete_task(
+    user: AuthUser,
+    db: web::Data<Database>,
+    gateway: Gateway,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    set_completed(&db, &gateway, user.id, path.into_inner(), None)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::json;
+
+    #[actix_web::test]
+    async fn crud() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let body = json!({
+            "title": "  Pay rent ",
+            "category": "Home",
+            "due": "2023-03-01T09:00:00Z",
+        });
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &token).set_json(body),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["task"]["title"], "Pay rent");
+        assert_eq!(body["task"]["category"], "home");
+        assert_eq!(body["task"]["due"], "2023-03-01T09:00:00Z");
+        let id = body["task"]["id"].as_i64().unwrap();
+        let uri = format!("/api/tasks/{id}");
+
+        // Explicit nulls clear a field, missing fields are left alone
+        let changes = json!({ "notes": "Landlord takes checks", "category": null });
+        let (status, body) =
+            call(&app, request(Method::PATCH, &uri, &token).set_json(changes)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["task"]["notes"], "Landlord takes checks");
+        assert_eq!(body["task"]["category"], json!(null));
+        assert_eq!(body["task"]["due"], "2023-03-01T09:00:00Z");
+
+        let (_, body) = call(
+            &app,
+            request(Method::POST, &format!("{uri}/complete"), &token),
+        )
+        .await;
+        assert!(body["task"]["completed"].is_string());
+        let (_, body) = call(
+            &app,
+            request(Method::DELETE, &format!("{uri}/complete"), &token),
+        )
+        .await;
+        assert!(body["task"]["completed"].is_null());
+
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &token)).await;
+        assert_eq!(body["tasks"].as_array().unwrap().len(), 1);
+
+        let (status, _) = call(&app, request(Method::DELETE, &uri, &token)).await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (status, _) = call(&app, request(Method::GET, &uri, &token)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn quick_add() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let text = json!({
+            "text": "Pay rent tomorrow 9am #Home every month !high",
+            "time_zone": "Europe/Berlin",
+        });
+        let (status, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks/quick", &token).set_json(text),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["priority"], "high");
+        let task = &body["task"];
+        assert_eq!(task["title"], "Pay rent");
+        assert_eq!(task["category"], "home");
+        assert_eq!(task["recurrence"], "FREQ=MONTHLY");
+
+        let tz: chrono_tz::Tz = "Europe/Berlin".parse().unwrap();
+        let due: chrono::DateTime<chrono::Utc> = task["due"].as_str().unwrap().parse().unwrap();
+        let tomorrow = chrono::Utc::now()
+            .with_timezone(&tz)
+            .date_naive()
+            .succ_opt();
+        assert_eq!(due.with_timezone(&tz).date_naive(), tomorrow.unwrap());
+        assert_eq!(due.with_timezone(&tz).time().to_string(), "09:00:00");
+
+        // Good to go as is once the user is happy with it
+        let (status, _) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &token).set_json(task),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        for text in [
+            json!({ "text": "tomorrow 9am" }),
+            json!({ "text": "pay rent", "time_zone": "Mars/Olympus_Mons" }),
+        ] {
+            let (status, _) = call(
+                &app,
+                request(Method::POST, "/api/tasks/quick", &token).set_json(text),
+            )
+            .await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[actix_web::test]
+    async fn validation_and_ownership() {
+        let app = test::init_service(app()).await;
+        let steven = sign_up(&app, "steven").await;
+        let other = sign_up(&app, "other").await;
+
+        let backwards = json!({
+            "title": "Vacation",
+            "start": "2023-03-10T00:00:00Z",
+            "due": "2023-03-01T00:00:00Z",
+        });
+        let (status, _) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &steven).set_json(backwards),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, _) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &steven).set_json(json!({ "title": " " })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (_, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &steven).set_json(json!({ "title": "Mine" })),
+        )
+        .await;
+        let uri = format!("/api/tasks/{}", body["task"]["id"]);
+
+        let (status, _) = call(&app, request(Method::GET, &uri, &other)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(&app, request(Method::DELETE, &uri, &other)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+}
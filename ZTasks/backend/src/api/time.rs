@@ -0,0 +1,563 @@
+use super::calendar::{split, time_zone};
+use crate::{
+    auth::AuthUser,
+    calendar::Grouping,
+    database::{tasks, time, Database},
+    error::ApiError,
+    util::now,
+};
+use actix_web::{delete, get, patch, post, web, HttpResponse};
+use chrono::{DateTime, FixedOffset, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use utoipa::{IntoParams, OpenApi, ToSchema};
+
+#[derive(Deserialize, ToSchema)]
+struct NewEntry {
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    #[serde(default)]
+    note: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct EntryChanges {
+    start: Option<DateTime<Utc>>,
+    end: Option<DateTime<Utc>>,
+    note: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct ReportQuery {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tz: Option<String>,
+    /// Without one, the whole range is a single period
+    group: Option<Grouping>,
+    format: Option<Format>,
+}
+
+/// How long was spent on one category. Tasks without a category are under `null`.
+#[derive(Serialize, ToSchema)]
+struct Total {
+    category: Option<String>,
+    seconds: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Period {
+    label: Option<String>,
+    start: DateTime<FixedOffset>,
+    end: DateTime<FixedOffset>,
+    seconds: i64,
+    categories: Vec<Total>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TimerResponse {
+    timer: Option<time::Entry>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct EntryResponse {
+    entry: time::Entry,
+}
+
+#[derive(Serialize, ToSchema)]
+struct EntriesResponse {
+    entries: Vec<time::Entry>,
+    seconds: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ReportResponse {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    tz: String,
+    group: Option<Grouping>,
+    seconds: i64,
+    categories: Vec<Total>,
+    periods: Vec<Period>,
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(
+    get_timer,
+    start_timer,
+    stop_timer,
+    list_entries,
+    add_entry,
+    update_entry,
+    delete_entry,
+    report
+))]
+pub(super) struct Docs;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_timer)
+        .service(start_timer)
+        .service(stop_timer)
+        .service(list_entries)
+        .service(add_entry)
+        .service(report)
+        .service(update_entry)
+        .service(delete_entry);
+}
+
+fn task_not_found() -> ApiError {
+    ApiError::NotFound("Task not found.".to_string())
+}
+
+fn entry_not_found() -> ApiError {
+    ApiError::NotFound("Time entry not found.".to_string())
+}
+
+fn check_entry(entry: &time::Entry) -> Result<(), ApiError> {
+    if entry.end.is_some_and(|end| end <= entry.start) {
+        return Err(ApiError::field(
+            "end",
+            "Time entries have to end after they start.",
+        ));
+    }
+
+    if entry.note.chars().count() > 200 {
+        return Err(ApiError::field(
+            "note",
+            "Note cannot be longer than 200 characters.",
+        ));
+    }
+    Ok(())
+}
+
+#[utoipa::path(responses((status = OK, body = TimerResponse)))]
+#[get("/timer")]
+async fn get_timer(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    Ok(HttpResponse::Ok().json(TimerResponse {
+        timer: time::running(&conn, user.id)?,
+    }))
+}
+
+/// Only one timer runs at a time, so starting another one while it does is a conflict rather than
+/// quietly stopping it.
+#[utoipa::path(responses((status = CREATED, body = TimerResponse)))]
+#[post("/tasks/{id}/timer")]
+async fn start_timer(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    tasks::get(&conn, user.id, id)?.ok_or_else(task_not_found)?;
+
+    if let Some(running) = time::running(&conn, user.id)? {
+        return Err(ApiError::Conflict(format!(
+            "A timer is already running on task {}, stop that one first.",
+            running.task_id
+        )));
+    }
+
+    let timer = time::insert(&conn, user.id, id, tasks::from_timestamp(now()), None, "")?;
+    Ok(HttpResponse::Created().json(TimerResponse { timer: Some(timer) }))
+}
+
+#[utoipa::path(responses((status = OK, body = EntryResponse)))]
+#[delete("/timer")]
+async fn stop_timer(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    let mut entry = time::running(&conn, user.id)?
+        .ok_or_else(|| ApiError::NotFound("No timer is running.".to_string()))?;
+
+    // Entries have to end after they start, even if the timer was stopped right away
+    let end = now().max(entry.start.timestamp() + 1);
+    entry.end = Some(tasks::from_timestamp(end));
+    time::update(&conn, &entry)?;
+    Ok(HttpResponse::Ok().json(EntryResponse { entry }))
+}
+
+/// The time the user spent on a task. Everyone in a shared list tracks their own.
+#[utoipa::path(responses((status = OK, body = EntriesResponse)))]
+#[get("/tasks/{id}/time")]
+async fn list_entries(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let conn = db.lock();
+    tasks::get(&conn, user.id, id)?.ok_or_else(task_not_found)?;
+
+    let entries = time::list(&conn, user.id, id)?;
+    let now = tasks::from_timestamp(now());
+    let seconds: i64 = entries.iter().map(|entry| entry.seconds(now)).sum();
+
+    Ok(HttpResponse::Ok().json(EntriesResponse { entries, seconds }))
+}
+
+#[utoipa::path(responses((status = CREATED, body = EntryResponse)))]
+#[post("/tasks/{id}/time")]
+async fn add_entry(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+    body: web::Json<NewEntry>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let body = body.into_inner();
+    check_entry(&time::Entry {
+        id: 0,
+        task_id: id,
+        start: body.start,
+        end: Some(body.end),
+        note: body.note.clone(),
+    })?;
+
+    let conn = db.lock();
+    tasks::get(&conn, user.id, id)?.ok_or_else(task_not_found)?;
+
+    let entry = time::insert(&conn, user.id, id, body.start, Some(body.end), &body.note)?;
+    Ok(HttpResponse::Created().json(EntryResponse { entry }))
+}
+
+/// Running timers can have their start and note changed too, but they only end by being stopped.
+#[utoipa::path(responses((status = OK, body = EntryResponse)))]
+#[patch("/time/{id}")]
+async fn update_entry(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+    body: web::Json<EntryChanges>,
+) -> Result<HttpResponse, ApiError> {
+    let id = path.into_inner();
+    let body = body.into_inner();
+    let conn = db.lock();
+    let mut entry = time::get(&conn, user.id, id)?.ok_or_else(entry_not_found)?;
+
+    if body.end.is_some() && entry.end.is_none() {
+        return Err(ApiError::field(
+            "end",
+            "Stop the timer instead of setting when it ends.",
+        ));
+    }
+
+    entry.start = body.start.unwrap_or(entry.start);
+    entry.end = body.end.or(entry.end);
+    entry.note = body.note.unwrap_or(entry.note);
+    check_entry(&entry)?;
+
+    time::update(&conn, &entry)?;
+    Ok(HttpResponse::Ok().json(EntryResponse { entry }))
+}
+
+#[utoipa::path(responses((status = NO_CONTENT)))]
+#[delete("/time/{id}")]
+async fn delete_entry(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let conn = db.lock();
+    match time::delete(&conn, user.id, path.into_inner())? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(entry_not_found()),
+    }
+}
+
+/// Time spent in `[from, to)`, by category, and optionally by day, week or month in `tz` too. Entries
+/// that cross into the next period are split up between them, and running timers count up to now.
+#[utoipa::path(
+    params(ReportQuery),
+    responses((status = OK, content(
+        (ReportResponse = "application/json"),
+        (String = "text/csv")
+    )))
+)]
+#[get("/time/report")]
+async fn report(
+    user: AuthUser,
+    db: web::Data<Database>,
+    query: web::Query<ReportQuery>,
+) -> Result<HttpResponse, ApiError> {
+    let query = query.into_inner();
+    let tz = time_zone(query.tz.as_deref())?;
+
+    let periods: Vec<(Option<String>, DateTime<FixedOffset>, DateTime<FixedOffset>)> =
+        match query.group {
+            Some(group) => split(query.from, query.to, tz, group)?
+                .into_iter()
+                .map(|bucket| (Some(bucket.label), bucket.start, bucket.end))
+                .collect(),
+            None if query.from < query.to => vec![(
+                None,
+                query.from.with_timezone(&tz).fixed_offset(),
+                query.to.with_timezone(&tz).fixed_offset(),
+            )],
+            None => return Err(ApiError::field("from", "\"from\" must be before \"to\".")),
+        };
+
+    // Like the calendar, grouping widens the range to whole periods
+    let from = periods
+        .first()
+        .map_or(query.from, |period| period.1.to_utc());
+    let to = periods.last().map_or(query.to, |period| period.2.to_utc());
+    let now = tasks::from_timestamp(now());
+
+    let entries = time::list_between(&db.lock(), user.id, from, to, now)?;
+
+    let mut totals = HashMap::new();
+    let periods: Vec<Period> = periods
+        .into_iter()
+        .map(|(label, start, end)| {
+            let mut categories = HashMap::new();
+            for (entry, category) in &entries {
+                let overlap =
+                    entry.end.unwrap_or(now).min(end.to_utc()) - entry.start.max(start.to_utc());
+                if overlap.num_seconds() > 0 {
+                    *categories.entry(category.clone()).or_default() += overlap.num_seconds();
+                    *totals.entry(category.clone()).or_default() += overlap.num_seconds();
+                }
+            }
+
+            let categories = sorted(categories);
+            Period {
+                label,
+                start,
+                end,
+                seconds: categories.iter().map(|total| total.seconds).sum(),
+                categories,
+            }
+        })
+        .collect();
+
+    if let Some(Format::Csv) = query.format {
+        return Ok(HttpResponse::Ok()
+            .content_type("text/csv; charset=utf-8")
+            .insert_header((
+                "Content-Disposition",
+                "attachment; filename=\"ztasks-time.csv\"",
+            ))
+            .body(csv(&periods)));
+    }
+
+    let categories = sorted(totals);
+    Ok(HttpResponse::Ok().json(ReportResponse {
+        from,
+        to,
+        tz: tz.name().to_string(),
+        group: query.group,
+        seconds: categories.iter().map(|total| total.seconds).sum(),
+        categories,
+        periods,
+    }))
+}
+
+/// Most time first, then alphabetically.
+fn sorted(totals: HashMap<Option<String>, i64>) -> Vec<Total> {
+    let mut totals: Vec<Total> = totals
+        .into_iter()
+        .map(|(category, seconds)| Total { category, seconds })
+        .collect();
+    totals.sort_by(|a, b| b.seconds.cmp(&a.seconds).then(a.category.cmp(&b.category)));
+    totals
+}
+
+/// One row per period and category that had any time spent on it.
+fn csv(periods: &[Period]) -> String {
+    let mut out = String::from("period,start,end,category,seconds,hours\r\n");
+    for period in periods {
+        for total in &period.categories {
+            let row = [
+                period.label.clone().unwrap_or_default(),
+                period.start.to_rfc3339(),
+                period.end.to_rfc3339(),
+                total.category.clone().unwrap_or_default(),
+                total.seconds.to_string(),
+                format!("{:.2}", total.seconds as f64 / 3600.0),
+            ];
+            let row: Vec<String> = row.iter().map(|field| csv_field(field)).collect();
+            out.push_str(&row.join(","));
+            out.push_str("\r\n");
+        }
+    }
+    out
+}
+
+/// Quotes fields that need it, and keeps spreadsheets from running categories like `=1+1` as formulas.
+fn csv_field(field: &str) -> String {
+    let field = match field.starts_with(['=', '+', '-', '@']) {
+        true => format!("'{field}"),
+        false => field.to_string(),
+    };
+
+    match field.contains([',', '"', '\r', '\n']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::{json, Value};
+
+    #[actix_web::test]
+    async fn timers_and_entries() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+        let other = sign_up(&app, "lisa").await;
+
+        let req =
+            request(Method::POST, "/api/tasks", &token).set_json(json!({ "title": "Invoice" }));
+        let task = call(&app, req).await.1["task"]["id"].as_i64().unwrap();
+        let req = request(Method::POST, "/api/tasks", &token).set_json(json!({ "title": "Taxes" }));
+        let taxes = call(&app, req).await.1["task"]["id"].as_i64().unwrap();
+
+        let (_, body) = call(&app, request(Method::GET, "/api/timer", &token)).await;
+        assert_eq!(body["timer"], Value::Null);
+
+        let uri = format!("/api/tasks/{task}/timer");
+        let (status, body) = call(&app, request(Method::POST, &uri, &token)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["timer"]["task_id"], task);
+        assert_eq!(body["timer"]["end"], Value::Null);
+
+        // One at a time, and only on tasks the user can see
+        let (status, _) = call(
+            &app,
+            request(Method::POST, &format!("/api/tasks/{taxes}/timer"), &token),
+        )
+        .await;
+        assert_eq!(status, StatusCode::CONFLICT);
+        let (status, _) = call(&app, request(Method::POST, &uri, &other)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = call(&app, request(Method::GET, "/api/timer", &token)).await;
+        assert_eq!(body["timer"]["task_id"], task);
+        let (status, body) = call(&app, request(Method::DELETE, "/api/timer", &token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body["entry"]["end"].is_string());
+        let (status, _) = call(&app, request(Method::DELETE, "/api/timer", &token)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let uri = format!("/api/tasks/{task}/time");
+        let entry = json!({ "start": "2023-03-01T10:00:00Z", "end": "2023-03-01T09:00:00Z" });
+        let (status, _) = call(&app, request(Method::POST, &uri, &token).set_json(entry)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let entry = json!({ "start": "2023-03-01T09:00:00Z", "end": "2023-03-01T10:30:00Z", "note": "Drafts" });
+        let (status, body) = call(&app, request(Method::POST, &uri, &token).set_json(entry)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["entry"]["note"], "Drafts");
+        let id = body["entry"]["id"].as_i64().unwrap();
+
+        let changes = json!({ "end": "2023-03-01T11:00:00Z" });
+        let (status, body) = call(
+            &app,
+            request(Method::PATCH, &format!("/api/time/{id}"), &token).set_json(changes),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["entry"]["end"], "2023-03-01T11:00:00Z");
+
+        let (_, body) = call(&app, request(Method::GET, &uri, &token)).await;
+        assert_eq!(body["entries"].as_array().unwrap().len(), 2);
+        assert!(body["seconds"].as_i64().unwrap() >= 2 * 3600);
+
+        // Entries are private, even on the same task
+        let (status, _) = call(
+            &app,
+            request(Method::DELETE, &format!("/api/time/{id}"), &other),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = call(
+            &app,
+            request(Method::DELETE, &format!("/api/time/{id}"), &token),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn reports() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+
+        let create = |category: Value| {
+            let req = request(Method::POST, "/api/tasks", &token)
+                .set_json(json!({ "title": "Work", "category": category }));
+            let app = &app;
+            async move { call(app, req).await.1["task"]["id"].as_i64().unwrap() }
+        };
+        let client = create(json!("client, inc")).await;
+        let admin = create(Value::Null).await;
+
+        for (task, start, end) in [
+            // 10 PM to 1 AM in New York, so it's split over two days there
+            (client, "2023-03-07T03:00:00Z", "2023-03-07T06:00:00Z"),
+            (client, "2023-03-08T14:00:00Z", "2023-03-08T14:30:00Z"),
+            (admin, "2023-03-08T15:00:00Z", "2023-03-08T16:00:00Z"),
+            (admin, "2023-04-01T15:00:00Z", "2023-04-01T16:00:00Z"),
+        ] {
+            let req = request(Method::POST, &format!("/api/tasks/{task}/time"), &token)
+                .set_json(json!({ "start": start, "end": end }));
+            assert_eq!(call(&app, req).await.0, StatusCode::CREATED);
+        }
+
+        let uri = "/api/time/report?from=2023-03-06T05:00:00Z&to=2023-03-09T05:00:00Z&tz=America/New_York&group=day";
+        let (status, body) = call(&app, request(Method::GET, uri, &token)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["seconds"], 5 * 3600 - 1800);
+        assert_eq!(
+            body["categories"],
+            json!([
+                { "category": "client, inc", "seconds": 3 * 3600 + 1800 },
+                { "category": null, "seconds": 3600 },
+            ])
+        );
+        let seconds: Vec<&Value> = body["periods"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|period| &period["seconds"])
+            .collect();
+        assert_eq!(
+            seconds,
+            [&json!(2 * 3600), &json!(3600), &json!(3600 + 1800)]
+        );
+        assert_eq!(body["periods"][0]["label"], "2023-03-06");
+
+        let uri = "/api/time/report?from=2023-03-01T00:00:00Z&to=2023-05-01T00:00:00Z&format=csv";
+        let res = test::call_service(&app, request(Method::GET, uri, &token).to_request()).await;
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "text/csv; charset=utf-8"
+        );
+        let csv = String::from_utf8(test::read_body(res).await.to_vec()).unwrap();
+        assert_eq!(
+            csv,
+            "period,start,end,category,seconds,hours\r\n\
+             ,2023-03-01T00:00:00+00:00,2023-05-01T00:00:00+00:00,\"client, inc\",12600,3.50\r\n\
+             ,2023-03-01T00:00:00+00:00,2023-05-01T00:00:00+00:00,,7200,2.00\r\n"
+        );
+
+        let uri = "/api/time/report?from=2023-03-09T00:00:00Z&to=2023-03-01T00:00:00Z";
+        assert_eq!(
+            call(&app, request(Method::GET, uri, &token)).await.0,
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
@@ -0,0 +1,221 @@
+use crate::{
+    auth::AuthUser, database::views, database::Database, error::ApiError, filter, util::now,
+};
+use actix_web::{delete, get, put, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::{OpenApi, ToSchema};
+
+#[derive(Deserialize, ToSchema)]
+struct ViewBody {
+    query: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ViewsResponse {
+    views: Vec<views::View>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ViewResponse {
+    view: views::View,
+}
+
+#[derive(OpenApi)]
+#[openapi(paths(list_views, save_view, delete_view))]
+pub(super) struct Docs;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(list_views)
+        .service(save_view)
+        .service(delete_view);
+}
+
+fn not_found() -> ApiError {
+    ApiError::NotFound("View not found.".to_string())
+}
+
+#[utoipa::path(responses((status = OK, body = ViewsResponse)))]
+#[get("/views")]
+async fn list_views(user: AuthUser, db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    let views = views::list(&db.lock(), user.id)?;
+    Ok(HttpResponse::Ok().json(ViewsResponse { views }))
+}
+
+/// Creates or replaces a view. The query is checked now so a broken view can't be saved.
+#[utoipa::path(responses((status = OK, body = ViewResponse)))]
+#[put("/views/{name}")]
+async fn save_view(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<String>,
+    body: web::Json<ViewBody>,
+) -> Result<HttpResponse, ApiError> {
+    let name = path.into_inner();
+    let name = name.trim();
+    let query = body.into_inner().query.trim().to_string();
+
+    if name.is_empty() || name.chars().count() > 50 {
+        return Err(ApiError::field(
+            "name",
+            "View names must be between 1 and 50 characters.",
+        ));
+    }
+
+    filter::parse(&query)
+        .map_err(|error| ApiError::field("query", format!("Invalid filter: {error}")))?;
+
+    let view = views::save(&db.lock(), user.id, name, &query, now())?;
+    Ok(HttpResponse::Ok().json(ViewResponse { view }))
+}
+
+#[utoipa::path(responses((status = NO_CONTENT)))]
+#[delete("/views/{name}")]
+async fn delete_view(
+    user: AuthUser,
+    db: web::Data<Database>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    match views::delete(&db.lock(), user.id, path.into_inner().trim())? {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(not_found()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+    use serde_json::{json, Value};
+
+    fn titles(body: &Value) -> Vec<&str> {
+        body["tasks"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|task| task["title"].as_str().unwrap())
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn filters_and_views() {
+        let app = test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+        let other = sign_up(&app, "other").await;
+
+        for (title, category, due) in [
+            ("Write report", Some("work"), Some("2000-01-01T00:00:00Z")),
+            ("Review 50% of PRs", Some("Work"), None),
+            ("Far off", Some("work"), Some("2999-01-01T00:00:00Z")),
+            ("Pay rent", Some("home"), Some("2000-01-02T00:00:00Z")),
+            ("Loose end", None, None),
+            ("Trip", Some("Über"), None),
+        ] {
+            let task = json!({ "title": title, "category": category, "due": due });
+            call(
+                &app,
+                request(Method::POST, "/api/tasks", &token).set_json(task),
+            )
+            .await;
+        }
+        call(
+            &app,
+            request(Method::POST, "/api/tasks", &other)
+                .set_json(json!({ "title": "Not mine", "category": "work" })),
+        )
+        .await;
+        call(&app, request(Method::POST, "/api/tasks/1/complete", &token)).await;
+
+        for (filter, expected) in [
+            (
+                "category:work",
+                vec!["Write report", "Far off", "Review 50% of PRs"],
+            ),
+            ("category:work AND NOT done AND due<7d", vec![]),
+            ("category:work -done", vec!["Far off", "Review 50% of PRs"]),
+            (
+                "NOT due<7d",
+                vec!["Far off", "Review 50% of PRs", "Loose end", "Trip"],
+            ),
+            (
+                "due:none OR category:home",
+                vec!["Pay rent", "Review 50% of PRs", "Loose end", "Trip"],
+            ),
+            ("category:none", vec!["Loose end"]),
+            ("category:ÜBER", vec!["Trip"]),
+            ("\"50%\"", vec!["Review 50% of PRs"]),
+            ("_", vec![]),
+        ] {
+            let uri = format!("/api/tasks?filter={}", urlencode(filter));
+            let (status, body) = call(&app, request(Method::GET, &uri, &token)).await;
+            assert_eq!(status, StatusCode::OK, "{filter}");
+            assert_eq!(titles(&body), expected, "{filter}");
+        }
+
+        let uri = format!("/api/tasks?filter={}", urlencode("category:(work"));
+        let (status, body) = call(&app, request(Method::GET, &uri, &token)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid filter"));
+
+        // Saved views
+        let (status, _) = call(
+            &app,
+            request(Method::PUT, "/api/views/Broken", &token)
+                .set_json(json!({ "query": "due:soon" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = call(
+            &app,
+            request(Method::PUT, "/api/views/Open%20work", &token)
+                .set_json(json!({ "query": "category:work NOT done" })),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["view"]["name"], "Open work");
+
+        let (_, body) = call(
+            &app,
+            request(Method::GET, "/api/tasks?view=Open%20work", &token),
+        )
+        .await;
+        assert_eq!(titles(&body), ["Far off", "Review 50% of PRs"]);
+
+        // Views are per user
+        let (status, _) = call(
+            &app,
+            request(Method::GET, "/api/tasks?view=Open%20work", &other),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = call(&app, request(Method::GET, "/api/views", &token)).await;
+        assert_eq!(body["views"].as_array().unwrap().len(), 1);
+
+        let (status, _) = call(
+            &app,
+            request(Method::DELETE, "/api/views/Open%20work", &token),
+        )
+        .await;
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        let (_, body) = call(&app, request(Method::GET, "/api/views", &token)).await;
+        assert!(body["views"].as_array().unwrap().is_empty());
+    }
+
+    fn urlencode(text: &str) -> String {
+        text.bytes()
+            .map(|byte| match byte {
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                    (byte as char).to_string()
+                }
+                _ => format!("%{byte:02X}"),
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,49 @@
+use super::tokens::TokenKey;
+use crate::{
+    database::{sessions, Database},
+    error::ApiError,
+    util::now,
+};
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest};
+use std::future::{ready, Ready};
+
+/// Handlers that take this as an argument can only be reached with a valid `Authorization: Bearer <access token>` header.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: i64,
+}
+
+impl FromRequest for AuthUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(authenticate(req))
+    }
+}
+
+fn authenticate(req: &HttpRequest) -> Result<AuthUser, ApiError> {
+    let key = req
+        .app_data::<web::Data<TokenKey>>()
+        .ok_or_else(|| ApiError::Internal("Token key is not configured.".to_string()))?;
+
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| ApiError::Unauthorized("Missing access token.".to_string()))?;
+
+    let db = req
+        .app_data::<web::Data<Database>>()
+        .ok_or_else(|| ApiError::Internal("Database is not configured.".to_string()))?;
+
+    let invalid = || ApiError::Unauthorized("Invalid or expired access token.".to_string());
+    let claims = key.verify(token, now()).ok_or_else(invalid)?;
+    // Tokens of a family that was revoked since they were issued die along with it
+    if !sessions::is_active(&db.lock(), &claims.fam)? {
+        return Err(invalid());
+    }
+
+    Ok(AuthUser { id: claims.sub })
+}
@@ -0,0 +1,5 @@
+mod extractor;
+pub mod password;
+pub mod tokens;
+
+pub use extractor::AuthUser;
@@ -0,0 +1,49 @@
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use rand::rngs::OsRng;
+use std::sync::OnceLock;
+
+// Verified against when the username doesn't exist, so a miss takes as long as a wrong password
+static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+/// Hashes a password with Argon2id, returning a PHC string (salt and parameters included).
+pub fn hash(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// Checks a password against a PHC string produced by `hash()`.
+pub fn verify(password: &str, hash: &str) -> bool {
+    match PasswordHash::new(hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// A hash no password is checked against for real, see `DUMMY_HASH`.
+pub fn dummy_hash() -> &'static str {
+    DUMMY_HASH.get_or_init(|| hash("ztasks").unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let hashed = hash("hunter22").unwrap();
+        assert!(hashed.starts_with("$argon2id$"));
+        assert!(verify("hunter22", &hashed));
+        assert!(!verify("hunter23", &hashed));
+    }
+
+    #[test]
+    fn malformed_hash() {
+        assert!(!verify("hunter22", "not a hash"));
+    }
+}
@@ -0,0 +1,118 @@
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// How long an access token stays valid, in seconds.
+pub const ACCESS_TOKEN_LIFETIME: i64 = 15 * 60;
+/// How long a refresh token stays valid, in seconds.
+pub const REFRESH_TOKEN_LIFETIME: i64 = 30 * 24 * 60 * 60;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// What an access token vouches for.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Claims {
+    /// User ID
+    pub sub: i64,
+    /// Session family the token was issued under
+    pub fam: String,
+    /// Expiry as a UNIX timestamp
+    pub exp: i64,
+}
+
+/// Signs and verifies access tokens, which look like `<base64 claims>.<base64 HMAC-SHA256>`.
+pub struct TokenKey {
+    secret: Vec<u8>,
+}
+
+impl TokenKey {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+
+    /// Key that only lives as long as the process, so every token dies on restart.
+    pub fn random() -> Self {
+        let mut secret = vec![0; 32];
+        OsRng.fill_bytes(&mut secret);
+        Self::new(secret)
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret).expect("HMAC accepts keys of any length")
+    }
+
+    pub fn sign(&self, claims: &Claims) -> String {
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap_or_default());
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{payload}.{signature}")
+    }
+
+    /// Returns the claims if the signature matches and the token hasn't expired at `now`.
+    pub fn verify(&self, token: &str, now: i64) -> Option<Claims> {
+        let (payload, signature) = token.split_once('.')?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        let mut mac = self.mac();
+        mac.update(payload.as_bytes());
+        mac.verify_slice(&signature).ok()?;
+
+        let claims: Claims = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).ok()?).ok()?;
+        (claims.exp > now).then_some(claims)
+    }
+}
+
+/// Random opaque token, for refresh tokens, session families and invite links.
+pub fn random_token() -> String {
+    let mut bytes = [0; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Refresh tokens and invites are only ever stored hashed, so a leaked database can't be used to log in
+/// (or join lists).
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> Claims {
+        Claims {
+            sub: 1,
+            fam: "family".to_string(),
+            exp: 1000,
+        }
+    }
+
+    #[test]
+    fn sign_and_verify() {
+        let key = TokenKey::new("secret");
+        let token = key.sign(&claims());
+        assert_eq!(key.verify(&token, 999), Some(claims()));
+        assert_eq!(key.verify(&token, 1000), None);
+    }
+
+    #[test]
+    fn rejects_tampering() {
+        let key = TokenKey::new("secret");
+        let token = key.sign(&claims());
+
+        assert_eq!(TokenKey::new("other").verify(&token, 0), None);
+
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = URL_SAFE_NO_PAD.encode(r#"{"sub":2,"fam":"family","exp":1000}"#);
+        assert_eq!(key.verify(&format!("{forged}.{signature}"), 0), None);
+        assert_eq!(key.verify("garbage", 0), None);
+    }
+}
@@ -0,0 +1,311 @@
+//! Whole-account backups as a single JSON document, for moving to another server. Only the user's own tasks
+//! are in them, not the ones in shared lists.
+
+use crate::{
+    database::{
+        occurrences::Override,
+        revisions::{Kind, Revision, State},
+        tasks::{self, from_timestamp, TaskFields},
+        views::View,
+    },
+    plan,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use utoipa::ToSchema;
+
+pub const FORMAT: &str = "ztasks-backup";
+/// Bumped whenever backups change in a way older servers can't read. Servers read every version up to
+/// their own.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    pub exported: DateTime<Utc>,
+    pub tasks: Vec<Task>,
+    /// Every category the tasks use. Categories only exist on tasks, so this is ignored when importing.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    #[serde(default)]
+    pub history: Vec<Entry>,
+    #[serde(default)]
+    pub settings: Settings,
+}
+
+/// A task along with what hangs off of it. IDs are the ones from the server it came from.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Task {
+    pub id: i64,
+    pub uid: String,
+    pub title: String,
+    #[serde(default)]
+    pub notes: String,
+    pub category: Option<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub due: Option<DateTime<Utc>>,
+    pub completed: Option<DateTime<Utc>>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    pub recurrence: Option<String>,
+    pub time_zone: String,
+    pub parent_id: Option<i64>,
+    #[serde(default)]
+    pub blocked_by: Vec<i64>,
+    /// Changes to single occurrences of a recurring task
+    #[serde(default)]
+    pub occurrences: Vec<Override>,
+}
+
+impl Task {
+    pub fn of(task: tasks::Task, blocked_by: Vec<i64>, occurrences: Vec<Override>) -> Self {
+        Self {
+            id: task.id,
+            uid: task.uid,
+            title: task.title,
+            notes: task.notes,
+            category: task.category,
+            start: task.start,
+            due: task.due,
+            completed: task.completed,
+            created: task.created,
+            updated: task.updated,
+            recurrence: task.recurrence,
+            time_zone: task.time_zone,
+            parent_id: task.parent_id,
+            blocked_by,
+            occurrences,
+        }
+    }
+
+    pub fn fields(&self) -> TaskFields {
+        TaskFields {
+            title: self.title.clone(),
+            notes: self.notes.clone(),
+            category: self.category.clone(),
+            start: self.start,
+            due: self.due,
+            recurrence: self.recurrence.clone(),
+            time_zone: self.time_zone.clone(),
+        }
+    }
+}
+
+/// One revision of a task, which might not be around anymore.
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Entry {
+    pub task_id: i64,
+    pub kind: Kind,
+    pub time: DateTime<Utc>,
+    pub state: State,
+}
+
+impl From<Revision> for Entry {
+    fn from(revision: Revision) -> Self {
+        Self {
+            task_id: revision.task_id,
+            kind: revision.kind,
+            time: from_timestamp(revision.time),
+            state: revision.state,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    #[serde(default)]
+    pub views: Vec<View>,
+}
+
+/// Just enough to tell whether the rest can be read, whatever version it is.
+#[derive(Deserialize)]
+struct Header {
+    format: Option<String>,
+    version: Option<u32>,
+}
+
+impl Backup {
+    pub fn new(
+        exported: DateTime<Utc>,
+        tasks: Vec<Task>,
+        history: Vec<Entry>,
+        views: Vec<View>,
+    ) -> Self {
+        let mut categories: Vec<_> = tasks
+            .iter()
+            .filter_map(|task| task.category.clone())
+            .collect();
+        categories.sort();
+        categories.dedup();
+
+        Self {
+            format: FORMAT.to_string(),
+            version: VERSION,
+            exported,
+            tasks,
+            categories,
+            history,
+            settings: Settings { views },
+        }
+    }
+
+    /// Reads a backup and makes sure its tasks fit together. The fields of each task still have to be
+    /// checked like any other task's.
+    pub fn read(json: &[u8]) -> Result<Self, String> {
+        let header: Header =
+            serde_json::from_slice(json).map_err(|error| format!("Invalid backup: {error}"))?;
+        if header.format.as_deref() != Some(FORMAT) {
+            return Err("Not a ZTasks backup.".to_string());
+        }
+        match header.version {
+            Some(version) if (1..=VERSION).contains(&version) => {}
+            Some(version) => {
+                return Err(format!(
+                    "Backups from version {version} can't be read, only up to version {VERSION}."
+                ))
+            }
+            None => return Err("The backup doesn't say what version it is.".to_string()),
+        }
+
+        let backup: Self =
+            serde_json::from_slice(json).map_err(|error| format!("Invalid backup: {error}"))?;
+        backup.check()?;
+        Ok(backup)
+    }
+
+    fn check(&self) -> Result<(), String> {
+        let mut ids = HashSet::new();
+        let mut uids = HashSet::new();
+        for task in &self.tasks {
+            if !ids.insert(task.id) {
+                return Err(format!("Task {} is in the backup twice.", task.id));
+            }
+            if !uids.insert(task.uid.as_str()) {
+                return Err(format!("More than one task has the UID \"{}\".", task.uid));
+            }
+        }
+
+        // Same as for tasks that are already here, nothing can end up waiting on itself
+        let mut edges = Vec::new();
+        for task in &self.tasks {
+            if let Some(parent_id) = task.parent_id {
+                if !ids.contains(&parent_id) {
+                    return Err(format!(
+                        "Task {} is a subtask of task {parent_id}, which isn't in the backup.",
+                        task.id
+                    ));
+                }
+                if plan::creates_cycle(&edges, parent_id, task.id) {
+                    return Err(format!("Task {} ends up waiting on itself.", task.id));
+                }
+                edges.push((parent_id, task.id));
+            }
+
+            for &blocker_id in &task.blocked_by {
+                if !ids.contains(&blocker_id) {
+                    return Err(format!(
+                        "Task {} is blocked by task {blocker_id}, which isn't in the backup.",
+                        task.id
+                    ));
+                }
+                if plan::creates_cycle(&edges, task.id, blocker_id) {
+                    return Err(format!("Task {} ends up waiting on itself.", task.id));
+                }
+                edges.push((task.id, blocker_id));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn backup(tasks: Value) -> Vec<u8> {
+        json!({
+            "format": "ztasks-backup",
+            "version": 1,
+            "exported": "2023-03-01T00:00:00Z",
+            "tasks": tasks,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn task(id: i64, parent_id: Option<i64>, blocked_by: &[i64]) -> Value {
+        json!({
+            "id": id,
+            "uid": format!("{id}@ztasks"),
+            "title": format!("Task {id}"),
+            "category": null,
+            "start": null,
+            "due": null,
+            "completed": null,
+            "created": "2023-03-01T00:00:00Z",
+            "updated": "2023-03-01T00:00:00Z",
+            "recurrence": null,
+            "time_zone": "UTC",
+            "parent_id": parent_id,
+            "blocked_by": blocked_by,
+        })
+    }
+
+    #[test]
+    fn reading() {
+        let tasks = json!([
+            task(1, None, &[]),
+            task(2, Some(1), &[]),
+            task(3, None, &[2])
+        ]);
+        assert_eq!(Backup::read(&backup(tasks)).unwrap().tasks.len(), 3);
+
+        let errors = [
+            (b"[]".to_vec(), "Invalid backup"),
+            (
+                br#"{ "format": "zip", "version": 1 }"#.to_vec(),
+                "Not a ZTasks backup.",
+            ),
+            (
+                br#"{ "format": "ztasks-backup", "version": 2, "tasks": [], "new": true }"#
+                    .to_vec(),
+                "Backups from version 2 can't be read, only up to version 1.",
+            ),
+            (
+                br#"{ "format": "ztasks-backup", "version": 1, "tasks": [], "new": true }"#
+                    .to_vec(),
+                "Invalid backup: unknown field `new`",
+            ),
+            (
+                backup(json!([task(1, None, &[]), task(1, None, &[])])),
+                "Task 1 is in the backup twice.",
+            ),
+            (
+                backup(json!([task(1, Some(3), &[])])),
+                "Task 1 is a subtask of task 3, which isn't in the backup.",
+            ),
+            (
+                backup(json!([task(1, None, &[2])])),
+                "Task 1 is blocked by task 2, which isn't in the backup.",
+            ),
+            (
+                // 1 waits on its subtask 2, which waits on 1
+                backup(json!([task(1, None, &[]), task(2, Some(1), &[1])])),
+                "Task 2 ends up waiting on itself.",
+            ),
+        ];
+
+        for (json, error) in errors {
+            let result = Backup::read(&json).unwrap_err();
+            assert!(result.starts_with(error), "{result}");
+        }
+    }
+}
@@ -0,0 +1,867 @@
+//! Just enough of RFC 5545 to move tasks in and out of other calendar apps. Date ranges become VEVENTs,
+//! everything else becomes a VTODO, and changes to single occurrences are written as EXDATEs or
+//! components with a RECURRENCE-ID.
+
+use super::{recurrence::Rule, repeat};
+use crate::database::{
+    occurrences::Override,
+    tasks::{Task, TaskFields},
+};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+// Lines longer than this (in bytes, not counting the line break) get folded
+const MAX_LINE_LENGTH: usize = 75;
+
+/// Start and due/end of a component
+type Times = (Option<DateTime<Utc>>, Option<DateTime<Utc>>);
+
+/// `NAME;PARAM=VALUE:VALUE`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    pub value: String,
+}
+
+impl Property {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// `BEGIN:NAME` ... `END:NAME`
+#[derive(Debug, Clone, Default)]
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub children: Vec<Component>,
+}
+
+impl Component {
+    pub fn get(&self, name: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .find(|property| property.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Property> {
+        self.properties
+            .iter()
+            .filter(move |property| property.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Every VTODO and VEVENT, however deeply nested.
+    fn tasks(&self) -> Vec<&Component> {
+        let mut found = Vec::new();
+
+        for child in &self.children {
+            match child.name.as_str() {
+                "VTODO" | "VEVENT" => found.push(child),
+                _ => found.extend(child.tasks()),
+            }
+        }
+
+        found
+    }
+}
+
+/// Parses a whole .ics file into its top level components (usually a single VCALENDAR).
+pub fn parse(input: &str) -> Result<Vec<Component>, String> {
+    let mut stack = vec![Component::default()];
+
+    for (index, line) in unfold(input).iter().enumerate() {
+        let property = parse_line(line).map_err(|error| format!("Line {}: {error}", index + 1))?;
+
+        match property.name.as_str() {
+            "BEGIN" => stack.push(Component {
+                name: property.value.to_ascii_uppercase(),
+                ..Default::default()
+            }),
+            "END" => {
+                let component = stack
+                    .pop()
+                    .filter(|component| component.name.eq_ignore_ascii_case(&property.value))
+                    .ok_or_else(|| format!("Unexpected END:{}.", property.value))?;
+                stack
+                    .last_mut()
+                    .ok_or_else(|| format!("Unexpected END:{}.", property.value))?
+                    .children
+                    .push(component);
+            }
+            _ if stack.len() > 1 => {
+                if let Some(component) = stack.last_mut() {
+                    component.properties.push(property);
+                }
+            }
+            _ => return Err(format!("{} is outside of any component.", property.name)),
+        }
+    }
+
+    match stack.pop() {
+        Some(root) if stack.is_empty() => Ok(root.children),
+        _ => Err("Missing END.".to_string()),
+    }
+}
+
+/// Joins folded lines back together.
+fn unfold(input: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in input.lines() {
+        match (
+            line.strip_prefix(' ').or_else(|| line.strip_prefix('\t')),
+            lines.last_mut(),
+        ) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.trim().is_empty() => (),
+            _ => lines.push(line.to_string()),
+        }
+    }
+
+    lines
+}
+
+fn parse_line(line: &str) -> Result<Property, String> {
+    let mut in_quotes = false;
+    let mut split = None;
+
+    for (index, c) in line.char_indices() {
+        match c {
+            '"' => in_quotes = !in_quotes,
+            ':' if !in_quotes => {
+                split = Some(index);
+                break;
+            }
+            _ => (),
+        }
+    }
+
+    let split = split.ok_or_else(|| "Missing \":\".".to_string())?;
+    let (head, value) = (&line[..split], &line[split + 1..]);
+    let mut parts = head.split(';');
+    let name = parts.next().unwrap_or_default().trim().to_ascii_uppercase();
+
+    if name.is_empty() {
+        return Err("Missing property name.".to_string());
+    }
+
+    let params = parts
+        .map(|param| {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            (
+                key.trim().to_ascii_uppercase(),
+                value.trim_matches('"').to_string(),
+            )
+        })
+        .collect();
+
+    Ok(Property {
+        name,
+        params,
+        value: value.to_string(),
+    })
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+pub fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => result.push('\n'),
+                Some(other) => result.push(other),
+                None => result.push('\\'),
+            },
+            c => result.push(c),
+        }
+    }
+
+    result
+}
+
+/// Splits a list value on commas that aren't escaped.
+fn split_list(text: &str) -> Vec<String> {
+    let mut items = vec![String::new()];
+    let mut escaped = false;
+
+    for c in text.chars() {
+        match c {
+            ',' if !escaped => items.push(String::new()),
+            c => {
+                escaped = c == '\\' && !escaped;
+                items.last_mut().unwrap().push(c);
+            }
+        }
+    }
+
+    items.iter().map(|item| unescape(item.trim())).collect()
+}
+
+/// Accepts `20230301`, `20230301T090000Z` and `20230301T090000` (local to `TZID`, or UTC without one).
+pub fn parse_time(value: &str, tzid: Option<&str>) -> Result<DateTime<Utc>, String> {
+    let value = value.trim();
+    let invalid = || format!("Invalid date \"{value}\".");
+    let tz: Tz = tzid.and_then(|tzid| tzid.parse().ok()).unwrap_or(Tz::UTC);
+
+    let local = match value.len() {
+        8 => NaiveDate::parse_from_str(value, "%Y%m%d")
+            .map_err(|_| invalid())?
+            .and_hms_opt(0, 0, 0)
+            .ok_or_else(invalid)?,
+        _ => match value.strip_suffix('Z') {
+            Some(utc) => {
+                return NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+                    .map(|time| Utc.from_utc_datetime(&time))
+                    .map_err(|_| invalid())
+            }
+            None => NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").map_err(|_| invalid())?,
+        },
+    };
+
+    tz.from_local_datetime(&local)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+        .ok_or_else(invalid)
+}
+
+fn property_time(property: &Property) -> Result<DateTime<Utc>, String> {
+    parse_time(&property.value, property.param("TZID"))
+}
+
+/// Only the simple `P1W`, `P1D`, `PT1H30M` and `P1DT12H` kind.
+fn parse_duration(value: &str) -> Result<Duration, String> {
+    let invalid = || format!("Invalid duration \"{value}\".");
+    let (negative, rest) = match value.trim().strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.trim().trim_start_matches('+')),
+    };
+    let rest = rest.strip_prefix('P').ok_or_else(invalid)?;
+
+    let mut total = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in rest.chars() {
+        match c {
+            'T' => in_time = true,
+            '0'..='9' => number.push(c),
+            unit => {
+                let amount: i64 = number.parse().map_err(|_| invalid())?;
+                number.clear();
+                let part = match (unit, in_time) {
+                    ('W', false) => Duration::try_weeks(amount),
+                    ('D', false) => Duration::try_days(amount),
+                    ('H', true) => Duration::try_hours(amount),
+                    ('M', true) => Duration::try_minutes(amount),
+                    ('S', true) => Duration::try_seconds(amount),
+                    _ => None,
+                };
+                total = part
+                    .and_then(|part| total.checked_add(&part))
+                    .ok_or_else(invalid)?;
+            }
+        }
+    }
+
+    match number.is_empty() {
+        true if negative => Ok(-total),
+        true => Ok(total),
+        false => Err(invalid()),
+    }
+}
+
+/// Accumulates content lines, folding them as it goes.
+struct Writer {
+    out: String,
+}
+
+impl Writer {
+    fn line(&mut self, name: &str, value: &str) {
+        let line = format!("{name}:{value}");
+        let mut width = 0;
+
+        for c in line.chars() {
+            if width + c.len_utf8() > MAX_LINE_LENGTH {
+                self.out.push_str("\r\n ");
+                width = 1;
+            }
+            self.out.push(c);
+            width += c.len_utf8();
+        }
+
+        self.out.push_str("\r\n");
+    }
+
+    fn text(&mut self, name: &str, text: &str) {
+        self.line(name, &escape(text));
+    }
+
+    /// UTC unless the series repeats in some other time zone, since RRULEs expand in local time.
+    fn time(&mut self, name: &str, time: DateTime<Utc>, tz: Option<Tz>) {
+        match tz {
+            Some(tz) if tz != Tz::UTC => self.line(
+                &format!("{name};TZID={}", tz.name()),
+                &time.with_timezone(&tz).format("%Y%m%dT%H%M%S").to_string(),
+            ),
+            _ => self.line(name, &time.format("%Y%m%dT%H%M%SZ").to_string()),
+        }
+    }
+}
+
+/// Writes a whole VCALENDAR with every task and its changed occurrences.
+pub fn export(entries: &[(Task, Vec<Override>)], now: DateTime<Utc>) -> String {
+    let mut writer = Writer { out: String::new() };
+    writer.line("BEGIN", "VCALENDAR");
+    writer.line("VERSION", "2.0");
+    writer.line("PRODID", "-//ZTasks//ZTasks//EN");
+
+    for (task, overrides) in entries {
+        write_task(&mut writer, task, overrides, now);
+    }
+
+    writer.line("END", "VCALENDAR");
+    writer.out
+}
+
+fn component_name(task: &Task) -> &'static str {
+    match (task.start, task.due) {
+        (Some(_), Some(_)) => "VEVENT",
+        _ => "VTODO",
+    }
+}
+
+/// Writes a VCALENDAR with just one task, always as a VTODO, for CalDAV clients. Stamped with when the task
+/// last changed, so the same task always comes out the same.
+pub fn export_todo(task: &Task, overrides: &[Override]) -> String {
+    let mut writer = Writer { out: String::new() };
+    writer.line("BEGIN", "VCALENDAR");
+    writer.line("VERSION", "2.0");
+    writer.line("PRODID", "-//ZTasks//ZTasks//EN");
+    write_component(&mut writer, "VTODO", task, overrides, task.updated);
+    writer.line("END", "VCALENDAR");
+    writer.out
+}
+
+fn write_task(writer: &mut Writer, task: &Task, overrides: &[Override], now: DateTime<Utc>) {
+    write_component(writer, component_name(task), task, overrides, now);
+}
+
+fn write_component(
+    writer: &mut Writer,
+    name: &str,
+    task: &Task,
+    overrides: &[Override],
+    now: DateTime<Utc>,
+) {
+    let tz = task
+        .recurrence
+        .as_ref()
+        .and_then(|_| task.time_zone.parse::<Tz>().ok());
+
+    writer.line("BEGIN", name);
+    writer.text("UID", &task.uid);
+    writer.time("DTSTAMP", now, None);
+    writer.time("CREATED", task.created, None);
+    writer.time("LAST-MODIFIED", task.updated, None);
+    write_details(
+        writer,
+        name,
+        &task.title,
+        &task.notes,
+        task.start,
+        task.due,
+        task.completed,
+        tz,
+    );
+
+    if let Some(category) = &task.category {
+        writer.text("CATEGORIES", category);
+    }
+
+    if let Some(recurrence) = &task.recurrence {
+        writer.line("RRULE", recurrence);
+
+        for skipped in overrides.iter().filter(|changes| changes.cancelled) {
+            writer.time("EXDATE", skipped.recurrence_id, tz);
+        }
+    }
+
+    writer.line("END", name);
+
+    let Some(anchor) = super::anchor(task).filter(|_| task.recurrence.is_some()) else {
+        return;
+    };
+
+    for changes in overrides.iter().filter(|changes| !changes.cancelled) {
+        let occurrence = repeat(task, anchor, changes.recurrence_id, Some(changes));
+
+        writer.line("BEGIN", name);
+        writer.text("UID", &task.uid);
+        writer.time("DTSTAMP", now, None);
+        writer.time("RECURRENCE-ID", changes.recurrence_id, tz);
+        write_details(
+            writer,
+            name,
+            &occurrence.title,
+            &occurrence.notes,
+            occurrence.start,
+            occurrence.due,
+            occurrence.completed,
+            tz,
+        );
+        writer.line("END", name);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn write_details(
+    writer: &mut Writer,
+    name: &str,
+    title: &str,
+    notes: &str,
+    start: Option<DateTime<Utc>>,
+    due: Option<DateTime<Utc>>,
+    completed: Option<DateTime<Utc>>,
+    tz: Option<Tz>,
+) {
+    writer.text("SUMMARY", title);
+
+    if !notes.is_empty() {
+        writer.text("DESCRIPTION", notes);
+    }
+
+    if let Some(start) = start {
+        writer.time("DTSTART", start, tz);
+    }
+
+    if let Some(due) = due {
+        let property = if name == "VEVENT" { "DTEND" } else { "DUE" };
+        writer.time(property, due, tz);
+    }
+
+    match (name, completed) {
+        ("VTODO", Some(completed)) => {
+            writer.time("COMPLETED", completed, None);
+            writer.line("STATUS", "COMPLETED");
+        }
+        ("VTODO", None) => writer.line("STATUS", "NEEDS-ACTION"),
+        // Events can't be completed, so this only round trips through ZTasks
+        (_, Some(completed)) => writer.time("X-ZTASKS-COMPLETED", completed, None),
+        (_, None) => (),
+    }
+}
+
+/// A task read out of an .ics file.
+#[derive(Debug)]
+pub struct Imported {
+    pub uid: String,
+    pub fields: TaskFields,
+    pub completed: Option<DateTime<Utc>>,
+    pub overrides: Vec<Override>,
+}
+
+/// Something in the file that couldn't be turned into a task.
+#[derive(Debug, serde::Serialize, utoipa::ToSchema)]
+pub struct Skipped {
+    pub uid: Option<String>,
+    pub reason: String,
+}
+
+/// Reads every VTODO and VEVENT in the file. Broken entries are skipped rather than failing the whole import.
+pub fn import(input: &str, now: DateTime<Utc>) -> Result<(Vec<Imported>, Vec<Skipped>), String> {
+    let calendars = parse(input)?;
+
+    if calendars.is_empty() {
+        return Err("No calendar found.".to_string());
+    }
+
+    let components: Vec<_> = calendars
+        .iter()
+        .flat_map(|calendar| calendar.tasks())
+        .collect();
+
+    let mut imported: Vec<Imported> = Vec::new();
+    let mut skipped = Vec::new();
+    let mut occurrences = Vec::new();
+
+    for component in components {
+        let uid = component.get("UID").map(|uid| unescape(&uid.value));
+
+        // Occurrences get attached to their series once every series has been read
+        if component.get("RECURRENCE-ID").is_some() {
+            occurrences.push((uid, component));
+            continue;
+        }
+
+        match read_task(component, now) {
+            Ok(task) => match imported.iter_mut().find(|other| other.uid == task.uid) {
+                Some(other) => *other = task,
+                None => imported.push(task),
+            },
+            Err(reason) => skipped.push(Skipped { uid, reason }),
+        }
+    }
+
+    for (uid, component) in occurrences {
+        let series = imported
+            .iter_mut()
+            .find(|task| Some(&task.uid) == uid.as_ref() && task.fields.recurrence.is_some());
+
+        let result = match series {
+            Some(series) => read_occurrence(component, series, now).map(|changes| {
+                series
+                    .overrides
+                    .retain(|other| other.recurrence_id != changes.recurrence_id);
+                series.overrides.push(changes);
+            }),
+            None => Err("No recurring task with this UID.".to_string()),
+        };
+
+        if let Err(reason) = result {
+            skipped.push(Skipped { uid, reason });
+        }
+    }
+
+    Ok((imported, skipped))
+}
+
+fn read_task(component: &Component, now: DateTime<Utc>) -> Result<Imported, String> {
+    let uid = component
+        .get("UID")
+        .map(|uid| unescape(&uid.value))
+        .filter(|uid| !uid.trim().is_empty())
+        .ok_or_else(|| "Missing UID.".to_string())?;
+    let (start, due) = read_times(component)?;
+
+    // Repeats in whatever zone DTSTART is in, as long as it's an IANA name
+    let time_zone = component
+        .get("DTSTART")
+        .or_else(|| component.get("DUE"))
+        .and_then(|property| property.param("TZID"))
+        .filter(|tzid| tzid.parse::<Tz>().is_ok())
+        .unwrap_or("UTC")
+        .to_string();
+
+    let recurrence = match component.get("RRULE") {
+        Some(rule) => Some(rule.value.parse::<Rule>()?.to_string()),
+        None => None,
+    };
+
+    let mut overrides = Vec::new();
+
+    for exdate in component.all("EXDATE") {
+        for value in exdate.value.split(',') {
+            let mut skipped = Override::new(parse_time(value, exdate.param("TZID"))?);
+            skipped.cancelled = true;
+            overrides.push(skipped);
+        }
+    }
+
+    Ok(Imported {
+        uid,
+        fields: TaskFields {
+            title: read_title(component),
+            notes: read_notes(component),
+            category: component
+                .get("CATEGORIES")
+                .and_then(|categories| split_list(&categories.value).into_iter().next()),
+            start,
+            due,
+            recurrence,
+            time_zone,
+        },
+        completed: read_completed(component, now)?,
+        overrides,
+    })
+}
+
+fn read_occurrence(
+    component: &Component,
+    series: &Imported,
+    now: DateTime<Utc>,
+) -> Result<Override, String> {
+    let recurrence_id = component
+        .get("RECURRENCE-ID")
+        .map(property_time)
+        .transpose()?
+        .ok_or_else(|| "Missing RECURRENCE-ID.".to_string())?;
+    let mut changes = Override::new(recurrence_id);
+
+    let status = component
+        .get("STATUS")
+        .map(|status| status.value.to_ascii_uppercase());
+    if status.as_deref() == Some("CANCELLED") {
+        changes.cancelled = true;
+        return Ok(changes);
+    }
+
+    let title = read_title(component);
+    let notes = read_notes(component);
+    changes.title = (title != series.fields.title).then_some(title);
+    changes.notes = (notes != series.fields.notes).then_some(notes);
+
+    // Only keep times that actually moved away from where the series would put them
+    let anchor = series.fields.start.or(series.fields.due);
+    let (start, due) = read_times(component)?;
+    if let Some(anchor) = anchor {
+        let offset = recurrence_id - anchor;
+        changes.start =
+            start.filter(|start| Some(*start) != series.fields.start.map(|time| time + offset));
+        changes.due = due.filter(|due| Some(*due) != series.fields.due.map(|time| time + offset));
+    }
+
+    changes.completed = read_completed(component, now)?;
+    Ok(changes)
+}
+
+fn read_title(component: &Component) -> String {
+    component
+        .get("SUMMARY")
+        .map(|summary| unescape(&summary.value).trim().to_string())
+        .filter(|summary| !summary.is_empty())
+        .unwrap_or_else(|| "Untitled".to_string())
+}
+
+fn read_notes(component: &Component) -> String {
+    component
+        .get("DESCRIPTION")
+        .map(|description| unescape(&description.value))
+        .unwrap_or_default()
+}
+
+fn read_times(component: &Component) -> Result<Times, String> {
+    let start = component.get("DTSTART").map(property_time).transpose()?;
+    let end = component
+        .get("DUE")
+        .or_else(|| component.get("DTEND"))
+        .map(property_time)
+        .transpose()?;
+    let duration = component
+        .get("DURATION")
+        .map(|duration| parse_duration(&duration.value))
+        .transpose()?;
+
+    let end = match (start, end, duration) {
+        (_, Some(end), _) => Some(end),
+        (Some(start), None, Some(duration)) => Some(
+            start
+                .checked_add_signed(duration)
+                .ok_or_else(|| "Invalid DURATION.".to_string())?,
+        ),
+        // An event with only a start is a single moment, which ZTasks calls a due date
+        (Some(start), None, None) if component.name == "VEVENT" => {
+            return Ok((None, Some(start)));
+        }
+        _ => None,
+    };
+
+    if let (Some(start), Some(end)) = (start, end) {
+        if start > end {
+            return Err("Ends before it starts.".to_string());
+        }
+    }
+
+    Ok((start, end))
+}
+
+fn read_completed(
+    component: &Component,
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, String> {
+    if let Some(completed) = component
+        .get("COMPLETED")
+        .or_else(|| component.get("X-ZTASKS-COMPLETED"))
+    {
+        return property_time(completed).map(Some);
+    }
+
+    let status = component
+        .get("STATUS")
+        .map(|status| status.value.to_ascii_uppercase());
+    Ok((status.as_deref() == Some("COMPLETED")).then_some(now))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tasks::from_timestamp;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn task(id: i64, title: &str) -> Task {
+        Task {
+            id,
+            title: title.to_string(),
+            notes: String::new(),
+            category: None,
+            start: None,
+            due: None,
+            completed: None,
+            created: from_timestamp(0),
+            updated: from_timestamp(0),
+            recurrence: None,
+            time_zone: "UTC".to_string(),
+            uid: format!("task-{id}@ztasks"),
+            parent_id: None,
+            list_id: None,
+        }
+    }
+
+    #[test]
+    fn escaping() {
+        let text = "a, b; c\\d\nnew line";
+        assert_eq!(escape(text), "a\\, b\\; c\\\\d\\nnew line");
+        assert_eq!(unescape(&escape(text)), text);
+        assert_eq!(split_list("work,home\\,garden"), ["work", "home,garden"]);
+    }
+
+    #[test]
+    fn folds_long_lines() {
+        let mut writer = Writer { out: String::new() };
+        writer.text("SUMMARY", &"ä".repeat(60));
+
+        for line in writer.out.split("\r\n") {
+            assert!(line.len() <= MAX_LINE_LENGTH, "{line}");
+        }
+        assert_eq!(
+            unfold(&writer.out)[0],
+            format!("SUMMARY:{}", "ä".repeat(60))
+        );
+    }
+
+    #[test]
+    fn parses_params_and_nesting() {
+        let input = "BEGIN:VCALENDAR\r\nBEGIN:VTODO\r\nUID:1\r\nDTSTART;TZID=\"America/New_York\":20230306T090000\r\nSUMMARY:Long\r\n  title\r\nEND:VTODO\r\nEND:VCALENDAR\r\n";
+        let calendars = parse(input).unwrap();
+        let todo = &calendars[0].children[0];
+
+        assert_eq!(todo.get("SUMMARY").unwrap().value, "Long title");
+        let start = todo.get("DTSTART").unwrap();
+        assert_eq!(start.param("tzid"), Some("America/New_York"));
+        assert_eq!(property_time(start).unwrap(), utc("2023-03-06T14:00:00Z"));
+
+        assert!(parse("BEGIN:VCALENDAR\r\nEND:VTODO\r\n").is_err());
+        assert!(parse("BEGIN:VCALENDAR\r\n").is_err());
+        assert!(parse("SUMMARY:Loose\r\n").is_err());
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("PT1H30M").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_duration("P1DT12H").unwrap(), Duration::hours(36));
+        assert_eq!(parse_duration("-P1W").unwrap(), -Duration::weeks(1));
+        assert!(parse_duration("P1H").is_err());
+        assert!(parse_duration("1D").is_err());
+        assert!(parse_duration("P99999999999999W").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut todo = task(1, "Pay rent, on time");
+        todo.due = Some(utc("2023-03-01T09:00:00Z"));
+        todo.completed = Some(utc("2023-02-28T20:00:00Z"));
+        todo.category = Some("home".to_string());
+        todo.notes = "Landlord\ntakes checks".to_string();
+
+        let mut standup = task(2, "Standup");
+        standup.start = Some(utc("2023-03-06T14:00:00Z"));
+        standup.due = Some(utc("2023-03-06T14:15:00Z"));
+        standup.recurrence = Some("FREQ=WEEKLY;BYDAY=MO,WE".to_string());
+        standup.time_zone = "America/New_York".to_string();
+
+        let mut skipped = Override::new(utc("2023-03-08T14:00:00Z"));
+        skipped.cancelled = true;
+        let mut long = Override::new(utc("2023-03-13T13:00:00Z"));
+        long.title = Some("Long standup".to_string());
+        long.due = Some(utc("2023-03-13T14:00:00Z"));
+
+        let now = utc("2023-03-01T00:00:00Z");
+        let ics = export(&[(todo, Vec::new()), (standup, vec![skipped, long])], now);
+        assert!(ics.contains("BEGIN:VTODO\r\n"));
+        assert!(ics.contains("DTSTART;TZID=America/New_York:20230306T090000\r\n"));
+        assert!(ics.contains("EXDATE;TZID=America/New_York:20230308T090000\r\n"));
+        assert!(ics.contains("RECURRENCE-ID;TZID=America/New_York:20230313T090000\r\n"));
+
+        let (imported, skipped) = import(&ics, now).unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(imported.len(), 2);
+
+        let todo = &imported[0];
+        assert_eq!(todo.uid, "task-1@ztasks");
+        assert_eq!(todo.fields.title, "Pay rent, on time");
+        assert_eq!(todo.fields.notes, "Landlord\ntakes checks");
+        assert_eq!(todo.fields.category.as_deref(), Some("home"));
+        assert_eq!(todo.fields.due, Some(utc("2023-03-01T09:00:00Z")));
+        assert_eq!(todo.completed, Some(utc("2023-02-28T20:00:00Z")));
+
+        let standup = &imported[1];
+        assert_eq!(standup.fields.start, Some(utc("2023-03-06T14:00:00Z")));
+        assert_eq!(standup.fields.due, Some(utc("2023-03-06T14:15:00Z")));
+        assert_eq!(
+            standup.fields.recurrence.as_deref(),
+            Some("FREQ=WEEKLY;BYDAY=MO,WE")
+        );
+        assert_eq!(standup.fields.time_zone, "America/New_York");
+        assert_eq!(standup.overrides.len(), 2);
+        assert!(standup.overrides[0].cancelled);
+        assert_eq!(standup.overrides[1].title.as_deref(), Some("Long standup"));
+        assert_eq!(standup.overrides[1].start, None);
+        assert_eq!(standup.overrides[1].due, Some(utc("2023-03-13T14:00:00Z")));
+    }
+
+    #[test]
+    fn imports_foreign_events() {
+        let input = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VTIMEZONE\r
+TZID:Europe/Berlin\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+UID:abc@example.com\r
+SUMMARY:Dentist\r
+DTSTART;TZID=Europe/Berlin:20230410T100000\r
+DURATION:PT45M\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:allday@example.com\r
+SUMMARY:Holiday\r
+DTSTART;VALUE=DATE:20230501\r
+END:VEVENT\r
+BEGIN:VTODO\r
+SUMMARY:No UID\r
+END:VTODO\r
+BEGIN:VTODO\r
+UID:yearly@example.com\r
+DUE:20230101T000000Z\r
+RRULE:FREQ=YEARLY\r
+END:VTODO\r
+END:VCALENDAR\r
+";
+        let (imported, skipped) = import(input, utc("2023-01-01T00:00:00Z")).unwrap();
+
+        assert_eq!(imported.len(), 2);
+        assert_eq!(imported[0].fields.start, Some(utc("2023-04-10T08:00:00Z")));
+        assert_eq!(imported[0].fields.due, Some(utc("2023-04-10T08:45:00Z")));
+        assert_eq!(imported[1].fields.start, None);
+        assert_eq!(imported[1].fields.due, Some(utc("2023-05-01T00:00:00Z")));
+
+        assert_eq!(skipped.len(), 2);
+        assert_eq!(skipped[0].reason, "Missing UID.");
+        assert_eq!(skipped[1].uid.as_deref(), Some("yearly@example.com"));
+    }
+}
@@ -0,0 +1,339 @@
+pub mod ics;
+pub mod recurrence;
+
+use crate::database::{occurrences::Override, tasks::Task};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Months, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use recurrence::Rule;
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// How finely the calendar view is split up.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Grouping {
+    Day,
+    Week,
+    Month,
+}
+
+/// A day, week or month in the client's time zone, along with whatever lands in it.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Bucket {
+    pub label: String,
+    pub start: DateTime<FixedOffset>,
+    pub end: DateTime<FixedOffset>,
+    /// Keys of the occurrences in this bucket
+    pub occurrences: Vec<String>,
+}
+
+impl Bucket {
+    pub fn contains(&self, span: Span) -> bool {
+        span.overlaps(self.start.with_timezone(&Utc), self.end.with_timezone(&Utc))
+    }
+}
+
+/// The stretch of time a task occupies, which is a single instant if it only has a due date.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+impl Span {
+    /// Tasks without any dates don't show up on the calendar.
+    pub fn of(start: Option<DateTime<Utc>>, due: Option<DateTime<Utc>>) -> Option<Self> {
+        match (start, due) {
+            (Some(start), Some(due)) => Some(Self { start, end: due }),
+            (Some(time), None) | (None, Some(time)) => Some(Self {
+                start: time,
+                end: time,
+            }),
+            (None, None) => None,
+        }
+    }
+
+    /// Whether this touches the half-open window `[from, to)`.
+    pub fn overlaps(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> bool {
+        if self.start == self.end {
+            from <= self.start && self.start < to
+        } else {
+            self.start < to && self.end > from
+        }
+    }
+}
+
+/// A task as it shows up on the calendar. Regular tasks have exactly one, recurring tasks have one per repeat.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Occurrence {
+    /// `"<task id>"` for regular tasks, `"<task id>@<recurrence id as UNIX timestamp>"` for repeats
+    pub key: String,
+    pub task_id: i64,
+    /// When this repeat was originally supposed to happen, which is what identifies it
+    pub recurrence_id: Option<DateTime<Utc>>,
+    pub title: String,
+    pub notes: String,
+    pub category: Option<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub due: Option<DateTime<Utc>>,
+    pub completed: Option<DateTime<Utc>>,
+}
+
+impl Occurrence {
+    pub fn span(&self) -> Option<Span> {
+        Span::of(self.start, self.due)
+    }
+}
+
+/// When a recurring task's occurrences are measured from.
+pub fn anchor(task: &Task) -> Option<DateTime<Utc>> {
+    task.start.or(task.due)
+}
+
+/// Every occurrence of `task` that overlaps `[from, to)`, with `overrides` applied to repeats.
+pub fn occurrences(
+    task: &Task,
+    overrides: &[Override],
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Vec<Occurrence> {
+    let single = Occurrence {
+        key: task.id.to_string(),
+        task_id: task.id,
+        recurrence_id: None,
+        title: task.title.clone(),
+        notes: task.notes.clone(),
+        category: task.category.clone(),
+        start: task.start,
+        due: task.due,
+        completed: task.completed,
+    };
+
+    let (Some(rule), Some(anchor)) = (&task.recurrence, anchor(task)) else {
+        return match single.span() {
+            Some(span) if span.overlaps(from, to) => vec![single],
+            _ => Vec::new(),
+        };
+    };
+
+    // Stored rules were validated on the way in, so this is only ever a corrupt database
+    let Ok(rule) = rule.parse::<Rule>() else {
+        return Vec::new();
+    };
+    let tz: Tz = task.time_zone.parse().unwrap_or(Tz::UTC);
+    let length = match (task.start, task.due) {
+        (Some(start), Some(due)) => due - start,
+        _ => Duration::zero(),
+    };
+
+    rule.occurrences(anchor, tz)
+        // Repeats that start before `from` can still be running into the window
+        .take_while(|time| *time < to)
+        .filter(|time| *time + length >= from)
+        .filter_map(|time| {
+            let changes = overrides
+                .iter()
+                .find(|changes| changes.recurrence_id == time);
+
+            match changes {
+                Some(changes) if changes.cancelled => None,
+                _ => Some(repeat(task, anchor, time, changes)),
+            }
+        })
+        .filter(|occurrence| {
+            occurrence
+                .span()
+                .is_some_and(|span| span.overlaps(from, to))
+        })
+        .collect()
+}
+
+/// The earliest a repeat of `task` that's due at or after `after` can be due, or `None` if there won't be any.
+/// Changed repeats can be due anywhere, so this errs on the early side.
+pub fn next_due(
+    task: &Task,
+    overrides: &[Override],
+    after: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let changed = overrides
+        .iter()
+        .filter_map(|changes| changes.due)
+        .filter(|due| *due >= after);
+
+    let repeated = match (&task.recurrence, anchor(task), task.due) {
+        (Some(rule), Some(anchor), Some(due)) => rule.parse::<Rule>().ok().and_then(|rule| {
+            let tz: Tz = task.time_zone.parse().unwrap_or(Tz::UTC);
+            rule.occurrences(anchor, tz)
+                .map_while(|time| due.checked_add_signed(time - anchor))
+                .find(|due| *due >= after)
+        }),
+        _ => None,
+    };
+
+    changed.chain(repeated).min()
+}
+
+/// The repeat of a recurring task that was supposed to happen at `recurrence_id`, with any changes made to it.
+pub fn repeat(
+    task: &Task,
+    anchor: DateTime<Utc>,
+    recurrence_id: DateTime<Utc>,
+    changes: Option<&Override>,
+) -> Occurrence {
+    let offset = recurrence_id - anchor;
+    let mut occurrence = Occurrence {
+        key: format!("{}@{}", task.id, recurrence_id.timestamp()),
+        task_id: task.id,
+        recurrence_id: Some(recurrence_id),
+        title: task.title.clone(),
+        notes: task.notes.clone(),
+        category: task.category.clone(),
+        start: task.start.map(|start| start + offset),
+        due: task.due.map(|due| due + offset),
+        completed: task.completed,
+    };
+
+    if let Some(changes) = changes {
+        occurrence.title = changes.title.clone().unwrap_or(occurrence.title);
+        occurrence.notes = changes.notes.clone().unwrap_or(occurrence.notes);
+        occurrence.start = changes.start.or(occurrence.start);
+        occurrence.due = changes.due.or(occurrence.due);
+        occurrence.completed = changes.completed.or(occurrence.completed);
+    }
+
+    occurrence
+}
+
+/// Splits `[from, to)` into buckets aligned to local midnight in `tz`. Weeks start on Monday.
+pub fn buckets(from: DateTime<Utc>, to: DateTime<Utc>, tz: Tz, grouping: Grouping) -> Vec<Bucket> {
+    let mut buckets = Vec::new();
+    let mut date = align(from.with_timezone(&tz).date_naive(), grouping);
+
+    loop {
+        let start = local_midnight(tz, date);
+
+        if start >= to {
+            break;
+        }
+
+        let next = advance(date, grouping);
+        buckets.push(Bucket {
+            label: label(date, grouping),
+            start: start.with_timezone(&tz).fixed_offset(),
+            end: local_midnight(tz, next).with_timezone(&tz).fixed_offset(),
+            occurrences: Vec::new(),
+        });
+        date = next;
+    }
+
+    buckets
+}
+
+fn align(date: NaiveDate, grouping: Grouping) -> NaiveDate {
+    match grouping {
+        Grouping::Day => date,
+        Grouping::Week => date - Duration::days(date.weekday().num_days_from_monday() as i64),
+        Grouping::Month => date.with_day(1).unwrap_or(date),
+    }
+}
+
+fn advance(date: NaiveDate, grouping: Grouping) -> NaiveDate {
+    match grouping {
+        Grouping::Day => date + Duration::days(1),
+        Grouping::Week => date + Duration::days(7),
+        Grouping::Month => date + Months::new(1),
+    }
+}
+
+fn label(date: NaiveDate, grouping: Grouping) -> String {
+    match grouping {
+        Grouping::Day => date.format("%Y-%m-%d").to_string(),
+        Grouping::Week => {
+            let week = date.iso_week();
+            format!("{}-W{:02}", week.year(), week.week())
+        }
+        Grouping::Month => date.format("%Y-%m").to_string(),
+    }
+}
+
+/// Some zones skip midnight when DST kicks in, in which case the day starts at the first valid hour.
+pub fn local_midnight(tz: Tz, date: NaiveDate) -> DateTime<Utc> {
+    let mut time = date.and_hms_opt(0, 0, 0).unwrap_or_default();
+
+    for _ in 0..24 {
+        if let Some(local) = tz.from_local_datetime(&time).earliest() {
+            return local.with_timezone(&Utc);
+        }
+        time += Duration::hours(1);
+    }
+
+    Utc.from_utc_datetime(&time)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn days_in_time_zone() {
+        let tz: Tz = "America/New_York".parse().unwrap();
+        let buckets = buckets(
+            utc("2023-03-11T12:00:00Z"),
+            utc("2023-03-13T05:00:00Z"),
+            tz,
+            Grouping::Day,
+        );
+
+        let labels: Vec<_> = buckets.iter().map(|bucket| bucket.label.as_str()).collect();
+        assert_eq!(labels, ["2023-03-11", "2023-03-12", "2023-03-13"]);
+
+        // DST starts on the 12th, so that day is only 23 hours long
+        assert_eq!(buckets[1].start.to_rfc3339(), "2023-03-12T00:00:00-05:00");
+        assert_eq!(buckets[1].end.to_rfc3339(), "2023-03-13T00:00:00-04:00");
+    }
+
+    #[test]
+    fn weeks_and_months() {
+        let weeks = buckets(
+            utc("2023-03-01T00:00:00Z"),
+            utc("2023-03-14T00:00:00Z"),
+            Tz::UTC,
+            Grouping::Week,
+        );
+        let labels: Vec<_> = weeks.iter().map(|bucket| bucket.label.as_str()).collect();
+        assert_eq!(labels, ["2023-W09", "2023-W10", "2023-W11"]);
+        assert_eq!(weeks[0].start.to_rfc3339(), "2023-02-27T00:00:00+00:00");
+
+        let months = buckets(
+            utc("2023-01-31T00:00:00Z"),
+            utc("2023-03-01T00:00:00Z"),
+            Tz::UTC,
+            Grouping::Month,
+        );
+        let labels: Vec<_> = months.iter().map(|bucket| bucket.label.as_str()).collect();
+        assert_eq!(labels, ["2023-01", "2023-02"]);
+    }
+
+    #[test]
+    fn span_overlap() {
+        let from = utc("2023-03-01T00:00:00Z");
+        let to = utc("2023-03-02T00:00:00Z");
+
+        let due = Span::of(None, Some(to)).unwrap();
+        assert!(!due.overlaps(from, to));
+        let due = Span::of(None, Some(from)).unwrap();
+        assert!(due.overlaps(from, to));
+
+        // A range ending right at midnight doesn't spill into the next day
+        let range = Span::of(Some(utc("2023-02-28T00:00:00Z")), Some(from)).unwrap();
+        assert!(!range.overlaps(from, to));
+        let range = Span::of(Some(utc("2023-02-28T00:00:00Z")), Some(to)).unwrap();
+        assert!(range.overlaps(from, to));
+
+        assert_eq!(Span::of(None, None), None);
+    }
+}
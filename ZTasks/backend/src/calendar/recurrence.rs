@@ -0,0 +1,557 @@
+//! The subset of RFC 5545 recurrence rules that ZTasks understands: `FREQ` (`DAILY`, `WEEKLY` or
+//! `MONTHLY`), `INTERVAL`, `BYDAY`, `COUNT` and `UNTIL`. Weeks always start on Monday.
+
+use chrono::{
+    DateTime, Datelike, Days, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc,
+    Weekday,
+};
+use chrono_tz::Tz;
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+// Give up on rules that can never match anything (like every 7 days but only on Tuesdays, starting on a Monday)
+const MAX_EMPTY_PERIODS: u32 = 1000;
+/// Anything longer would jump past the end of the calendar within a few repeats.
+pub const MAX_INTERVAL: u32 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+/// `MO`, or with an ordinal like `2MO` (second Monday) or `-1FR` (last Friday) for monthly rules.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByDay {
+    pub ordinal: Option<i8>,
+    pub weekday: Weekday,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<ByDay>,
+    pub count: Option<u32>,
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("RRULE:").unwrap_or(s);
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut by_day = Vec::new();
+        let mut count = None;
+        let mut until = None;
+
+        for part in s.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("Expected KEY=VALUE, got \"{part}\"."))?;
+
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.to_ascii_uppercase().as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        _ => return Err(format!("Unsupported frequency \"{value}\".")),
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| (1..=MAX_INTERVAL).contains(interval))
+                        .ok_or_else(|| {
+                            format!(
+                                "Invalid interval \"{value}\", it has to be 1 to {MAX_INTERVAL}."
+                            )
+                        })?
+                }
+                "BYDAY" => {
+                    by_day = value
+                        .split(',')
+                        .map(parse_by_day)
+                        .collect::<Result<_, _>>()?
+                }
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|count| *count > 0)
+                            .ok_or_else(|| format!("Invalid count \"{value}\"."))?,
+                    )
+                }
+                "UNTIL" => until = Some(parse_until(value)?),
+                "WKST" if value.eq_ignore_ascii_case("MO") => (),
+                _ => return Err(format!("Unsupported rule part \"{key}\".")),
+            }
+        }
+
+        let frequency = frequency.ok_or_else(|| "Missing FREQ.".to_string())?;
+
+        if count.is_some() && until.is_some() {
+            return Err("COUNT and UNTIL can't be used together.".to_string());
+        }
+
+        if frequency != Frequency::Monthly && by_day.iter().any(|day| day.ordinal.is_some()) {
+            return Err("Numbered BYDAY values are only allowed in MONTHLY rules.".to_string());
+        }
+
+        Ok(Self {
+            frequency,
+            interval,
+            by_day,
+            count,
+            until,
+        })
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+        };
+        write!(f, "FREQ={frequency}")?;
+
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+
+        if !self.by_day.is_empty() {
+            let days: Vec<_> = self
+                .by_day
+                .iter()
+                .map(|day| match day.ordinal {
+                    Some(ordinal) => format!("{ordinal}{}", weekday_code(day.weekday)),
+                    None => weekday_code(day.weekday).to_string(),
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={count}")?;
+        }
+
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", until.format("%Y%m%dT%H%M%SZ"))?;
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_by_day(value: &str) -> Result<ByDay, String> {
+    let value = value.trim().to_ascii_uppercase();
+
+    if !value.is_ascii() {
+        return Err(format!("Invalid weekday \"{value}\"."));
+    }
+
+    let split = value.len().saturating_sub(2);
+    let (ordinal, code) = value.split_at(split);
+
+    let weekday = match code {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return Err(format!("Invalid weekday \"{value}\".")),
+    };
+
+    let ordinal = match ordinal {
+        "" => None,
+        ordinal => Some(
+            ordinal
+                .trim_start_matches('+')
+                .parse::<i8>()
+                .ok()
+                .filter(|ordinal| *ordinal != 0 && (-5..=5).contains(ordinal))
+                .ok_or_else(|| format!("Invalid weekday \"{value}\"."))?,
+        ),
+    };
+
+    Ok(ByDay { ordinal, weekday })
+}
+
+fn parse_until(value: &str) -> Result<DateTime<Utc>, String> {
+    let invalid = || format!("Invalid UNTIL \"{value}\".");
+
+    // Date-only values mean the end of that day
+    if value.len() == 8 {
+        let date = NaiveDate::parse_from_str(value, "%Y%m%d").map_err(|_| invalid())?;
+        let end = date.and_hms_opt(23, 59, 59).ok_or_else(invalid)?;
+        return Ok(Utc.from_utc_datetime(&end));
+    }
+
+    let time = value.strip_suffix('Z').unwrap_or(value);
+    NaiveDateTime::parse_from_str(time, "%Y%m%dT%H%M%S")
+        .map(|time| Utc.from_utc_datetime(&time))
+        .map_err(|_| invalid())
+}
+
+fn weekday_code(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "MO",
+        Weekday::Tue => "TU",
+        Weekday::Wed => "WE",
+        Weekday::Thu => "TH",
+        Weekday::Fri => "FR",
+        Weekday::Sat => "SA",
+        Weekday::Sun => "SU",
+    }
+}
+
+impl Rule {
+    /// Lazily walks every occurrence starting from `start`, keeping the same wall clock time in `tz`
+    /// even across DST changes. Infinite unless the rule has a COUNT or UNTIL.
+    pub fn occurrences(&self, start: DateTime<Utc>, tz: Tz) -> Occurrences<'_> {
+        let local = start.with_timezone(&tz).naive_local();
+
+        Occurrences {
+            rule: self,
+            tz,
+            start: local,
+            time: local.time(),
+            period: 0,
+            emitted: 0,
+            empty_periods: 0,
+            pending: VecDeque::new(),
+            done: false,
+        }
+    }
+
+    /// Whether `time` is one of the occurrences of a series starting at `start`.
+    pub fn includes(&self, start: DateTime<Utc>, tz: Tz, time: DateTime<Utc>) -> bool {
+        self.occurrences(start, tz)
+            .take_while(|occurrence| *occurrence <= time)
+            .any(|occurrence| occurrence == time)
+    }
+
+    /// The dates in the `period`th period after the one `start` is in, or `None` once that's past the end
+    /// of the calendar.
+    fn dates_in_period(&self, start: NaiveDate, period: u32) -> Option<Vec<NaiveDate>> {
+        let steps = period.checked_mul(self.interval)?;
+
+        let mut dates: Vec<_> = match self.frequency {
+            Frequency::Daily => {
+                let date = start.checked_add_days(Days::new(steps.into()))?;
+                let allowed = self.by_day.is_empty()
+                    || self.by_day.iter().any(|day| day.weekday == date.weekday());
+                allowed.then_some(date).into_iter().collect()
+            }
+            Frequency::Weekly => {
+                let monday = start
+                    .checked_sub_days(Days::new(start.weekday().num_days_from_monday().into()))?
+                    .checked_add_days(Days::new(u64::from(steps) * 7))?;
+                let weekdays = match self.by_day.is_empty() {
+                    true => vec![start.weekday()],
+                    false => self.by_day.iter().map(|day| day.weekday).collect(),
+                };
+
+                weekdays
+                    .into_iter()
+                    .map(|weekday| {
+                        monday.checked_add_days(Days::new(weekday.num_days_from_monday().into()))
+                    })
+                    .collect::<Option<_>>()?
+            }
+            Frequency::Monthly => {
+                let first = start
+                    .with_day(1)
+                    .and_then(|first| first.checked_add_months(Months::new(steps)))?;
+
+                match self.by_day.is_empty() {
+                    // Months that are too short for the start day are skipped, like the RFC says
+                    true => first.with_day(start.day()).into_iter().collect(),
+                    false => self
+                        .by_day
+                        .iter()
+                        .flat_map(|day| weekdays_in_month(first, *day))
+                        .collect(),
+                }
+            }
+        };
+
+        dates.sort();
+        dates.dedup();
+        Some(dates)
+    }
+}
+
+fn weekdays_in_month(first: NaiveDate, by_day: ByDay) -> Vec<NaiveDate> {
+    let offset = (7 + u64::from(by_day.weekday.num_days_from_monday())
+        - u64::from(first.weekday().num_days_from_monday()))
+        % 7;
+    let matching: Vec<_> = (0..5)
+        .filter_map(|week| first.checked_add_days(Days::new(offset + week * 7)))
+        .filter(|date| date.month() == first.month())
+        .collect();
+
+    match by_day.ordinal {
+        None => matching,
+        Some(ordinal) if ordinal > 0 => matching
+            .get(ordinal as usize - 1)
+            .copied()
+            .into_iter()
+            .collect(),
+        Some(ordinal) => matching
+            .len()
+            .checked_sub(ordinal.unsigned_abs() as usize)
+            .and_then(|index| matching.get(index).copied())
+            .into_iter()
+            .collect(),
+    }
+}
+
+/// Iterator returned by `Rule::occurrences()`.
+pub struct Occurrences<'a> {
+    rule: &'a Rule,
+    tz: Tz,
+    start: NaiveDateTime,
+    time: NaiveTime,
+    period: u32,
+    emitted: u32,
+    empty_periods: u32,
+    pending: VecDeque<DateTime<Utc>>,
+    done: bool,
+}
+
+impl Occurrences<'_> {
+    /// Local times that don't exist (skipped by DST) get pushed forward to the next valid hour.
+    fn resolve(&self, date: NaiveDate) -> Option<DateTime<Utc>> {
+        let mut time = date.and_time(self.time);
+
+        for _ in 0..3 {
+            if let Some(local) = self.tz.from_local_datetime(&time).earliest() {
+                return Some(local.with_timezone(&Utc));
+            }
+            time = time.checked_add_signed(Duration::hours(1))?;
+        }
+
+        None
+    }
+}
+
+impl Iterator for Occurrences<'_> {
+    type Item = DateTime<Utc>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done && self.pending.is_empty() {
+            let Some(dates) = self.rule.dates_in_period(self.start.date(), self.period) else {
+                self.done = true;
+                break;
+            };
+            self.period += 1;
+
+            let times: Vec<_> = dates
+                .into_iter()
+                .filter(|date| date.and_time(self.time) >= self.start)
+                .filter_map(|date| self.resolve(date))
+                .collect();
+
+            if times.is_empty() {
+                self.empty_periods += 1;
+                self.done = self.empty_periods >= MAX_EMPTY_PERIODS || self.period == u32::MAX;
+            } else {
+                self.empty_periods = 0;
+                self.pending.extend(times);
+            }
+        }
+
+        let next = self.pending.pop_front()?;
+
+        if self.rule.until.is_some_and(|until| next > until)
+            || self.rule.count.is_some_and(|count| self.emitted >= count)
+        {
+            self.done = true;
+            self.pending.clear();
+            return None;
+        }
+
+        self.emitted += 1;
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        s.parse().unwrap()
+    }
+
+    fn expand(rule: &str, start: &str, tz: &str, take: usize) -> Vec<String> {
+        let rule: Rule = rule.parse().unwrap();
+        let tz: Tz = tz.parse().unwrap();
+        rule.occurrences(utc(start), tz)
+            .take(take)
+            .map(|time| {
+                time.with_timezone(&tz)
+                    .format("%Y-%m-%d %H:%M %a")
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_and_display() {
+        let rule: Rule = "RRULE:freq=weekly;interval=2;byday=mo,fr;count=3"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,FR;COUNT=3"
+        );
+
+        let rule: Rule = "FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20231231".parse().unwrap();
+        assert_eq!(
+            rule.to_string(),
+            "FREQ=MONTHLY;BYDAY=-1FR;UNTIL=20231231T235959Z"
+        );
+
+        for invalid in [
+            "INTERVAL=2",
+            "FREQ=YEARLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;INTERVAL=4000000000",
+            "FREQ=WEEKLY;INTERVAL=1001",
+            "FREQ=DAILY;COUNT=2;UNTIL=20230101",
+            "FREQ=WEEKLY;BYDAY=2MO",
+            "FREQ=MONTHLY;BYDAY=XX",
+            "FREQ=DAILY;BYHOUR=9",
+        ] {
+            assert!(invalid.parse::<Rule>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn daily_keeps_wall_clock_time_across_dst() {
+        let occurrences = expand(
+            "FREQ=DAILY;COUNT=3",
+            "2023-03-11T14:00:00Z",
+            "America/New_York",
+            10,
+        );
+        assert_eq!(
+            occurrences,
+            [
+                "2023-03-11 09:00 Sat",
+                "2023-03-12 09:00 Sun",
+                "2023-03-13 09:00 Mon"
+            ]
+        );
+    }
+
+    #[test]
+    fn weekly_by_day() {
+        let occurrences = expand(
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=MO,WE",
+            "2023-03-01T09:00:00Z",
+            "UTC",
+            4,
+        );
+        // Starts on a Wednesday, so that week's Monday is already in the past
+        assert_eq!(
+            occurrences,
+            [
+                "2023-03-01 09:00 Wed",
+                "2023-03-13 09:00 Mon",
+                "2023-03-15 09:00 Wed",
+                "2023-03-27 09:00 Mon"
+            ]
+        );
+    }
+
+    #[test]
+    fn monthly() {
+        let occurrences = expand("FREQ=MONTHLY", "2023-01-31T12:00:00Z", "UTC", 3);
+        assert_eq!(
+            occurrences,
+            [
+                "2023-01-31 12:00 Tue",
+                "2023-03-31 12:00 Fri",
+                "2023-05-31 12:00 Wed"
+            ]
+        );
+
+        let occurrences = expand(
+            "FREQ=MONTHLY;BYDAY=-1FR,1MO",
+            "2023-01-01T12:00:00Z",
+            "UTC",
+            4,
+        );
+        assert_eq!(
+            occurrences,
+            [
+                "2023-01-02 12:00 Mon",
+                "2023-01-27 12:00 Fri",
+                "2023-02-06 12:00 Mon",
+                "2023-02-24 12:00 Fri"
+            ]
+        );
+    }
+
+    #[test]
+    fn until_is_inclusive() {
+        let occurrences = expand(
+            "FREQ=DAILY;UNTIL=20230103T090000Z",
+            "2023-01-01T09:00:00Z",
+            "UTC",
+            10,
+        );
+        assert_eq!(occurrences.len(), 3);
+    }
+
+    #[test]
+    fn impossible_rules_end() {
+        let occurrences = expand(
+            "FREQ=DAILY;INTERVAL=7;BYDAY=TU",
+            "2023-01-02T09:00:00Z",
+            "UTC",
+            10,
+        );
+        assert!(occurrences.is_empty());
+    }
+
+    #[test]
+    fn huge_intervals_end_instead_of_overflowing() {
+        // Rules like these don't parse, but the ones that do still run out of calendar eventually
+        for frequency in [Frequency::Daily, Frequency::Weekly, Frequency::Monthly] {
+            let rule = Rule {
+                frequency,
+                interval: 4_000_000_000,
+                by_day: Vec::new(),
+                count: None,
+                until: None,
+            };
+            let occurrences: Vec<_> = rule
+                .occurrences(utc("2023-01-02T09:00:00Z"), Tz::UTC)
+                .take(10)
+                .collect();
+            assert_eq!(occurrences, [utc("2023-01-02T09:00:00Z")], "{frequency:?}");
+        }
+    }
+
+    #[test]
+    fn includes() {
+        let rule: Rule = "FREQ=WEEKLY".parse().unwrap();
+        let start = utc("2023-01-02T09:00:00Z");
+        assert!(rule.includes(start, Tz::UTC, utc("2023-01-16T09:00:00Z")));
+        assert!(!rule.includes(start, Tz::UTC, utc("2023-01-17T09:00:00Z")));
+    }
+}
@@ -0,0 +1,442 @@
+//! Settings come from (in order of priority) environment variables, `.env`, and an optional TOML file
+//! at `CONFIG_FILE` (`ztasks.toml` by default).
+
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use std::{fmt, net::IpAddr, path::PathBuf};
+
+const DEFAULT_CONFIG_FILE: &str = "ztasks.toml";
+const MIN_SECRET_LENGTH: usize = 32;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    pub database_path: PathBuf,
+    /// Signs access tokens. Without one, a random key is used and everyone is logged out on restart.
+    pub cookie_secret: Option<String>,
+    /// Origins allowed to call the API from a browser, like `https://tasks.example.com`
+    pub cors_origins: Vec<String>,
+    pub log_level: LevelFilter,
+    /// Reverse proxies whose `X-Forwarded-For` is believed, so clients behind them get their own rate limits
+    pub trusted_proxies: Vec<IpAddr>,
+    pub rate_limits: RateLimits,
+    /// How long before a task is due its reminder goes out
+    pub reminder_minutes: u32,
+}
+
+/// How hard the API is throttled, see `limiter`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimits {
+    /// Requests per minute from one IP address
+    pub requests_per_minute: u32,
+    /// Login attempts per minute, both per IP address and per username
+    pub logins_per_minute: u32,
+    /// Failed logins in a row before the username (or IP address) gets locked out
+    pub lockout_threshold: u32,
+    /// How long the first lockout lasts, every one after that doubles it
+    pub lockout_seconds: u64,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: 300,
+            logins_per_minute: 10,
+            lockout_threshold: 5,
+            lockout_seconds: 30,
+        }
+    }
+}
+
+/// Everything is optional here, missing values fall back to the environment and then the defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileSettings {
+    bind_address: Option<String>,
+    port: Option<u16>,
+    database_path: Option<String>,
+    cookie_secret: Option<String>,
+    cors_origins: Option<Vec<String>>,
+    log_level: Option<String>,
+    trusted_proxies: Option<Vec<String>>,
+    rate_limit: Option<u64>,
+    login_rate_limit: Option<u64>,
+    lockout_threshold: Option<u64>,
+    lockout_seconds: Option<u64>,
+    reminder_minutes: Option<u64>,
+}
+
+impl Config {
+    /// Reads the config from the process environment, after loading `.env`.
+    pub fn from_env() -> Result<Self, Vec<String>> {
+        dotenv::dotenv().ok();
+
+        let path = std::env::var("CONFIG_FILE").ok();
+        let file = match std::fs::read_to_string(path.as_deref().unwrap_or(DEFAULT_CONFIG_FILE)) {
+            Ok(contents) => Some(contents),
+            // Only complain about a missing file if it was asked for
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound && path.is_none() => None,
+            Err(error) => {
+                return Err(vec![format!(
+                    "Couldn't read config file {}: {error}",
+                    path.as_deref().unwrap_or(DEFAULT_CONFIG_FILE)
+                )])
+            }
+        };
+
+        Self::load(|name| std::env::var(name).ok(), file.as_deref())
+    }
+
+    /// Combines the TOML `file` with the variables from `env`, reporting every invalid value at once.
+    pub fn load(
+        env: impl Fn(&str) -> Option<String>,
+        file: Option<&str>,
+    ) -> Result<Self, Vec<String>> {
+        let file: FileSettings = match file {
+            Some(contents) => toml::from_str(contents)
+                .map_err(|error| vec![format!("Invalid config file: {}", error.message())])?,
+            None => FileSettings::default(),
+        };
+        let mut errors = Vec::new();
+
+        let bind_address = env("BIND_ADDRESS")
+            .or(file.bind_address)
+            .map(|address| {
+                address.trim().parse().map_err(|_| {
+                    format!("BIND_ADDRESS must be an IP address like 127.0.0.1, got \"{address}\".")
+                })
+            })
+            .unwrap_or(Ok(default_bind_address()));
+
+        let port = match env("PORT") {
+            Some(port) => port
+                .trim()
+                .parse::<u16>()
+                .ok()
+                .filter(|port| *port > 0)
+                .ok_or_else(|| {
+                    format!("PORT must be a number between 1 and 65535, got \"{port}\".")
+                }),
+            None => match file.port {
+                Some(0) => Err("port must be between 1 and 65535.".to_string()),
+                port => Ok(port.unwrap_or(3000)),
+            },
+        };
+
+        let database_path = env("DATABASE_PATH")
+            .or(file.database_path)
+            .unwrap_or_else(|| "ztasks.db".to_string());
+        let database_path = match database_path.trim() {
+            "" => Err("DATABASE_PATH can't be empty.".to_string()),
+            path => Ok(PathBuf::from(path)),
+        };
+
+        // TOKEN_SECRET is what this used to be called
+        let cookie_secret = env("COOKIE_SECRET")
+            .or_else(|| env("TOKEN_SECRET"))
+            .or(file.cookie_secret)
+            .filter(|secret| !secret.is_empty());
+        let cookie_secret = match cookie_secret {
+            Some(secret) if secret.len() < MIN_SECRET_LENGTH => Err(format!(
+                "COOKIE_SECRET must be at least {MIN_SECRET_LENGTH} characters long."
+            )),
+            secret => Ok(secret),
+        };
+
+        let cors_origins = match env("CORS_ORIGINS") {
+            Some(origins) => origins
+                .split(',')
+                .map(str::trim)
+                .filter(|origin| !origin.is_empty())
+                .map(str::to_string)
+                .collect(),
+            None => file.cors_origins.unwrap_or_default(),
+        };
+        let cors_origins = match cors_origins.iter().find(|origin| !is_origin(origin)) {
+            Some(origin) => Err(format!(
+                "CORS_ORIGINS must be a comma-separated list of origins like https://example.com, got \"{origin}\"."
+            )),
+            None => Ok(cors_origins),
+        };
+
+        let log_level = env("LOG_LEVEL")
+            .or(file.log_level)
+            .map(|level| {
+                level.trim().parse().map_err(|_| {
+                    format!("LOG_LEVEL must be one of off, error, warn, info, debug or trace, got \"{level}\".")
+                })
+            })
+            .unwrap_or(Ok(LevelFilter::Info));
+
+        let trusted_proxies = match env("TRUSTED_PROXIES") {
+            Some(proxies) => proxies
+                .split(',')
+                .map(str::trim)
+                .filter(|proxy| !proxy.is_empty())
+                .map(str::to_string)
+                .collect(),
+            None => file.trusted_proxies.unwrap_or_default(),
+        };
+        let trusted_proxies = trusted_proxies
+            .iter()
+            .map(|proxy| {
+                proxy.parse().map_err(|_| {
+                    format!("TRUSTED_PROXIES must be a comma-separated list of IP addresses, got \"{proxy}\".")
+                })
+            })
+            .collect::<Result<Vec<IpAddr>, _>>();
+
+        let defaults = RateLimits::default();
+        let requests_per_minute = positive(
+            "RATE_LIMIT",
+            env("RATE_LIMIT"),
+            file.rate_limit,
+            defaults.requests_per_minute,
+        );
+        let logins_per_minute = positive(
+            "LOGIN_RATE_LIMIT",
+            env("LOGIN_RATE_LIMIT"),
+            file.login_rate_limit,
+            defaults.logins_per_minute,
+        );
+        let lockout_threshold = positive(
+            "LOCKOUT_THRESHOLD",
+            env("LOCKOUT_THRESHOLD"),
+            file.lockout_threshold,
+            defaults.lockout_threshold,
+        );
+        let lockout_seconds = positive(
+            "LOCKOUT_SECONDS",
+            env("LOCKOUT_SECONDS"),
+            file.lockout_seconds,
+            defaults.lockout_seconds,
+        );
+        let reminder_minutes = positive(
+            "REMINDER_MINUTES",
+            env("REMINDER_MINUTES"),
+            file.reminder_minutes,
+            15,
+        );
+
+        let config = (
+            check(&mut errors, bind_address),
+            check(&mut errors, port),
+            check(&mut errors, database_path),
+            check(&mut errors, cookie_secret),
+            check(&mut errors, cors_origins),
+            check(&mut errors, log_level),
+            check(&mut errors, trusted_proxies),
+            check(&mut errors, requests_per_minute),
+            check(&mut errors, logins_per_minute),
+            check(&mut errors, lockout_threshold),
+            check(&mut errors, lockout_seconds),
+            check(&mut errors, reminder_minutes),
+        );
+
+        match config {
+            (
+                Some(bind_address),
+                Some(port),
+                Some(database_path),
+                Some(cookie_secret),
+                Some(cors_origins),
+                Some(log_level),
+                Some(trusted_proxies),
+                Some(requests_per_minute),
+                Some(logins_per_minute),
+                Some(lockout_threshold),
+                Some(lockout_seconds),
+                Some(reminder_minutes),
+            ) => Ok(Self {
+                bind_address,
+                port,
+                database_path,
+                cookie_secret,
+                cors_origins,
+                log_level,
+                trusted_proxies,
+                rate_limits: RateLimits {
+                    requests_per_minute,
+                    logins_per_minute,
+                    lockout_threshold,
+                    lockout_seconds,
+                },
+                reminder_minutes,
+            }),
+            _ => Err(errors),
+        }
+    }
+}
+
+fn check<T>(errors: &mut Vec<String>, result: Result<T, String>) -> Option<T> {
+    result.map_err(|error| errors.push(error)).ok()
+}
+
+/// A number above zero from the environment (or the file), which also has to fit in `T`.
+fn positive<T: TryFrom<u64> + Copy>(
+    name: &str,
+    env: Option<String>,
+    file: Option<u64>,
+    default: T,
+) -> Result<T, String> {
+    let invalid = |value: &str| format!("{name} must be a whole number above 0, got \"{value}\".");
+
+    let value = match env {
+        Some(value) => value.trim().parse::<u64>().map_err(|_| invalid(&value))?,
+        None => match file {
+            Some(value) => value,
+            None => return Ok(default),
+        },
+    };
+
+    match T::try_from(value) {
+        Ok(number) if value > 0 => Ok(number),
+        _ => Err(invalid(&value.to_string())),
+    }
+}
+
+/// Only accept connections from other machines in release builds.
+fn default_bind_address() -> IpAddr {
+    #[cfg(debug_assertions)]
+    return IpAddr::from([127, 0, 0, 1]);
+    #[cfg(not(debug_assertions))]
+    return IpAddr::from([0, 0, 0, 0]);
+}
+
+/// `scheme://host[:port]`, without a path or trailing slash, which is what browsers send in `Origin`.
+fn is_origin(origin: &str) -> bool {
+    let Some((scheme, host)) = origin.split_once("://") else {
+        return false;
+    };
+
+    matches!(scheme, "http" | "https") && !host.is_empty() && !host.contains(['/', '?', '#', ' '])
+}
+
+/// What `--print-config` shows, as TOML that can be used as a config file.
+#[derive(Serialize)]
+struct Printed<'a> {
+    bind_address: String,
+    port: u16,
+    database_path: String,
+    cookie_secret: &'a str,
+    cors_origins: &'a [String],
+    log_level: String,
+    trusted_proxies: Vec<String>,
+    rate_limit: u32,
+    login_rate_limit: u32,
+    lockout_threshold: u32,
+    lockout_seconds: u64,
+    reminder_minutes: u32,
+}
+
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let printed = Printed {
+            bind_address: self.bind_address.to_string(),
+            port: self.port,
+            database_path: self.database_path.display().to_string(),
+            cookie_secret: match self.cookie_secret {
+                Some(_) => "<redacted>",
+                None => "<not set>",
+            },
+            cors_origins: &self.cors_origins,
+            log_level: self.log_level.to_string().to_lowercase(),
+            trusted_proxies: self.trusted_proxies.iter().map(IpAddr::to_string).collect(),
+            rate_limit: self.rate_limits.requests_per_minute,
+            login_rate_limit: self.rate_limits.logins_per_minute,
+            lockout_threshold: self.rate_limits.lockout_threshold,
+            lockout_seconds: self.rate_limits.lockout_seconds,
+            reminder_minutes: self.reminder_minutes,
+        };
+
+        match toml::to_string(&printed) {
+            Ok(text) => write!(f, "{text}"),
+            Err(_) => Err(fmt::Error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(env: &[(&str, &str)], file: Option<&str>) -> Result<Config, Vec<String>> {
+        let env: HashMap<_, _> = env.iter().copied().collect();
+        Config::load(|name| env.get(name).map(|value| value.to_string()), file)
+    }
+
+    #[test]
+    fn environment_beats_file() {
+        let file = r#"
+            port = 8080
+            database_path = "/var/lib/ztasks.db"
+            cors_origins = ["https://tasks.example.com"]
+            log_level = "debug"
+            trusted_proxies = ["10.0.0.1"]
+            lockout_seconds = 60
+        "#;
+        let config = load(&[("PORT", "9000"), ("BIND_ADDRESS", "0.0.0.0")], Some(file)).unwrap();
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.bind_address, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(config.database_path, PathBuf::from("/var/lib/ztasks.db"));
+        assert_eq!(config.cors_origins, ["https://tasks.example.com"]);
+        assert_eq!(config.log_level, LevelFilter::Debug);
+        assert_eq!(config.trusted_proxies, [IpAddr::from([10, 0, 0, 1])]);
+        assert_eq!(config.cookie_secret, None);
+        assert_eq!(config.rate_limits.lockout_seconds, 60);
+        assert_eq!(config.rate_limits.logins_per_minute, 10);
+
+        let config = load(&[], None).unwrap();
+        assert_eq!(config.port, 3000);
+        assert_eq!(config.database_path, PathBuf::from("ztasks.db"));
+        assert_eq!(config.log_level, LevelFilter::Info);
+        assert_eq!(config.reminder_minutes, 15);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let errors = load(
+            &[
+                ("PORT", "30o0"),
+                ("BIND_ADDRESS", "localhost"),
+                ("COOKIE_SECRET", "hunter2"),
+                ("CORS_ORIGINS", "https://ok.example.com, example.com"),
+                ("LOG_LEVEL", "loud"),
+                ("TRUSTED_PROXIES", "10.0.0.1, proxy"),
+                ("LOGIN_RATE_LIMIT", "0"),
+            ],
+            None,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            errors,
+            [
+                "BIND_ADDRESS must be an IP address like 127.0.0.1, got \"localhost\".",
+                "PORT must be a number between 1 and 65535, got \"30o0\".",
+                "COOKIE_SECRET must be at least 32 characters long.",
+                "CORS_ORIGINS must be a comma-separated list of origins like https://example.com, got \"example.com\".",
+                "LOG_LEVEL must be one of off, error, warn, info, debug or trace, got \"loud\".",
+                "TRUSTED_PROXIES must be a comma-separated list of IP addresses, got \"proxy\".",
+                "LOGIN_RATE_LIMIT must be a whole number above 0, got \"0\".",
+            ]
+        );
+
+        let errors = load(&[], Some("prot = 3000")).unwrap_err();
+        assert!(errors[0].starts_with("Invalid config file: unknown field `prot`"));
+    }
+
+    #[test]
+    fn printing_redacts_secrets() {
+        let secret = "correct horse battery staple, but longer";
+        let config = load(&[("COOKIE_SECRET", secret)], None).unwrap();
+        let printed = config.to_string();
+
+        assert!(!printed.contains(secret));
+        assert!(printed.contains("cookie_secret = \"<redacted>\""));
+        assert!(printed.contains("port = 3000"));
+    }
+}
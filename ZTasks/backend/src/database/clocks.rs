@@ -0,0 +1,109 @@
+use crate::sync::{Field, Stamp};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// The stamp of the last synced write to each field of a task.
+pub fn stamps(conn: &Connection, task_id: i64) -> rusqlite::Result<HashMap<Field, Stamp>> {
+    let mut stmt =
+        conn.prepare("SELECT Field, Clock, ClientID FROM FieldClocks WHERE TaskID = ?1")?;
+    let rows = stmt.query_map([task_id], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            Stamp {
+                clock: row.get(1)?,
+                client_id: row.get(2)?,
+            },
+        ))
+    })?;
+
+    let mut stamps = HashMap::new();
+    for row in rows {
+        let (name, stamp) = row?;
+        if let Some(field) = Field::from_name(&name) {
+            stamps.insert(field, stamp);
+        }
+    }
+    Ok(stamps)
+}
+
+/// What sync last set each field of a task to, see `Snapshot::recall()`.
+pub fn written(conn: &Connection, task_id: i64) -> rusqlite::Result<HashMap<Field, Value>> {
+    let mut stmt = conn
+        .prepare("SELECT Field, Value FROM FieldClocks WHERE TaskID = ?1 AND Value IS NOT NULL")?;
+    let rows = stmt.query_map([task_id], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut written = HashMap::new();
+    for row in rows {
+        let (name, value) = row?;
+        if let (Some(field), Ok(value)) = (Field::from_name(&name), serde_json::from_str(&value)) {
+            written.insert(field, value);
+        }
+    }
+    Ok(written)
+}
+
+pub fn save(
+    conn: &Connection,
+    task_id: i64,
+    field: Field,
+    stamp: &Stamp,
+    value: &Value,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO FieldClocks (TaskID, Field, Clock, ClientID, Value) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![task_id, field.name(), stamp.clock, stamp.client_id, value.to_string()],
+    )?;
+    Ok(())
+}
+
+/// When the task with `uid` was deleted through sync, if it was.
+pub fn tombstone(conn: &Connection, user_id: i64, uid: &str) -> rusqlite::Result<Option<Stamp>> {
+    conn.query_row(
+        "SELECT Clock, ClientID FROM Tombstones WHERE UserID = ?1 AND UID = ?2",
+        params![user_id, uid],
+        |row| {
+            Ok(Stamp {
+                clock: row.get(0)?,
+                client_id: row.get(1)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Remembers that `uid` is gone. The first delete is the one that counts.
+pub fn bury(
+    conn: &Connection,
+    user_id: i64,
+    uid: &str,
+    stamp: &Stamp,
+    now: i64,
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO Tombstones (UserID, UID, Clock, ClientID, Deleted) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![user_id, uid, stamp.clock, stamp.client_id, now],
+    )?;
+    Ok(())
+}
+
+/// Forgets that `uid` was deleted, for tasks that have been restored since.
+pub fn unbury(conn: &Connection, user_id: i64, uid: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "DELETE FROM Tombstones WHERE UserID = ?1 AND UID = ?2",
+        params![user_id, uid],
+    )?;
+    Ok(())
+}
+
+/// The biggest clock the user's devices have synced, so they can move their own clocks past it.
+pub fn latest(conn: &Connection, user_id: i64) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT MAX(COALESCE((SELECT MAX(Clock) FROM FieldClocks JOIN Tasks ON Tasks.ID = TaskID WHERE UserID = ?1), 0),
+                    COALESCE((SELECT MAX(Clock) FROM Tombstones WHERE UserID = ?1), 0))",
+        [user_id],
+        |row| row.get(0),
+    )
+}
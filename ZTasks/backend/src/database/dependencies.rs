@@ -0,0 +1,71 @@
+use super::tasks::VISIBLE;
+use rusqlite::{params, Connection};
+
+/// Marks `task_id` as blocked by `blocker_id`. Returns false if it already was.
+pub fn add(conn: &Connection, task_id: i64, blocker_id: i64) -> rusqlite::Result<bool> {
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO TaskDependencies (TaskID, BlockerID) VALUES (?1, ?2)",
+        [task_id, blocker_id],
+    )?;
+    Ok(added > 0)
+}
+
+/// Returns whether there was anything to remove.
+pub fn remove(conn: &Connection, task_id: i64, blocker_id: i64) -> rusqlite::Result<bool> {
+    let removed = conn.execute(
+        "DELETE FROM TaskDependencies WHERE TaskID = ?1 AND BlockerID = ?2",
+        [task_id, blocker_id],
+    )?;
+    Ok(removed > 0)
+}
+
+/// Dependencies on deleted tasks are kept for when they're restored, but don't count in the meantime, as a
+/// condition on `TaskDependencies`.
+const LIVE: &str = "NOT EXISTS (SELECT 1 FROM Tasks WHERE Deleted IS NOT NULL
+                                AND ID IN (TaskDependencies.TaskID, TaskDependencies.BlockerID))";
+
+/// IDs of the tasks that `task_id` is blocked by, out of the ones `user_id` can see.
+pub fn blockers(conn: &Connection, user_id: i64, task_id: i64) -> rusqlite::Result<Vec<i64>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT BlockerID FROM TaskDependencies JOIN Tasks ON Tasks.ID = BlockerID
+         WHERE TaskID = ?2 AND {VISIBLE} AND {LIVE} ORDER BY BlockerID"
+    ))?;
+    let ids = stmt.query_map(params![user_id, task_id], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// IDs of the tasks that `task_id` blocks, out of the ones `user_id` can see.
+pub fn blocking(conn: &Connection, user_id: i64, task_id: i64) -> rusqlite::Result<Vec<i64>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT TaskID FROM TaskDependencies JOIN Tasks ON Tasks.ID = TaskID
+         WHERE BlockerID = ?2 AND {VISIBLE} AND {LIVE} ORDER BY TaskID"
+    ))?;
+    let ids = stmt.query_map(params![user_id, task_id], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// Every `(waiting, on)` pair among the tasks a user can see, see `plan`. Tasks wait on their blockers,
+/// and on their subtasks.
+pub fn edges(conn: &Connection, user_id: i64) -> rusqlite::Result<Vec<(i64, i64)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT TaskID, BlockerID FROM TaskDependencies
+         JOIN Tasks ON Tasks.ID = TaskDependencies.TaskID WHERE {VISIBLE} AND {LIVE}
+         UNION ALL
+         SELECT ParentID, ID FROM Tasks WHERE {VISIBLE} AND ParentID IS NOT NULL"
+    ))?;
+    let edges = stmt.query_map(params![user_id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    edges.collect()
+}
+
+/// How many of the subtasks below `task_id`, at any depth, are done out of how many there are.
+pub fn progress(conn: &Connection, task_id: i64) -> rusqlite::Result<(i64, i64)> {
+    conn.query_row(
+        "WITH RECURSIVE Below(ID) AS (
+             SELECT ID FROM Tasks WHERE ParentID = ?1
+             UNION SELECT Tasks.ID FROM Tasks JOIN Below ON Tasks.ParentID = Below.ID
+         )
+         SELECT COUNT(Completed), COUNT(*) FROM Tasks WHERE ID IN Below AND Deleted IS NULL",
+        [task_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+}
@@ -0,0 +1,246 @@
+use super::tasks::from_timestamp;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Manages the list and who's in it
+    Owner,
+    /// Adds, changes and deletes tasks
+    Editor,
+    /// Only sees the tasks
+    Viewer,
+}
+
+impl Role {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Owner => "owner",
+            Self::Editor => "editor",
+            Self::Viewer => "viewer",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "owner" => Some(Self::Owner),
+            "editor" => Some(Self::Editor),
+            "viewer" => Some(Self::Viewer),
+            _ => None,
+        }
+    }
+
+    pub fn can_edit(self) -> bool {
+        matches!(self, Self::Owner | Self::Editor)
+    }
+}
+
+fn role_at(row: &Row, index: usize) -> rusqlite::Result<Role> {
+    let name: String = row.get(index)?;
+    Role::from_name(&name).ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(
+            index,
+            Type::Text,
+            format!("Unknown role \"{name}\"").into(),
+        )
+    })
+}
+
+/// A list as one of its members sees it.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct List {
+    pub id: i64,
+    pub name: String,
+    pub role: Role,
+    pub created: DateTime<Utc>,
+}
+
+impl List {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            role: role_at(row, 2)?,
+            created: from_timestamp(row.get(3)?),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Member {
+    pub user_id: i64,
+    pub username: String,
+    pub role: Role,
+    pub joined: DateTime<Utc>,
+}
+
+/// Creates a list with `user_id` as its owner.
+pub fn create(conn: &Connection, user_id: i64, name: &str, now: i64) -> rusqlite::Result<List> {
+    conn.execute(
+        "INSERT INTO Lists (Name, Created) VALUES (?1, ?2)",
+        params![name, now],
+    )?;
+    let id = conn.last_insert_rowid();
+    add_member(conn, id, user_id, Role::Owner, now)?;
+
+    get(conn, user_id, id).map(|list| list.expect("List was just created"))
+}
+
+/// The lists `user_id` is a member of.
+pub fn list(conn: &Connection, user_id: i64) -> rusqlite::Result<Vec<List>> {
+    let mut stmt = conn.prepare(
+        "SELECT ID, Name, Role, Created FROM Lists JOIN ListMembers ON ListID = ID
+         WHERE UserID = ?1 ORDER BY Name, ID",
+    )?;
+    let lists = stmt.query_map([user_id], List::from_row)?;
+    lists.collect()
+}
+
+/// Returns `None` unless `user_id` is a member.
+pub fn get(conn: &Connection, user_id: i64, id: i64) -> rusqlite::Result<Option<List>> {
+    conn.query_row(
+        "SELECT ID, Name, Role, Created FROM Lists JOIN ListMembers ON ListID = ID
+         WHERE UserID = ?1 AND ID = ?2",
+        [user_id, id],
+        List::from_row,
+    )
+    .optional()
+}
+
+pub fn rename(conn: &Connection, id: i64, name: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE Lists SET Name = ?2 WHERE ID = ?1",
+        params![id, name],
+    )?;
+    Ok(())
+}
+
+/// Deletes the list along with its tasks.
+pub fn delete(conn: &Connection, id: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM Lists WHERE ID = ?1", [id])?;
+    Ok(())
+}
+
+/// What `user_id` can do in the list, `None` if they're not a member (or it doesn't exist).
+pub fn role(conn: &Connection, user_id: i64, id: i64) -> rusqlite::Result<Option<Role>> {
+    conn.query_row(
+        "SELECT Role FROM ListMembers WHERE UserID = ?1 AND ListID = ?2",
+        [user_id, id],
+        |row| role_at(row, 0),
+    )
+    .optional()
+}
+
+pub fn members(conn: &Connection, id: i64) -> rusqlite::Result<Vec<Member>> {
+    let mut stmt = conn.prepare(
+        "SELECT UserID, Username, Role, Joined FROM ListMembers JOIN Users ON Users.ID = UserID
+         WHERE ListID = ?1 ORDER BY Joined, UserID",
+    )?;
+    let members = stmt.query_map([id], |row| {
+        Ok(Member {
+            user_id: row.get(0)?,
+            username: row.get(1)?,
+            role: role_at(row, 2)?,
+            joined: from_timestamp(row.get(3)?),
+        })
+    })?;
+    members.collect()
+}
+
+/// Everyone who should hear about changes to a task in `list_id`. Tasks without a list are only
+/// for `user_id`, who has to be the one that made them.
+pub fn audience(
+    conn: &Connection,
+    user_id: i64,
+    list_id: Option<i64>,
+) -> rusqlite::Result<Vec<i64>> {
+    let Some(list_id) = list_id else {
+        return Ok(vec![user_id]);
+    };
+
+    let mut stmt = conn.prepare("SELECT UserID FROM ListMembers WHERE ListID = ?1")?;
+    let ids = stmt.query_map([list_id], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// Returns false if they already were a member, in which case their role stays the same.
+pub fn add_member(
+    conn: &Connection,
+    id: i64,
+    user_id: i64,
+    role: Role,
+    now: i64,
+) -> rusqlite::Result<bool> {
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO ListMembers (ListID, UserID, Role, Joined) VALUES (?1, ?2, ?3, ?4)",
+        params![id, user_id, role.name(), now],
+    )?;
+    Ok(added > 0)
+}
+
+/// Returns whether they were a member.
+pub fn set_role(conn: &Connection, id: i64, user_id: i64, role: Role) -> rusqlite::Result<bool> {
+    let changed = conn.execute(
+        "UPDATE ListMembers SET Role = ?3 WHERE ListID = ?1 AND UserID = ?2",
+        params![id, user_id, role.name()],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Returns whether they were a member.
+pub fn remove_member(conn: &Connection, id: i64, user_id: i64) -> rusqlite::Result<bool> {
+    let removed = conn.execute(
+        "DELETE FROM ListMembers WHERE ListID = ?1 AND UserID = ?2",
+        [id, user_id],
+    )?;
+    Ok(removed > 0)
+}
+
+pub fn count_owners(conn: &Connection, id: i64) -> rusqlite::Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM ListMembers WHERE ListID = ?1 AND Role = 'owner'",
+        [id],
+        |row| row.get(0),
+    )
+}
+
+pub fn create_invite(
+    conn: &Connection,
+    id: i64,
+    token_hash: &str,
+    role: Role,
+    created_by: i64,
+    (now, expires): (i64, i64),
+) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO ListInvites (TokenHash, ListID, Role, CreatedBy, Created, Expires)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![token_hash, id, role.name(), created_by, now, expires],
+    )?;
+    Ok(())
+}
+
+/// The list an invite is for and the role it gives, unless it's unknown or expired.
+pub fn find_invite(
+    conn: &Connection,
+    token_hash: &str,
+    now: i64,
+) -> rusqlite::Result<Option<(i64, Role)>> {
+    conn.query_row(
+        "SELECT ListID, Role FROM ListInvites WHERE TokenHash = ?1 AND Expires > ?2",
+        params![token_hash, now],
+        |row| Ok((row.get(0)?, role_at(row, 1)?)),
+    )
+    .optional()
+}
+
+/// Makes every open invite to the list stop working. Returns how many there were.
+pub fn revoke_invites(conn: &Connection, id: i64, now: i64) -> rusqlite::Result<usize> {
+    conn.execute(
+        "DELETE FROM ListInvites WHERE ListID = ?1 AND Expires > ?2",
+        params![id, now],
+    )
+}
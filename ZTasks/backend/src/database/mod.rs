@@ -0,0 +1,82 @@
+pub mod clocks;
+pub mod dependencies;
+pub mod lists;
+pub mod occurrences;
+pub mod reminders;
+pub mod revisions;
+pub mod sessions;
+pub mod tasks;
+pub mod time;
+pub mod users;
+pub mod views;
+
+use rusqlite::Connection;
+use std::{
+    path::Path,
+    sync::{Mutex, MutexGuard},
+};
+
+// Each entry is applied once, in order, and tracked via "PRAGMA user_version".
+const MIGRATIONS: &[&str] = &[
+    include_str!("sql/0.sql"),
+    include_str!("sql/1.sql"),
+    include_str!("sql/2.sql"),
+    include_str!("sql/3.sql"),
+    include_str!("sql/4.sql"),
+    include_str!("sql/5.sql"),
+    include_str!("sql/6.sql"),
+    include_str!("sql/7.sql"),
+    include_str!("sql/8.sql"),
+    include_str!("sql/9.sql"),
+    include_str!("sql/10.sql"),
+    include_str!("sql/11.sql"),
+    include_str!("sql/12.sql"),
+    include_str!("sql/13.sql"),
+    include_str!("sql/14.sql"),
+    include_str!("sql/15.sql"),
+    include_str!("sql/16.sql"),
+    include_str!("sql/17.sql"),
+];
+
+/// Shared handle to the SQLite database, meant to be wrapped in `web::Data`.
+pub struct Database {
+    conn: Mutex<Connection>,
+}
+
+impl Database {
+    pub fn open(path: impl AsRef<Path>) -> rusqlite::Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> rusqlite::Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        migrate(&conn)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Connection> {
+        // A panic while holding the lock doesn't leave SQLite in a bad state, so just keep going
+        self.conn
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+fn migrate(conn: &Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+    for (index, sql) in MIGRATIONS.iter().enumerate().skip(version) {
+        conn.execute_batch(sql)?;
+        conn.pragma_update(None, "user_version", index + 1)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use super::tasks::from_timestamp;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const COLUMNS: &str = "RecurrenceID, Title, Notes, Start, Due, Completed, Cancelled";
+
+/// Changes to one occurrence of a recurring task. `None` fields fall back to the series.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct Override {
+    /// When the occurrence was originally supposed to happen
+    pub recurrence_id: DateTime<Utc>,
+    pub title: Option<String>,
+    pub notes: Option<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub due: Option<DateTime<Utc>>,
+    pub completed: Option<DateTime<Utc>>,
+    pub cancelled: bool,
+}
+
+impl Override {
+    pub fn new(recurrence_id: DateTime<Utc>) -> Self {
+        Self {
+            recurrence_id,
+            title: None,
+            notes: None,
+            start: None,
+            due: None,
+            completed: None,
+            cancelled: false,
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            recurrence_id: from_timestamp(row.get(0)?),
+            title: row.get(1)?,
+            notes: row.get(2)?,
+            start: row.get::<_, Option<i64>>(3)?.map(from_timestamp),
+            due: row.get::<_, Option<i64>>(4)?.map(from_timestamp),
+            completed: row.get::<_, Option<i64>>(5)?.map(from_timestamp),
+            cancelled: row.get(6)?,
+        })
+    }
+}
+
+pub fn list(conn: &Connection, task_id: i64) -> rusqlite::Result<Vec<Override>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM TaskOccurrences WHERE TaskID = ?1 ORDER BY RecurrenceID"
+    ))?;
+    let overrides = stmt.query_map([task_id], Override::from_row)?;
+    overrides.collect()
+}
+
+pub fn get(
+    conn: &Connection,
+    task_id: i64,
+    recurrence_id: DateTime<Utc>,
+) -> rusqlite::Result<Option<Override>> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM TaskOccurrences WHERE TaskID = ?1 AND RecurrenceID = ?2"),
+        [task_id, recurrence_id.timestamp()],
+        Override::from_row,
+    )
+    .optional()
+}
+
+pub fn save(conn: &Connection, task_id: i64, occurrence: &Override) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO TaskOccurrences
+         (TaskID, RecurrenceID, Title, Notes, Start, Due, Completed, Cancelled)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            task_id,
+            occurrence.recurrence_id.timestamp(),
+            occurrence.title,
+            occurrence.notes,
+            occurrence.start.map(|time| time.timestamp()),
+            occurrence.due.map(|time| time.timestamp()),
+            occurrence.completed.map(|time| time.timestamp()),
+            occurrence.cancelled,
+        ],
+    )?;
+    Ok(())
+}
+
+/// Forgets every override, for when the series itself gets rescheduled.
+pub fn clear(conn: &Connection, task_id: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM TaskOccurrences WHERE TaskID = ?1", [task_id])?;
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use rusqlite::{params, Connection};
+
+/// Records that the reminder for `task_id` being due at `due` went out. Returns false if it already had,
+/// so only one caller ever sends it.
+pub fn mark_sent(conn: &Connection, task_id: i64, due: i64, now: i64) -> rusqlite::Result<bool> {
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO SentReminders (TaskID, Due, Sent) VALUES (?1, ?2, ?3)",
+        params![task_id, due, now],
+    )?;
+    Ok(added > 0)
+}
+
+/// Forgets reminders for anything that was due before `before`, which won't be sent again anyway.
+pub fn forget(conn: &Connection, before: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM SentReminders WHERE Due < ?1", [before])?;
+    Ok(())
+}
+
+/// Remembers that recurring task `task_id` has no repeat due before `next`, or none at all if `None`, until
+/// it's rescheduled.
+pub fn set_next(conn: &Connection, task_id: i64, next: Option<i64>) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO ReminderCursors (TaskID, Next) VALUES (?1, ?2)",
+        params![task_id, next],
+    )?;
+    Ok(())
+}
@@ -0,0 +1,241 @@
+//! The history of each task. Revisions are written by triggers (see `sql/9.sql` and `sql/15.sql`), this
+//! only reads them.
+
+use super::tasks::{from_timestamp, Task, TaskFields};
+use rusqlite::{params, types::Type, Connection, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+const COLUMNS: &str = "ID, TaskID, UserID, Kind, State, Created";
+
+/// Revisions the user in `?1` can see, going by the list the task was in at the time.
+const VISIBLE: &str = "(json_extract(State, '$.list_id') IS NULL AND UserID = ?1
+    OR json_extract(State, '$.list_id') IN (SELECT ListID FROM ListMembers WHERE UserID = ?1))";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    Created,
+    Updated,
+    Deleted,
+    /// Put back the way it was at an earlier revision
+    Restored,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Updated => "updated",
+            Self::Deleted => "deleted",
+            Self::Restored => "restored",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "created" => Some(Self::Created),
+            "updated" => Some(Self::Updated),
+            "deleted" => Some(Self::Deleted),
+            "restored" => Some(Self::Restored),
+            _ => None,
+        }
+    }
+}
+
+/// Everything about a task at some point, with times as UNIX timestamps like in the `Tasks` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, ToSchema)]
+pub struct State {
+    pub title: String,
+    pub notes: String,
+    pub category: Option<String>,
+    pub start: Option<i64>,
+    pub due: Option<i64>,
+    pub completed: Option<i64>,
+    pub created: i64,
+    pub recurrence: Option<String>,
+    pub time_zone: String,
+    pub uid: String,
+    pub parent_id: Option<i64>,
+    /// Missing from revisions from before there were lists
+    #[serde(default)]
+    pub list_id: Option<i64>,
+}
+
+impl State {
+    pub fn of(task: &Task) -> Self {
+        Self {
+            title: task.title.clone(),
+            notes: task.notes.clone(),
+            category: task.category.clone(),
+            start: task.start.map(|time| time.timestamp()),
+            due: task.due.map(|time| time.timestamp()),
+            completed: task.completed.map(|time| time.timestamp()),
+            created: task.created.timestamp(),
+            recurrence: task.recurrence.clone(),
+            time_zone: task.time_zone.clone(),
+            uid: task.uid.clone(),
+            parent_id: task.parent_id,
+            list_id: task.list_id,
+        }
+    }
+
+    pub fn fields(&self) -> TaskFields {
+        TaskFields {
+            title: self.title.clone(),
+            notes: self.notes.clone(),
+            category: self.category.clone(),
+            start: self.start.map(from_timestamp),
+            due: self.due.map(from_timestamp),
+            recurrence: self.recurrence.clone(),
+            time_zone: self.time_zone.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Revision {
+    pub id: i64,
+    pub task_id: i64,
+    /// Who the task belonged to, which only matters outside of lists
+    pub user_id: i64,
+    pub kind: Kind,
+    /// The task after the change, or right before it was deleted
+    pub state: State,
+    pub time: i64,
+}
+
+impl Revision {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let kind: String = row.get(3)?;
+        let state: String = row.get(4)?;
+
+        Ok(Self {
+            id: row.get(0)?,
+            task_id: row.get(1)?,
+            user_id: row.get(2)?,
+            kind: Kind::from_name(&kind).ok_or_else(|| {
+                rusqlite::Error::FromSqlConversionFailure(
+                    3,
+                    Type::Text,
+                    format!("Unknown revision kind \"{kind}\"").into(),
+                )
+            })?,
+            state: serde_json::from_str(&state).map_err(|error| {
+                rusqlite::Error::FromSqlConversionFailure(4, Type::Text, error.into())
+            })?,
+            time: row.get(5)?,
+        })
+    }
+}
+
+/// Every revision of a task, oldest first. Empty if the task never existed, or if the user can't see it
+/// as of its latest revision.
+pub fn list(conn: &Connection, user_id: i64, task_id: i64) -> rusqlite::Result<Vec<Revision>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM TaskRevisions WHERE TaskID = ?2
+           AND EXISTS (SELECT 1 FROM TaskRevisions
+                       WHERE ID = (SELECT MAX(ID) FROM TaskRevisions WHERE TaskID = ?2) AND {VISIBLE})
+         ORDER BY ID"
+    ))?;
+    let revisions = stmt.query_map([user_id, task_id], Revision::from_row)?;
+    revisions.collect()
+}
+
+pub fn get(
+    conn: &Connection,
+    user_id: i64,
+    task_id: i64,
+    id: i64,
+) -> rusqlite::Result<Option<Revision>> {
+    let revisions = list(conn, user_id, task_id)?;
+    Ok(revisions.into_iter().find(|revision| revision.id == id))
+}
+
+/// Marks the latest revision of a task as a restore, since the triggers can't tell.
+pub fn mark_restored(conn: &Connection, task_id: i64) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE TaskRevisions SET Kind = 'restored'
+         WHERE ID = (SELECT MAX(ID) FROM TaskRevisions WHERE TaskID = ?1)",
+        [task_id],
+    )?;
+    Ok(())
+}
+
+/// Revisions that deleted a task which is still gone, as a condition on `Revision`.
+const STILL_DELETED: &str = "Kind = 'deleted'
+    AND ID = (SELECT MAX(ID) FROM TaskRevisions WHERE TaskID = Revision.TaskID)
+    AND NOT EXISTS (SELECT 1 FROM Tasks WHERE Tasks.ID = Revision.TaskID AND Tasks.Deleted IS NULL)";
+
+/// The revisions that deleted tasks which are still gone, most recently deleted first.
+pub fn deleted(conn: &Connection, user_id: i64, limit: i64) -> rusqlite::Result<Vec<Revision>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM TaskRevisions AS Revision
+         WHERE {VISIBLE} AND {STILL_DELETED}
+         ORDER BY ID DESC
+         LIMIT ?2"
+    ))?;
+    let revisions = stmt.query_map(params![user_id, limit], Revision::from_row)?;
+    revisions.collect()
+}
+
+/// Subtasks of `parent_id` that went away in the deletion of `deletion` (see `tasks::deletion()`), and are
+/// still gone.
+pub fn deleted_with(
+    conn: &Connection,
+    user_id: i64,
+    parent_id: i64,
+    deletion: i64,
+) -> rusqlite::Result<Vec<Revision>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM TaskRevisions AS Revision
+         WHERE {VISIBLE} AND json_extract(State, '$.parent_id') = ?2 AND {STILL_DELETED}
+           AND TaskID IN (SELECT ID FROM Tasks WHERE DeletedWith = ?3)
+         ORDER BY ID"
+    ))?;
+    let revisions = stmt.query_map(params![user_id, parent_id, deletion], Revision::from_row)?;
+    revisions.collect()
+}
+
+/// Every revision of the tasks that are the user's own as of their latest revision, so not in a list, oldest
+/// first.
+pub fn personal(conn: &Connection, user_id: i64) -> rusqlite::Result<Vec<Revision>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM TaskRevisions AS Revision
+         WHERE UserID = ?1 AND json_extract((
+             SELECT State FROM TaskRevisions WHERE ID = (SELECT MAX(ID) FROM TaskRevisions WHERE TaskID = Revision.TaskID)
+         ), '$.list_id') IS NULL
+         ORDER BY ID"
+    ))?;
+    let revisions = stmt.query_map([user_id], Revision::from_row)?;
+    revisions.collect()
+}
+
+/// Whether the user has ever had a task with this UID, deleted or not.
+pub fn has_uid(conn: &Connection, user_id: i64, uid: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM TaskRevisions WHERE UserID = ?1 AND json_extract(State, '$.uid') = ?2)",
+        params![user_id, uid],
+        |row| row.get(0),
+    )
+}
+
+/// Swaps out the history of a task for `revisions`, given as `(kind, state, time)` oldest first.
+pub fn replace(
+    conn: &Connection,
+    user_id: i64,
+    task_id: i64,
+    revisions: &[(Kind, State, i64)],
+) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM TaskRevisions WHERE TaskID = ?1", [task_id])?;
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    for (kind, state, time) in revisions {
+        let state = serde_json::to_string(state)
+            .map_err(|error| rusqlite::Error::ToSqlConversionFailure(error.into()))?;
+        stmt.execute(params![user_id, task_id, kind.name(), state, time])?;
+    }
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use rusqlite::{params, Connection, OptionalExtension};
+
+/// Outcome of trading in a refresh token.
+#[derive(Debug, PartialEq)]
+pub enum Rotation {
+    /// The old token was retired and the new one now belongs to the same family.
+    Rotated { user_id: i64, family: String },
+    /// The token was already traded in before, so the whole family has been revoked.
+    Reused { user_id: i64 },
+    /// Unknown, expired or revoked.
+    Invalid,
+}
+
+/// Starts a new session family for a login and stores its first refresh token.
+pub fn create(
+    conn: &Connection,
+    user_id: i64,
+    family: &str,
+    token_hash: &str,
+    now: i64,
+    expires: i64,
+) -> rusqlite::Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO SessionFamilies (ID, UserID, Created) VALUES (?1, ?2, ?3)",
+        params![family, user_id, now],
+    )?;
+    tx.execute(
+        "INSERT INTO RefreshTokens (TokenHash, FamilyID, Expires) VALUES (?1, ?2, ?3)",
+        params![token_hash, family, expires],
+    )?;
+    tx.commit()
+}
+
+pub fn rotate(
+    conn: &Connection,
+    old_hash: &str,
+    new_hash: &str,
+    now: i64,
+    expires: i64,
+) -> rusqlite::Result<Rotation> {
+    let tx = conn.unchecked_transaction()?;
+
+    let token = tx
+        .query_row(
+            "SELECT RefreshTokens.FamilyID, RefreshTokens.Expires, RefreshTokens.Used,
+                    SessionFamilies.UserID, SessionFamilies.Revoked
+             FROM RefreshTokens
+             JOIN SessionFamilies ON SessionFamilies.ID = RefreshTokens.FamilyID
+             WHERE RefreshTokens.TokenHash = ?1",
+            [old_hash],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, bool>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, bool>(4)?,
+                ))
+            },
+        )
+        .optional()?;
+
+    let rotation = match token {
+        None => Rotation::Invalid,
+        Some((_, _, _, _, true)) => Rotation::Invalid,
+        Some((family, _, true, user_id, _)) => {
+            // Somebody is replaying an old token, so assume it was stolen and kill every token in the family
+            revoke(&tx, &family)?;
+            Rotation::Reused { user_id }
+        }
+        Some((_, expires, _, _, _)) if expires <= now => Rotation::Invalid,
+        Some((family, _, _, user_id, _)) => {
+            tx.execute(
+                "UPDATE RefreshTokens SET Used = 1 WHERE TokenHash = ?1",
+                [old_hash],
+            )?;
+            tx.execute(
+                "INSERT INTO RefreshTokens (TokenHash, FamilyID, Expires) VALUES (?1, ?2, ?3)",
+                params![new_hash, family, expires],
+            )?;
+            Rotation::Rotated { user_id, family }
+        }
+    };
+
+    prune(&tx, now)?;
+    tx.commit()?;
+    Ok(rotation)
+}
+
+/// Forgets expired refresh tokens, and the families that have none left. Their access tokens have
+/// long expired by then, since refresh tokens outlive them.
+fn prune(conn: &Connection, now: i64) -> rusqlite::Result<()> {
+    conn.execute("DELETE FROM RefreshTokens WHERE Expires <= ?1", [now])?;
+    conn.execute(
+        "DELETE FROM SessionFamilies
+         WHERE NOT EXISTS (SELECT 1 FROM RefreshTokens WHERE FamilyID = SessionFamilies.ID)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Whether access tokens issued under the family are still good, which they aren't once it's been
+/// revoked (by logging out, or reusing a refresh token) or forgotten.
+pub fn is_active(conn: &Connection, family: &str) -> rusqlite::Result<bool> {
+    let revoked = conn
+        .query_row(
+            "SELECT Revoked FROM SessionFamilies WHERE ID = ?1",
+            [family],
+            |row| row.get::<_, bool>(0),
+        )
+        .optional()?;
+    Ok(revoked == Some(false))
+}
+
+pub fn revoke(conn: &Connection, family: &str) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE SessionFamilies SET Revoked = 1 WHERE ID = ?1",
+        [family],
+    )?;
+    Ok(())
+}
+
+/// Revokes whichever family the refresh token belongs to, if any, and returns whose it was.
+pub fn revoke_by_token(conn: &Connection, token_hash: &str) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "UPDATE SessionFamilies SET Revoked = 1
+         WHERE ID = (SELECT FamilyID FROM RefreshTokens WHERE TokenHash = ?1)
+         RETURNING UserID",
+        [token_hash],
+        |row| row.get(0),
+    )
+    .optional()
+}
@@ -0,0 +1,6 @@
+CREATE TABLE Users (
+	ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	Username TEXT NOT NULL UNIQUE COLLATE NOCASE,
+	Password TEXT NOT NULL,
+	Created INTEGER NOT NULL
+);
@@ -0,0 +1,16 @@
+CREATE TABLE SessionFamilies (
+	ID TEXT NOT NULL PRIMARY KEY,
+	UserID INTEGER NOT NULL REFERENCES Users(ID) ON DELETE CASCADE,
+	Created INTEGER NOT NULL,
+	Revoked BOOLEAN NOT NULL CHECK(Revoked BETWEEN 0 AND 1) DEFAULT 0
+);
+
+-- Every refresh token ever issued for a family is kept, so reusing a rotated one can be detected
+CREATE TABLE RefreshTokens (
+	TokenHash TEXT NOT NULL PRIMARY KEY,
+	FamilyID TEXT NOT NULL REFERENCES SessionFamilies(ID) ON DELETE CASCADE,
+	Expires INTEGER NOT NULL,
+	Used BOOLEAN NOT NULL CHECK(Used BETWEEN 0 AND 1) DEFAULT 0
+);
+
+CREATE INDEX RefreshTokensByFamily ON RefreshTokens(FamilyID);
@@ -0,0 +1,69 @@
+-- Task lists shared between users. Tasks without a list are private to whoever made them.
+CREATE TABLE Lists (
+	ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	Name TEXT NOT NULL,
+	Created INTEGER NOT NULL
+);
+
+-- Owners manage the list and its members, editors change its tasks, viewers only see them
+CREATE TABLE ListMembers (
+	ListID INTEGER NOT NULL REFERENCES Lists(ID) ON DELETE CASCADE,
+	UserID INTEGER NOT NULL REFERENCES Users(ID) ON DELETE CASCADE,
+	Role TEXT NOT NULL CHECK(Role IN ('owner', 'editor', 'viewer')),
+	Joined INTEGER NOT NULL,
+	PRIMARY KEY(ListID, UserID)
+);
+
+CREATE INDEX ListMembersByUser ON ListMembers(UserID);
+
+-- Links anyone can use to join a list until they expire. Only a hash of the token is stored.
+CREATE TABLE ListInvites (
+	TokenHash TEXT NOT NULL PRIMARY KEY,
+	ListID INTEGER NOT NULL REFERENCES Lists(ID) ON DELETE CASCADE,
+	Role TEXT NOT NULL CHECK(Role IN ('editor', 'viewer')),
+	CreatedBy INTEGER NOT NULL REFERENCES Users(ID) ON DELETE CASCADE,
+	Created INTEGER NOT NULL,
+	Expires INTEGER NOT NULL
+);
+
+CREATE INDEX ListInvitesByList ON ListInvites(ListID);
+
+-- Subtasks are always in the same list as their parent
+ALTER TABLE Tasks ADD COLUMN ListID INTEGER REFERENCES Lists(ID) ON DELETE CASCADE;
+CREATE INDEX TasksByList ON Tasks(ListID);
+
+-- Revisions keep track of the list too, so members can see (and restore) each other's changes
+DROP TRIGGER TaskRevisionsInsert;
+DROP TRIGGER TaskRevisionsUpdate;
+DROP TRIGGER TaskRevisionsDelete;
+
+CREATE TRIGGER TaskRevisionsInsert AFTER INSERT ON Tasks BEGIN
+	INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (new.UserID, new.ID, 'created', json_object(
+		'title', new.Title, 'notes', new.Notes, 'category', new.Category, 'start', new.Start, 'due', new.Due,
+		'completed', new.Completed, 'created', new.Created, 'recurrence', new.Recurrence, 'time_zone', new.TimeZone,
+		'uid', new.UID, 'parent_id', new.ParentID, 'list_id', new.ListID
+	), new.Updated);
+END;
+
+CREATE TRIGGER TaskRevisionsUpdate AFTER UPDATE ON Tasks
+WHEN old.Title IS NOT new.Title OR old.Notes IS NOT new.Notes OR old.Category IS NOT new.Category
+	OR old.Start IS NOT new.Start OR old.Due IS NOT new.Due OR old.Completed IS NOT new.Completed
+	OR old.Recurrence IS NOT new.Recurrence OR old.TimeZone IS NOT new.TimeZone OR old.UID IS NOT new.UID
+	OR old.ParentID IS NOT new.ParentID OR old.ListID IS NOT new.ListID
+BEGIN
+	INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (new.UserID, new.ID, 'updated', json_object(
+		'title', new.Title, 'notes', new.Notes, 'category', new.Category, 'start', new.Start, 'due', new.Due,
+		'completed', new.Completed, 'created', new.Created, 'recurrence', new.Recurrence, 'time_zone', new.TimeZone,
+		'uid', new.UID, 'parent_id', new.ParentID, 'list_id', new.ListID
+	), new.Updated);
+END;
+
+CREATE TRIGGER TaskRevisionsDelete AFTER DELETE ON Tasks
+WHEN EXISTS (SELECT 1 FROM Users WHERE ID = old.UserID)
+BEGIN
+	INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (old.UserID, old.ID, 'deleted', json_object(
+		'title', old.Title, 'notes', old.Notes, 'category', old.Category, 'start', old.Start, 'due', old.Due,
+		'completed', old.Completed, 'created', old.Created, 'recurrence', old.Recurrence, 'time_zone', old.TimeZone,
+		'uid', old.UID, 'parent_id', old.ParentID, 'list_id', old.ListID
+	), CAST(strftime('%s', 'now') AS INTEGER));
+END;
@@ -0,0 +1,8 @@
+-- Reminders that went out, so they aren't sent again after a restart. Reminders are for a task being due
+-- at a certain time, so moving the due date (or the next repeat coming up) gets a new one.
+CREATE TABLE SentReminders (
+	TaskID INTEGER NOT NULL REFERENCES Tasks(ID) ON DELETE CASCADE,
+	Due INTEGER NOT NULL,
+	Sent INTEGER NOT NULL,
+	PRIMARY KEY(TaskID, Due)
+);
@@ -0,0 +1,12 @@
+-- Time spent on tasks. A timer that's still running is an entry without an end, and each user only gets
+-- one of those at a time.
+CREATE TABLE TimeEntries (
+	ID INTEGER PRIMARY KEY AUTOINCREMENT,
+	UserID INTEGER NOT NULL REFERENCES Users(ID) ON DELETE CASCADE,
+	TaskID INTEGER NOT NULL REFERENCES Tasks(ID) ON DELETE CASCADE,
+	Start INTEGER NOT NULL,
+	End INTEGER CHECK (End >= Start),
+	Note TEXT NOT NULL DEFAULT ''
+);
+CREATE UNIQUE INDEX RunningTimers ON TimeEntries(UserID) WHERE End IS NULL;
+CREATE INDEX TimeEntriesByStart ON TimeEntries(UserID, Start);
@@ -0,0 +1,8 @@
+-- Live notepads, one per task, kept apart from the task's notes. The gateway edits them in memory and
+-- saves a snapshot every few seconds.
+CREATE TABLE Notepads (
+	TaskID INTEGER PRIMARY KEY REFERENCES Tasks(ID) ON DELETE CASCADE,
+	Text TEXT NOT NULL DEFAULT '',
+	Revision INTEGER NOT NULL DEFAULT 0,
+	Updated INTEGER NOT NULL
+);
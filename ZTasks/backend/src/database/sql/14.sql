@@ -0,0 +1,3 @@
+-- What each field was last set to through sync, before clashes with other fields were settled. Empty for
+-- writes from before this was kept.
+ALTER TABLE FieldClocks ADD COLUMN Value TEXT;
@@ -0,0 +1,39 @@
+-- Deleting a task only marks it, so restoring it brings back the same row along with its dependencies, time
+-- entries and everything else that hangs off it. DeletedWith is the task whose deletion took it away: itself,
+-- or the parent it went along with.
+ALTER TABLE Tasks ADD COLUMN Deleted INTEGER;
+ALTER TABLE Tasks ADD COLUMN DeletedWith INTEGER;
+
+-- Deleted tasks give up their UID, so it can be imported again
+DROP INDEX TasksByUID;
+CREATE UNIQUE INDEX TasksByUID ON Tasks(UserID, UID) WHERE Deleted IS NULL;
+CREATE INDEX TasksByDeletion ON Tasks(DeletedWith) WHERE DeletedWith IS NOT NULL;
+
+-- Coming back counts as a change even if nothing else changed, and going away is a deletion at the time the
+-- task was marked. Tasks that go away for good (with their list, or their user) don't get a revision.
+DROP TRIGGER TaskRevisionsUpdate;
+DROP TRIGGER TaskRevisionsDelete;
+
+CREATE TRIGGER TaskRevisionsUpdate AFTER UPDATE ON Tasks
+WHEN new.Deleted IS NULL AND (old.Deleted IS NOT NULL
+	OR old.Title IS NOT new.Title OR old.Notes IS NOT new.Notes OR old.Category IS NOT new.Category
+	OR old.Start IS NOT new.Start OR old.Due IS NOT new.Due OR old.Completed IS NOT new.Completed
+	OR old.Recurrence IS NOT new.Recurrence OR old.TimeZone IS NOT new.TimeZone OR old.UID IS NOT new.UID
+	OR old.ParentID IS NOT new.ParentID OR old.ListID IS NOT new.ListID)
+BEGIN
+	INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (new.UserID, new.ID, 'updated', json_object(
+		'title', new.Title, 'notes', new.Notes, 'category', new.Category, 'start', new.Start, 'due', new.Due,
+		'completed', new.Completed, 'created', new.Created, 'recurrence', new.Recurrence, 'time_zone', new.TimeZone,
+		'uid', new.UID, 'parent_id', new.ParentID, 'list_id', new.ListID
+	), new.Updated);
+END;
+
+CREATE TRIGGER TaskRevisionsDelete AFTER UPDATE OF Deleted ON Tasks
+WHEN old.Deleted IS NULL AND new.Deleted IS NOT NULL
+BEGIN
+	INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (old.UserID, old.ID, 'deleted', json_object(
+		'title', old.Title, 'notes', old.Notes, 'category', old.Category, 'start', old.Start, 'due', old.Due,
+		'completed', old.Completed, 'created', old.Created, 'recurrence', old.Recurrence, 'time_zone', old.TimeZone,
+		'uid', old.UID, 'parent_id', old.ParentID, 'list_id', old.ListID
+	), new.Deleted);
+END;
@@ -0,0 +1,6 @@
+-- Notepads are the task's notes now. Whatever was written in one is kept at the end of the notes.
+UPDATE Tasks SET Notes = Notes || CASE WHEN Notes = '' THEN '' ELSE char(10, 10) END
+	|| (SELECT Text FROM Notepads WHERE TaskID = Tasks.ID)
+WHERE ID IN (SELECT TaskID FROM Notepads WHERE Text != '');
+
+DROP TABLE Notepads;
@@ -0,0 +1,22 @@
+-- How soon a recurring task can next need a reminder, so the scheduler only expands the series that can.
+-- Next is NULL once the series is over. Changing when a task repeats, or any of its repeats, forgets it.
+CREATE TABLE ReminderCursors (
+	TaskID INTEGER PRIMARY KEY REFERENCES Tasks(ID) ON DELETE CASCADE,
+	Next INTEGER
+);
+
+CREATE TRIGGER ReminderCursorsTask AFTER UPDATE OF Start, Due, Completed, Recurrence, TimeZone, Deleted ON Tasks BEGIN
+	DELETE FROM ReminderCursors WHERE TaskID = new.ID;
+END;
+
+CREATE TRIGGER ReminderCursorsOccurrenceInsert AFTER INSERT ON TaskOccurrences BEGIN
+	DELETE FROM ReminderCursors WHERE TaskID = new.TaskID;
+END;
+
+CREATE TRIGGER ReminderCursorsOccurrenceUpdate AFTER UPDATE ON TaskOccurrences BEGIN
+	DELETE FROM ReminderCursors WHERE TaskID = new.TaskID;
+END;
+
+CREATE TRIGGER ReminderCursorsOccurrenceDelete AFTER DELETE ON TaskOccurrences BEGIN
+	DELETE FROM ReminderCursors WHERE TaskID = old.TaskID;
+END;
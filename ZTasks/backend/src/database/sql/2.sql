@@ -0,0 +1,16 @@
+CREATE TABLE Tasks (
+	ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	UserID INTEGER NOT NULL REFERENCES Users(ID) ON DELETE CASCADE,
+	Title TEXT NOT NULL,
+	Notes TEXT NOT NULL DEFAULT '',
+	Category TEXT,
+	-- UNIX timestamps, a task with both Start and Due spans a date range
+	Start INTEGER,
+	Due INTEGER,
+	Completed INTEGER,
+	Created INTEGER NOT NULL,
+	Updated INTEGER NOT NULL,
+	CHECK(Start IS NULL OR Due IS NULL OR Start <= Due)
+);
+
+CREATE INDEX TasksByUser ON Tasks(UserID);
@@ -0,0 +1,16 @@
+-- RRULE for recurring tasks, expanded from Start (or Due if there's no Start) in TimeZone
+ALTER TABLE Tasks ADD COLUMN Recurrence TEXT;
+ALTER TABLE Tasks ADD COLUMN TimeZone TEXT NOT NULL DEFAULT 'UTC';
+
+-- Changes to a single occurrence of a recurring task, keyed by when it was originally supposed to happen
+CREATE TABLE TaskOccurrences (
+	TaskID INTEGER NOT NULL REFERENCES Tasks(ID) ON DELETE CASCADE,
+	RecurrenceID INTEGER NOT NULL,
+	Title TEXT,
+	Notes TEXT,
+	Start INTEGER,
+	Due INTEGER,
+	Completed INTEGER,
+	Cancelled BOOLEAN NOT NULL CHECK(Cancelled BETWEEN 0 AND 1) DEFAULT 0,
+	PRIMARY KEY (TaskID, RecurrenceID)
+);
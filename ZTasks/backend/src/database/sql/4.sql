@@ -0,0 +1,4 @@
+-- iCalendar UID, kept across imports so importing the same file twice updates instead of duplicating
+ALTER TABLE Tasks ADD COLUMN UID TEXT;
+UPDATE Tasks SET UID = 'ztasks-' || ID || '@ztasks';
+CREATE UNIQUE INDEX TasksByUID ON Tasks(UserID, UID);
@@ -0,0 +1,10 @@
+-- Named task filters, see the filter module for the query syntax
+CREATE TABLE Views (
+	ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	UserID INTEGER NOT NULL REFERENCES Users(ID) ON DELETE CASCADE,
+	Name TEXT NOT NULL,
+	Query TEXT NOT NULL,
+	Created INTEGER NOT NULL,
+	Updated INTEGER NOT NULL,
+	UNIQUE(UserID, Name)
+);
@@ -0,0 +1,18 @@
+-- When each field of a task was last written through sync, for last-writer-wins merging
+CREATE TABLE FieldClocks (
+	TaskID INTEGER NOT NULL REFERENCES Tasks(ID) ON DELETE CASCADE,
+	Field TEXT NOT NULL,
+	Clock INTEGER NOT NULL,
+	ClientID TEXT NOT NULL,
+	PRIMARY KEY(TaskID, Field)
+);
+
+-- Tasks deleted through sync, so edits from devices that haven't heard about it yet don't bring them back
+CREATE TABLE Tombstones (
+	UserID INTEGER NOT NULL REFERENCES Users(ID) ON DELETE CASCADE,
+	UID TEXT NOT NULL,
+	Clock INTEGER NOT NULL,
+	ClientID TEXT NOT NULL,
+	Deleted INTEGER NOT NULL,
+	PRIMARY KEY(UserID, UID)
+);
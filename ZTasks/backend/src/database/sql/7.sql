@@ -0,0 +1,12 @@
+-- Subtasks, which go away along with their parent
+ALTER TABLE Tasks ADD COLUMN ParentID INTEGER REFERENCES Tasks(ID) ON DELETE CASCADE;
+CREATE INDEX TasksByParent ON Tasks(ParentID);
+
+-- TaskID can't be done before BlockerID is, both always belong to the same user
+CREATE TABLE TaskDependencies (
+	TaskID INTEGER NOT NULL REFERENCES Tasks(ID) ON DELETE CASCADE,
+	BlockerID INTEGER NOT NULL REFERENCES Tasks(ID) ON DELETE CASCADE,
+	PRIMARY KEY(TaskID, BlockerID)
+);
+
+CREATE INDEX DependenciesByBlocker ON TaskDependencies(BlockerID);
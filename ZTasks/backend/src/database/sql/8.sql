@@ -0,0 +1,23 @@
+-- Full-text index over titles and notes, kept in sync with Tasks by the triggers below
+CREATE VIRTUAL TABLE TaskSearch USING fts5(
+	Title,
+	Notes,
+	content = 'Tasks',
+	content_rowid = 'ID',
+	tokenize = 'unicode61 remove_diacritics 2'
+);
+
+INSERT INTO TaskSearch(TaskSearch) VALUES ('rebuild');
+
+CREATE TRIGGER TaskSearchInsert AFTER INSERT ON Tasks BEGIN
+	INSERT INTO TaskSearch(rowid, Title, Notes) VALUES (new.ID, new.Title, new.Notes);
+END;
+
+CREATE TRIGGER TaskSearchDelete AFTER DELETE ON Tasks BEGIN
+	INSERT INTO TaskSearch(TaskSearch, rowid, Title, Notes) VALUES ('delete', old.ID, old.Title, old.Notes);
+END;
+
+CREATE TRIGGER TaskSearchUpdate AFTER UPDATE OF Title, Notes ON Tasks BEGIN
+	INSERT INTO TaskSearch(TaskSearch, rowid, Title, Notes) VALUES ('delete', old.ID, old.Title, old.Notes);
+	INSERT INTO TaskSearch(rowid, Title, Notes) VALUES (new.ID, new.Title, new.Notes);
+END;
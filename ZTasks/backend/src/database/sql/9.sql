@@ -0,0 +1,56 @@
+-- Every version of every task, written by the triggers below so no way of changing a task can skip it.
+-- TaskID isn't a foreign key, the history of a deleted task stays around so it can be restored.
+CREATE TABLE TaskRevisions (
+	ID INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+	UserID INTEGER NOT NULL REFERENCES Users(ID) ON DELETE CASCADE,
+	TaskID INTEGER NOT NULL,
+	-- created, updated, deleted or restored
+	Kind TEXT NOT NULL,
+	-- JSON of the task after the change, or right before it for deletes
+	State TEXT NOT NULL,
+	Created INTEGER NOT NULL
+);
+
+CREATE INDEX TaskRevisionsByTask ON TaskRevisions(TaskID, ID);
+CREATE INDEX TaskRevisionsByUser ON TaskRevisions(UserID, Kind);
+
+-- Tasks from before there was history start out with a single revision
+INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created)
+SELECT UserID, ID, 'created', json_object(
+	'title', Title, 'notes', Notes, 'category', Category, 'start', Start, 'due', Due, 'completed', Completed,
+	'created', Created, 'recurrence', Recurrence, 'time_zone', TimeZone, 'uid', UID, 'parent_id', ParentID
+), Updated FROM Tasks;
+
+CREATE TRIGGER TaskRevisionsInsert AFTER INSERT ON Tasks BEGIN
+	INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (new.UserID, new.ID, 'created', json_object(
+		'title', new.Title, 'notes', new.Notes, 'category', new.Category, 'start', new.Start, 'due', new.Due,
+		'completed', new.Completed, 'created', new.Created, 'recurrence', new.Recurrence, 'time_zone', new.TimeZone,
+		'uid', new.UID, 'parent_id', new.ParentID
+	), new.Updated);
+END;
+
+-- Only changes someone could see count, not just bumping Updated
+CREATE TRIGGER TaskRevisionsUpdate AFTER UPDATE ON Tasks
+WHEN old.Title IS NOT new.Title OR old.Notes IS NOT new.Notes OR old.Category IS NOT new.Category
+	OR old.Start IS NOT new.Start OR old.Due IS NOT new.Due OR old.Completed IS NOT new.Completed
+	OR old.Recurrence IS NOT new.Recurrence OR old.TimeZone IS NOT new.TimeZone OR old.UID IS NOT new.UID
+	OR old.ParentID IS NOT new.ParentID
+BEGIN
+	INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (new.UserID, new.ID, 'updated', json_object(
+		'title', new.Title, 'notes', new.Notes, 'category', new.Category, 'start', new.Start, 'due', new.Due,
+		'completed', new.Completed, 'created', new.Created, 'recurrence', new.Recurrence, 'time_zone', new.TimeZone,
+		'uid', new.UID, 'parent_id', new.ParentID
+	), new.Updated);
+END;
+
+-- Subtasks deleted along with their parent get the same time, which is how they're restored together.
+-- Nothing is kept when the whole account goes.
+CREATE TRIGGER TaskRevisionsDelete AFTER DELETE ON Tasks
+WHEN EXISTS (SELECT 1 FROM Users WHERE ID = old.UserID)
+BEGIN
+	INSERT INTO TaskRevisions (UserID, TaskID, Kind, State, Created) VALUES (old.UserID, old.ID, 'deleted', json_object(
+		'title', old.Title, 'notes', old.Notes, 'category', old.Category, 'start', old.Start, 'due', old.Due,
+		'completed', old.Completed, 'created', old.Created, 'recurrence', old.Recurrence, 'time_zone', old.TimeZone,
+		'uid', old.UID, 'parent_id', old.ParentID
+	), CAST(strftime('%s', 'now') AS INTEGER));
+END;
@@ -0,0 +1,511 @@
+use super::revisions::State;
+use crate::filter::Filter;
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+const COLUMNS: &str = "ID, Title, Notes, Category, Start, Due, Completed, Created, Updated, Recurrence, TimeZone, UID, \
+                       ParentID, ListID";
+
+/// Tasks the user in `?1` can see: the ones they made outside of any list, and everything in their lists.
+/// Deleted tasks are left out.
+pub const VISIBLE: &str = "(Deleted IS NULL AND (ListID IS NULL AND UserID = ?1 \
+                           OR ListID IN (SELECT ListID FROM ListMembers WHERE UserID = ?1)))";
+/// Tasks the user in `?1` can change, like `VISIBLE` minus the lists they only view.
+pub const EDITABLE: &str = "(Deleted IS NULL AND (ListID IS NULL AND UserID = ?1 \
+                            OR ListID IN (SELECT ListID FROM ListMembers \
+                                          WHERE UserID = ?1 AND Role IN ('owner', 'editor'))))";
+/// Like `EDITABLE`, but deleted tasks count too.
+const RESTORABLE: &str =
+    "(ListID IS NULL AND UserID = ?1 OR ListID IN (SELECT ListID FROM ListMembers \
+                            WHERE UserID = ?1 AND Role IN ('owner', 'editor')))";
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Task {
+    pub id: i64,
+    pub title: String,
+    pub notes: String,
+    pub category: Option<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub due: Option<DateTime<Utc>>,
+    pub completed: Option<DateTime<Utc>>,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+    /// RRULE, see `calendar::recurrence`
+    pub recurrence: Option<String>,
+    /// IANA time zone that recurrences keep their wall clock time in
+    pub time_zone: String,
+    /// iCalendar UID
+    pub uid: String,
+    /// The task this is a subtask of
+    pub parent_id: Option<i64>,
+    /// The shared list it's in, if any
+    pub list_id: Option<i64>,
+}
+
+/// The user-editable part of a task.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskFields {
+    pub title: String,
+    pub notes: String,
+    pub category: Option<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub due: Option<DateTime<Utc>>,
+    pub recurrence: Option<String>,
+    pub time_zone: String,
+}
+
+impl Task {
+    pub fn fields(&self) -> TaskFields {
+        TaskFields {
+            title: self.title.clone(),
+            notes: self.notes.clone(),
+            category: self.category.clone(),
+            start: self.start,
+            due: self.due,
+            recurrence: self.recurrence.clone(),
+            time_zone: self.time_zone.clone(),
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            title: row.get(1)?,
+            notes: row.get(2)?,
+            category: row.get(3)?,
+            start: row.get::<_, Option<i64>>(4)?.map(from_timestamp),
+            due: row.get::<_, Option<i64>>(5)?.map(from_timestamp),
+            completed: row.get::<_, Option<i64>>(6)?.map(from_timestamp),
+            created: from_timestamp(row.get(7)?),
+            updated: from_timestamp(row.get(8)?),
+            recurrence: row.get(9)?,
+            time_zone: row.get(10)?,
+            uid: row.get(11)?,
+            parent_id: row.get(12)?,
+            list_id: row.get(13)?,
+        })
+    }
+}
+
+pub fn from_timestamp(timestamp: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(timestamp, 0).single().unwrap_or_default()
+}
+
+pub fn new_uid() -> String {
+    format!("{}@ztasks", Uuid::new_v4())
+}
+
+/// Inserts a new task, already in `list_id` and under `parent_id` so it's created there in one go.
+pub fn insert(
+    conn: &Connection,
+    user_id: i64,
+    fields: &TaskFields,
+    list_id: Option<i64>,
+    parent_id: Option<i64>,
+    now: i64,
+) -> rusqlite::Result<Task> {
+    insert_row(conn, user_id, &new_uid(), fields, list_id, parent_id, now)
+}
+
+/// Like `insert()`, but for top-level private tasks that already have a UID from somewhere else.
+pub fn insert_with_uid(
+    conn: &Connection,
+    user_id: i64,
+    uid: &str,
+    fields: &TaskFields,
+    now: i64,
+) -> rusqlite::Result<Task> {
+    insert_row(conn, user_id, uid, fields, None, None, now)
+}
+
+fn insert_row(
+    conn: &Connection,
+    user_id: i64,
+    uid: &str,
+    fields: &TaskFields,
+    list_id: Option<i64>,
+    parent_id: Option<i64>,
+    now: i64,
+) -> rusqlite::Result<Task> {
+    conn.execute(
+        "INSERT INTO Tasks (UserID, Title, Notes, Category, Start, Due, Created, Updated, Recurrence, TimeZone, UID, ListID, ParentID)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7, ?8, ?9, ?10, ?11, ?12)",
+        params![
+            user_id,
+            fields.title,
+            fields.notes,
+            fields.category,
+            fields.start.map(|time| time.timestamp()),
+            fields.due.map(|time| time.timestamp()),
+            now,
+            fields.recurrence,
+            fields.time_zone,
+            uid,
+            list_id,
+            parent_id,
+        ],
+    )?;
+
+    let id = conn.last_insert_rowid();
+    get(conn, user_id, id).map(|task| task.expect("Task was just inserted"))
+}
+
+pub fn list(conn: &Connection, user_id: i64) -> rusqlite::Result<Vec<Task>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM Tasks WHERE {VISIBLE} ORDER BY Due IS NULL, Due, ID"
+    ))?;
+    let tasks = stmt.query_map([user_id], Task::from_row)?;
+    tasks.collect()
+}
+
+/// Like `list()`, but only the tasks matching `filter`, with relative dates measured from `now`.
+pub fn list_matching(
+    conn: &Connection,
+    user_id: i64,
+    filter: &Filter,
+    now: DateTime<Utc>,
+) -> rusqlite::Result<Vec<Task>> {
+    let mut values = vec![Value::Integer(user_id)];
+    let condition = filter.to_sql(now, &mut values);
+
+    // The filter's own `?`s are numbered after `?1`
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM Tasks WHERE {VISIBLE} AND {condition} ORDER BY Due IS NULL, Due, ID"
+    ))?;
+    let tasks = stmt.query_map(params_from_iter(values), Task::from_row)?;
+    tasks.collect()
+}
+
+/// A task found by `search()`.
+pub struct Hit {
+    pub task: Task,
+    /// The title and a bit of the notes, with matches between `MATCH_START` and `MATCH_END`
+    pub title: String,
+    pub snippet: String,
+}
+
+pub const MATCH_START: char = '\u{2}';
+pub const MATCH_END: char = '\u{3}';
+
+/// Tasks matching an FTS5 `query`, best matches first. Title matches count for more than notes.
+pub fn search(
+    conn: &Connection,
+    user_id: i64,
+    query: &str,
+    limit: i64,
+) -> rusqlite::Result<Vec<Hit>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS}, Highlighted, Snippet FROM Tasks
+         JOIN (
+             SELECT rowid AS Hit,
+                    highlight(TaskSearch, 0, ?4, ?5) AS Highlighted,
+                    snippet(TaskSearch, 1, ?4, ?5, '…', 16) AS Snippet,
+                    bm25(TaskSearch, 10.0, 1.0) AS Rank
+             FROM TaskSearch WHERE TaskSearch MATCH ?2
+         ) ON ID = Hit
+         WHERE {VISIBLE}
+         ORDER BY Rank, ID
+         LIMIT ?3"
+    ))?;
+    let hits = stmt.query_map(
+        params![
+            user_id,
+            query,
+            limit,
+            MATCH_START.to_string(),
+            MATCH_END.to_string()
+        ],
+        |row| {
+            Ok(Hit {
+                task: Task::from_row(row)?,
+                title: row.get(14)?,
+                snippet: row.get(15)?,
+            })
+        },
+    )?;
+    hits.collect()
+}
+
+/// Tasks with any date in or around `[from, to)`, plus recurring tasks that started before `to`.
+/// Callers still need to check `Span::overlaps()` and expand recurrences.
+pub fn list_between(
+    conn: &Connection,
+    user_id: i64,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> rusqlite::Result<Vec<Task>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM Tasks
+         WHERE {VISIBLE} AND COALESCE(Start, Due) < ?3
+           AND (COALESCE(Due, Start) >= ?2 OR Recurrence IS NOT NULL)
+         ORDER BY COALESCE(Start, Due), ID"
+    ))?;
+    let tasks = stmt.query_map(
+        params![user_id, from.timestamp(), to.timestamp()],
+        Task::from_row,
+    )?;
+    tasks.collect()
+}
+
+pub fn find_by_uid(conn: &Connection, user_id: i64, uid: &str) -> rusqlite::Result<Option<Task>> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM Tasks WHERE {VISIBLE} AND UID = ?2 ORDER BY ListID IS NOT NULL, ID"),
+        params![user_id, uid],
+        Task::from_row,
+    )
+    .optional()
+}
+
+pub fn get(conn: &Connection, user_id: i64, id: i64) -> rusqlite::Result<Option<Task>> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM Tasks WHERE {VISIBLE} AND ID = ?2"),
+        [user_id, id],
+        Task::from_row,
+    )
+    .optional()
+}
+
+/// Returns `None` if the task doesn't exist (or belongs to someone else).
+pub fn update(
+    conn: &Connection,
+    user_id: i64,
+    id: i64,
+    fields: &TaskFields,
+    now: i64,
+) -> rusqlite::Result<Option<Task>> {
+    let changed = conn.execute(
+        &format!(
+            "UPDATE Tasks SET Title = ?3, Notes = ?4, Category = ?5, Start = ?6, Due = ?7, Updated = ?8,
+                              Recurrence = ?9, TimeZone = ?10
+             WHERE ID = ?2 AND {EDITABLE}"
+        ),
+        params![
+            user_id,
+            id,
+            fields.title,
+            fields.notes,
+            fields.category,
+            fields.start.map(|time| time.timestamp()),
+            fields.due.map(|time| time.timestamp()),
+            now,
+            fields.recurrence,
+            fields.time_zone,
+        ],
+    )?;
+
+    match changed {
+        0 => Ok(None),
+        _ => get(conn, user_id, id),
+    }
+}
+
+/// Marks a task as done at `completed`, or as not done if `None`.
+pub fn set_completed(
+    conn: &Connection,
+    user_id: i64,
+    id: i64,
+    completed: Option<i64>,
+    now: i64,
+) -> rusqlite::Result<Option<Task>> {
+    let changed = conn.execute(
+        &format!("UPDATE Tasks SET Completed = ?3, Updated = ?4 WHERE ID = ?2 AND {EDITABLE}"),
+        params![user_id, id, completed, now],
+    )?;
+
+    match changed {
+        0 => Ok(None),
+        _ => get(conn, user_id, id),
+    }
+}
+
+/// Moves a task under `parent_id`, or back to the top level if `None`.
+pub fn set_parent(
+    conn: &Connection,
+    user_id: i64,
+    id: i64,
+    parent_id: Option<i64>,
+    now: i64,
+) -> rusqlite::Result<Option<Task>> {
+    let changed = conn.execute(
+        &format!("UPDATE Tasks SET ParentID = ?3, Updated = ?4 WHERE ID = ?2 AND {EDITABLE}"),
+        params![user_id, id, parent_id, now],
+    )?;
+
+    match changed {
+        0 => Ok(None),
+        _ => get(conn, user_id, id),
+    }
+}
+
+/// Unfinished tasks of every user that are due in `[from, to)`, and the recurring tasks that can have a
+/// repeat due before `to` (see `reminders::set_next()`), along with whose they are.
+pub fn upcoming(conn: &Connection, from: i64, to: i64) -> rusqlite::Result<Vec<(i64, Task)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS}, UserID FROM Tasks LEFT JOIN ReminderCursors ON TaskID = ID
+         WHERE Deleted IS NULL AND Completed IS NULL
+           AND (Recurrence IS NULL AND Due >= ?1 AND Due < ?2
+                OR Recurrence IS NOT NULL AND (TaskID IS NULL OR Next < ?2))"
+    ))?;
+    let tasks = stmt.query_map([from, to], |row| Ok((row.get(14)?, Task::from_row(row)?)))?;
+    tasks.collect()
+}
+
+/// The direct subtasks of a task.
+pub fn subtasks(conn: &Connection, user_id: i64, id: i64) -> rusqlite::Result<Vec<Task>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM Tasks WHERE {VISIBLE} AND ParentID = ?2 ORDER BY Due IS NULL, Due, ID"
+    ))?;
+    let tasks = stmt.query_map([user_id, id], Task::from_row)?;
+    tasks.collect()
+}
+
+/// Every subtask below a task, however deep, in no particular order.
+pub fn descendants(conn: &Connection, user_id: i64, id: i64) -> rusqlite::Result<Vec<Task>> {
+    let mut stmt = conn.prepare(&format!(
+        "WITH RECURSIVE Below(ID) AS (
+             SELECT ID FROM Tasks WHERE {VISIBLE} AND ParentID = ?2
+             UNION SELECT Tasks.ID FROM Tasks JOIN Below ON Tasks.ParentID = Below.ID
+         )
+         SELECT {COLUMNS} FROM Tasks WHERE ID IN Below AND Deleted IS NULL"
+    ))?;
+    let tasks = stmt.query_map([user_id, id], Task::from_row)?;
+    tasks.collect()
+}
+
+/// Deletes a task along with its subtasks, and returns everything that was deleted (the task itself first).
+/// Returns nothing if the user can't change the task. The rows stay around, marked as deleted at `now`, so
+/// `restore()` can bring them back.
+pub fn delete(conn: &Connection, user_id: i64, id: i64, now: i64) -> rusqlite::Result<Vec<Task>> {
+    let Some(task) = get(conn, user_id, id)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut deleted = vec![task];
+    deleted.extend(descendants(conn, user_id, id)?);
+
+    let changed = conn.execute(
+        &format!(
+            "WITH RECURSIVE Below(ID) AS (
+                 SELECT ID FROM Tasks WHERE ID = ?2 AND {EDITABLE}
+                 UNION SELECT Tasks.ID FROM Tasks JOIN Below ON Tasks.ParentID = Below.ID
+             )
+             UPDATE Tasks SET Deleted = ?3, DeletedWith = ?2 WHERE ID IN Below AND Deleted IS NULL"
+        ),
+        [user_id, id, now],
+    )?;
+    if changed == 0 {
+        return Ok(Vec::new());
+    }
+
+    // Timers can't keep running on tasks that are gone, and they'd be in the way of starting a new one
+    conn.execute(
+        "UPDATE TimeEntries SET End = MAX(?2, Start + 1)
+         WHERE End IS NULL AND TaskID IN (SELECT ID FROM Tasks WHERE DeletedWith = ?1)",
+        [id, now],
+    )?;
+
+    Ok(deleted)
+}
+
+/// The task whose deletion took a deleted task away (which may be the task itself), so everything that went
+/// with it can come back together. `None` if the task isn't deleted, or never was here.
+pub fn deletion(conn: &Connection, id: i64) -> rusqlite::Result<Option<i64>> {
+    conn.query_row(
+        "SELECT DeletedWith FROM Tasks WHERE ID = ?1 AND Deleted IS NOT NULL",
+        [id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map(Option::flatten)
+}
+
+/// Puts a task back the way `state` describes, bringing it back under the same ID (and for `owner_id`) if
+/// it was deleted. Returns `None` if the task exists but `user_id` can't change it. The caller is in charge
+/// of `state.parent_id`, `state.list_id` and `state.uid` still making sense.
+pub fn restore(
+    conn: &Connection,
+    user_id: i64,
+    owner_id: i64,
+    id: i64,
+    state: &State,
+    now: i64,
+) -> rusqlite::Result<Option<Task>> {
+    conn.execute(
+        &format!(
+            "INSERT INTO Tasks (UserID, ID, Title, Notes, Category, Start, Due, Completed, Created, Updated,
+                                Recurrence, TimeZone, UID, ParentID, ListID)
+             VALUES (?15, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?16)
+             ON CONFLICT(ID) DO UPDATE SET Title = excluded.Title, Notes = excluded.Notes,
+                 Category = excluded.Category, Start = excluded.Start, Due = excluded.Due,
+                 Completed = excluded.Completed, Updated = excluded.Updated, Recurrence = excluded.Recurrence,
+                 TimeZone = excluded.TimeZone, UID = excluded.UID, ParentID = excluded.ParentID,
+                 ListID = excluded.ListID, Deleted = NULL, DeletedWith = NULL
+             WHERE {RESTORABLE}"
+        ),
+        params![
+            user_id,
+            id,
+            state.title,
+            state.notes,
+            state.category,
+            state.start,
+            state.due,
+            state.completed,
+            state.created,
+            now,
+            state.recurrence,
+            state.time_zone,
+            state.uid,
+            state.parent_id,
+            owner_id,
+            state.list_id,
+        ],
+    )?;
+
+    get(conn, user_id, id)
+}
+
+/// Moves a task and all of its subtasks into `list_id`, or out of any list if `None`, in which case they
+/// become private tasks of whoever moved them.
+pub fn set_list(
+    conn: &Connection,
+    user_id: i64,
+    id: i64,
+    list_id: Option<i64>,
+    now: i64,
+) -> rusqlite::Result<Option<Task>> {
+    let changed = conn.execute(
+        &format!(
+            "WITH RECURSIVE Moving(ID) AS (
+                 SELECT ?2 UNION SELECT Tasks.ID FROM Tasks JOIN Moving ON Tasks.ParentID = Moving.ID
+             )
+             UPDATE Tasks SET ListID = ?3, UserID = CASE WHEN ?3 IS NULL THEN ?1 ELSE UserID END, Updated = ?4
+             WHERE ID IN Moving AND {EDITABLE}"
+        ),
+        params![user_id, id, list_id, now],
+    )?;
+
+    match changed {
+        0 => Ok(None),
+        _ => get(conn, user_id, id),
+    }
+}
+
+/// Sets aside `count` task IDs that won't be handed out to new tasks, for the history of tasks that were
+/// deleted somewhere else. Returns the first one.
+pub fn reserve_ids(conn: &Connection, count: i64) -> rusqlite::Result<i64> {
+    let last: i64 = conn.query_row(
+        "SELECT COALESCE(MAX(seq), 0) FROM sqlite_sequence WHERE name = 'Tasks'",
+        [],
+        |row| row.get(0),
+    )?;
+    conn.execute("DELETE FROM sqlite_sequence WHERE name = 'Tasks'", [])?;
+    conn.execute(
+        "INSERT INTO sqlite_sequence (name, seq) VALUES ('Tasks', ?1)",
+        [last + count],
+    )?;
+    Ok(last + 1)
+}
@@ -0,0 +1,134 @@
+use super::tasks::from_timestamp;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+const COLUMNS: &str = "TimeEntries.ID, TaskID, TimeEntries.Start, End, Note";
+
+/// Time a user spent on a task. Entries without an `end` are running timers.
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct Entry {
+    pub id: i64,
+    pub task_id: i64,
+    pub start: DateTime<Utc>,
+    pub end: Option<DateTime<Utc>>,
+    pub note: String,
+}
+
+impl Entry {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            task_id: row.get(1)?,
+            start: from_timestamp(row.get(2)?),
+            end: row.get::<_, Option<i64>>(3)?.map(from_timestamp),
+            note: row.get(4)?,
+        })
+    }
+
+    /// How long it's been going on for, up to `now` if it still is.
+    pub fn seconds(&self, now: DateTime<Utc>) -> i64 {
+        (self.end.unwrap_or(now) - self.start).num_seconds().max(0)
+    }
+}
+
+/// Adds an entry, or starts a timer if `end` is `None`. Fails on the `RunningTimers` index if the user
+/// already has one going, so check `running()` first.
+pub fn insert(
+    conn: &Connection,
+    user_id: i64,
+    task_id: i64,
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    note: &str,
+) -> rusqlite::Result<Entry> {
+    conn.execute(
+        "INSERT INTO TimeEntries (UserID, TaskID, Start, End, Note) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![
+            user_id,
+            task_id,
+            start.timestamp(),
+            end.map(|end| end.timestamp()),
+            note
+        ],
+    )?;
+    Ok(Entry {
+        id: conn.last_insert_rowid(),
+        task_id,
+        start,
+        end,
+        note: note.to_string(),
+    })
+}
+
+/// The user's running timer, if they have one.
+pub fn running(conn: &Connection, user_id: i64) -> rusqlite::Result<Option<Entry>> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM TimeEntries WHERE UserID = ?1 AND End IS NULL"),
+        [user_id],
+        Entry::from_row,
+    )
+    .optional()
+}
+
+pub fn get(conn: &Connection, user_id: i64, id: i64) -> rusqlite::Result<Option<Entry>> {
+    conn.query_row(
+        &format!("SELECT {COLUMNS} FROM TimeEntries WHERE UserID = ?1 AND ID = ?2"),
+        [user_id, id],
+        Entry::from_row,
+    )
+    .optional()
+}
+
+pub fn update(conn: &Connection, entry: &Entry) -> rusqlite::Result<()> {
+    conn.execute(
+        "UPDATE TimeEntries SET Start = ?2, End = ?3, Note = ?4 WHERE ID = ?1",
+        params![
+            entry.id,
+            entry.start.timestamp(),
+            entry.end.map(|end| end.timestamp()),
+            entry.note
+        ],
+    )?;
+    Ok(())
+}
+
+/// Returns whether there was anything to delete.
+pub fn delete(conn: &Connection, user_id: i64, id: i64) -> rusqlite::Result<bool> {
+    let deleted = conn.execute(
+        "DELETE FROM TimeEntries WHERE UserID = ?1 AND ID = ?2",
+        [user_id, id],
+    )?;
+    Ok(deleted > 0)
+}
+
+/// The user's entries for one task, oldest first.
+pub fn list(conn: &Connection, user_id: i64, task_id: i64) -> rusqlite::Result<Vec<Entry>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS} FROM TimeEntries WHERE UserID = ?1 AND TaskID = ?2 ORDER BY Start, ID"
+    ))?;
+    let entries = stmt.query_map([user_id, task_id], Entry::from_row)?;
+    entries.collect()
+}
+
+/// The user's entries that overlap `[from, to)`, with the category of their task. Running timers count
+/// as going on until `now`. Time spent on deleted tasks is left out until they're restored.
+pub fn list_between(
+    conn: &Connection,
+    user_id: i64,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> rusqlite::Result<Vec<(Entry, Option<String>)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {COLUMNS}, Tasks.Category FROM TimeEntries JOIN Tasks ON Tasks.ID = TaskID
+         WHERE TimeEntries.UserID = ?1 AND Tasks.Deleted IS NULL AND TimeEntries.Start < ?3 AND COALESCE(End, ?4) > ?2
+         ORDER BY TimeEntries.Start, TimeEntries.ID"
+    ))?;
+    let entries = stmt.query_map(
+        params![user_id, from.timestamp(), to.timestamp(), now.timestamp()],
+        |row| Ok((Entry::from_row(row)?, row.get(5)?)),
+    )?;
+    entries.collect()
+}
@@ -0,0 +1,68 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    #[serde(skip)]
+    pub password: String,
+}
+
+/// Returns `None` if the username is already taken.
+pub fn create(
+    conn: &Connection,
+    username: &str,
+    password: &str,
+    now: i64,
+) -> rusqlite::Result<Option<User>> {
+    let result = conn.execute(
+        "INSERT INTO Users (Username, Password, Created) VALUES (?1, ?2, ?3)",
+        params![username, password, now],
+    );
+
+    match result {
+        Ok(_) => Ok(Some(User {
+            id: conn.last_insert_rowid(),
+            username: username.to_string(),
+            password: password.to_string(),
+        })),
+        Err(rusqlite::Error::SqliteFailure(error, _))
+            if error.code == rusqlite::ErrorCode::ConstraintViolation =>
+        {
+            Ok(None)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+pub fn find_by_username(conn: &Connection, username: &str) -> rusqlite::Result<Option<User>> {
+    conn.query_row(
+        "SELECT ID, Username, Password FROM Users WHERE Username = ?1",
+        [username],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                password: row.get(2)?,
+            })
+        },
+    )
+    .optional()
+}
+
+pub fn find_by_id(conn: &Connection, id: i64) -> rusqlite::Result<Option<User>> {
+    conn.query_row(
+        "SELECT ID, Username, Password FROM Users WHERE ID = ?1",
+        [id],
+        |row| {
+            Ok(User {
+                id: row.get(0)?,
+                username: row.get(1)?,
+                password: row.get(2)?,
+            })
+        },
+    )
+    .optional()
+}
@@ -0,0 +1,67 @@
+use super::tasks::from_timestamp;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+/// A named filter, see `crate::filter`.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct View {
+    pub name: String,
+    pub query: String,
+    pub created: DateTime<Utc>,
+    pub updated: DateTime<Utc>,
+}
+
+impl View {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            name: row.get(0)?,
+            query: row.get(1)?,
+            created: from_timestamp(row.get(2)?),
+            updated: from_timestamp(row.get(3)?),
+        })
+    }
+}
+
+pub fn list(conn: &Connection, user_id: i64) -> rusqlite::Result<Vec<View>> {
+    let mut stmt = conn.prepare(
+        "SELECT Name, Query, Created, Updated FROM Views WHERE UserID = ?1 ORDER BY Name",
+    )?;
+    let views = stmt.query_map([user_id], View::from_row)?;
+    views.collect()
+}
+
+pub fn get(conn: &Connection, user_id: i64, name: &str) -> rusqlite::Result<Option<View>> {
+    conn.query_row(
+        "SELECT Name, Query, Created, Updated FROM Views WHERE UserID = ?1 AND Name = ?2",
+        params![user_id, name],
+        View::from_row,
+    )
+    .optional()
+}
+
+/// Creates the view or replaces its query if one with the same name already exists.
+pub fn save(
+    conn: &Connection,
+    user_id: i64,
+    name: &str,
+    query: &str,
+    now: i64,
+) -> rusqlite::Result<View> {
+    conn.execute(
+        "INSERT INTO Views (UserID, Name, Query, Created, Updated) VALUES (?1, ?2, ?3, ?4, ?4)
+         ON CONFLICT(UserID, Name) DO UPDATE SET Query = excluded.Query, Updated = excluded.Updated",
+        params![user_id, name, query, now],
+    )?;
+    get(conn, user_id, name).map(|view| view.expect("View was just saved"))
+}
+
+/// Returns whether anything was deleted.
+pub fn delete(conn: &Connection, user_id: i64, name: &str) -> rusqlite::Result<bool> {
+    let deleted = conn.execute(
+        "DELETE FROM Views WHERE UserID = ?1 AND Name = ?2",
+        params![user_id, name],
+    )?;
+    Ok(deleted > 0)
+}
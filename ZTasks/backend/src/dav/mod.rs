@@ -0,0 +1,1224 @@
+//! Enough of CalDAV (RFC 4791) for phone and desktop calendar apps to sync tasks. Each category is a
+//! calendar of VTODOs, and tasks without one are in a calendar called "Tasks". Only the user's own tasks
+//! are in there, not the ones in shared lists.
+//!
+//! Clients log in with HTTP Basic, using the same username and password as the app:
+//!
+//! - `/dav/principals/<username>/`
+//! - `/dav/calendars/<username>/` lists the calendars
+//! - `/dav/calendars/<username>/<category>/` (`_` for no category)
+//! - `/dav/calendars/<username>/<category>/<uid>.ics`
+
+mod xml;
+
+use crate::{
+    auth::{password, tokens},
+    calendar::{self, ics, Span},
+    database::{
+        occurrences::{self, Override},
+        tasks::{self, Task},
+        users::{self, User},
+        Database,
+    },
+    error::ApiError,
+    gateway::Gateway,
+    limiter::{self, Limiter},
+    util::now,
+};
+use actix_web::{
+    http::{header, Method, StatusCode},
+    middleware,
+    rt::time::Instant,
+    web, HttpRequest, HttpResponse, HttpResponseBuilder,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rusqlite::Connection;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+use xml::{Multistatus, Name, Props, CALDAV, CALENDARSERVER, DAV};
+
+const PREFIX: &str = "/dav";
+/// The path segment of the calendar for tasks without a category. Underscores in categories are always
+/// encoded, so no category can end up here.
+const UNCATEGORIZED: &str = "_";
+/// Everything but letters, digits, `-` and `.` is percent-encoded in paths.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.');
+const CALENDAR_CONTENT_TYPE: &str = "text/calendar; charset=utf-8; component=VTODO";
+const ALLOW: &str = "OPTIONS, PROPFIND, REPORT, GET, HEAD, PUT, DELETE";
+/// How long a username and password are trusted after checking them. Clients send them with every request,
+/// and checking is slow on purpose.
+const LOGIN_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// What's answered for `<allprop/>`. Calendar data is left out on purpose, see RFC 4791 section 9.6.
+const ALL_PROPS: [(&str, &str); 7] = [
+    (DAV, "resourcetype"),
+    (DAV, "displayname"),
+    (DAV, "current-user-principal"),
+    (DAV, "getetag"),
+    (DAV, "getcontenttype"),
+    (CALDAV, "calendar-home-set"),
+    (CALENDARSERVER, "getctag"),
+];
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/.well-known/caldav", web::to(well_known))
+        .service(
+            web::scope(PREFIX)
+                .wrap(middleware::from_fn(limiter::middleware))
+                .default_service(web::to(handle)),
+        );
+}
+
+/// Where clients that are only given the server's address find the calendars, see RFC 6764.
+async fn well_known() -> HttpResponse {
+    HttpResponse::MovedPermanently()
+        .insert_header((header::LOCATION, format!("{PREFIX}/")))
+        .finish()
+}
+
+#[derive(Debug, PartialEq)]
+enum Resource {
+    Root,
+    Principal(String),
+    Home(String),
+    /// `None` is the calendar for tasks without a category
+    Calendar(String, Option<String>),
+    /// A task, by its UID
+    Object(String, Option<String>, String),
+}
+
+impl Resource {
+    fn parse(path: &str) -> Option<Self> {
+        // Multiget hrefs can be whole URLs
+        let path = match path.split_once("://") {
+            Some((_, rest)) => &rest[rest.find('/')?..],
+            None => path,
+        };
+        let path = path.strip_prefix(PREFIX)?;
+        if !path.is_empty() && !path.starts_with('/') {
+            return None;
+        }
+
+        let decode = |segment: &str| {
+            percent_decode_str(segment)
+                .decode_utf8()
+                .ok()
+                .map(|segment| segment.into_owned())
+        };
+        let category = |segment: &str| match segment {
+            UNCATEGORIZED => Some(None),
+            _ => decode(segment).map(Some),
+        };
+
+        let segments: Vec<_> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty())
+            .collect();
+        Some(match segments[..] {
+            [] => Self::Root,
+            ["principals", user] => Self::Principal(decode(user)?),
+            ["calendars", user] => Self::Home(decode(user)?),
+            ["calendars", user, calendar] => Self::Calendar(decode(user)?, category(calendar)?),
+            ["calendars", user, calendar, name] => Self::Object(
+                decode(user)?,
+                category(calendar)?,
+                decode(name.strip_suffix(".ics")?)?,
+            ),
+            _ => return None,
+        })
+    }
+
+    fn owner(&self) -> Option<&str> {
+        match self {
+            Self::Root => None,
+            Self::Principal(user)
+            | Self::Home(user)
+            | Self::Calendar(user, _)
+            | Self::Object(user, ..) => Some(user),
+        }
+    }
+}
+
+fn encode(segment: &str) -> String {
+    utf8_percent_encode(segment, SEGMENT).to_string()
+}
+
+fn principal_href(user: &User) -> String {
+    format!("{PREFIX}/principals/{}/", user.username)
+}
+
+fn home_href(user: &User) -> String {
+    format!("{PREFIX}/calendars/{}/", user.username)
+}
+
+fn calendar_href(user: &User, category: Option<&str>) -> String {
+    let segment = category.map_or(UNCATEGORIZED.to_string(), encode);
+    format!("{}{segment}/", home_href(user))
+}
+
+fn object_href(user: &User, task: &Task) -> String {
+    format!(
+        "{}{}.ics",
+        calendar_href(user, task.category.as_deref()),
+        encode(&task.uid)
+    )
+}
+
+/// A quoted hash of `content`, ready to go in an `ETag` header.
+fn etag(content: &str) -> String {
+    let hash: String = Sha256::digest(content.as_bytes())[..8]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("\"{hash}\"")
+}
+
+/// A task as a calendar object.
+struct Object {
+    task: Task,
+    overrides: Vec<Override>,
+    ics: String,
+    etag: String,
+}
+
+impl Object {
+    fn new(task: Task, overrides: Vec<Override>) -> Self {
+        let ics = ics::export_todo(&task, &overrides);
+        let etag = etag(&ics);
+        Self {
+            task,
+            overrides,
+            ics,
+            etag,
+        }
+    }
+}
+
+struct Calendar {
+    category: Option<String>,
+    objects: Vec<Object>,
+}
+
+impl Calendar {
+    fn name(&self) -> &str {
+        self.category.as_deref().unwrap_or("Tasks")
+    }
+
+    /// Changes whenever anything in the calendar does, so clients know when to look closer.
+    fn ctag(&self) -> String {
+        etag(
+            &self
+                .objects
+                .iter()
+                .map(|object| object.etag.as_str())
+                .collect::<String>(),
+        )
+    }
+}
+
+/// Every calendar of the user, the one for tasks without a category first. That one is always there, the
+/// others only as long as some task has their category.
+fn calendars(conn: &Connection, user_id: i64) -> rusqlite::Result<Vec<Calendar>> {
+    let mut calendars: BTreeMap<Option<String>, Vec<Object>> = BTreeMap::from([(None, Vec::new())]);
+
+    for task in tasks::list(conn, user_id)? {
+        if task.list_id.is_some() {
+            continue;
+        }
+        let overrides = occurrences::list(conn, task.id)?;
+        calendars
+            .entry(task.category.clone())
+            .or_default()
+            .push(Object::new(task, overrides));
+    }
+
+    Ok(calendars
+        .into_iter()
+        .map(|(category, objects)| Calendar { category, objects })
+        .collect())
+}
+
+fn find_calendar(
+    conn: &Connection,
+    user_id: i64,
+    category: &Option<String>,
+) -> rusqlite::Result<Option<Calendar>> {
+    Ok(calendars(conn, user_id)?
+        .into_iter()
+        .find(|calendar| calendar.category == *category))
+}
+
+fn find_object(
+    conn: &Connection,
+    user_id: i64,
+    category: &Option<String>,
+    uid: &str,
+) -> rusqlite::Result<Option<Object>> {
+    let Some(task) = tasks::find_by_uid(conn, user_id, uid)?
+        .filter(|task| task.list_id.is_none() && task.category == *category)
+    else {
+        return Ok(None);
+    };
+
+    let overrides = occurrences::list(conn, task.id)?;
+    Ok(Some(Object::new(task, overrides)))
+}
+
+fn not_found() -> ApiError {
+    ApiError::NotFound("Not found.".to_string())
+}
+
+/// Answers every method on everything under `/dav`.
+async fn handle(
+    req: HttpRequest,
+    body: web::Bytes,
+    db: web::Data<Database>,
+    limiter: web::Data<Limiter>,
+    logins: web::Data<Logins>,
+    gateway: Gateway,
+) -> Result<HttpResponse, ApiError> {
+    // Clients ask what's supported before logging in
+    if req.method() == Method::OPTIONS {
+        return Ok(capabilities(HttpResponse::Ok()).finish());
+    }
+
+    let Some(user) = authenticate(&req, &db, &limiter, &logins).await? else {
+        return Ok(HttpResponse::Unauthorized()
+            .insert_header((
+                header::WWW_AUTHENTICATE,
+                "Basic realm=\"ZTasks\", charset=\"UTF-8\"",
+            ))
+            .finish());
+    };
+
+    // Other people's calendars might as well not exist
+    let resource = Resource::parse(req.uri().path())
+        .filter(|resource| resource.owner().is_none_or(|owner| owner == user.username))
+        .ok_or_else(not_found)?;
+    let body = std::str::from_utf8(&body)
+        .map_err(|_| ApiError::BadRequest("The body has to be UTF-8.".to_string()))?;
+
+    let conn = db.lock();
+    match req.method().as_str() {
+        "PROPFIND" => propfind(&conn, &user, &resource, depth(&req), body),
+        "REPORT" => report(&conn, &user, &resource, body),
+        "GET" | "HEAD" => get(&conn, &user, &resource),
+        "PUT" => put(&conn, &user, &gateway, &resource, &req, body),
+        "DELETE" => delete(&conn, &user, &gateway, &resource, &req),
+        _ => Ok(capabilities(HttpResponse::MethodNotAllowed()).finish()),
+    }
+}
+
+fn capabilities(mut response: HttpResponseBuilder) -> HttpResponseBuilder {
+    response
+        .insert_header(("DAV", "1, 3, calendar-access"))
+        .insert_header((header::ALLOW, ALLOW));
+    response
+}
+
+/// The username and password from an `Authorization: Basic` header.
+fn credentials(req: &HttpRequest) -> Option<(String, String)> {
+    let encoded = req
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (username, password) = decoded.split_once(':')?;
+    Some((username.to_string(), password.to_string()))
+}
+
+/// Recently checked logins, by a hash of the stored password hash and the password that matched it, along
+/// with whose they are. A changed password changes the stored hash, so old entries stop matching right away.
+#[derive(Default)]
+pub struct Logins {
+    entries: Mutex<HashMap<String, (i64, Instant)>>,
+}
+
+impl Logins {
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, (i64, Instant)>> {
+        // Entries are only ever inserted or removed whole, so a panic elsewhere doesn't matter
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn contains(&self, key: &str, now: Instant) -> bool {
+        let mut entries = self.lock();
+        entries.retain(|_, (_, until)| *until > now);
+        entries.contains_key(key)
+    }
+
+    fn insert(&self, key: String, user_id: i64, now: Instant) {
+        self.lock().insert(key, (user_id, now + LOGIN_LIFETIME));
+    }
+
+    /// Makes the user's clients check their password again, for when their sessions are revoked.
+    pub fn forget(&self, user_id: i64) {
+        self.lock().retain(|_, (id, _)| *id != user_id);
+    }
+}
+
+/// Checks the login the same way `/api/login` does, lockouts included. Returns `None` without one, or if
+/// it's wrong.
+async fn authenticate(
+    req: &HttpRequest,
+    db: &Database,
+    limiter: &Limiter,
+    logins: &Logins,
+) -> Result<Option<User>, ApiError> {
+    let Some((username, password)) = credentials(req) else {
+        return Ok(None);
+    };
+
+    let user = users::find_by_username(&db.lock(), &username)?;
+    let key = user
+        .as_ref()
+        .map(|user| tokens::hash_token(&format!("{}\0{password}", user.password)));
+    if key
+        .as_ref()
+        .is_some_and(|key| logins.contains(key, Instant::now()))
+    {
+        return Ok(user);
+    }
+
+    let ip = limiter.client_ip(req);
+    limiter.login(ip, &username, Instant::now())?;
+
+    let (user, valid) = web::block(move || {
+        let hash = match &user {
+            Some(user) => user.password.as_str(),
+            None => password::dummy_hash(),
+        };
+        let valid = password::verify(&password, hash);
+        (user, valid)
+    })
+    .await?;
+
+    match (user, key) {
+        (Some(user), Some(key)) if valid => {
+            limiter.login_succeeded(ip, &username);
+            logins.insert(key, user.id, Instant::now());
+            Ok(Some(user))
+        }
+        _ => {
+            limiter.login_failed(ip, &username, Instant::now());
+            Ok(None)
+        }
+    }
+}
+
+/// `Depth: 0` only looks at the resource itself, anything else at its children too.
+fn depth(req: &HttpRequest) -> u8 {
+    match req
+        .headers()
+        .get("Depth")
+        .and_then(|depth| depth.to_str().ok())
+    {
+        Some("0") => 0,
+        _ => 1,
+    }
+}
+
+/// What a property is being looked up on, with what's needed to answer.
+enum Target<'a> {
+    Root,
+    Principal,
+    Home,
+    Calendar(&'a Calendar),
+    Object(&'a Object),
+}
+
+/// The inner XML of a property, or `None` if `target` doesn't have it.
+fn property(user: &User, target: &Target, name: &Name) -> Option<String> {
+    let href = |href: String| format!("<D:href>{}</D:href>", xml::escape(&href));
+
+    let value = match (name.namespace.as_str(), name.local.as_str(), target) {
+        (DAV, "resourcetype", Target::Object(_)) => String::new(),
+        (DAV, "resourcetype", Target::Principal) => "<D:collection/><D:principal/>".to_string(),
+        (DAV, "resourcetype", Target::Calendar(_)) => "<D:collection/><C:calendar/>".to_string(),
+        (DAV, "resourcetype", _) => "<D:collection/>".to_string(),
+        (DAV, "displayname", Target::Principal | Target::Home) => xml::escape(&user.username),
+        (DAV, "displayname", Target::Calendar(calendar)) => xml::escape(calendar.name()),
+        (DAV, "current-user-principal", _) => href(principal_href(user)),
+        (DAV, "principal-URL", Target::Principal) => href(principal_href(user)),
+        (CALDAV, "calendar-home-set", Target::Root | Target::Principal) => href(home_href(user)),
+        (CALDAV, "supported-calendar-component-set", Target::Calendar(_)) => {
+            "<C:comp name=\"VTODO\"/>".to_string()
+        }
+        (DAV, "supported-report-set", Target::Calendar(_)) => [
+            "calendar-query",
+            "calendar-multiget",
+        ]
+        .map(|report| {
+            format!("<D:supported-report><D:report><C:{report}/></D:report></D:supported-report>")
+        })
+        .concat(),
+        (DAV, "current-user-privilege-set", Target::Calendar(_) | Target::Object(_)) => {
+            "<D:privilege><D:read/></D:privilege><D:privilege><D:write/></D:privilege>".to_string()
+        }
+        (CALENDARSERVER, "getctag", Target::Calendar(calendar)) => xml::escape(&calendar.ctag()),
+        (DAV, "getetag", Target::Object(object)) => xml::escape(&object.etag),
+        (DAV, "getcontenttype", Target::Object(_)) => CALENDAR_CONTENT_TYPE.to_string(),
+        (CALDAV, "calendar-data", Target::Object(object)) => xml::escape(&object.ics),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+/// Adds a response for `target` with the properties in `props`.
+fn describe(out: &mut Multistatus, user: &User, href: &str, target: &Target, props: &Props) {
+    let mut found = Vec::new();
+    let mut missing = Vec::new();
+
+    match props {
+        Props::All => {
+            for (namespace, local) in ALL_PROPS {
+                let name = Name::new(namespace, local);
+                if let Some(value) = property(user, target, &name) {
+                    found.push((name, value));
+                }
+            }
+        }
+        Props::Named(names) => {
+            for name in names {
+                match property(user, target, name) {
+                    Some(value) => found.push((name.clone(), value)),
+                    None => missing.push(name.clone()),
+                }
+            }
+        }
+    }
+
+    out.response(href, &found, &missing);
+}
+
+fn propfind(
+    conn: &Connection,
+    user: &User,
+    resource: &Resource,
+    depth: u8,
+    body: &str,
+) -> Result<HttpResponse, ApiError> {
+    let props = match body.trim() {
+        "" => Props::All,
+        body => xml::parse(body).map_err(ApiError::BadRequest)?.props,
+    };
+    let mut out = Multistatus::default();
+
+    match resource {
+        Resource::Root => describe(&mut out, user, &format!("{PREFIX}/"), &Target::Root, &props),
+        Resource::Principal(_) => describe(
+            &mut out,
+            user,
+            &principal_href(user),
+            &Target::Principal,
+            &props,
+        ),
+        Resource::Home(_) => {
+            describe(&mut out, user, &home_href(user), &Target::Home, &props);
+            if depth > 0 {
+                for calendar in calendars(conn, user.id)? {
+                    let href = calendar_href(user, calendar.category.as_deref());
+                    describe(&mut out, user, &href, &Target::Calendar(&calendar), &props);
+                }
+            }
+        }
+        Resource::Calendar(_, category) => {
+            let calendar = find_calendar(conn, user.id, category)?.ok_or_else(not_found)?;
+            let href = calendar_href(user, category.as_deref());
+            describe(&mut out, user, &href, &Target::Calendar(&calendar), &props);
+            if depth > 0 {
+                for object in &calendar.objects {
+                    let href = object_href(user, &object.task);
+                    describe(&mut out, user, &href, &Target::Object(object), &props);
+                }
+            }
+        }
+        Resource::Object(_, category, uid) => {
+            let object = find_object(conn, user.id, category, uid)?.ok_or_else(not_found)?;
+            let href = object_href(user, &object.task);
+            describe(&mut out, user, &href, &Target::Object(&object), &props);
+        }
+    }
+
+    Ok(out.finish())
+}
+
+fn report(
+    conn: &Connection,
+    user: &User,
+    resource: &Resource,
+    body: &str,
+) -> Result<HttpResponse, ApiError> {
+    let request = xml::parse(body).map_err(ApiError::BadRequest)?;
+    let mut out = Multistatus::default();
+
+    if request.kind.is(CALDAV, "calendar-multiget") {
+        for href in &request.hrefs {
+            let object = match Resource::parse(href) {
+                Some(Resource::Object(owner, category, uid)) if owner == user.username => {
+                    find_object(conn, user.id, &category, &uid)?
+                }
+                _ => None,
+            };
+            match object {
+                Some(object) => describe(
+                    &mut out,
+                    user,
+                    href,
+                    &Target::Object(&object),
+                    &request.props,
+                ),
+                None => out.not_found(href),
+            }
+        }
+    } else if request.kind.is(CALDAV, "calendar-query") {
+        let Resource::Calendar(_, category) = resource else {
+            return Err(ApiError::BadRequest(
+                "calendar-query only works on calendars.".to_string(),
+            ));
+        };
+        let calendar = find_calendar(conn, user.id, category)?.ok_or_else(not_found)?;
+        let time = |value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| ics::parse_time(value, None))
+                .transpose()
+                .map_err(ApiError::BadRequest)
+        };
+        let (from, to) = (time(&request.filter.start)?, time(&request.filter.end)?);
+        // There's nothing but VTODOs in here
+        let only_todos = request
+            .filter
+            .components
+            .iter()
+            .all(|name| name == "VCALENDAR" || name == "VTODO");
+
+        for object in &calendar.objects {
+            if only_todos && in_range(object, from, to) {
+                let href = object_href(user, &object.task);
+                describe(
+                    &mut out,
+                    user,
+                    &href,
+                    &Target::Object(object),
+                    &request.props,
+                );
+            }
+        }
+    } else {
+        return Ok(xml::error(
+            StatusCode::FORBIDDEN,
+            &Name::new(DAV, "supported-report"),
+        ));
+    }
+
+    Ok(out.finish())
+}
+
+/// Whether the task touches the time range of a `calendar-query`. Tasks without any dates are always in it.
+fn in_range(object: &Object, from: Option<DateTime<Utc>>, to: Option<DateTime<Utc>>) -> bool {
+    let task = &object.task;
+
+    match (from, to) {
+        _ if calendar::anchor(task).is_none() => true,
+        (from, Some(to)) => !calendar::occurrences(
+            task,
+            &object.overrides,
+            from.unwrap_or(DateTime::<Utc>::MIN_UTC),
+            to,
+        )
+        .is_empty(),
+        // Repeats might go on forever, so only ones that stopped before `from` could be left out
+        (Some(from), None) => {
+            task.recurrence.is_some()
+                || Span::of(task.start, task.due).is_some_and(|span| span.end >= from)
+        }
+        (None, None) => true,
+    }
+}
+
+fn get(conn: &Connection, user: &User, resource: &Resource) -> Result<HttpResponse, ApiError> {
+    let Resource::Object(_, category, uid) = resource else {
+        return Ok(capabilities(HttpResponse::MethodNotAllowed()).finish());
+    };
+    let object = find_object(conn, user.id, category, uid)?.ok_or_else(not_found)?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(CALENDAR_CONTENT_TYPE)
+        .insert_header((header::ETAG, object.etag))
+        .body(object.ics))
+}
+
+/// Checks `If-Match` and `If-None-Match` against the current ETag of the resource, or `None` if there
+/// isn't one yet.
+fn preconditions_hold(req: &HttpRequest, etag: Option<&str>) -> bool {
+    let header = |name| {
+        req.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let matches = |value: &str| {
+        value
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" && etag.is_some() || Some(tag) == etag)
+    };
+
+    header(header::IF_MATCH).is_none_or(matches)
+        && !header(header::IF_NONE_MATCH).is_some_and(matches)
+}
+
+fn put(
+    conn: &Connection,
+    user: &User,
+    gateway: &Gateway,
+    resource: &Resource,
+    req: &HttpRequest,
+    body: &str,
+) -> Result<HttpResponse, ApiError> {
+    let Resource::Object(_, category, uid) = resource else {
+        return Ok(capabilities(HttpResponse::MethodNotAllowed()).finish());
+    };
+
+    let timestamp = now();
+    let (mut imported, skipped) =
+        ics::import(body, tasks::from_timestamp(timestamp)).map_err(ApiError::BadRequest)?;
+    let entry = match (imported.pop(), imported.is_empty()) {
+        (Some(entry), true) => entry,
+        (None, _) => {
+            let reason = skipped.into_iter().next().map(|skipped| skipped.reason);
+            return Err(ApiError::BadRequest(
+                reason.unwrap_or_else(|| "There's no task in there.".to_string()),
+            ));
+        }
+        (Some(_), false) => {
+            return Err(ApiError::BadRequest(
+                "Only one task can be put at a time.".to_string(),
+            ))
+        }
+    };
+    if entry.uid != *uid {
+        return Err(ApiError::BadRequest(
+            "The name has to be the task's UID followed by .ics.".to_string(),
+        ));
+    }
+
+    let mut fields = entry.fields;
+    // The calendar it's put in decides the category
+    fields.category = category.clone();
+    let fields = crate::api::validate(fields)?;
+
+    let existing = tasks::find_by_uid(conn, user.id, uid)?;
+    if existing.as_ref().is_some_and(|task| task.list_id.is_some()) {
+        return Err(ApiError::Conflict(
+            "A task in a shared list already has this UID.".to_string(),
+        ));
+    }
+    // Moving between calendars is a PUT to the new one, which isn't where the client thinks it is yet
+    let current = existing
+        .as_ref()
+        .filter(|task| task.category == *category)
+        .map(|task| -> rusqlite::Result<_> {
+            Ok(Object::new(task.clone(), occurrences::list(conn, task.id)?).etag)
+        })
+        .transpose()?;
+    if !preconditions_hold(req, current.as_deref()) {
+        return Ok(HttpResponse::PreconditionFailed().finish());
+    }
+
+    let tx = conn.unchecked_transaction()?;
+    let task = match &existing {
+        Some(task) => tasks::update(&tx, user.id, task.id, &fields, timestamp)?,
+        None => Some(tasks::insert_with_uid(
+            &tx, user.id, uid, &fields, timestamp,
+        )?),
+    }
+    .ok_or_else(not_found)?;
+    let task = tasks::set_completed(
+        &tx,
+        user.id,
+        task.id,
+        entry.completed.map(|time| time.timestamp()),
+        timestamp,
+    )?
+    .unwrap_or(task);
+
+    // Like importing, the client has the whole picture of which occurrences were changed
+    occurrences::clear(&tx, task.id)?;
+    for changes in &entry.overrides {
+        occurrences::save(&tx, task.id, changes)?;
+    }
+    tx.commit()?;
+
+    // No ETag, since what's stored isn't byte for byte what was sent. Clients fetch it again instead.
+    match existing {
+        Some(_) => {
+            gateway.task_updated(&[user.id], &task);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        None => {
+            gateway.task_created(&[user.id], &task);
+            Ok(HttpResponse::Created().finish())
+        }
+    }
+}
+
+fn delete(
+    conn: &Connection,
+    user: &User,
+    gateway: &Gateway,
+    resource: &Resource,
+    req: &HttpRequest,
+) -> Result<HttpResponse, ApiError> {
+    let Resource::Object(_, category, uid) = resource else {
+        return Ok(capabilities(HttpResponse::MethodNotAllowed()).finish());
+    };
+    let object = find_object(conn, user.id, category, uid)?.ok_or_else(not_found)?;
+    if !preconditions_hold(req, Some(&object.etag)) {
+        return Ok(HttpResponse::PreconditionFailed().finish());
+    }
+
+    // Subtasks go too, same as everywhere else
+    for task in tasks::delete(conn, user.id, object.task.id, now())? {
+        gateway.task_deleted(&[user.id], &task);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    //! Driven by requests recorded from DAVx⁵, Apple's apps and Thunderbird, in `src/testing/caldav`.
+
+    use super::{Logins, Resource, LOGIN_LIFETIME};
+    use crate::testing::{app, call, request, sign_up};
+    use actix_web::{
+        dev::{Service, ServiceResponse},
+        http::{header, Method, StatusCode},
+        rt::time::Instant,
+        Error,
+    };
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    /// Every property in a response, `None` for the ones that weren't found.
+    type Props = BTreeMap<String, Option<String>>;
+
+    struct Response {
+        status: StatusCode,
+        headers: header::HeaderMap,
+        body: String,
+    }
+
+    async fn dav(
+        app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+        method: &str,
+        uri: &str,
+        body: &str,
+        headers: &[(&str, &str)],
+    ) -> Response {
+        let mut req = actix_web::test::TestRequest::default()
+            .method(Method::from_bytes(method.as_bytes()).unwrap())
+            .uri(uri)
+            .insert_header((
+                header::AUTHORIZATION,
+                format!("Basic {}", STANDARD.encode("steven:correct horse")),
+            ))
+            .set_payload(body.to_string());
+        for header in headers {
+            req = req.insert_header(*header);
+        }
+
+        let res = actix_web::test::call_service(app, req.to_request()).await;
+        Response {
+            status: res.status(),
+            headers: res.headers().clone(),
+            body: String::from_utf8(actix_web::test::read_body(res).await.to_vec()).unwrap(),
+        }
+    }
+
+    /// The href and properties of each response in a multistatus. Values are the text inside, or the names
+    /// of the elements inside if there isn't any.
+    fn multistatus(body: &str) -> Vec<(String, Props)> {
+        let document = roxmltree::Document::parse(body).unwrap();
+        let named = |node: &roxmltree::Node, name: &str| node.tag_name().name() == name;
+
+        document
+            .root_element()
+            .children()
+            .filter(|node| named(node, "response"))
+            .map(|response| {
+                let href = response
+                    .children()
+                    .find(|node| named(node, "href"))
+                    .unwrap();
+                let mut props = Props::new();
+
+                for propstat in response.children().filter(|node| named(node, "propstat")) {
+                    let status = propstat
+                        .children()
+                        .find(|node| named(node, "status"))
+                        .unwrap();
+                    let found = status.text().unwrap().contains(" 200 ");
+                    let prop = propstat
+                        .children()
+                        .find(|node| named(node, "prop"))
+                        .unwrap();
+
+                    for property in prop.children().filter(roxmltree::Node::is_element) {
+                        let text: String = property
+                            .descendants()
+                            .filter(roxmltree::Node::is_text)
+                            .filter_map(|node| node.text())
+                            .collect();
+                        let value = match text.trim() {
+                            "" => property
+                                .descendants()
+                                .skip(1)
+                                .filter(roxmltree::Node::is_element)
+                                .map(|node| node.tag_name().name())
+                                .collect::<Vec<_>>()
+                                .join(","),
+                            text => text.to_string(),
+                        };
+                        props.insert(
+                            property.tag_name().name().to_string(),
+                            found.then_some(value),
+                        );
+                    }
+                }
+
+                (href.text().unwrap().to_string(), props)
+            })
+            .collect()
+    }
+
+    fn prop<'a>(props: &'a Props, name: &str) -> Option<&'a str> {
+        props.get(name)?.as_deref()
+    }
+
+    #[test]
+    fn paths() {
+        let object = |category: Option<&str>, uid: &str| {
+            Resource::Object(
+                "steven".to_string(),
+                category.map(str::to_string),
+                uid.to_string(),
+            )
+        };
+
+        assert_eq!(Resource::parse("/dav"), Some(Resource::Root));
+        assert_eq!(Resource::parse("/davx/"), None);
+        assert_eq!(
+            Resource::parse("/dav/calendars/steven/_/"),
+            Some(Resource::Calendar("steven".to_string(), None))
+        );
+        assert_eq!(
+            Resource::parse("/dav/calendars/steven/home%5F%26%20garden/a%40b.ics"),
+            Some(object(Some("home_& garden"), "a@b"))
+        );
+        assert_eq!(
+            Resource::parse("https://example.com/dav/calendars/steven/_/a@b.ics"),
+            Some(object(None, "a@b"))
+        );
+        assert_eq!(Resource::parse("/dav/calendars/steven/_/a@b.txt"), None);
+        assert_eq!(Resource::parse("/dav/calendars/steven/_/a/b.ics"), None);
+    }
+
+    #[test]
+    fn logins() {
+        let logins = Logins::default();
+        let now = Instant::now();
+        logins.insert("steven".to_string(), 1, now);
+        logins.insert("steven's phone".to_string(), 1, now);
+        logins.insert("other".to_string(), 2, now);
+        assert!(logins.contains("steven", now));
+
+        logins.forget(1);
+        assert!(!logins.contains("steven", now));
+        assert!(!logins.contains("steven's phone", now));
+        assert!(logins.contains("other", now));
+        assert!(!logins.contains("other", now + LOGIN_LIFETIME));
+    }
+
+    #[actix_web::test]
+    async fn discovery() {
+        let app = actix_web::test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+        sign_up(&app, "other").await;
+        for task in [
+            json!({ "title": "Pay rent", "category": "home" }),
+            json!({ "title": "Plan trip", "category": "Work & play" }),
+            json!({ "title": "Read" }),
+        ] {
+            call(
+                &app,
+                request(Method::POST, "/api/tasks", &token).set_json(task),
+            )
+            .await;
+        }
+
+        let res = actix_web::test::call_service(
+            &app,
+            actix_web::test::TestRequest::get()
+                .uri("/.well-known/caldav")
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(res.headers().get(header::LOCATION).unwrap(), "/dav/");
+
+        let res = actix_web::test::call_service(
+            &app,
+            actix_web::test::TestRequest::default()
+                .method(Method::OPTIONS)
+                .uri("/dav/")
+                .to_request(),
+        )
+        .await;
+        assert!(res
+            .headers()
+            .get("DAV")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .contains("calendar-access"));
+
+        let principal = include_str!("../testing/caldav/davx5-current-user-principal.xml");
+        let res = actix_web::test::call_service(
+            &app,
+            actix_web::test::TestRequest::default()
+                .method(Method::from_bytes(b"PROPFIND").unwrap())
+                .uri("/dav/")
+                .insert_header((
+                    header::AUTHORIZATION,
+                    format!("Basic {}", STANDARD.encode("steven:wrong horse")),
+                ))
+                .set_payload(principal)
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        assert!(res.headers().contains_key(header::WWW_AUTHENTICATE));
+
+        let res = dav(&app, "PROPFIND", "/dav/", principal, &[("Depth", "0")]).await;
+        assert_eq!(res.status, StatusCode::MULTI_STATUS);
+        let responses = multistatus(&res.body);
+        assert_eq!(
+            prop(&responses[0].1, "current-user-principal"),
+            Some("/dav/principals/steven/")
+        );
+
+        let home_set = include_str!("../testing/caldav/davx5-home-set.xml");
+        let res = dav(
+            &app,
+            "PROPFIND",
+            "/dav/principals/steven/",
+            home_set,
+            &[("Depth", "0")],
+        )
+        .await;
+        let responses = multistatus(&res.body);
+        let props = &responses[0].1;
+        assert_eq!(
+            prop(props, "calendar-home-set"),
+            Some("/dav/calendars/steven/")
+        );
+        assert_eq!(prop(props, "resourcetype"), Some("collection,principal"));
+        assert_eq!(props["calendar-user-address-set"], None);
+
+        let calendars = include_str!("../testing/caldav/davx5-calendars.xml");
+        let res = dav(
+            &app,
+            "PROPFIND",
+            "/dav/calendars/steven/",
+            calendars,
+            &[("Depth", "1")],
+        )
+        .await;
+        let responses = multistatus(&res.body);
+        let listed: Vec<_> = responses
+            .iter()
+            .map(|(href, props)| (href.as_str(), prop(props, "displayname")))
+            .collect();
+        assert_eq!(
+            listed,
+            [
+                ("/dav/calendars/steven/", Some("steven")),
+                ("/dav/calendars/steven/_/", Some("Tasks")),
+                ("/dav/calendars/steven/home/", Some("home")),
+                (
+                    "/dav/calendars/steven/work%20%26%20play/",
+                    Some("work & play")
+                ),
+            ]
+        );
+        let props = &responses[2].1;
+        assert_eq!(prop(props, "resourcetype"), Some("collection,calendar"));
+        assert_eq!(
+            prop(props, "supported-calendar-component-set"),
+            Some("comp")
+        );
+        assert_eq!(
+            prop(props, "current-user-privilege-set"),
+            Some("privilege,read,privilege,write")
+        );
+        assert_eq!(props["calendar-color"], None);
+
+        // Nobody else's calendars are there, not even to be listed
+        let res = dav(&app, "PROPFIND", "/dav/calendars/other/", calendars, &[]).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        let res = dav(
+            &app,
+            "PROPFIND",
+            "/dav/calendars/steven/gone/",
+            calendars,
+            &[],
+        )
+        .await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn syncing() {
+        let app = actix_web::test::init_service(app()).await;
+        let token = sign_up(&app, "steven").await;
+        for task in [
+            json!({ "title": "Mow the lawn", "category": "home", "due": "2024-05-01T09:00:00Z" }),
+            json!({ "title": "Tidy up", "category": "home" }),
+        ] {
+            call(
+                &app,
+                request(Method::POST, "/api/tasks", &token).set_json(task),
+            )
+            .await;
+        }
+
+        let todo = include_str!("../testing/caldav/apple-todo.ics");
+        let href = "/dav/calendars/steven/home/20230301T091500Z-plumber%40example.com.ics";
+        let res = dav(&app, "PUT", href, todo, &[("If-None-Match", "*")]).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        let res = dav(&app, "PUT", href, todo, &[("If-None-Match", "*")]).await;
+        assert_eq!(res.status, StatusCode::PRECONDITION_FAILED);
+        let res = dav(
+            &app,
+            "PUT",
+            "/dav/calendars/steven/home/other.ics",
+            todo,
+            &[],
+        )
+        .await;
+        assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &token)).await;
+        let plumber = &body["tasks"][0];
+        assert_eq!(plumber["title"], "Call the plumber");
+        assert_eq!(plumber["notes"], "Kitchen sink, again");
+        assert_eq!(plumber["category"], "home");
+
+        let etags = include_str!("../testing/caldav/apple-calendar-etags.xml");
+        let res = dav(
+            &app,
+            "PROPFIND",
+            "/dav/calendars/steven/home/",
+            etags,
+            &[("Depth", "1")],
+        )
+        .await;
+        let responses = multistatus(&res.body);
+        assert_eq!(responses.len(), 4);
+        // Sorted by due date like everywhere else, and the lawn isn't due until next year
+        assert_eq!(responses[1].0, href);
+        let ctag = prop(&responses[0].1, "getctag").unwrap().to_string();
+        let etag = prop(&responses[1].1, "getetag").unwrap().to_string();
+        assert_eq!(responses[0].1["sync-token"], None);
+
+        let res = dav(&app, "GET", href, "", &[]).await;
+        assert_eq!(res.status, StatusCode::OK);
+        assert_eq!(
+            res.headers.get(header::ETAG).unwrap().to_str().unwrap(),
+            etag
+        );
+        assert!(res.body.contains("BEGIN:VTODO\r\n"));
+        assert!(res.body.contains("DUE:20230310T090000Z\r\n"));
+
+        // Undated tasks are in every time range
+        let query = include_str!("../testing/caldav/thunderbird-calendar-query.xml");
+        let res = dav(
+            &app,
+            "REPORT",
+            "/dav/calendars/steven/home/",
+            query,
+            &[("Depth", "1")],
+        )
+        .await;
+        let hrefs: Vec<_> = multistatus(&res.body)
+            .into_iter()
+            .map(|(href, _)| href)
+            .collect();
+        assert_eq!(hrefs.len(), 2);
+        assert!(hrefs.iter().any(|found| found == href));
+        let query = include_str!("../testing/caldav/thunderbird-event-query.xml");
+        let res = dav(
+            &app,
+            "REPORT",
+            "/dav/calendars/steven/home/",
+            query,
+            &[("Depth", "1")],
+        )
+        .await;
+        assert!(multistatus(&res.body).is_empty());
+
+        let multiget = include_str!("../testing/caldav/davx5-multiget.xml");
+        let res = dav(&app, "REPORT", "/dav/calendars/steven/home/", multiget, &[]).await;
+        let responses = multistatus(&res.body);
+        assert!(prop(&responses[0].1, "calendar-data")
+            .unwrap()
+            .contains("SUMMARY:Call the plumber"));
+        assert!(responses[1].1.is_empty());
+        assert!(res.body.contains("404 Not Found"));
+
+        let sync = include_str!("../testing/caldav/davx5-sync-collection.xml");
+        let res = dav(&app, "REPORT", "/dav/calendars/steven/home/", sync, &[]).await;
+        assert_eq!(res.status, StatusCode::FORBIDDEN);
+        assert!(res.body.contains("supported-report"));
+
+        // Changed on the phone, which only goes through if nothing changed here in the meantime
+        let changed = todo.replace("SUMMARY:Call the plumber", "SUMMARY:Call the plumber back");
+        let res = dav(&app, "PUT", href, &changed, &[("If-Match", "\"stale\"")]).await;
+        assert_eq!(res.status, StatusCode::PRECONDITION_FAILED);
+        let res = dav(&app, "PUT", href, &changed, &[("If-Match", &etag)]).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &token)).await;
+        assert_eq!(body["tasks"][0]["title"], "Call the plumber back");
+
+        let res = dav(
+            &app,
+            "PROPFIND",
+            "/dav/calendars/steven/home/",
+            etags,
+            &[("Depth", "0")],
+        )
+        .await;
+        assert_ne!(prop(&multistatus(&res.body)[0].1, "getctag").unwrap(), ctag);
+
+        let res = dav(&app, "DELETE", href, "", &[("If-Match", &etag)]).await;
+        assert_eq!(res.status, StatusCode::PRECONDITION_FAILED);
+        let res = dav(&app, "DELETE", href, "", &[]).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+        let res = dav(&app, "GET", href, "", &[]).await;
+        assert_eq!(res.status, StatusCode::NOT_FOUND);
+        let (_, body) = call(&app, request(Method::GET, "/api/tasks", &token)).await;
+        assert_eq!(body["tasks"].as_array().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,271 @@
+//! Reading WebDAV request bodies and writing `207 Multi-Status` responses.
+
+use actix_web::{http::StatusCode, HttpResponse};
+use roxmltree::{Document, Node};
+
+pub const DAV: &str = "DAV:";
+pub const CALDAV: &str = "urn:ietf:params:xml:ns:caldav";
+/// Apple's namespace, for `getctag`
+pub const CALENDARSERVER: &str = "http://calendarserver.org/ns/";
+
+pub const CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+
+/// An element name along with its namespace, like `{DAV:}getetag`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Name {
+    pub namespace: String,
+    pub local: String,
+}
+
+impl Name {
+    pub fn new(namespace: &str, local: &str) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            local: local.to_string(),
+        }
+    }
+
+    fn of(node: Node) -> Self {
+        Self::new(
+            node.tag_name().namespace().unwrap_or_default(),
+            node.tag_name().name(),
+        )
+    }
+
+    pub fn is(&self, namespace: &str, local: &str) -> bool {
+        self.namespace == namespace && self.local == local
+    }
+}
+
+/// The properties a PROPFIND or REPORT asks for.
+#[derive(Debug, PartialEq)]
+pub enum Props {
+    /// `<allprop/>`, `<propname/>` or no body at all
+    All,
+    Named(Vec<Name>),
+}
+
+/// A PROPFIND or REPORT body, with everything this server looks at.
+#[derive(Debug)]
+pub struct Request {
+    /// The root element, which says what kind of REPORT it is
+    pub kind: Name,
+    pub props: Props,
+    /// Which resources a `calendar-multiget` wants
+    pub hrefs: Vec<String>,
+    pub filter: Filter,
+}
+
+/// The parts of a `calendar-query` filter that are understood.
+#[derive(Debug, Default)]
+pub struct Filter {
+    /// Every `comp-filter` name, however deeply nested
+    pub components: Vec<String>,
+    pub start: Option<String>,
+    pub end: Option<String>,
+}
+
+pub fn parse(body: &str) -> Result<Request, String> {
+    let document = Document::parse(body).map_err(|error| format!("Invalid XML: {error}"))?;
+    let root = document.root_element();
+    let is = |node: &Node, namespace: &str, local: &str| Name::of(*node).is(namespace, local);
+
+    let props = match root.children().find(|node| is(node, DAV, "prop")) {
+        Some(prop) => Props::Named(
+            prop.children()
+                .filter(Node::is_element)
+                .map(Name::of)
+                .collect(),
+        ),
+        None => Props::All,
+    };
+
+    let mut request = Request {
+        kind: Name::of(root),
+        props,
+        hrefs: Vec::new(),
+        filter: Filter::default(),
+    };
+
+    for node in root.descendants() {
+        if is(&node, DAV, "href") {
+            request
+                .hrefs
+                .push(node.text().unwrap_or_default().trim().to_string());
+        } else if is(&node, CALDAV, "comp-filter") {
+            let name = node.attribute("name").unwrap_or_default();
+            request.filter.components.push(name.to_ascii_uppercase());
+        } else if is(&node, CALDAV, "time-range") {
+            request.filter.start = node.attribute("start").map(str::to_string);
+            request.filter.end = node.attribute("end").map(str::to_string);
+        }
+    }
+
+    Ok(request)
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// `<D:local>inner</D:local>`, with an inline namespace for anything but the ones declared on
+/// `<D:multistatus>`.
+pub fn element(name: &Name, inner: &str) -> String {
+    let prefix = match name.namespace.as_str() {
+        DAV => "D:",
+        CALDAV => "C:",
+        CALENDARSERVER => "CS:",
+        _ => "",
+    };
+    let declaration = match prefix {
+        "" => format!(" xmlns=\"{}\"", escape(&name.namespace)),
+        _ => String::new(),
+    };
+
+    match inner {
+        "" => format!("<{prefix}{}{declaration}/>", name.local),
+        _ => format!(
+            "<{prefix}{0}{declaration}>{inner}</{prefix}{0}>",
+            name.local
+        ),
+    }
+}
+
+fn status(status: StatusCode) -> String {
+    format!(
+        "<D:status>HTTP/1.1 {} {}</D:status>",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+}
+
+/// Accumulates one `<D:response>` per resource.
+pub struct Multistatus {
+    out: String,
+}
+
+impl Default for Multistatus {
+    fn default() -> Self {
+        Self {
+            out: format!(
+                "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"{DAV}\" \
+                 xmlns:C=\"{CALDAV}\" xmlns:CS=\"{CALENDARSERVER}\">"
+            ),
+        }
+    }
+}
+
+impl Multistatus {
+    /// A resource with the properties that were found (as their inner XML), and the ones that weren't.
+    pub fn response(&mut self, href: &str, found: &[(Name, String)], missing: &[Name]) {
+        self.out
+            .push_str(&format!("<D:response><D:href>{}</D:href>", escape(href)));
+
+        for (props, code) in [
+            (
+                found
+                    .iter()
+                    .map(|(name, value)| element(name, value))
+                    .collect::<String>(),
+                StatusCode::OK,
+            ),
+            (
+                missing.iter().map(|name| element(name, "")).collect(),
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            if !props.is_empty() {
+                self.out.push_str(&format!(
+                    "<D:propstat><D:prop>{props}</D:prop>{}</D:propstat>",
+                    status(code)
+                ));
+            }
+        }
+
+        self.out.push_str("</D:response>");
+    }
+
+    pub fn not_found(&mut self, href: &str) {
+        self.out.push_str(&format!(
+            "<D:response><D:href>{}</D:href>{}</D:response>",
+            escape(href),
+            status(StatusCode::NOT_FOUND)
+        ));
+    }
+
+    pub fn finish(mut self) -> HttpResponse {
+        self.out.push_str("</D:multistatus>\n");
+        HttpResponse::build(StatusCode::MULTI_STATUS)
+            .content_type(CONTENT_TYPE)
+            .body(self.out)
+    }
+}
+
+/// A `<D:error>` body, for requests that break one of the preconditions.
+pub fn error(status: StatusCode, condition: &Name) -> HttpResponse {
+    HttpResponse::build(status).content_type(CONTENT_TYPE).body(format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:error xmlns:D=\"{DAV}\" xmlns:C=\"{CALDAV}\">{}</D:error>\n",
+        element(condition, "")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let request = parse(
+            r#"<?xml version="1.0"?>
+            <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+                <d:prop><d:getetag/><x:color xmlns:x="http://apple.com/ns/ical/"/></d:prop>
+                <c:filter>
+                    <c:comp-filter name="VCALENDAR">
+                        <c:comp-filter name="vtodo">
+                            <c:time-range start="20230301T000000Z"/>
+                        </c:comp-filter>
+                    </c:comp-filter>
+                </c:filter>
+            </c:calendar-query>"#,
+        )
+        .unwrap();
+
+        assert!(request.kind.is(CALDAV, "calendar-query"));
+        assert_eq!(
+            request.props,
+            Props::Named(vec![
+                Name::new(DAV, "getetag"),
+                Name::new("http://apple.com/ns/ical/", "color")
+            ])
+        );
+        assert_eq!(request.filter.components, ["VCALENDAR", "VTODO"]);
+        assert_eq!(request.filter.start.as_deref(), Some("20230301T000000Z"));
+        assert_eq!(request.filter.end, None);
+
+        let request = parse(r#"<propfind xmlns="DAV:"><allprop/></propfind>"#).unwrap();
+        assert_eq!(request.props, Props::All);
+        assert!(parse("<propfind").is_err());
+    }
+
+    #[test]
+    fn writing() {
+        let mut multistatus = Multistatus::default();
+        multistatus.response(
+            "/dav/a&b/",
+            &[(Name::new(DAV, "displayname"), escape("Home & garden"))],
+            &[Name::new("http://apple.com/ns/ical/", "calendar-color")],
+        );
+        let body = multistatus.out;
+
+        assert!(body.contains("<D:href>/dav/a&amp;b/</D:href>"));
+        assert!(body.contains(
+            "<D:prop><D:displayname>Home &amp; garden</D:displayname></D:prop><D:status>HTTP/1.1 200 OK</D:status>"
+        ));
+        assert!(body.contains(
+            "<D:prop><calendar-color xmlns=\"http://apple.com/ns/ical/\"/></D:prop><D:status>HTTP/1.1 404 Not Found</D:status>"
+        ));
+    }
+}
@@ -0,0 +1,188 @@
+use actix_web::{
+    dev::ServiceResponse,
+    http::{header, StatusCode},
+    middleware::ErrorHandlerResponse,
+    HttpResponse, ResponseError,
+};
+use serde::Serialize;
+use std::fmt;
+use utoipa::ToSchema;
+
+/// Errors returned by the API, rendered as an `ErrorBody`.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    /// Some fields of the request were wrong, along with what's wrong with each of them
+    Invalid(Vec<FieldError>),
+    Unauthorized(String),
+    /// Logged in, but not allowed to do that
+    Forbidden(String),
+    NotFound(String),
+    Conflict(String),
+    /// Along with how many seconds to wait, which goes in `Retry-After`
+    TooManyRequests(String, u64),
+    Internal(String),
+    /// Something the server depends on is down
+    Unavailable(String),
+}
+
+/// What's wrong with one field of a request.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, message: impl Into<String>) -> Self {
+        Self {
+            field: field.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// The body of every error response from the API.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// Stable, machine-readable kind of error, like `not_found`
+    pub code: &'static str,
+    /// Meant for people, and may change
+    pub message: String,
+    /// Only filled in for `invalid`
+    pub fields: Vec<FieldError>,
+}
+
+impl ApiError {
+    /// An `Invalid` error for a single field.
+    pub fn field(field: &str, message: impl Into<String>) -> Self {
+        Self::Invalid(vec![FieldError::new(field, message)])
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Invalid(_) => "invalid",
+            _ => code(self.status_code()),
+        }
+    }
+}
+
+/// The `code` for errors that only have a status to go on.
+fn code(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::BAD_REQUEST => "bad_request",
+        StatusCode::UNAUTHORIZED => "unauthorized",
+        StatusCode::FORBIDDEN => "forbidden",
+        StatusCode::NOT_FOUND => "not_found",
+        StatusCode::METHOD_NOT_ALLOWED => "method_not_allowed",
+        StatusCode::CONFLICT => "conflict",
+        StatusCode::PAYLOAD_TOO_LARGE => "payload_too_large",
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => "unsupported_media_type",
+        StatusCode::TOO_MANY_REQUESTS => "too_many_requests",
+        StatusCode::SERVICE_UNAVAILABLE => "unavailable",
+        status if status.is_server_error() => "internal",
+        _ => "error",
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadRequest(message)
+            | Self::Unauthorized(message)
+            | Self::Forbidden(message)
+            | Self::NotFound(message)
+            | Self::Conflict(message)
+            | Self::TooManyRequests(message, _)
+            | Self::Internal(message)
+            | Self::Unavailable(message) => write!(f, "{message}"),
+            Self::Invalid(fields) => {
+                let messages: Vec<&str> =
+                    fields.iter().map(|field| field.message.as_str()).collect();
+                write!(f, "{}", messages.join(" "))
+            }
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) | Self::Invalid(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Conflict(_) => StatusCode::CONFLICT,
+            Self::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let Self::TooManyRequests(_, seconds) = self {
+            response.insert_header((header::RETRY_AFTER, seconds.to_string()));
+        }
+
+        response.json(ErrorBody {
+            code: self.code(),
+            message: self.to_string(),
+            fields: match self {
+                Self::Invalid(fields) => fields.clone(),
+                _ => Vec::new(),
+            },
+        })
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(error: rusqlite::Error) -> Self {
+        // Don't leak query details to the client
+        log::error!("Database error: {error}");
+        Self::Internal("Internal database error.".to_string())
+    }
+}
+
+impl From<actix_web::error::BlockingError> for ApiError {
+    fn from(_: actix_web::error::BlockingError) -> Self {
+        Self::Internal("Internal server error.".to_string())
+    }
+}
+
+/// For `middleware::ErrorHandlers`. Errors that didn't come from an `ApiError`, like a body that isn't
+/// valid JSON or a route that doesn't exist, get rendered as an `ErrorBody` too instead of plain text.
+pub fn envelope<B>(res: ServiceResponse<B>) -> actix_web::Result<ErrorHandlerResponse<B>> {
+    let is_json = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if is_json {
+        return Ok(ErrorHandlerResponse::Response(res.map_into_left_body()));
+    }
+
+    let status = res.status();
+    let message = match res.response().error() {
+        // Internal errors might say more than they should
+        Some(error) if !status.is_server_error() => error.to_string(),
+        _ => status.canonical_reason().unwrap_or("Error").to_string(),
+    };
+
+    let (req, res) = res.into_parts();
+    let mut envelope = HttpResponse::build(status);
+    for (name, value) in res.headers() {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            envelope.append_header((name.clone(), value.clone()));
+        }
+    }
+    let envelope = envelope.json(ErrorBody {
+        code: code(status),
+        message,
+        fields: Vec::new(),
+    });
+
+    Ok(ErrorHandlerResponse::Response(ServiceResponse::new(
+        req,
+        envelope.map_into_right_body(),
+    )))
+}
@@ -0,0 +1,196 @@
+mod parser;
+
+pub use parser::parse;
+
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::types::Value;
+
+/// How categories are stored and compared, so `category:` filters match what tasks were saved with.
+pub fn normalize_category(category: &str) -> String {
+    category.trim().to_lowercase()
+}
+
+/// A parsed task filter, see `parse()` for the syntax.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Done,
+    Recurring,
+    /// `None` matches tasks without a category
+    Category(Option<String>),
+    /// Case-insensitive substring match
+    Contains(TextField, String),
+    /// The date is set at all
+    Has(DateField),
+    /// Tasks without the date never match
+    Compare(DateField, Op, TimeValue),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TextField {
+    Title,
+    Notes,
+    /// Either the title or the notes
+    Any,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DateField {
+    Start,
+    Due,
+    Completed,
+    Created,
+    Updated,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// `:` or `=`, only used while parsing since dates are compared with the others
+    Is,
+    Before,
+    BeforeOrAt,
+    After,
+    AfterOrAt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeValue {
+    Absolute(DateTime<Utc>),
+    /// Offset from whenever the filter runs, so saved views stay current
+    Relative(Duration),
+}
+
+impl DateField {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "start" => Some(Self::Start),
+            "due" => Some(Self::Due),
+            "completed" => Some(Self::Completed),
+            "created" => Some(Self::Created),
+            "updated" => Some(Self::Updated),
+            _ => None,
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            Self::Start => "Start",
+            Self::Due => "Due",
+            Self::Completed => "Completed",
+            Self::Created => "Created",
+            Self::Updated => "Updated",
+        }
+    }
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Is => ":",
+            Self::Before => "<",
+            Self::BeforeOrAt => "<=",
+            Self::After => ">",
+            Self::AfterOrAt => ">=",
+        }
+    }
+}
+
+impl TimeValue {
+    fn resolve(self, now: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Self::Absolute(time) => time,
+            Self::Relative(offset) => now.checked_add_signed(offset).unwrap_or(now),
+        }
+    }
+}
+
+/// Escapes `%`, `_` and `\` so user input only ever matches literally in `LIKE ... ESCAPE '\'`.
+fn like_pattern(text: &str) -> String {
+    let mut pattern = String::from("%");
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+impl Filter {
+    /// Turns the filter into a `WHERE` clause over the Tasks table, pushing values for each `?` onto `params`.
+    /// Every clause evaluates to 0 or 1 rather than NULL, so `NOT` works as expected on missing dates.
+    pub fn to_sql(&self, now: DateTime<Utc>, params: &mut Vec<Value>) -> String {
+        match self {
+            Self::And(left, right) => format!(
+                "({} AND {})",
+                left.to_sql(now, params),
+                right.to_sql(now, params)
+            ),
+            Self::Or(left, right) => format!(
+                "({} OR {})",
+                left.to_sql(now, params),
+                right.to_sql(now, params)
+            ),
+            Self::Not(filter) => format!("NOT ({})", filter.to_sql(now, params)),
+            Self::Done => "Completed IS NOT NULL".to_string(),
+            Self::Recurring => "Recurrence IS NOT NULL".to_string(),
+            Self::Category(None) => "Category IS NULL".to_string(),
+            Self::Category(Some(category)) => {
+                params.push(Value::Text(category.clone()));
+                "COALESCE(Category = ?, 0)".to_string()
+            }
+            Self::Contains(field, text) => {
+                let columns: &[&str] = match field {
+                    TextField::Title => &["Title"],
+                    TextField::Notes => &["Notes"],
+                    TextField::Any => &["Title", "Notes"],
+                };
+                let clauses: Vec<_> = columns
+                    .iter()
+                    .map(|column| {
+                        params.push(Value::Text(like_pattern(text)));
+                        format!("{column} LIKE ? ESCAPE '\\'")
+                    })
+                    .collect();
+                format!("({})", clauses.join(" OR "))
+            }
+            Self::Has(field) => format!("{} IS NOT NULL", field.column()),
+            Self::Compare(field, op, time) => {
+                params.push(Value::Integer(time.resolve(now).timestamp()));
+                format!("COALESCE({} {} ?, 0)", field.column(), op.symbol())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sql() {
+        let now: DateTime<Utc> = "2023-03-01T00:00:00Z".parse().unwrap();
+        let mut params = Vec::new();
+        let sql = parse("category:Work AND NOT done due<7d \"100%\"")
+            .unwrap()
+            .to_sql(now, &mut params);
+
+        assert_eq!(
+            sql,
+            "(((COALESCE(Category = ?, 0) AND NOT (Completed IS NOT NULL)) AND COALESCE(Due < ?, 0)) \
+             AND (Title LIKE ? ESCAPE '\\' OR Notes LIKE ? ESCAPE '\\'))"
+        );
+        assert_eq!(
+            params,
+            [
+                Value::Text("work".to_string()),
+                Value::Integer(1678233600),
+                Value::Text("%100\\%%".to_string()),
+                Value::Text("%100\\%%".to_string()),
+            ]
+        );
+    }
+}
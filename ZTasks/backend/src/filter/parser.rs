@@ -0,0 +1,377 @@
+use super::{normalize_category, DateField, Filter, Op, TextField, TimeValue};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+// Deeply nested parentheses would otherwise blow the stack
+const MAX_DEPTH: usize = 32;
+const MAX_LENGTH: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Open,
+    Close,
+    Op(Op),
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            ':' => tokens.push(Token::Op(Op::Is)),
+            '=' => tokens.push(Token::Op(Op::Is)),
+            '<' | '>' => {
+                let or_equal = chars.next_if_eq(&'=').is_some();
+                tokens.push(Token::Op(match (c, or_equal) {
+                    ('<', false) => Op::Before,
+                    ('<', true) => Op::BeforeOrAt,
+                    ('>', false) => Op::After,
+                    _ => Op::AfterOrAt,
+                }));
+            }
+            '"' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => text.extend(chars.next()),
+                        Some(c) => text.push(c),
+                        None => return Err("Unterminated quote.".to_string()),
+                    }
+                }
+                tokens.push(Token::Quoted(text));
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) =
+                    chars.next_if(|c| !c.is_whitespace() && !"()<>=:\"".contains(*c))
+                {
+                    word.push(c);
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    depth: usize,
+}
+
+/// Parses something like `category:work AND NOT done AND due<7d`.
+///
+/// Terms can be joined with `AND` (which is also implied between terms), `OR` and `NOT`, and grouped with parentheses.
+pub fn parse(input: &str) -> Result<Filter, String> {
+    if input.len() > MAX_LENGTH {
+        return Err(format!(
+            "Filters can't be longer than {MAX_LENGTH} characters."
+        ));
+    }
+
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        position: 0,
+        depth: 0,
+    };
+
+    if parser.tokens.is_empty() {
+        return Err("Filter is empty.".to_string());
+    }
+
+    let filter = parser.or()?;
+
+    match parser.peek() {
+        None => Ok(filter),
+        Some(Token::Close) => Err("Unexpected \")\".".to_string()),
+        Some(token) => Err(format!("Unexpected {}.", describe(token))),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(word) => format!("\"{word}\""),
+        Token::Quoted(text) => format!("\"\\\"{text}\\\"\""),
+        Token::Open => "\"(\"".to_string(),
+        Token::Close => "\")\"".to_string(),
+        Token::Op(op) => format!("\"{}\"", op.symbol()),
+    }
+}
+
+fn is_keyword(token: Option<&Token>, keyword: &str) -> bool {
+    matches!(token, Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword))
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Filter, String> {
+        let mut filter = self.and()?;
+
+        while is_keyword(self.peek(), "OR") {
+            self.next();
+            filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+        }
+
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Filter, String> {
+        let mut filter = self.unary()?;
+
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) => break,
+                token if is_keyword(token, "OR") => break,
+                token if is_keyword(token, "AND") => {
+                    self.next();
+                }
+                _ => (),
+            }
+            filter = Filter::And(Box::new(filter), Box::new(self.unary()?));
+        }
+
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> Result<Filter, String> {
+        self.depth += 1;
+
+        if self.depth > MAX_DEPTH {
+            return Err("Filter is nested too deeply.".to_string());
+        }
+
+        let filter = match self.next() {
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("NOT") => {
+                Filter::Not(Box::new(self.unary()?))
+            }
+            Some(Token::Word(word)) if word == "-" => Filter::Not(Box::new(self.unary()?)),
+            // `-done` is short for `NOT done`
+            Some(Token::Word(word)) if word.len() > 1 && word.starts_with('-') => {
+                Filter::Not(Box::new(self.term(word[1..].to_string())?))
+            }
+            Some(Token::Open) => {
+                let filter = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => filter,
+                    _ => return Err("Missing \")\".".to_string()),
+                }
+            }
+            Some(Token::Word(word)) => self.term(word)?,
+            Some(Token::Quoted(text)) => Filter::Contains(TextField::Any, text),
+            Some(token) => return Err(format!("Unexpected {}.", describe(&token))),
+            None => return Err("Unexpected end of filter.".to_string()),
+        };
+
+        self.depth -= 1;
+        Ok(filter)
+    }
+
+    fn value(&mut self, field: &str) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => Ok(value),
+            _ => Err(format!("Expected a value after \"{field}\".")),
+        }
+    }
+
+    fn term(&mut self, word: String) -> Result<Filter, String> {
+        let op = match self.peek() {
+            Some(Token::Op(op)) => *op,
+            _ => {
+                return Ok(match word.to_ascii_lowercase().as_str() {
+                    "done" => Filter::Done,
+                    "recurring" => Filter::Recurring,
+                    "and" | "or" => return Err(format!("Expected something before \"{word}\".")),
+                    _ => Filter::Contains(TextField::Any, word),
+                })
+            }
+        };
+        self.next();
+
+        let field = word.to_ascii_lowercase();
+        let value = self.value(&field)?;
+
+        if let Some(date_field) = DateField::from_name(&field) {
+            return match (op, value.to_ascii_lowercase().as_str()) {
+                (Op::Is, "none") => Ok(Filter::Not(Box::new(Filter::Has(date_field)))),
+                (Op::Is, "any") => Ok(Filter::Has(date_field)),
+                (Op::Is, _) => Err(format!(
+                    "Use <, <=, > or >= to compare \"{field}\", or {field}:any / {field}:none."
+                )),
+                _ => Ok(Filter::Compare(date_field, op, parse_time(&value)?)),
+            };
+        }
+
+        if op != Op::Is {
+            return Err(format!("\"{}\" only works on dates.", op.symbol()));
+        }
+
+        match field.as_str() {
+            "category" => Ok(match normalize_category(&value).as_str() {
+                "none" => Filter::Category(None),
+                value => Filter::Category(Some(value.to_string())),
+            }),
+            "title" => Ok(Filter::Contains(TextField::Title, value)),
+            "notes" => Ok(Filter::Contains(TextField::Notes, value)),
+            "is" => match value.to_ascii_lowercase().as_str() {
+                "done" => Ok(Filter::Done),
+                "recurring" => Ok(Filter::Recurring),
+                _ => Err(format!("Unknown state \"{value}\".")),
+            },
+            _ => Err(format!("Unknown field \"{word}\".")),
+        }
+    }
+}
+
+/// `now`, a relative offset like `7d`, `-2w` or `12h`, a date like `2023-03-01` or an RFC 3339 timestamp.
+fn parse_time(value: &str) -> Result<TimeValue, String> {
+    let invalid = || format!("Invalid date \"{value}\".");
+
+    if value.eq_ignore_ascii_case("now") {
+        return Ok(TimeValue::Relative(Duration::zero()));
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).ok_or_else(invalid)?;
+        return Ok(TimeValue::Absolute(midnight.and_utc()));
+    }
+
+    if let Ok(time) = value.parse::<DateTime<Utc>>() {
+        return Ok(TimeValue::Absolute(time));
+    }
+
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let amount: i64 = value[..value.len() - unit.len_utf8()]
+        .trim_start_matches('+')
+        .parse()
+        .map_err(|_| invalid())?;
+
+    let offset = match unit {
+        'm' => Duration::try_minutes(amount),
+        'h' => Duration::try_hours(amount),
+        'd' => Duration::try_days(amount),
+        'w' => Duration::try_weeks(amount),
+        _ => None,
+    };
+
+    // Keep offsets within a few thousand years so adding them to now can't overflow
+    offset
+        .filter(|offset| offset.num_days().abs() < 1_000_000)
+        .map(TimeValue::Relative)
+        .ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse("category:work AND NOT done due<7d OR recurring").unwrap(),
+            Filter::Or(
+                Box::new(Filter::And(
+                    Box::new(Filter::And(
+                        Box::new(Filter::Category(Some("work".to_string()))),
+                        Box::new(Filter::Not(Box::new(Filter::Done))),
+                    )),
+                    Box::new(Filter::Compare(
+                        DateField::Due,
+                        Op::Before,
+                        TimeValue::Relative(Duration::days(7)),
+                    )),
+                )),
+                Box::new(Filter::Recurring),
+            )
+        );
+    }
+
+    #[test]
+    fn grouping_and_values() {
+        assert_eq!(
+            parse("-(title:\"pay rent\" OR is:done) due:none start>=2023-03-01").unwrap(),
+            Filter::And(
+                Box::new(Filter::And(
+                    Box::new(Filter::Not(Box::new(Filter::Or(
+                        Box::new(Filter::Contains(TextField::Title, "pay rent".to_string())),
+                        Box::new(Filter::Done),
+                    )))),
+                    Box::new(Filter::Not(Box::new(Filter::Has(DateField::Due)))),
+                )),
+                Box::new(Filter::Compare(
+                    DateField::Start,
+                    Op::AfterOrAt,
+                    TimeValue::Absolute("2023-03-01T00:00:00Z".parse().unwrap()),
+                )),
+            )
+        );
+
+        assert_eq!(
+            parse("updated>\"2023-03-01T09:00:00-05:00\"").unwrap(),
+            Filter::Compare(
+                DateField::Updated,
+                Op::After,
+                TimeValue::Absolute("2023-03-01T14:00:00Z".parse().unwrap()),
+            )
+        );
+    }
+
+    #[test]
+    fn categories() {
+        for (input, category) in [
+            ("category:Work", Some("work")),
+            ("category:Über", Some("über")),
+            ("category:\" Straße \"", Some("straße")),
+            ("category:NONE", None),
+        ] {
+            assert_eq!(
+                parse(input),
+                Ok(Filter::Category(category.map(str::to_string))),
+                "{input}"
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        for (input, error) in [
+            ("", "Filter is empty."),
+            ("category:", "Expected a value after \"category\"."),
+            ("priority:high", "Unknown field \"priority\"."),
+            ("title<3", "\"<\" only works on dates."),
+            (
+                "due:tomorrow",
+                "Use <, <=, > or >= to compare \"due\", or due:any / due:none.",
+            ),
+            ("due<7y", "Invalid date \"7y\"."),
+            ("(done", "Missing \")\"."),
+            ("done)", "Unexpected \")\"."),
+            ("AND done", "Expected something before \"AND\"."),
+            ("done AND", "Unexpected end of filter."),
+            ("\"open", "Unterminated quote."),
+        ] {
+            assert_eq!(parse(input), Err(error.to_string()), "{input}");
+        }
+
+        assert_eq!(
+            parse(&"(".repeat(100)),
+            Err("Filter is nested too deeply.".to_string())
+        );
+    }
+}
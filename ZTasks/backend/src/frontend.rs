@@ -0,0 +1,192 @@
+//! The React app from `ZTasks/frontend`, after `npm run build`. Release builds embed it into the binary,
+//! debug builds read it from disk so it can be rebuilt without restarting the server.
+
+use crate::error::ApiError;
+use actix_web::{
+    http::{header, Method},
+    HttpRequest, HttpResponse,
+};
+use rust_embed::{EmbeddedFile, RustEmbed};
+
+#[derive(RustEmbed)]
+#[cfg_attr(not(test), folder = "../frontend/build/")]
+#[cfg_attr(test, folder = "src/testing/frontend/")]
+// So the backend still builds before the frontend has been
+#[allow_missing = true]
+struct Assets;
+
+/// Precompressed variants that `npm run build` writes next to each file, best first.
+const ENCODINGS: [(&str, &str); 2] = [("br", ".br"), ("gzip", ".gz")];
+
+/// Whether the client listed `encoding` in `Accept-Encoding` without turning it off with `q=0`.
+fn accepts(req: &HttpRequest, encoding: &str) -> bool {
+    let Some(accepted) = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+
+    accepted.split(',').any(|item| {
+        let mut parts = item.split(';').map(str::trim);
+        parts.next() == Some(encoding)
+            && parts.all(|param| !matches!(param, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"))
+    })
+}
+
+/// Files under `static/` have a hash in their name, so they can be cached forever.
+/// Everything else (mostly `index.html`) has to be checked with the server every time.
+fn cache_control(path: &str) -> &'static str {
+    if path.starts_with("static/") {
+        "public, max-age=31536000, immutable"
+    } else {
+        "no-cache"
+    }
+}
+
+fn respond(req: &HttpRequest, path: &str, file: EmbeddedFile) -> HttpResponse {
+    let content_type = file.metadata.mimetype().to_string();
+    let (file, encoding) = ENCODINGS
+        .iter()
+        .filter(|(encoding, _)| accepts(req, encoding))
+        .find_map(|(encoding, extension)| {
+            Assets::get(&format!("{path}{extension}")).map(|file| (file, Some(*encoding)))
+        })
+        .unwrap_or((file, None));
+
+    let hash: String = file
+        .metadata
+        .sha256_hash()
+        .iter()
+        .take(16)
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    let etag = format!("\"{hash}\"");
+
+    let cached = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag));
+
+    let mut response = match cached {
+        true => HttpResponse::NotModified(),
+        false => HttpResponse::Ok(),
+    };
+
+    response
+        .insert_header((header::CONTENT_TYPE, content_type))
+        .insert_header((header::CACHE_CONTROL, cache_control(path)))
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::VARY, "Accept-Encoding"));
+    if let Some(encoding) = encoding {
+        response.insert_header((header::CONTENT_ENCODING, encoding));
+    }
+
+    match cached {
+        true => response.finish(),
+        false => response.body(file.data.into_owned()),
+    }
+}
+
+/// Serves the frontend's files, and `index.html` for any other page so the app can route it itself.
+/// Unknown API routes still get a JSON 404.
+pub async fn serve(req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let path = req.path().trim_start_matches('/');
+
+    if path == "api" || path.starts_with("api/") {
+        return Err(ApiError::NotFound("Not found.".to_string()));
+    }
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Ok(HttpResponse::MethodNotAllowed().finish());
+    }
+
+    if let Some(file) = Assets::get(path).filter(|_| !path.is_empty()) {
+        return Ok(respond(&req, path, file));
+    }
+    // A missing script or stylesheet shouldn't come back as HTML
+    if path.starts_with("static/") {
+        return Err(ApiError::NotFound("Not found.".to_string()));
+    }
+
+    match Assets::get("index.html") {
+        Some(file) => Ok(respond(&req, "index.html", file)),
+        None => Err(ApiError::NotFound(
+            "The frontend hasn't been built, run `npm run build` in ZTasks/frontend.".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, web};
+
+    async fn get(uri: &str, headers: &[(header::HeaderName, &str)]) -> HttpResponse {
+        let mut req = test::TestRequest::get().uri(uri);
+        for (name, value) in headers {
+            req = req.insert_header((name.clone(), *value));
+        }
+        serve(req.to_http_request())
+            .await
+            .unwrap_or_else(|error| actix_web::ResponseError::error_response(&error))
+    }
+
+    fn value(response: &HttpResponse, name: header::HeaderName) -> Option<&str> {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    #[actix_web::test]
+    async fn pages_fall_back_to_index() {
+        for uri in ["/", "/login", "/register", "/tasks/12?view=week"] {
+            let response = get(uri, &[]).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+            assert_eq!(value(&response, header::CONTENT_TYPE), Some("text/html"));
+            assert_eq!(value(&response, header::CACHE_CONTROL), Some("no-cache"));
+        }
+
+        assert_eq!(
+            get("/static/js/missing.js", &[]).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        // The rest of the API still wins over the fallback
+        let app = test::init_service(crate::testing::app().default_service(web::to(serve))).await;
+        let (status, body) =
+            crate::testing::call(&app, test::TestRequest::get().uri("/api/nonexistent")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["message"], "Not found.");
+    }
+
+    #[actix_web::test]
+    async fn assets_are_cached_and_compressed() {
+        let uri = "/static/js/main.1a2b3c4d.js";
+        let response = get(uri, &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            value(&response, header::CONTENT_TYPE),
+            Some("text/javascript")
+        );
+        assert_eq!(
+            value(&response, header::CACHE_CONTROL),
+            Some("public, max-age=31536000, immutable")
+        );
+        assert_eq!(value(&response, header::CONTENT_ENCODING), None);
+        let etag = value(&response, header::ETAG).unwrap().to_string();
+
+        let compressed = get(uri, &[(header::ACCEPT_ENCODING, "gzip, deflate, br;q=0")]).await;
+        assert_eq!(value(&compressed, header::CONTENT_ENCODING), Some("gzip"));
+        assert_eq!(
+            value(&compressed, header::CONTENT_TYPE),
+            Some("text/javascript")
+        );
+        assert_ne!(value(&compressed, header::ETAG), Some(etag.as_str()));
+
+        let response = get(uri, &[(header::IF_NONE_MATCH, &etag)]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+    }
+}
@@ -0,0 +1,431 @@
+use super::protocol::{Event, ServerMessage, Topic};
+use actix::{Actor, Context, Handler, Message, Recipient};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// How many events are kept per user for sockets that reconnect with `resume_from`.
+pub const HISTORY_LIMIT: usize = 1000;
+
+/// Delivered to a socket's session actor, which writes it out as a text frame.
+#[derive(Debug, Clone, Message)]
+#[rtype(result = "()")]
+pub struct Push(pub ServerMessage);
+
+/// Tells a socket's session actor to close it, because the server is shutting down.
+#[derive(Debug, Message)]
+#[rtype(result = "()")]
+pub struct Close;
+
+/// A socket that just opened, which may not have said hello yet.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Open {
+    pub session_id: String,
+    pub recipient: Recipient<Close>,
+}
+
+/// An authenticated socket. The broker answers with `ready` (and `resync_required` if it can't resume).
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Connect {
+    pub session_id: String,
+    pub user_id: i64,
+    pub recipient: Recipient<Push>,
+    /// The last sequence number the client saw on a previous socket
+    pub resume_from: Option<u64>,
+}
+
+/// Sent when a socket closes, whether or not it said hello.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Disconnect {
+    pub session_id: String,
+}
+
+/// Turns topics on (or off) for a socket.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Subscribe {
+    pub session_id: String,
+    pub topics: Vec<Topic>,
+    pub enabled: bool,
+}
+
+/// Takes topics away from every socket of `user_id`, like when they're removed from a list.
+/// Sockets that had them get told they're unsubscribed.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Revoke {
+    pub user_id: i64,
+    pub topics: Vec<Topic>,
+}
+
+/// An event for every subscribed socket of `user_id`, except the one that caused it.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Publish {
+    pub user_id: i64,
+    pub origin: Option<String>,
+    pub event: Event,
+}
+
+/// Closes every open socket, and answers with how many there were.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct Drain;
+
+/// How many sockets are open.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct Sockets;
+
+struct Client {
+    user_id: i64,
+    recipient: Recipient<Push>,
+    topics: HashSet<Topic>,
+    /// Events after this are replayed when a topic gets subscribed to
+    cursor: u64,
+}
+
+/// Recent events of one user.
+struct History {
+    events: VecDeque<(u64, Event)>,
+    /// Anything up to and including this has been forgotten
+    floor: u64,
+}
+
+/// Keeps track of every open socket so changes can be fanned out to the right ones.
+pub struct Broker {
+    /// Every open socket, authenticated or not
+    sockets: HashMap<String, Recipient<Close>>,
+    clients: HashMap<String, Client>,
+    by_user: HashMap<i64, HashSet<String>>,
+    history: HashMap<i64, History>,
+    history_limit: usize,
+    /// The last sequence number handed out
+    seq: u64,
+    /// Sequence numbers from before this broker started can't be resumed from
+    started_at: u64,
+}
+
+impl Default for Broker {
+    fn default() -> Self {
+        Self::new(HISTORY_LIMIT)
+    }
+}
+
+impl Broker {
+    pub fn new(history_limit: usize) -> Self {
+        // Counting from the current time in microseconds keeps sequence numbers going up across restarts,
+        // so a client resuming from before one gets told to resync instead of silently missing events
+        let seq = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_micros() as u64)
+            .unwrap_or_default();
+
+        Self {
+            sockets: HashMap::new(),
+            clients: HashMap::new(),
+            by_user: HashMap::new(),
+            history: HashMap::new(),
+            history_limit,
+            seq,
+            started_at: seq,
+        }
+    }
+
+    /// Whether every event of `user_id` after `seq` is still around.
+    fn can_resume(&self, user_id: i64, seq: u64) -> bool {
+        let floor = self
+            .history
+            .get(&user_id)
+            .map_or(self.started_at, |history| history.floor);
+        floor <= seq && seq <= self.seq
+    }
+}
+
+impl Actor for Broker {
+    type Context = Context<Self>;
+}
+
+impl Handler<Open> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Open, _: &mut Self::Context) {
+        self.sockets.insert(msg.session_id, msg.recipient);
+    }
+}
+
+impl Handler<Connect> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Connect, _: &mut Self::Context) {
+        let (cursor, resync) = match msg.resume_from {
+            Some(seq) if self.can_resume(msg.user_id, seq) => (seq, false),
+            Some(_) => (self.seq, true),
+            None => (self.seq, false),
+        };
+
+        msg.recipient.do_send(Push(ServerMessage::Ready {
+            session_id: msg.session_id.clone(),
+            user_id: msg.user_id,
+            seq: cursor,
+        }));
+        if resync {
+            msg.recipient
+                .do_send(Push(ServerMessage::ResyncRequired { seq: cursor }));
+        }
+
+        self.by_user
+            .entry(msg.user_id)
+            .or_default()
+            .insert(msg.session_id.clone());
+        self.clients.insert(
+            msg.session_id,
+            Client {
+                user_id: msg.user_id,
+                recipient: msg.recipient,
+                topics: HashSet::new(),
+                cursor,
+            },
+        );
+    }
+}
+
+impl Handler<Disconnect> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Disconnect, _: &mut Self::Context) {
+        self.sockets.remove(&msg.session_id);
+        let Some(client) = self.clients.remove(&msg.session_id) else {
+            return;
+        };
+
+        if let Some(sessions) = self.by_user.get_mut(&client.user_id) {
+            sessions.remove(&msg.session_id);
+            if sessions.is_empty() {
+                self.by_user.remove(&client.user_id);
+            }
+        }
+    }
+}
+
+impl Handler<Subscribe> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Subscribe, _: &mut Self::Context) {
+        let Some(client) = self.clients.get_mut(&msg.session_id) else {
+            return;
+        };
+
+        if !msg.enabled {
+            for topic in &msg.topics {
+                client.topics.remove(topic);
+            }
+            return;
+        }
+
+        let added: HashSet<_> = msg
+            .topics
+            .into_iter()
+            .filter(|topic| client.topics.insert(*topic))
+            .collect();
+
+        // Catch up on whatever happened in these topics since the socket connected (or resumed from)
+        let Some(history) = self.history.get(&client.user_id) else {
+            return;
+        };
+        for (seq, event) in &history.events {
+            if *seq > client.cursor && added.contains(&event.topic()) {
+                client.recipient.do_send(Push(ServerMessage::Event {
+                    seq: *seq,
+                    event: event.clone(),
+                }));
+            }
+        }
+    }
+}
+
+impl Handler<Revoke> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Revoke, _: &mut Self::Context) {
+        let Some(sessions) = self.by_user.get(&msg.user_id) else {
+            return;
+        };
+
+        for session_id in sessions {
+            let Some(client) = self.clients.get_mut(session_id) else {
+                continue;
+            };
+            let removed: Vec<_> = msg
+                .topics
+                .iter()
+                .filter(|topic| client.topics.remove(topic))
+                .copied()
+                .collect();
+            if !removed.is_empty() {
+                client
+                    .recipient
+                    .do_send(Push(ServerMessage::Unsubscribed { topics: removed }));
+            }
+        }
+    }
+}
+
+impl Handler<Publish> for Broker {
+    type Result = ();
+
+    fn handle(&mut self, msg: Publish, _: &mut Self::Context) {
+        self.seq += 1;
+        let seq = self.seq;
+        let topic = msg.event.topic();
+
+        let history = self.history.entry(msg.user_id).or_insert_with(|| History {
+            events: VecDeque::new(),
+            floor: self.started_at,
+        });
+        history.events.push_back((seq, msg.event.clone()));
+        while history.events.len() > self.history_limit {
+            if let Some((forgotten, _)) = history.events.pop_front() {
+                history.floor = forgotten;
+            }
+        }
+
+        let Some(sessions) = self.by_user.get(&msg.user_id) else {
+            return;
+        };
+
+        for session_id in sessions {
+            if msg.origin.as_ref() == Some(session_id) {
+                continue;
+            }
+
+            match self.clients.get(session_id) {
+                Some(client) if client.topics.contains(&topic) => {
+                    client.recipient.do_send(Push(ServerMessage::Event {
+                        seq,
+                        event: msg.event.clone(),
+                    }))
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Handler<Drain> for Broker {
+    type Result = usize;
+
+    fn handle(&mut self, _: Drain, _: &mut Self::Context) -> usize {
+        for recipient in self.sockets.values() {
+            recipient.do_send(Close);
+        }
+        self.sockets.len()
+    }
+}
+
+impl Handler<Sockets> for Broker {
+    type Result = usize;
+
+    fn handle(&mut self, _: Sockets, _: &mut Self::Context) -> usize {
+        self.sockets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{events, received, Collector};
+
+    fn publish(broker: &actix::Addr<Broker>, user_id: i64, id: i64) {
+        broker.do_send(Publish {
+            user_id,
+            origin: None,
+            event: Event::TaskDeleted { id, list_id: None },
+        });
+    }
+
+    fn deleted(messages: &[ServerMessage]) -> Vec<i64> {
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessage::Event {
+                    event: Event::TaskDeleted { id, .. },
+                    ..
+                } => Some(*id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    async fn connect(
+        broker: &actix::Addr<Broker>,
+        session_id: &str,
+        resume_from: Option<u64>,
+    ) -> (actix::Addr<Collector>, Vec<ServerMessage>) {
+        let collector = Collector::default().start();
+        broker.do_send(Connect {
+            session_id: session_id.to_string(),
+            user_id: 1,
+            recipient: collector.clone().recipient(),
+            resume_from,
+        });
+        broker.do_send(Subscribe {
+            session_id: session_id.to_string(),
+            topics: vec![Topic::Tasks],
+            enabled: true,
+        });
+        let messages = received(broker, &collector).await;
+        (collector, messages)
+    }
+
+    fn sequence_numbers(messages: &[ServerMessage]) -> Vec<u64> {
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                ServerMessage::Event { seq, .. } => Some(*seq),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn resume_replays_missed_events() {
+        let broker = Broker::new(3).start();
+        let (socket, _) = connect(&broker, "first", None).await;
+
+        publish(&broker, 1, 10);
+        publish(&broker, 2, 99);
+        let seen = received(&broker, &socket).await;
+        assert_eq!(deleted(&seen), [10]);
+        let last_seen = *sequence_numbers(&seen).last().unwrap();
+
+        // The socket drops, and a few things happen while it's gone
+        broker.do_send(Disconnect {
+            session_id: "first".to_string(),
+        });
+        publish(&broker, 1, 11);
+        publish(&broker, 1, 12);
+
+        let (_, messages) = connect(&broker, "second", Some(last_seen)).await;
+        assert!(matches!(messages[0], ServerMessage::Ready { seq, .. } if seq == last_seen));
+        assert_eq!(deleted(&messages), [11, 12]);
+        let seqs = sequence_numbers(&events(messages));
+        assert!(seqs[0] > last_seen && seqs[1] > seqs[0]);
+
+        // Only three events are kept, so resuming from before 10 isn't possible anymore
+        publish(&broker, 1, 13);
+        let (_, messages) = connect(&broker, "third", Some(last_seen - 1)).await;
+        assert!(matches!(messages[1], ServerMessage::ResyncRequired { .. }));
+        assert!(deleted(&messages).is_empty());
+
+        // Neither is resuming from a sequence number this broker never handed out
+        let (_, messages) = connect(&broker, "fourth", Some(u64::MAX)).await;
+        assert!(matches!(messages[1], ServerMessage::ResyncRequired { .. }));
+        let (_, messages) = connect(&broker, "fifth", Some(1)).await;
+        assert!(matches!(messages[1], ServerMessage::ResyncRequired { .. }));
+    }
+}
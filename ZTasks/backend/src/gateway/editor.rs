@@ -0,0 +1,567 @@
+//! Live notepads, which several sockets can edit at once. Each client sends its edits along with the
+//! revision it made them on, and the editor transforms them past whatever happened since (see
+//! `notepad`) before applying them and passing them on to everyone else.
+//!
+//! A notepad is the task's notes. Revisions only count the changes since it was opened, and edits are saved
+//! to the task like any other change to it.
+
+use super::{
+    broker::{Broker, Publish, Push},
+    protocol::{Event, Presence, ServerMessage},
+};
+use crate::{
+    database::{
+        lists,
+        tasks::{self, TaskFields},
+        Database,
+    },
+    notepad::{self, Cursor, Op},
+    util::now,
+};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message, Recipient};
+use actix_web::web;
+use rusqlite::Connection;
+use std::{
+    collections::{hash_map, HashMap, VecDeque},
+    time::Duration,
+};
+
+/// How often notepads with new edits are saved, and who has them open is checked again.
+pub const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(5);
+/// How many changes are kept around for edits made on older revisions.
+pub const HISTORY_LIMIT: usize = 500;
+/// In characters.
+pub const MAX_LENGTH: usize = 100_000;
+
+/// A socket opening a notepad. It gets the whole thing back, or an error if the task isn't there.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Join {
+    pub session_id: String,
+    pub user_id: i64,
+    pub task_id: i64,
+    pub recipient: Recipient<Push>,
+}
+
+/// A socket closing a notepad, or every notepad it has open if `task_id` is `None`.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Leave {
+    pub session_id: String,
+    pub task_id: Option<i64>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Edit {
+    pub session_id: String,
+    pub task_id: i64,
+    pub revision: i64,
+    pub ops: Vec<Op>,
+}
+
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MoveCursor {
+    pub session_id: String,
+    pub task_id: i64,
+    pub revision: i64,
+    pub cursor: Option<Cursor>,
+}
+
+/// Saves every notepad with unsaved edits right away, and answers with how many there were.
+#[derive(Message)]
+#[rtype(result = "usize")]
+pub struct Flush;
+
+struct Member {
+    user_id: i64,
+    recipient: Recipient<Push>,
+    cursor: Option<Cursor>,
+}
+
+/// A notepad someone has open.
+struct Pad {
+    text: String,
+    /// Of `text`, in characters
+    len: usize,
+    revision: i64,
+    /// The last few changes, the final one being what made `revision`
+    history: VecDeque<Vec<Op>>,
+    members: HashMap<String, Member>,
+    /// The task's notes as of when they were last loaded or saved, and the revision that was
+    saved: String,
+    saved_revision: i64,
+    /// Who made the latest edit that hasn't been saved yet, if there is one. Edits are saved as theirs.
+    editor: Option<i64>,
+}
+
+impl Pad {
+    fn new(notes: String) -> Self {
+        Self {
+            len: notes.chars().count(),
+            text: notes.clone(),
+            revision: 0,
+            history: VecDeque::new(),
+            members: HashMap::new(),
+            saved: notes,
+            saved_revision: 0,
+            editor: None,
+        }
+    }
+
+    fn state(&self, task_id: i64, session_id: &str, editable: bool) -> ServerMessage {
+        ServerMessage::Notepad {
+            task_id,
+            text: self.text.clone(),
+            revision: self.revision,
+            editable,
+            presence: self
+                .members
+                .iter()
+                .filter(|(id, _)| *id != session_id)
+                .map(|(id, member)| presence(id, member))
+                .collect(),
+        }
+    }
+
+    fn broadcast(&self, except: &str, message: ServerMessage) {
+        for (session_id, member) in &self.members {
+            if session_id != except {
+                member.recipient.do_send(Push(message.clone()));
+            }
+        }
+    }
+
+    /// The changes made after `revision`, unless it's too old (or new) to tell.
+    fn since(&self, revision: i64) -> Option<impl Iterator<Item = &Vec<Op>>> {
+        let behind = usize::try_from(self.revision - revision).ok()?;
+        let start = self.history.len().checked_sub(behind)?;
+        Some(self.history.range(start..))
+    }
+
+    /// Applies a change made at `revision`, and returns it as it was applied to the latest one.
+    fn edit(&mut self, revision: i64, ops: Vec<Op>) -> Result<Vec<Op>, String> {
+        let since: Vec<_> = self
+            .since(revision)
+            .ok_or("Unknown revision.")?
+            .cloned()
+            .collect();
+        // The text was as long as what the first change after it started from
+        let len = since
+            .first()
+            .map_or(self.len, |change| notepad::base_len(change));
+
+        let mut change = notepad::complete(ops, len)?;
+        for past in &since {
+            change = notepad::transform(&change, past)?.0;
+        }
+
+        let text = notepad::apply(&self.text, &change)?;
+        let len = text.chars().count();
+        if len > MAX_LENGTH {
+            return Err(format!(
+                "Notepads can't be longer than {MAX_LENGTH} characters."
+            ));
+        }
+
+        self.text = text;
+        self.len = len;
+        self.revision += 1;
+        self.history.push_back(change.clone());
+        if self.history.len() > HISTORY_LIMIT {
+            self.history.pop_front();
+        }
+        for member in self.members.values_mut() {
+            member.cursor = member.cursor.map(|cursor| cursor.transform(&change));
+        }
+
+        Ok(change)
+    }
+
+    /// Brings in the task's notes having been changed to `notes` some other way since they were last
+    /// saved, as a change made on the revision that was, and passes it on to everyone.
+    fn merge(&mut self, task_id: i64, notes: &str) -> Result<(), String> {
+        let ops = self.edit(self.saved_revision, notepad::diff(&self.saved, notes))?;
+        self.saved = notes.to_string();
+        if self.editor.is_none() {
+            self.saved_revision = self.revision;
+        }
+
+        self.broadcast(
+            "",
+            ServerMessage::NotepadEdit {
+                task_id,
+                revision: self.revision,
+                session_id: None,
+                user_id: None,
+                ops,
+            },
+        );
+        Ok(())
+    }
+}
+
+fn presence(session_id: &str, member: &Member) -> Presence {
+    Presence {
+        session_id: session_id.to_string(),
+        user_id: member.user_id,
+        cursor: member.cursor,
+    }
+}
+
+fn error(recipient: &Recipient<Push>, message: impl Into<String>) {
+    recipient.do_send(Push(ServerMessage::Error {
+        message: message.into(),
+    }));
+}
+
+/// Whether the user can see the task, and if so whether they can edit it.
+fn access(conn: &Connection, user_id: i64, task_id: i64) -> rusqlite::Result<Option<bool>> {
+    let Some(task) = tasks::get(conn, user_id, task_id)? else {
+        return Ok(None);
+    };
+
+    match task.list_id {
+        Some(list_id) => Ok(Some(
+            lists::role(conn, user_id, list_id)?.is_some_and(|role| role.can_edit()),
+        )),
+        None => Ok(Some(true)),
+    }
+}
+
+/// Keeps the open notepads in memory, and saves them every `SNAPSHOT_INTERVAL`.
+pub struct Editor {
+    db: web::Data<Database>,
+    broker: Addr<Broker>,
+    pads: HashMap<i64, Pad>,
+}
+
+impl Editor {
+    pub fn new(db: web::Data<Database>, broker: Addr<Broker>) -> Self {
+        Self {
+            db,
+            broker,
+            pads: HashMap::new(),
+        }
+    }
+
+    /// Like `access()`, with database errors counting as no access.
+    fn access(&self, user_id: i64, task_id: i64) -> Option<bool> {
+        match access(&self.db.lock(), user_id, task_id) {
+            Ok(access) => access,
+            Err(error) => {
+                log::error!("Database error: {error}");
+                None
+            }
+        }
+    }
+
+    fn leave(&mut self, task_id: i64, session_id: &str) {
+        let Some(pad) = self.pads.get_mut(&task_id) else {
+            return;
+        };
+        if pad.members.remove(session_id).is_none() {
+            return;
+        }
+
+        pad.broadcast(
+            session_id,
+            ServerMessage::NotepadLeft {
+                task_id,
+                session_id: session_id.to_string(),
+            },
+        );
+        if pad.members.is_empty() {
+            self.save(task_id);
+            self.pads.remove(&task_id);
+        }
+    }
+
+    /// Stops sending the notepad to `session_id`, telling it why.
+    fn close(&mut self, task_id: i64, session_id: &str, message: &str) {
+        if let Some(member) = self
+            .pads
+            .get(&task_id)
+            .and_then(|pad| pad.members.get(session_id))
+        {
+            member.recipient.do_send(Push(ServerMessage::NotepadClosed {
+                task_id,
+                message: message.to_string(),
+            }));
+        }
+        self.leave(task_id, session_id);
+    }
+
+    /// Saves the notepad to the task's notes if it has unsaved edits, and returns whether it did. Changes
+    /// made to the notes some other way in the meantime are merged in first.
+    fn save(&mut self, task_id: i64) -> bool {
+        match self.try_save(task_id) {
+            Ok(saved) => saved,
+            Err(error) => {
+                log::error!("Couldn't save the notepad of task {task_id}: {error}");
+                false
+            }
+        }
+    }
+
+    fn try_save(&mut self, task_id: i64) -> rusqlite::Result<bool> {
+        let Some(pad) = self.pads.get_mut(&task_id) else {
+            return Ok(false);
+        };
+        // Without unsaved edits, anyone who has it open can look for changes
+        let Some(user_id) = pad
+            .editor
+            .or_else(|| pad.members.values().next().map(|member| member.user_id))
+        else {
+            return Ok(false);
+        };
+
+        let conn = self.db.lock();
+        // If they can't see the task anymore, the snapshot closes it for them
+        let Some(task) = tasks::get(&conn, user_id, task_id)? else {
+            pad.editor = None;
+            return Ok(false);
+        };
+
+        if task.notes != pad.saved {
+            if let Err(message) = pad.merge(task_id, &task.notes) {
+                log::warn!(
+                    "Couldn't merge the notes of task {task_id} into its notepad: {message}"
+                );
+                for member in pad.members.values() {
+                    member.recipient.do_send(Push(ServerMessage::NotepadClosed {
+                        task_id,
+                        message: "The notes were changed somewhere else, open them again."
+                            .to_string(),
+                    }));
+                }
+                self.pads.remove(&task_id);
+                return Ok(false);
+            }
+        }
+        let Some(user_id) = pad.editor.take() else {
+            return Ok(false);
+        };
+
+        let fields = TaskFields {
+            notes: pad.text.clone(),
+            ..task.fields()
+        };
+        pad.saved = pad.text.clone();
+        pad.saved_revision = pad.revision;
+        let Some(task) = tasks::update(&conn, user_id, task_id, &fields, now())? else {
+            log::warn!(
+                "Dropped notepad edits to task {task_id}, user {user_id} can't change it anymore"
+            );
+            return Ok(false);
+        };
+
+        for user_id in lists::audience(&conn, user_id, task.list_id)? {
+            self.broker.do_send(Publish {
+                user_id,
+                origin: None,
+                event: Event::TaskUpdated {
+                    task: Box::new(task.clone()),
+                },
+            });
+        }
+        Ok(true)
+    }
+
+    fn snapshot(&mut self) -> usize {
+        let task_ids: Vec<_> = self.pads.keys().copied().collect();
+        let mut saved = 0;
+
+        for task_id in task_ids {
+            if self.save(task_id) {
+                saved += 1;
+            }
+
+            let members: Vec<_> = self
+                .pads
+                .get(&task_id)
+                .into_iter()
+                .flat_map(|pad| &pad.members)
+                .map(|(session_id, member)| (session_id.clone(), member.user_id))
+                .collect();
+            for (session_id, user_id) in members {
+                if self.access(user_id, task_id).is_none() {
+                    self.close(task_id, &session_id, "You can't see this task anymore.");
+                }
+            }
+        }
+
+        saved
+    }
+}
+
+impl Actor for Editor {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        ctx.run_interval(SNAPSHOT_INTERVAL, |editor, _| {
+            editor.snapshot();
+        });
+    }
+}
+
+impl Handler<Join> for Editor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Join, _: &mut Self::Context) {
+        let Some(editable) = self.access(msg.user_id, msg.task_id) else {
+            return error(&msg.recipient, "Task not found.");
+        };
+
+        let pad = match self.pads.entry(msg.task_id) {
+            hash_map::Entry::Occupied(entry) => entry.into_mut(),
+            hash_map::Entry::Vacant(entry) => {
+                match tasks::get(&self.db.lock(), msg.user_id, msg.task_id) {
+                    Ok(Some(task)) => entry.insert(Pad::new(task.notes)),
+                    Ok(None) => return error(&msg.recipient, "Task not found."),
+                    Err(error) => {
+                        log::error!("Database error: {error}");
+                        return self::error(&msg.recipient, "Internal database error.");
+                    }
+                }
+            }
+        };
+
+        let member = Member {
+            user_id: msg.user_id,
+            recipient: msg.recipient,
+            cursor: None,
+        };
+        member
+            .recipient
+            .do_send(Push(pad.state(msg.task_id, &msg.session_id, editable)));
+        pad.broadcast(
+            &msg.session_id,
+            ServerMessage::NotepadPresence {
+                task_id: msg.task_id,
+                presence: presence(&msg.session_id, &member),
+            },
+        );
+        pad.members.insert(msg.session_id, member);
+    }
+}
+
+impl Handler<Leave> for Editor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _: &mut Self::Context) {
+        let task_ids: Vec<_> = match msg.task_id {
+            Some(task_id) => vec![task_id],
+            None => self.pads.keys().copied().collect(),
+        };
+
+        for task_id in task_ids {
+            self.leave(task_id, &msg.session_id);
+        }
+    }
+}
+
+impl Handler<Edit> for Editor {
+    type Result = ();
+
+    fn handle(&mut self, msg: Edit, _: &mut Self::Context) {
+        let Some(member) = self
+            .pads
+            .get(&msg.task_id)
+            .and_then(|pad| pad.members.get(&msg.session_id))
+        else {
+            return;
+        };
+        let (user_id, recipient) = (member.user_id, member.recipient.clone());
+
+        let Some(editable) = self.access(user_id, msg.task_id) else {
+            return self.close(
+                msg.task_id,
+                &msg.session_id,
+                "You can't see this task anymore.",
+            );
+        };
+        if !editable {
+            return error(&recipient, "Viewers can't edit the notepad.");
+        }
+
+        let Some(pad) = self.pads.get_mut(&msg.task_id) else {
+            return;
+        };
+        if pad.since(msg.revision).is_none() {
+            // Too far behind to catch up on, so it has to start over
+            error(
+                &recipient,
+                "That revision is too old, reloading the notepad.",
+            );
+            return recipient.do_send(Push(pad.state(msg.task_id, &msg.session_id, editable)));
+        }
+
+        match pad.edit(msg.revision, msg.ops) {
+            Ok(ops) => {
+                pad.editor = Some(user_id);
+                recipient.do_send(Push(ServerMessage::NotepadAck {
+                    task_id: msg.task_id,
+                    revision: pad.revision,
+                }));
+                pad.broadcast(
+                    &msg.session_id,
+                    ServerMessage::NotepadEdit {
+                        task_id: msg.task_id,
+                        revision: pad.revision,
+                        session_id: Some(msg.session_id.clone()),
+                        user_id: Some(user_id),
+                        ops,
+                    },
+                );
+            }
+            Err(message) => error(&recipient, message),
+        }
+    }
+}
+
+impl Handler<MoveCursor> for Editor {
+    type Result = ();
+
+    fn handle(&mut self, msg: MoveCursor, _: &mut Self::Context) {
+        let Some(pad) = self.pads.get_mut(&msg.task_id) else {
+            return;
+        };
+        // Cursors from too long ago don't matter anymore, the client will send a newer one
+        let Some(since) = pad.since(msg.revision) else {
+            return;
+        };
+        let cursor = msg
+            .cursor
+            .map(|cursor| since.fold(cursor, |cursor, change| cursor.transform(change)));
+        let len = pad.len;
+
+        let Some(member) = pad.members.get_mut(&msg.session_id) else {
+            return;
+        };
+        if cursor.is_some_and(|cursor| {
+            cursor.position > len || cursor.anchor.is_some_and(|anchor| anchor > len)
+        }) {
+            return error(&member.recipient, "The cursor is past the end of the text.");
+        }
+        member.cursor = cursor;
+
+        let presence = presence(&msg.session_id, member);
+        pad.broadcast(
+            &msg.session_id,
+            ServerMessage::NotepadPresence {
+                task_id: msg.task_id,
+                presence,
+            },
+        );
+    }
+}
+
+impl Handler<Flush> for Editor {
+    type Result = usize;
+
+    fn handle(&mut self, _: Flush, _: &mut Self::Context) -> usize {
+        self.snapshot()
+    }
+}
@@ -0,0 +1,540 @@
+//! Real-time updates over a websocket, see `protocol` for the messages.
+
+pub mod broker;
+pub mod editor;
+pub mod protocol;
+mod session;
+
+use crate::{
+    auth::tokens::TokenKey,
+    database::{tasks::Task, Database},
+    error::{self, ApiError},
+};
+use actix::Addr;
+use actix_web::{dev::Payload, middleware, web, FromRequest, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use broker::{Broker, Publish, Revoke};
+use editor::Editor;
+use protocol::{Event, Topic};
+use serde::Deserialize;
+use session::Session;
+use std::future::{ready, Ready};
+
+/// REST requests can send the `session_id` from the gateway's `ready` message in this header,
+/// so that socket isn't told about changes it made itself.
+pub const SESSION_HEADER: &str = "Gateway-Session";
+
+#[derive(Deserialize)]
+pub struct ConnectQuery {
+    /// The last sequence number seen on a previous socket, to get the events missed since then
+    resume_from: Option<u64>,
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/gateway")
+            .wrap(middleware::ErrorHandlers::new().default_handler(error::envelope))
+            .route(web::get().to(connect)),
+    );
+}
+
+pub async fn connect(
+    req: HttpRequest,
+    stream: web::Payload,
+    query: web::Query<ConnectQuery>,
+    broker: web::Data<Addr<Broker>>,
+    editor: web::Data<Addr<Editor>>,
+    key: web::Data<TokenKey>,
+    db: web::Data<Database>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let session = Session::new(
+        broker.get_ref().clone(),
+        editor.get_ref().clone(),
+        key,
+        db,
+        query.resume_from,
+    );
+    ws::start(session, &req, stream)
+}
+
+/// Lets handlers push changes to the user's open sockets.
+pub struct Gateway {
+    broker: Addr<Broker>,
+    origin: Option<String>,
+}
+
+impl FromRequest for Gateway {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let gateway = req
+            .app_data::<web::Data<Addr<Broker>>>()
+            .map(|broker| Gateway {
+                broker: broker.get_ref().clone(),
+                origin: req
+                    .headers()
+                    .get(SESSION_HEADER)
+                    .and_then(|value| value.to_str().ok())
+                    .map(str::to_string),
+            })
+            .ok_or_else(|| ApiError::Internal("Gateway is not configured.".to_string()));
+
+        ready(gateway)
+    }
+}
+
+impl Gateway {
+    pub fn publish(&self, user_id: i64, event: Event) {
+        self.broker.do_send(Publish {
+            user_id,
+            origin: self.origin.clone(),
+            event,
+        });
+    }
+
+    /// Publishes `event` to each of `audience`, see `lists::audience()`.
+    fn publish_to(&self, audience: &[i64], event: Event) {
+        for user_id in audience {
+            self.publish(*user_id, event.clone());
+        }
+    }
+
+    pub fn task_created(&self, audience: &[i64], task: &Task) {
+        self.publish_to(
+            audience,
+            Event::TaskCreated {
+                task: Box::new(task.clone()),
+            },
+        );
+    }
+
+    pub fn task_updated(&self, audience: &[i64], task: &Task) {
+        self.publish_to(
+            audience,
+            Event::TaskUpdated {
+                task: Box::new(task.clone()),
+            },
+        );
+    }
+
+    pub fn task_deleted(&self, audience: &[i64], task: &Task) {
+        self.publish_to(
+            audience,
+            Event::TaskDeleted {
+                id: task.id,
+                list_id: task.list_id,
+            },
+        );
+    }
+
+    pub fn task_restored(&self, audience: &[i64], task: &Task, revision: i64) {
+        self.publish_to(
+            audience,
+            Event::TaskRestored {
+                task: Box::new(task.clone()),
+                revision,
+            },
+        );
+    }
+
+    /// Stops every socket of `user_id` from getting events for `topics` right away.
+    pub fn revoke(&self, user_id: i64, topics: Vec<Topic>) {
+        self.broker.do_send(Revoke { user_id, topics });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        broker::{Broker, Connect, Drain, Sockets, Subscribe},
+        editor::Editor,
+        protocol::{Event, ServerMessage, Topic},
+        session::{Session, CLIENT_TIMEOUT, HEARTBEAT_INTERVAL},
+        SESSION_HEADER,
+    };
+    use crate::{
+        auth::tokens::{Claims, TokenKey},
+        database::{sessions, users, Database},
+        testing::{app_with, call, events, received, request, sign_up, Collector, Socket},
+    };
+    use actix::Actor;
+    use actix_http::ws::{CloseCode, Frame};
+    use actix_web::{http::Method, test, web};
+    use serde_json::json;
+
+    fn db() -> web::Data<Database> {
+        web::Data::new(Database::open_in_memory().unwrap())
+    }
+
+    fn editor() -> actix::Addr<Editor> {
+        Editor::new(db(), Broker::default().start()).start()
+    }
+
+    /// An access token for a new user, under a session family that's still active.
+    fn sign_in(db: &Database, key: &TokenKey, username: &str) -> String {
+        let conn = db.lock();
+        let user = users::create(&conn, username, "hash", 0).unwrap().unwrap();
+        let family = format!("{username}-family");
+        sessions::create(&conn, user.id, &family, &family, 0, i64::MAX).unwrap();
+        key.sign(&Claims {
+            sub: user.id,
+            fam: family,
+            exp: i64::MAX,
+        })
+    }
+
+    #[actix_web::test]
+    async fn rest_changes_reach_other_sockets() {
+        let broker = Broker::default().start();
+        let app = test::init_service(app_with(broker.clone())).await;
+        let token = sign_up(&app, "steven").await;
+        let other = sign_up(&app, "other").await;
+
+        // User IDs are handed out in order
+        let sockets = [
+            ("phone", 1, true),
+            ("laptop", 1, true),
+            ("idle", 1, false),
+            ("other", 2, true),
+        ]
+        .map(|(session_id, user_id, subscribe)| {
+            let collector = Collector::default().start();
+            broker.do_send(Connect {
+                session_id: session_id.to_string(),
+                user_id,
+                recipient: collector.clone().recipient(),
+                resume_from: None,
+            });
+            if subscribe {
+                broker.do_send(Subscribe {
+                    session_id: session_id.to_string(),
+                    topics: vec![Topic::Tasks],
+                    enabled: true,
+                });
+            }
+            collector
+        });
+
+        // Made from the phone, so only the laptop hears about it
+        let (_, body) = call(
+            &app,
+            request(Method::POST, "/api/tasks", &token)
+                .insert_header((SESSION_HEADER, "phone"))
+                .set_json(json!({ "title": "Pay rent" })),
+        )
+        .await;
+        let id = body["task"]["id"].as_i64().unwrap();
+
+        call(
+            &app,
+            request(Method::DELETE, &format!("/api/tasks/{id}"), &token),
+        )
+        .await;
+        call(
+            &app,
+            request(Method::POST, "/api/tasks", &other).set_json(json!({ "title": "Theirs" })),
+        )
+        .await;
+
+        let [phone, laptop, idle, theirs] = sockets;
+        let phone = events(received(&broker, &phone).await);
+        let laptop = events(received(&broker, &laptop).await);
+        let idle = events(received(&broker, &idle).await);
+        let theirs = events(received(&broker, &theirs).await);
+
+        assert!(matches!(
+            phone[..],
+            [ServerMessage::Event { event: Event::TaskDeleted { id: deleted, .. }, .. }] if deleted == id
+        ));
+        let [ServerMessage::Event {
+            seq: created,
+            event: Event::TaskCreated { task },
+        }, ServerMessage::Event { seq: deleted, .. }] = &laptop[..]
+        else {
+            panic!("Unexpected events {laptop:?}");
+        };
+        assert_eq!(task.title, "Pay rent");
+        assert!(deleted > created);
+        assert!(idle.is_empty());
+        assert!(matches!(
+            &theirs[..],
+            [ServerMessage::Event { event: Event::TaskCreated { task }, .. }] if task.title == "Theirs"
+        ));
+    }
+
+    #[actix_web::test]
+    async fn handshake_and_subscribe() {
+        let broker = Broker::default().start();
+        let key = web::Data::new(TokenKey::new("test"));
+        let db = db();
+        let token = sign_in(&db, &key, "steven");
+        let mut socket = Socket::open(Session::new(
+            broker.clone(),
+            editor(),
+            key.clone(),
+            db.clone(),
+            None,
+        ));
+
+        assert_eq!(
+            socket.recv_json().await,
+            json!({ "type": "hello", "version": 1, "heartbeat_interval": HEARTBEAT_INTERVAL.as_millis() as u64 })
+        );
+
+        socket.send_json(json!({ "type": "subscribe", "topics": ["tasks"] }));
+        assert_eq!(socket.recv_json().await["message"], "Say hello first.");
+
+        socket.send_json(json!({ "type": "hello", "version": 1, "token": token }));
+        let ready = socket.recv_json().await;
+        assert_eq!(ready["type"], "ready");
+        assert_eq!(ready["user_id"], 1);
+
+        socket.send_json(json!({ "type": "subscribe", "topics": ["tasks"] }));
+        assert_eq!(socket.recv_json().await["type"], "subscribed");
+
+        broker.do_send(super::broker::Publish {
+            user_id: 1,
+            origin: None,
+            event: Event::TaskDeleted {
+                id: 5,
+                list_id: None,
+            },
+        });
+        let event = socket.recv_json().await;
+        assert_eq!(event["type"], "task_deleted");
+        assert_eq!(event["id"], 5);
+        assert!(event["seq"].as_u64().unwrap() > ready["seq"].as_u64().unwrap());
+
+        let mut socket = Socket::open(Session::new(
+            broker.clone(),
+            editor(),
+            key.clone(),
+            db.clone(),
+            None,
+        ));
+        socket.recv_json().await;
+        socket.send_json(json!({ "type": "hello", "version": 2, "token": token }));
+        assert_eq!(
+            socket.recv_json().await["message"],
+            "Unsupported protocol version, expected 1."
+        );
+        assert!(
+            matches!(socket.recv().await, Some(Frame::Close(Some(reason))) if reason.code == CloseCode::Policy)
+        );
+
+        // Logging out revokes the family, and its access tokens along with it
+        sessions::revoke(&db.lock(), "steven-family").unwrap();
+        let mut socket = Socket::open(Session::new(broker, editor(), key, db, None));
+        socket.recv_json().await;
+        socket.send_json(json!({ "type": "hello", "version": 1, "token": token }));
+        assert_eq!(
+            socket.recv_json().await["message"],
+            "Invalid or expired access token."
+        );
+    }
+
+    #[actix_web::test]
+    async fn silent_sockets_are_dropped() {
+        // Paused time skips ahead whenever everything is waiting on a timer
+        tokio::time::pause();
+        let broker = Broker::default().start();
+        let key = web::Data::new(TokenKey::new("test"));
+        let db = db();
+        let token = sign_in(&db, &key, "steven");
+
+        let mut socket = Socket::open(Session::new(
+            broker.clone(),
+            editor(),
+            key.clone(),
+            db.clone(),
+            None,
+        ));
+        socket.recv_json().await;
+        socket.send_json(json!({ "type": "hello", "version": 1, "token": token }));
+        socket.recv_json().await;
+
+        let started = tokio::time::Instant::now();
+        let mut pings = 0;
+        let reason = loop {
+            match socket.recv().await {
+                Some(Frame::Ping(_)) => pings += 1,
+                Some(Frame::Close(reason)) => break reason.unwrap(),
+                frame => panic!("Unexpected frame {frame:?}"),
+            }
+        };
+        assert_eq!(reason.code, CloseCode::Away);
+        assert!(pings >= 2);
+        assert!(started.elapsed() > CLIENT_TIMEOUT);
+        assert!(socket.recv().await.is_none());
+
+        // Never saying hello gets the socket closed too, even if it answers pings
+        let mut socket = Socket::open(Session::new(broker, editor(), key, db, None));
+        socket.recv_json().await;
+        assert_eq!(
+            socket.recv_json().await["message"],
+            "Didn't say hello in time."
+        );
+    }
+
+    #[actix_web::test]
+    async fn shutdown_closes_every_socket() {
+        let broker = Broker::default().start();
+        let key = web::Data::new(TokenKey::new("test"));
+
+        // Sockets get closed whether or not they said hello
+        let mut socket = Socket::open(Session::new(broker.clone(), editor(), key, db(), None));
+        socket.recv_json().await;
+        assert_eq!(broker.send(Sockets).await.unwrap(), 1);
+
+        assert_eq!(broker.send(Drain).await.unwrap(), 1);
+        let Some(Frame::Close(Some(reason))) = socket.recv().await else {
+            panic!("Socket wasn't closed");
+        };
+        assert_eq!(reason.code, CloseCode::Away);
+        assert!(socket.recv().await.is_none());
+        assert_eq!(broker.send(Sockets).await.unwrap(), 0);
+    }
+
+    #[actix_web::test]
+    async fn notepads_merge_concurrent_edits() {
+        use super::editor::Flush;
+        use crate::database::{revisions, tasks};
+
+        let db = db();
+        let key = web::Data::new(TokenKey::new("test"));
+        let alice = sign_in(&db, &key, "alice");
+        let bob = sign_in(&db, &key, "bob");
+        let task = {
+            let conn = db.lock();
+            let fields = tasks::TaskFields {
+                title: "Groceries".to_string(),
+                notes: "Buy milk".to_string(),
+                category: None,
+                start: None,
+                due: None,
+                recurrence: None,
+                time_zone: "UTC".to_string(),
+            };
+            tasks::insert(&conn, 1, &fields, None, None, 0).unwrap()
+        };
+
+        let broker = Broker::default().start();
+        let editor = Editor::new(db.clone(), broker.clone()).start();
+        let mut sockets = Vec::new();
+        for token in [&alice, &alice, &bob] {
+            let mut socket = Socket::open(Session::new(
+                broker.clone(),
+                editor.clone(),
+                key.clone(),
+                db.clone(),
+                None,
+            ));
+            socket.recv_json().await;
+            socket.send_json(json!({ "type": "hello", "version": 1, "token": token }));
+            let session_id = socket.recv_json().await["session_id"].clone();
+            sockets.push((socket, session_id));
+        }
+        let [(mut phone, phone_id), (mut laptop, laptop_id), (mut bob, _)] =
+            <[_; 3]>::try_from(sockets).ok().unwrap();
+
+        // Bob can't see Alice's task, so he can't see its notepad either
+        bob.send_json(json!({ "type": "notepad_open", "task_id": task.id }));
+        assert_eq!(bob.recv_json().await["message"], "Task not found.");
+
+        phone.send_json(json!({ "type": "notepad_open", "task_id": task.id }));
+        assert_eq!(
+            phone.recv_json().await,
+            json!({ "type": "notepad", "task_id": task.id, "text": "Buy milk", "revision": 0, "editable": true, "presence": [] })
+        );
+        laptop.send_json(json!({ "type": "notepad_open", "task_id": task.id }));
+        assert_eq!(
+            laptop.recv_json().await["presence"],
+            json!([{ "session_id": phone_id, "user_id": 1, "cursor": null }])
+        );
+        assert_eq!(
+            phone.recv_json().await,
+            json!({ "type": "notepad_presence", "task_id": task.id, "session_id": laptop_id, "user_id": 1, "cursor": null })
+        );
+
+        // A socket's session only runs while the test reads from it, so each message sent is
+        // followed by one the socket gets back
+        phone.send_json(json!({ "type": "notepad_cursor", "task_id": task.id, "revision": 0, "cursor": { "position": 8 } }));
+        phone.send_json(json!({ "type": "notepad_edit", "task_id": task.id, "revision": 0, "ops": [{ "retain": 8 }, { "insert": "!" }] }));
+        assert_eq!(
+            phone.recv_json().await,
+            json!({ "type": "notepad_ack", "task_id": task.id, "revision": 1 })
+        );
+        assert_eq!(laptop.recv_json().await["cursor"], json!({ "position": 8 }));
+
+        // The laptop edited revision 0 at the same time, so its edit gets moved past the phone's
+        laptop.send_json(json!({ "type": "notepad_edit", "task_id": task.id, "revision": 0, "ops": [{ "retain": 4 }, { "insert": "oat " }] }));
+        assert_eq!(
+            laptop.recv_json().await,
+            json!({ "type": "notepad_edit", "task_id": task.id, "revision": 1, "session_id": phone_id, "user_id": 1, "ops": [{ "retain": 8 }, { "insert": "!" }] })
+        );
+        assert_eq!(
+            laptop.recv_json().await,
+            json!({ "type": "notepad_ack", "task_id": task.id, "revision": 2 })
+        );
+        assert_eq!(
+            phone.recv_json().await,
+            json!({ "type": "notepad_edit", "task_id": task.id, "revision": 2, "session_id": laptop_id, "user_id": 1, "ops": [{ "retain": 4 }, { "insert": "oat " }, { "retain": 5 }] })
+        );
+
+        phone.send_json(
+            json!({ "type": "notepad_edit", "task_id": task.id, "revision": 7, "ops": [] }),
+        );
+        assert_eq!(
+            phone.recv_json().await["message"],
+            "That revision is too old, reloading the notepad."
+        );
+        let state = phone.recv_json().await;
+        assert_eq!(
+            (&state["text"], &state["revision"]),
+            (&json!("Buy oat milk!"), &json!(2))
+        );
+
+        laptop.send_json(json!({ "type": "notepad_close", "task_id": task.id }));
+        laptop.send_json(json!({ "type": "notepad_open", "task_id": task.id }));
+        // The phone's cursor moved along with the text typed at and before it
+        assert_eq!(
+            laptop.recv_json().await["presence"],
+            json!([{ "session_id": phone_id, "user_id": 1, "cursor": { "position": 13 } }])
+        );
+        assert_eq!(
+            phone.recv_json().await,
+            json!({ "type": "notepad_left", "task_id": task.id, "session_id": laptop_id })
+        );
+        assert_eq!(phone.recv_json().await["type"], "notepad_presence");
+
+        // Saving changes the task's notes, which goes into its history like any other change
+        assert_eq!(editor.send(Flush).await.unwrap(), 1);
+        let saved = tasks::get(&db.lock(), 1, task.id).unwrap().unwrap();
+        assert_eq!(saved.notes, "Buy oat milk!");
+        assert_eq!(revisions::list(&db.lock(), 1, task.id).unwrap().len(), 2);
+        // Nothing new to save
+        assert_eq!(editor.send(Flush).await.unwrap(), 0);
+
+        // Notes changed some other way come in as an edit, merged with the ones made in the meantime
+        let fields = tasks::TaskFields {
+            notes: "Buy oat milk!\nEggs".to_string(),
+            ..saved.fields()
+        };
+        tasks::update(&db.lock(), 1, task.id, &fields, 0).unwrap();
+        phone.send_json(json!({ "type": "notepad_edit", "task_id": task.id, "revision": 2, "ops": [{ "insert": "- " }] }));
+        assert_eq!(
+            phone.recv_json().await,
+            json!({ "type": "notepad_ack", "task_id": task.id, "revision": 3 })
+        );
+        assert_eq!(editor.send(Flush).await.unwrap(), 1);
+        assert_eq!(
+            phone.recv_json().await,
+            json!({ "type": "notepad_edit", "task_id": task.id, "revision": 4, "session_id": null, "user_id": null, "ops": [{ "retain": 15 }, { "insert": "\nEggs" }] })
+        );
+        assert_eq!(
+            tasks::get(&db.lock(), 1, task.id).unwrap().unwrap().notes,
+            "- Buy oat milk!\nEggs"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Messages sent over `/gateway`, as JSON text frames tagged by `type`.
+
+use crate::{
+    calendar::Occurrence,
+    database::tasks::Task,
+    notepad::{Cursor, Op},
+};
+use serde::{Deserialize, Serialize};
+
+/// Bumped whenever a message changes in a way old clients can't handle.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    /// Has to be the first message on every socket.
+    Hello {
+        version: u32,
+        token: String,
+    },
+    Subscribe {
+        topics: Vec<Topic>,
+    },
+    Unsubscribe {
+        topics: Vec<Topic>,
+    },
+    /// Starts following a task's notepad, see `editor`.
+    NotepadOpen {
+        task_id: i64,
+    },
+    NotepadClose {
+        task_id: i64,
+    },
+    /// A change to the notepad as it was at `revision`.
+    NotepadEdit {
+        task_id: i64,
+        revision: i64,
+        ops: Vec<Op>,
+    },
+    /// Where the cursor is as of `revision`, or `null` to hide it.
+    NotepadCursor {
+        task_id: i64,
+        revision: i64,
+        cursor: Option<Cursor>,
+    },
+}
+
+/// Groups of events a socket can ask for. Nothing is pushed until the socket subscribes.
+/// On the wire they're `"tasks"` and `"list:<id>"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Topic {
+    /// The user's own tasks, outside of any list
+    Tasks,
+    /// Tasks in a shared list, for its members only
+    List(i64),
+}
+
+impl TryFrom<String> for Topic {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match name.split_once(':') {
+            None if name == "tasks" => Ok(Self::Tasks),
+            Some(("list", id)) => id
+                .parse()
+                .map(Self::List)
+                .map_err(|_| format!("Invalid list ID in topic \"{name}\".")),
+            _ => Err(format!("Unknown topic \"{name}\".")),
+        }
+    }
+}
+
+impl From<Topic> for String {
+    fn from(topic: Topic) -> Self {
+        match topic {
+            Topic::Tasks => "tasks".to_string(),
+            Topic::List(id) => format!("list:{id}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Sent as soon as the socket opens. The server pings every `heartbeat_interval` milliseconds and
+    /// drops sockets that stay quiet for a few intervals.
+    Hello {
+        version: u32,
+        heartbeat_interval: u64,
+    },
+    /// The client's hello was accepted. `session_id` can be sent as the `Gateway-Session` header on REST
+    /// requests so the socket doesn't get told about its own changes. Events after `seq` will be sent
+    /// for each topic once it's subscribed to.
+    Ready {
+        session_id: String,
+        user_id: i64,
+        seq: u64,
+    },
+    /// The client asked to resume from further back than the server remembers, so it has to reload
+    /// everything. Events continue after `seq`.
+    ResyncRequired {
+        seq: u64,
+    },
+    Subscribed {
+        topics: Vec<Topic>,
+    },
+    Unsubscribed {
+        topics: Vec<Topic>,
+    },
+    Error {
+        message: String,
+    },
+    /// The whole notepad of a task, in reply to `notepad_open`. It's sent again whenever the client
+    /// has to start over, like after sending an edit the server can't place anymore.
+    Notepad {
+        task_id: i64,
+        text: String,
+        revision: i64,
+        /// Viewers of a list can follow along, but not edit
+        editable: bool,
+        /// Everyone else who has it open
+        presence: Vec<Presence>,
+    },
+    /// The client's own edit went through, and made `revision`.
+    NotepadAck {
+        task_id: i64,
+        revision: i64,
+    },
+    /// Someone else's edit, which made `revision`. Edits to the task's notes made some other way, like over
+    /// REST, come without a `session_id` and `user_id`.
+    NotepadEdit {
+        task_id: i64,
+        revision: i64,
+        session_id: Option<String>,
+        user_id: Option<i64>,
+        ops: Vec<Op>,
+    },
+    /// Someone opened the notepad or moved their cursor, as of the latest revision.
+    NotepadPresence {
+        task_id: i64,
+        #[serde(flatten)]
+        presence: Presence,
+    },
+    NotepadLeft {
+        task_id: i64,
+        session_id: String,
+    },
+    /// The notepad can't be followed anymore, like when the task was deleted.
+    NotepadClosed {
+        task_id: i64,
+        message: String,
+    },
+    #[serde(untagged)]
+    Event {
+        seq: u64,
+        #[serde(flatten)]
+        event: Event,
+    },
+}
+
+/// Someone else with a notepad open.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Presence {
+    pub session_id: String,
+    pub user_id: i64,
+    pub cursor: Option<Cursor>,
+}
+
+/// A change, which gets a sequence number when it's published.
+// The variant names are what goes over the wire
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    TaskCreated {
+        task: Box<Task>,
+    },
+    TaskUpdated {
+        task: Box<Task>,
+    },
+    TaskDeleted {
+        id: i64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        list_id: Option<i64>,
+    },
+    /// Put back the way it was at `revision`, possibly after being deleted
+    TaskRestored {
+        task: Box<Task>,
+        revision: i64,
+    },
+    /// The task (or one repeat of it) is due soon
+    Reminder {
+        occurrence: Box<Occurrence>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        list_id: Option<i64>,
+    },
+}
+
+impl Event {
+    pub fn topic(&self) -> Topic {
+        let list_id = match self {
+            Self::TaskCreated { task }
+            | Self::TaskUpdated { task }
+            | Self::TaskRestored { task, .. } => task.list_id,
+            Self::TaskDeleted { list_id, .. } | Self::Reminder { list_id, .. } => *list_id,
+        };
+
+        match list_id {
+            Some(id) => Topic::List(id),
+            None => Topic::Tasks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn wire_format() {
+        let hello: ClientMessage =
+            serde_json::from_value(json!({ "type": "hello", "version": 1, "token": "abc" }))
+                .unwrap();
+        assert!(matches!(hello, ClientMessage::Hello { version: 1, token } if token == "abc"));
+
+        let subscribe: ClientMessage =
+            serde_json::from_value(json!({ "type": "subscribe", "topics": ["tasks"] })).unwrap();
+        assert!(
+            matches!(subscribe, ClientMessage::Subscribe { topics } if topics == [Topic::Tasks])
+        );
+
+        assert!(serde_json::from_value::<ClientMessage>(json!({ "type": "echo" })).is_err());
+
+        let lists: ClientMessage =
+            serde_json::from_value(json!({ "type": "subscribe", "topics": ["list:12"] })).unwrap();
+        assert!(
+            matches!(lists, ClientMessage::Subscribe { topics } if topics == [Topic::List(12)])
+        );
+        for topic in ["list:", "list:x", "lists", "tasks:1"] {
+            let message = json!({ "type": "subscribe", "topics": [topic] });
+            assert!(serde_json::from_value::<ClientMessage>(message).is_err());
+        }
+        assert_eq!(
+            serde_json::to_value(ServerMessage::Unsubscribed {
+                topics: vec![Topic::List(2)]
+            })
+            .unwrap(),
+            json!({ "type": "unsubscribed", "topics": ["list:2"] })
+        );
+
+        assert_eq!(
+            serde_json::to_value(ServerMessage::Event {
+                seq: 7,
+                event: Event::TaskDeleted {
+                    id: 3,
+                    list_id: None,
+                },
+            })
+            .unwrap(),
+            json!({ "type": "task_deleted", "seq": 7, "id": 3 })
+        );
+    }
+}
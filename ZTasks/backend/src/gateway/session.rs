@@ -0,0 +1,284 @@
+use super::{
+    broker::{Broker, Close, Connect, Disconnect, Open, Push, Subscribe},
+    editor::{Edit, Editor, Join, Leave, MoveCursor},
+    protocol::{ClientMessage, ServerMessage, Topic, VERSION},
+};
+use crate::{
+    auth::tokens::TokenKey,
+    database::{lists, sessions, Database},
+    util::now,
+};
+use actix::{Actor, ActorContext, Addr, AsyncContext, Handler, StreamHandler};
+use actix_web::rt::time::Instant;
+use actix_web::web;
+use actix_web_actors::ws;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// How often the server pings.
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+/// Sockets that haven't sent anything (pongs included) for this long are closed.
+pub const CLIENT_TIMEOUT: Duration = Duration::from_secs(45);
+/// Clients are expected to say hello right away.
+pub const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// One open socket. It stays anonymous until the client says hello with a valid access token.
+pub struct Session {
+    id: String,
+    user_id: Option<i64>,
+    broker: Addr<Broker>,
+    editor: Addr<Editor>,
+    key: web::Data<TokenKey>,
+    db: web::Data<Database>,
+    resume_from: Option<u64>,
+    last_seen: Instant,
+}
+
+impl Session {
+    pub fn new(
+        broker: Addr<Broker>,
+        editor: Addr<Editor>,
+        key: web::Data<TokenKey>,
+        db: web::Data<Database>,
+        resume_from: Option<u64>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            user_id: None,
+            broker,
+            editor,
+            key,
+            db,
+            resume_from,
+            last_seen: Instant::now(),
+        }
+    }
+
+    fn send(ctx: &mut ws::WebsocketContext<Self>, message: &ServerMessage) {
+        match serde_json::to_string(message) {
+            Ok(text) => ctx.text(text),
+            Err(error) => log::error!("Couldn't serialize gateway message: {error}"),
+        }
+    }
+
+    fn error(ctx: &mut ws::WebsocketContext<Self>, message: impl Into<String>) {
+        Self::send(
+            ctx,
+            &ServerMessage::Error {
+                message: message.into(),
+            },
+        );
+    }
+
+    /// Errors the client can't recover from on this socket.
+    fn reject(ctx: &mut ws::WebsocketContext<Self>, message: &str) {
+        Self::error(ctx, message);
+        Self::close(ctx, ws::CloseCode::Policy, message);
+    }
+
+    fn close(ctx: &mut ws::WebsocketContext<Self>, code: ws::CloseCode, reason: &str) {
+        ctx.close(Some(ws::CloseReason {
+            code,
+            description: Some(reason.to_string()),
+        }));
+        ctx.stop();
+    }
+
+    fn heartbeat(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if self.last_seen.elapsed() > CLIENT_TIMEOUT {
+            Self::close(ctx, ws::CloseCode::Away, "Heartbeat timed out.");
+        } else {
+            ctx.ping(b"");
+        }
+    }
+
+    /// Whether the user can subscribe to `topic`. Lists are for their members only.
+    fn allowed(&self, user_id: i64, topic: Topic) -> bool {
+        let Topic::List(list_id) = topic else {
+            return true;
+        };
+
+        match lists::role(&self.db.lock(), user_id, list_id) {
+            Ok(role) => role.is_some(),
+            Err(error) => {
+                log::error!("Database error: {error}");
+                false
+            }
+        }
+    }
+
+    /// Whether access tokens of the session family are still good, see `sessions::is_active()`.
+    fn active(&self, family: &str) -> bool {
+        match sessions::is_active(&self.db.lock(), family) {
+            Ok(active) => active,
+            Err(error) => {
+                log::error!("Database error: {error}");
+                false
+            }
+        }
+    }
+
+    fn receive(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let message = match serde_json::from_str::<ClientMessage>(text) {
+            Ok(message) => message,
+            Err(error) => return Self::error(ctx, format!("Invalid message: {error}")),
+        };
+
+        match (message, self.user_id) {
+            (ClientMessage::Hello { .. }, Some(_)) => Self::error(ctx, "Already said hello."),
+            (ClientMessage::Hello { version, .. }, None) if version != VERSION => Self::reject(
+                ctx,
+                &format!("Unsupported protocol version, expected {VERSION}."),
+            ),
+            (ClientMessage::Hello { token, .. }, None) => match self
+                .key
+                .verify(&token, now())
+                .filter(|claims| self.active(&claims.fam))
+            {
+                Some(claims) => {
+                    self.user_id = Some(claims.sub);
+                    // The broker says ready once it knows where to resume from
+                    self.broker.do_send(Connect {
+                        session_id: self.id.clone(),
+                        user_id: claims.sub,
+                        recipient: ctx.address().recipient(),
+                        resume_from: self.resume_from,
+                    });
+                }
+                None => Self::reject(ctx, "Invalid or expired access token."),
+            },
+            (_, None) => Self::error(ctx, "Say hello first."),
+            (ClientMessage::Subscribe { topics }, Some(user_id)) => {
+                let (topics, denied): (Vec<_>, Vec<_>) = topics
+                    .into_iter()
+                    .partition(|topic| self.allowed(user_id, *topic));
+                for topic in denied {
+                    Self::error(ctx, format!("Can't subscribe to {}.", String::from(topic)));
+                }
+                if topics.is_empty() {
+                    return;
+                }
+
+                self.broker.do_send(Subscribe {
+                    session_id: self.id.clone(),
+                    topics: topics.clone(),
+                    enabled: true,
+                });
+                Self::send(ctx, &ServerMessage::Subscribed { topics });
+            }
+            (ClientMessage::Unsubscribe { topics }, Some(_)) => {
+                self.broker.do_send(Subscribe {
+                    session_id: self.id.clone(),
+                    topics: topics.clone(),
+                    enabled: false,
+                });
+                Self::send(ctx, &ServerMessage::Unsubscribed { topics });
+            }
+            (ClientMessage::NotepadOpen { task_id }, Some(user_id)) => self.editor.do_send(Join {
+                session_id: self.id.clone(),
+                user_id,
+                task_id,
+                recipient: ctx.address().recipient(),
+            }),
+            (ClientMessage::NotepadClose { task_id }, Some(_)) => self.editor.do_send(Leave {
+                session_id: self.id.clone(),
+                task_id: Some(task_id),
+            }),
+            (
+                ClientMessage::NotepadEdit {
+                    task_id,
+                    revision,
+                    ops,
+                },
+                Some(_),
+            ) => self.editor.do_send(Edit {
+                session_id: self.id.clone(),
+                task_id,
+                revision,
+                ops,
+            }),
+            (
+                ClientMessage::NotepadCursor {
+                    task_id,
+                    revision,
+                    cursor,
+                },
+                Some(_),
+            ) => self.editor.do_send(MoveCursor {
+                session_id: self.id.clone(),
+                task_id,
+                revision,
+                cursor,
+            }),
+        }
+    }
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.broker.do_send(Open {
+            session_id: self.id.clone(),
+            recipient: ctx.address().recipient(),
+        });
+        Self::send(
+            ctx,
+            &ServerMessage::Hello {
+                version: VERSION,
+                heartbeat_interval: HEARTBEAT_INTERVAL.as_millis() as u64,
+            },
+        );
+
+        ctx.run_interval(HEARTBEAT_INTERVAL, Self::heartbeat);
+        ctx.run_later(HELLO_TIMEOUT, |session, ctx| {
+            if session.user_id.is_none() {
+                Self::reject(ctx, "Didn't say hello in time.");
+            }
+        });
+    }
+
+    fn stopped(&mut self, _: &mut Self::Context) {
+        self.broker.do_send(Disconnect {
+            session_id: self.id.clone(),
+        });
+        self.editor.do_send(Leave {
+            session_id: self.id.clone(),
+            task_id: None,
+        });
+    }
+}
+
+impl Handler<Push> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: Push, ctx: &mut Self::Context) {
+        Self::send(ctx, &msg.0);
+    }
+}
+
+impl Handler<Close> for Session {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, ctx: &mut Self::Context) {
+        Self::close(ctx, ws::CloseCode::Away, "Server is shutting down.");
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
+    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
+        self.last_seen = Instant::now();
+
+        match msg {
+            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
+            Ok(ws::Message::Text(text)) => self.receive(&text, ctx),
+            Ok(ws::Message::Binary(_)) => Self::error(ctx, "Only text frames are supported."),
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Err(_) => ctx.stop(),
+            _ => (),
+        }
+    }
+}
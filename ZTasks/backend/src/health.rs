@@ -0,0 +1,46 @@
+//! Probes for process supervisors and load balancers.
+
+use crate::{database::Database, error::ApiError};
+use actix_web::{get, web, HttpResponse};
+use serde_json::json;
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(healthz).service(readyz);
+}
+
+/// The process is up and serving requests.
+#[get("/healthz")]
+async fn healthz() -> HttpResponse {
+    HttpResponse::Ok().json(json!({ "status": "ok" }))
+}
+
+/// The database can be queried, so requests that need it have a chance of working.
+#[get("/readyz")]
+async fn readyz(db: web::Data<Database>) -> Result<HttpResponse, ApiError> {
+    match db.lock().query_row("SELECT 1", [], |_| Ok(())) {
+        Ok(()) => Ok(HttpResponse::Ok().json(json!({ "status": "ok" }))),
+        Err(error) => {
+            log::error!("Readiness check failed: {error}");
+            Err(ApiError::Unavailable(
+                "The database isn't reachable.".to_string(),
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{app, call};
+    use actix_web::{http::StatusCode, test};
+
+    #[actix_web::test]
+    async fn probes() {
+        let app = test::init_service(app()).await;
+
+        for uri in ["/healthz", "/readyz"] {
+            let (status, body) = call(&app, test::TestRequest::get().uri(uri)).await;
+            assert_eq!(status, StatusCode::OK, "{uri}");
+            assert_eq!(body["status"], "ok");
+        }
+    }
+}
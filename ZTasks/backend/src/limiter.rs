@@ -0,0 +1,401 @@
+//! Throttling for the API. Every IP address gets a token bucket for its requests, and logins get another
+//! bucket per IP address and per username. Failed logins in a row lock out the IP address, and that IP
+//! address trying that username, for longer every time. Usernames are never locked out on their own, so
+//! nobody can lock someone else out of their account by guessing wrong on purpose.
+//!
+//! Everything takes the current time as an argument so tests can control it.
+
+use crate::{config::RateLimits, error::ApiError};
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::X_FORWARDED_FOR,
+    middleware::Next,
+    rt::time::Instant,
+    web, Error, HttpRequest,
+};
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::Duration};
+
+/// Lockouts stop doubling here, and failures this old are forgotten.
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+/// Idle entries are only cleaned up past this many, so a flood of addresses can't use up memory.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Ip(IpAddr),
+    Username(String),
+    /// One IP address logging in as one username
+    Login(IpAddr, String),
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(per_minute: u32, now: Instant) -> Self {
+        Self {
+            tokens: per_minute as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, per_minute: u32, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_minute as f64 / 60.0).min(per_minute as f64);
+        self.updated = now;
+    }
+
+    /// Takes a token if there is one, otherwise returns how long until there will be.
+    fn take(&mut self, per_minute: u32, now: Instant) -> Result<(), Duration> {
+        self.refill(per_minute, now);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) * 60.0 / per_minute as f64,
+            ))
+        }
+    }
+}
+
+struct Failures {
+    /// Failed logins in a row
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    requests: HashMap<IpAddr, Bucket>,
+    logins: HashMap<Key, Bucket>,
+    failures: HashMap<Key, Failures>,
+}
+
+pub struct Limiter {
+    limits: RateLimits,
+    trusted_proxies: Vec<IpAddr>,
+    state: Mutex<State>,
+}
+
+/// Rounds up, so clients that wait exactly that long don't get turned away again.
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
+
+/// Who pays for a login attempt, which is only a matter of waiting a few seconds.
+fn bucket_keys(ip: IpAddr, username: &str) -> [Key; 2] {
+    [Key::Ip(ip), Key::Username(username.to_lowercase())]
+}
+
+/// Who gets locked out after too many failed logins.
+fn lockout_keys(ip: IpAddr, username: &str) -> [Key; 2] {
+    [Key::Ip(ip), Key::Login(ip, username.to_lowercase())]
+}
+
+impl Limiter {
+    /// `X-Forwarded-For` is only believed when it comes from one of the `trusted_proxies`.
+    pub fn new(limits: RateLimits, trusted_proxies: Vec<IpAddr>) -> Self {
+        Self {
+            limits,
+            trusted_proxies,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// The address the request came from. Behind trusted proxies, that's the last address in
+    /// `X-Forwarded-For` that isn't one of them, since each proxy adds the one it got the request
+    /// from. Requests without an address (like in tests) all share a bucket.
+    pub fn client_ip(&self, req: &HttpRequest) -> IpAddr {
+        let mut ip = req
+            .peer_addr()
+            .map(|address| address.ip())
+            .unwrap_or(IpAddr::from([0, 0, 0, 0]));
+
+        let forwarded: Vec<_> = req
+            .headers()
+            .get_all(X_FORWARDED_FOR)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for address in forwarded.into_iter().rev() {
+            if !self.trusted_proxies.contains(&ip) {
+                break;
+            }
+            // Anything before an address that doesn't parse can't be told apart from made up ones
+            match address.trim().parse() {
+                Ok(address) => ip = address,
+                Err(_) => break,
+            }
+        }
+
+        ip
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // The state is always consistent between statements, so a panic elsewhere doesn't matter
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Counts a request from `ip`.
+    pub fn request(&self, ip: IpAddr, now: Instant) -> Result<(), ApiError> {
+        let per_minute = self.limits.requests_per_minute;
+        let mut state = self.lock();
+
+        if state.requests.len() > PRUNE_THRESHOLD {
+            state.requests.retain(|_, bucket| {
+                bucket.refill(per_minute, now);
+                bucket.tokens < per_minute as f64
+            });
+        }
+
+        state
+            .requests
+            .entry(ip)
+            .or_insert_with(|| Bucket::full(per_minute, now))
+            .take(per_minute, now)
+            .map_err(|wait| {
+                ApiError::TooManyRequests(
+                    format!("Too many requests, try again in {} seconds.", seconds(wait)),
+                    seconds(wait),
+                )
+            })
+    }
+
+    /// Counts an attempt to log in as `username` from `ip`, unless `ip` is locked out (from that
+    /// username, or altogether).
+    pub fn login(&self, ip: IpAddr, username: &str, now: Instant) -> Result<(), ApiError> {
+        let per_minute = self.limits.logins_per_minute;
+        let mut state = self.lock();
+
+        let locked = lockout_keys(ip, username)
+            .iter()
+            .filter_map(|key| state.failures.get(key)?.locked_until)
+            .max()
+            .filter(|until| *until > now);
+        if let Some(until) = locked {
+            let wait = seconds(until - now);
+            return Err(ApiError::TooManyRequests(
+                format!("Too many failed logins, try again in {wait} seconds."),
+                wait,
+            ));
+        }
+
+        if state.logins.len() > PRUNE_THRESHOLD {
+            state.logins.retain(|_, bucket| {
+                bucket.refill(per_minute, now);
+                bucket.tokens < per_minute as f64
+            });
+            state
+                .failures
+                .retain(|_, failures| now.saturating_duration_since(failures.last) < MAX_LOCKOUT);
+        }
+
+        // Both buckets pay for the attempt, even if only one of them turns it away
+        let wait = bucket_keys(ip, username)
+            .into_iter()
+            .filter_map(|key| {
+                state
+                    .logins
+                    .entry(key)
+                    .or_insert_with(|| Bucket::full(per_minute, now))
+                    .take(per_minute, now)
+                    .err()
+            })
+            .max();
+
+        match wait {
+            Some(wait) => Err(ApiError::TooManyRequests(
+                format!(
+                    "Too many login attempts, try again in {} seconds.",
+                    seconds(wait)
+                ),
+                seconds(wait),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Locks out `ip` from `username`, or from every username, once it's failed too many times in a row.
+    pub fn login_failed(&self, ip: IpAddr, username: &str, now: Instant) {
+        let threshold = self.limits.lockout_threshold;
+        let mut state = self.lock();
+
+        for key in lockout_keys(ip, username) {
+            let failures = state.failures.entry(key).or_insert(Failures {
+                count: 0,
+                last: now,
+                locked_until: None,
+            });
+
+            if now.saturating_duration_since(failures.last) >= MAX_LOCKOUT {
+                failures.count = 0;
+            }
+            failures.count += 1;
+            failures.last = now;
+
+            if failures.count >= threshold {
+                let doublings = (failures.count - threshold).min(16);
+                let lockout = Duration::from_secs(self.limits.lockout_seconds)
+                    .saturating_mul(1 << doublings)
+                    .min(MAX_LOCKOUT);
+                failures.locked_until = Some(now + lockout);
+            }
+        }
+    }
+
+    pub fn login_succeeded(&self, ip: IpAddr, username: &str) {
+        let mut state = self.lock();
+
+        for key in lockout_keys(ip, username) {
+            state.failures.remove(&key);
+        }
+    }
+}
+
+/// Turns away clients that send too many requests. Use with `middleware::from_fn`.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let limiter = req
+        .app_data::<web::Data<Limiter>>()
+        .ok_or_else(|| ApiError::Internal("Rate limiter is not configured.".to_string()))?;
+    limiter.request(limiter.client_ip(req.request()), Instant::now())?;
+
+    next.call(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> Limiter {
+        Limiter::new(
+            RateLimits {
+                requests_per_minute: 2,
+                logins_per_minute: 3,
+                lockout_threshold: 2,
+                lockout_seconds: 10,
+            },
+            vec![IpAddr::from([10, 0, 0, 100]), IpAddr::from([10, 0, 0, 101])],
+        )
+    }
+
+    fn retry_after(result: Result<(), ApiError>) -> Option<u64> {
+        match result {
+            Err(ApiError::TooManyRequests(_, seconds)) => Some(seconds),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn buckets_refill() {
+        let limiter = limiter();
+        let start = Instant::now();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+
+        assert!(limiter.request(ip, start).is_ok());
+        assert!(limiter.request(ip, start).is_ok());
+        // Two a minute means a new token every 30 seconds
+        assert_eq!(retry_after(limiter.request(ip, start)), Some(30));
+        assert_eq!(
+            retry_after(limiter.request(ip, start + Duration::from_secs(20))),
+            Some(10)
+        );
+        assert!(limiter.request(ip, start + Duration::from_secs(30)).is_ok());
+
+        // Other addresses have their own bucket
+        assert!(limiter.request(IpAddr::from([10, 0, 0, 2]), start).is_ok());
+    }
+
+    #[test]
+    fn lockouts_double() {
+        let limiter = limiter();
+        let mut now = Instant::now();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+
+        assert!(limiter.login(ip, "steven", now).is_ok());
+        limiter.login_failed(ip, "steven", now);
+        assert!(limiter.login(ip, "steven", now).is_ok());
+        limiter.login_failed(ip, "steven", now);
+
+        // Two failures in a row lock out the address, from the username whatever its case and from
+        // every other one
+        assert_eq!(retry_after(limiter.login(ip, "Steven", now)), Some(10));
+        assert_eq!(retry_after(limiter.login(ip, "someone", now)), Some(10));
+
+        now += Duration::from_secs(10);
+        assert!(limiter.login(ip, "steven", now).is_ok());
+        limiter.login_failed(ip, "steven", now);
+        assert_eq!(retry_after(limiter.login(ip, "steven", now)), Some(20));
+
+        now += Duration::from_secs(20);
+        assert!(limiter.login(ip, "steven", now).is_ok());
+        limiter.login_succeeded(ip, "steven");
+
+        // Attempts still cost tokens though, there's one every 20 seconds
+        assert_eq!(retry_after(limiter.login(ip, "steven", now)), Some(10));
+        now += Duration::from_secs(10);
+        assert!(limiter.login(ip, "steven", now).is_ok());
+
+        // Succeeding started the count over
+        limiter.login_failed(ip, "steven", now);
+        now += Duration::from_secs(20);
+        assert!(limiter.login(ip, "steven", now).is_ok());
+    }
+
+    #[test]
+    fn others_cant_lock_out_a_username() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let attacker = IpAddr::from([10, 0, 0, 1]);
+        let owner = IpAddr::from([10, 0, 0, 2]);
+
+        for _ in 0..2 {
+            assert!(limiter.login(attacker, "steven", now).is_ok());
+            limiter.login_failed(attacker, "steven", now);
+        }
+        assert_eq!(
+            retry_after(limiter.login(attacker, "steven", now)),
+            Some(10)
+        );
+
+        // The username only has a bucket, which the attacker's lockout stopped draining
+        assert!(limiter.login(owner, "steven", now).is_ok());
+        assert_eq!(retry_after(limiter.login(owner, "steven", now)), Some(20));
+    }
+
+    #[test]
+    fn forwarded_addresses_from_trusted_proxies() {
+        use actix_web::test::TestRequest;
+        use std::net::SocketAddr;
+
+        let limiter = limiter();
+        let request = |peer: [u8; 4], forwarded: &str| {
+            TestRequest::default()
+                .peer_addr(SocketAddr::from((peer, 1234)))
+                .insert_header((X_FORWARDED_FOR, forwarded))
+                .to_http_request()
+        };
+
+        // The client, then the outer proxy, then the inner one this came from
+        let req = request([10, 0, 0, 100], "203.0.113.9, 198.51.100.1, 10.0.0.101");
+        assert_eq!(limiter.client_ip(&req), IpAddr::from([198, 51, 100, 1]));
+        let req = request([10, 0, 0, 100], "203.0.113.9, 10.0.0.101");
+        assert_eq!(limiter.client_ip(&req), IpAddr::from([203, 0, 113, 9]));
+
+        // Anyone else could be making it up
+        let req = request([203, 0, 113, 9], "198.51.100.1");
+        assert_eq!(limiter.client_ip(&req), IpAddr::from([203, 0, 113, 9]));
+        let req = request([10, 0, 0, 100], "garbage");
+        assert_eq!(limiter.client_ip(&req), IpAddr::from([10, 0, 0, 100]));
+    }
+}
@@ -1,97 +1,195 @@
+mod api;
+mod auth;
+mod backup;
+mod calendar;
+mod config;
+mod database;
+mod dav;
+mod error;
+mod filter;
+mod frontend;
+mod gateway;
+mod health;
+mod limiter;
+mod metrics;
+mod notepad;
+mod plan;
+mod quick;
+mod reminders;
+mod sync;
+#[cfg(test)]
+mod testing;
 mod util;
 
-use crate::util::port;
-//use actix_web::{get, post, App, HttpResponse, HttpServer, Responder};
-use dotenv::dotenv;
-
-/*#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    dotenv().ok();
-
-    #[cfg(debug_assertions)]
-    let addr = "127.0.0.1";
-    #[cfg(not(debug_assertions))]
-    let addr = "0.0.0.0";
-
-    HttpServer::new(|| {
-        App::new()
-            .service(hello)
-            .service(echo)
-            .service(manual_hello)
-        //.route("/hey", web::get().to(manual_hello))
-    })
-    .bind((addr, port()))?
-    .run()
-    .await
+use crate::{
+    auth::tokens::TokenKey, config::Config, database::Database, dav::Logins, limiter::Limiter,
+    metrics::Metrics,
+};
+use actix::{Actor, Addr};
+use actix_cors::Cors;
+use actix_web::{
+    dev::ServerHandle,
+    middleware::{self, Logger},
+    web, App, HttpServer,
+};
+use gateway::{
+    broker::{Broker, Drain},
+    editor::{Editor, Flush},
+};
+use reminders::{Scheduler, SystemClock};
+
+fn cors(origins: &[String]) -> Cors {
+    origins
+        .iter()
+        .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
+        .allow_any_method()
+        .allow_any_header()
+        .max_age(3600)
 }
 
-#[get("/")]
-async fn hello() -> impl Responder {
-    HttpResponse::Ok().body("Hello world!")
-}
+/// Resolves on SIGTERM, or Ctrl+C.
+async fn terminated() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => return,
+                    _ = tokio::signal::ctrl_c() => return,
+                }
+            }
+            Err(error) => log::error!("Couldn't listen for SIGTERM: {error}"),
+        }
+    }
 
-#[post("/echo")]
-async fn echo(req_body: String) -> impl Responder {
-    HttpResponse::Ok().body(req_body)
+    if let Err(error) = tokio::signal::ctrl_c().await {
+        log::error!("Couldn't listen for Ctrl+C: {error}");
+        std::future::pending::<()>().await;
+    }
 }
 
-#[get("/")]
-async fn manual_hello() -> impl Responder {
-    HttpResponse::Ok().body("Hey there!")
-}
+/// Stops taking new connections, closes every gateway socket so clients go reconnect once the
+/// server is back, then waits for requests that are still running.
+async fn shutdown(server: ServerHandle, broker: Addr<Broker>) {
+    terminated().await;
+    log::info!("Shutting down");
 
-#[cfg(test)]
-mod tests {
-    #[test]
-    fn asdf() {
-        //
+    server.pause().await;
+    match broker.send(Drain).await {
+        Ok(sockets) => log::info!("Closed {sockets} gateway sockets"),
+        Err(error) => log::error!("Couldn't close gateway sockets: {error}"),
     }
-}*/
-
-use actix::{Actor, StreamHandler};
-use actix_web::{get, web, App, Error, HttpRequest, HttpResponse, HttpServer, Responder};
-use actix_web_actors::ws;
-
-/// Define HTTP actor
-struct MyWs;
-
-impl Actor for MyWs {
-    type Context = ws::WebsocketContext<Self>;
+    server.stop(true).await;
 }
 
-/// Handler for ws::Message message
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for MyWs {
-    fn handle(&mut self, msg: Result<ws::Message, ws::ProtocolError>, ctx: &mut Self::Context) {
-        match msg {
-            Ok(ws::Message::Ping(msg)) => ctx.pong(&msg),
-            Ok(ws::Message::Text(text)) => ctx.text(text),
-            Ok(ws::Message::Binary(bin)) => ctx.binary(bin),
-            _ => (),
+#[actix_web::main]
+async fn main() {
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(errors) => {
+            eprintln!("Invalid configuration:");
+            for error in errors {
+                eprintln!("  {error}");
+            }
+            std::process::exit(1);
         }
-    }
-}
-
-async fn index(req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
-    let resp = ws::start(MyWs {}, &req, stream);
-    println!("{:?}", resp);
-    resp
-}
+    };
 
-#[get("/")]
-async fn hello() -> impl Responder {
-    HttpResponse::Ok().body("Hello world!")
-}
+    if std::env::args().any(|arg| arg == "--print-config") {
+        print!("{config}");
+        return;
+    }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    dotenv().ok();
+    env_logger::Builder::new()
+        .filter_level(config.log_level)
+        .init();
+
+    let db = match Database::open(&config.database_path) {
+        Ok(db) => web::Data::new(db),
+        Err(error) => {
+            log::error!(
+                "Couldn't open the database at {}: {error}",
+                config.database_path.display()
+            );
+            std::process::exit(1);
+        }
+    };
 
-    HttpServer::new(|| {
+    let key = match &config.cookie_secret {
+        Some(secret) => TokenKey::new(secret.as_str()),
+        None => {
+            log::warn!("COOKIE_SECRET is not set, so sessions won't survive a restart.");
+            TokenKey::random()
+        }
+    };
+    let key = web::Data::new(key);
+    let broker = web::Data::new(Broker::default().start());
+    let editor = web::Data::new(Editor::new(db.clone(), broker.get_ref().clone()).start());
+    Scheduler::new(
+        db.clone(),
+        broker.get_ref().clone(),
+        SystemClock,
+        config.reminder_minutes,
+    )
+    .start();
+    let limiter = web::Data::new(Limiter::new(
+        config.rate_limits,
+        config.trusted_proxies.clone(),
+    ));
+    let logins = web::Data::new(Logins::default());
+    let metrics = web::Data::new(Metrics::default());
+    let origins = config.cors_origins.clone();
+    let gateway = broker.get_ref().clone();
+    let notepads = editor.get_ref().clone();
+
+    log::info!("Listening on {}:{}", config.bind_address, config.port);
+
+    let server = HttpServer::new(move || {
         App::new()
-            .service(hello)
-            .route("/gateway", web::get().to(index))
+            .wrap(cors(&origins))
+            .wrap(middleware::from_fn(metrics::middleware))
+            .wrap(Logger::default())
+            .app_data(db.clone())
+            .app_data(key.clone())
+            .app_data(broker.clone())
+            .app_data(editor.clone())
+            .app_data(limiter.clone())
+            .app_data(logins.clone())
+            .app_data(metrics.clone())
+            .configure(api::config)
+            .configure(dav::config)
+            .configure(gateway::config)
+            .configure(health::config)
+            .configure(metrics::config)
+            .default_service(web::to(frontend::serve))
     })
-    .bind(("127.0.0.1", port()))?
-    .run()
-    .await
+    // Signals are handled by `shutdown()` instead, which closes sockets before waiting on them
+    .disable_signals()
+    .bind((config.bind_address, config.port));
+
+    let server = match server {
+        Ok(server) => server.run(),
+        Err(error) => {
+            log::error!(
+                "Couldn't listen on {}:{}: {error}",
+                config.bind_address,
+                config.port
+            );
+            std::process::exit(1);
+        }
+    };
+    actix_web::rt::spawn(shutdown(server.handle(), gateway));
+
+    if let Err(error) = server.await {
+        log::error!("Server stopped with an error: {error}");
+        std::process::exit(1);
+    }
+
+    // Every socket is closed by now, so nobody can edit them anymore
+    match notepads.send(Flush).await {
+        Ok(saved) => log::info!("Saved {saved} notepads"),
+        Err(error) => log::error!("Couldn't save notepads: {error}"),
+    }
 }
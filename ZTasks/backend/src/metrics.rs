@@ -0,0 +1,191 @@
+//! Prometheus metrics at `/metrics`: requests and their latency per route, and open gateway sockets.
+
+use crate::{
+    error::ApiError,
+    gateway::broker::{Broker, Sockets},
+};
+use actix::Addr;
+use actix_web::{
+    body::MessageBody,
+    dev::{ServiceRequest, ServiceResponse},
+    get,
+    http::{Method, StatusCode},
+    middleware::Next,
+    web, Error, HttpResponse,
+};
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder, TEXT_FORMAT,
+};
+use std::time::{Duration, Instant};
+
+/// Requests that didn't match any route (like the frontend's) are all counted under this.
+const UNMATCHED: &str = "unmatched";
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    latency: HistogramVec,
+    sockets: IntGauge,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        let requests = IntCounterVec::new(
+            Opts::new("ztasks_http_requests_total", "HTTP requests handled"),
+            &["method", "route", "status"],
+        )
+        .expect("Metric options are valid");
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "ztasks_http_request_duration_seconds",
+                "Time until the response started",
+            ),
+            &["method", "route"],
+        )
+        .expect("Metric options are valid");
+        let sockets = IntGauge::new("ztasks_gateway_sockets", "Open gateway websockets")
+            .expect("Metric options are valid");
+
+        let registry = Registry::new();
+        for metric in [
+            Box::new(requests.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(latency.clone()),
+            Box::new(sockets.clone()),
+        ] {
+            registry
+                .register(metric)
+                .expect("Metrics are only registered once");
+        }
+
+        Self {
+            registry,
+            requests,
+            latency,
+            sockets,
+        }
+    }
+}
+
+impl Metrics {
+    /// `route` is the pattern the request matched, like `/api/tasks/{id}`, so IDs don't each get their own series.
+    pub fn observe(
+        &self,
+        method: &Method,
+        route: Option<&str>,
+        status: StatusCode,
+        elapsed: Duration,
+    ) {
+        let route = route.unwrap_or(UNMATCHED);
+        self.requests
+            .with_label_values(&[method.as_str(), route, status.as_str()])
+            .inc();
+        self.latency
+            .with_label_values(&[method.as_str(), route])
+            .observe(elapsed.as_secs_f64());
+    }
+}
+
+pub fn config(cfg: &mut web::ServiceConfig) {
+    cfg.service(get_metrics);
+}
+
+/// Counts every request. Apps without `Metrics` just don't get any.
+pub async fn middleware(
+    req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse, Error> {
+    let metrics = req.app_data::<web::Data<Metrics>>().cloned();
+    let method = req.method().clone();
+    let started = Instant::now();
+    let result = next
+        .call(req)
+        .await
+        .map(ServiceResponse::map_into_boxed_body);
+
+    if let Some(metrics) = metrics {
+        // The route is only known once the request has been through the router, and errors from
+        // middleware (like the rate limiter's) come back before it ever got there
+        let (route, status) = match &result {
+            Ok(res) => (res.request().match_pattern(), res.status()),
+            Err(error) => (None, error.as_response_error().status_code()),
+        };
+        metrics.observe(&method, route.as_deref(), status, started.elapsed());
+    }
+    result
+}
+
+#[get("/metrics")]
+async fn get_metrics(
+    metrics: web::Data<Metrics>,
+    broker: web::Data<Addr<Broker>>,
+) -> Result<HttpResponse, ApiError> {
+    let sockets = broker
+        .send(Sockets)
+        .await
+        .map_err(|_| ApiError::Internal("Gateway is not running.".to_string()))?;
+    metrics.sockets.set(sockets as i64);
+
+    let mut text = String::new();
+    TextEncoder::new()
+        .encode_utf8(&metrics.registry.gather(), &mut text)
+        .map_err(|error| ApiError::Internal(format!("Couldn't encode metrics: {error}")))?;
+
+    Ok(HttpResponse::Ok().content_type(TEXT_FORMAT).body(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        gateway::broker::{Broker, Open},
+        testing::{app_with, call, request, sign_up, Collector},
+    };
+    use actix::Actor;
+    use actix_web::{
+        http::{Method, StatusCode},
+        test,
+    };
+
+    #[actix_web::test]
+    async fn counts_requests_per_route() {
+        let broker = Broker::default().start();
+        let app = test::init_service(app_with(broker.clone())).await;
+        let token = sign_up(&app, "steven").await;
+
+        for id in [1, 2, 3] {
+            let uri = format!("/api/tasks/{id}");
+            let (status, _) = call(&app, request(Method::GET, &uri, &token)).await;
+            assert_eq!(status, StatusCode::NOT_FOUND);
+        }
+        call(&app, test::TestRequest::get().uri("/api/tasks")).await;
+
+        // Anything that can take a `Close` stands in for a socket
+        broker.do_send(Open {
+            session_id: "phone".to_string(),
+            recipient: Collector::default().start().recipient(),
+        });
+
+        let res =
+            test::call_service(&app, test::TestRequest::get().uri("/metrics").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res
+            .headers()
+            .get("content-type")
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .starts_with("text/plain"));
+        let body = test::read_body(res).await;
+        let text = std::str::from_utf8(&body).unwrap();
+
+        let lines: Vec<_> = text.lines().collect();
+        for line in [
+            r#"ztasks_http_requests_total{method="GET",route="/api/tasks/{id}",status="404"} 3"#,
+            r#"ztasks_http_requests_total{method="GET",route="/api/tasks",status="401"} 1"#,
+            r#"ztasks_http_requests_total{method="POST",route="/api/login",status="200"} 1"#,
+            r#"ztasks_http_request_duration_seconds_count{method="GET",route="/api/tasks/{id}"} 3"#,
+            "ztasks_gateway_sockets 1",
+        ] {
+            assert!(lines.contains(&line), "{line} in\n{text}");
+        }
+    }
+}
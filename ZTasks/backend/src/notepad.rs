@@ -0,0 +1,399 @@
+//! Operational transformation for the live notepads, see `gateway::editor`.
+//!
+//! A change walks over the whole text, keeping (`retain`), adding (`insert`) or removing (`delete`) it
+//! bit by bit. Lengths and positions count characters (Unicode code points), not bytes or UTF-16 units.
+//! Two changes made to the same text at the same time are merged by transforming one past the other.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// On the wire, `{ "retain": 5 }`, `{ "insert": "text" }` or `{ "delete": 2 }`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Op {
+    Retain(usize),
+    Insert(String),
+    Delete(usize),
+}
+
+/// Where someone's cursor is. `anchor` is the other end of their selection, if they have one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Cursor {
+    pub position: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anchor: Option<usize>,
+}
+
+impl Cursor {
+    /// Where the cursor ends up once `change` is applied.
+    pub fn transform(self, change: &[Op]) -> Self {
+        Self {
+            position: transform_position(self.position, change),
+            anchor: self.anchor.map(|anchor| transform_position(anchor, change)),
+        }
+    }
+}
+
+/// How long the text has to be for `change` to apply to it.
+pub fn base_len(change: &[Op]) -> usize {
+    change
+        .iter()
+        .map(|op| match op {
+            Op::Retain(n) | Op::Delete(n) => *n,
+            Op::Insert(_) => 0,
+        })
+        .sum()
+}
+
+/// Appends `op` to `change`, merging it into the last op if they're the same kind.
+fn push(change: &mut Vec<Op>, op: Op) {
+    match (change.last_mut(), op) {
+        (_, Op::Retain(0) | Op::Delete(0)) => (),
+        (_, Op::Insert(text)) if text.is_empty() => (),
+        (Some(Op::Retain(last)), Op::Retain(n)) | (Some(Op::Delete(last)), Op::Delete(n)) => {
+            *last += n
+        }
+        (Some(Op::Insert(last)), Op::Insert(text)) => last.push_str(&text),
+        (_, op) => change.push(op),
+    }
+}
+
+/// Clients can leave out the retain at the end, which this puts back for text `len` characters long.
+pub fn complete(mut change: Vec<Op>, len: usize) -> Result<Vec<Op>, String> {
+    let base = base_len(&change);
+    if base > len {
+        return Err(format!(
+            "The change covers {base} characters, but the text only has {len}."
+        ));
+    }
+    push(&mut change, Op::Retain(len - base));
+    Ok(change)
+}
+
+/// A change that turns `old` into `new`, for text changed some other way. Whatever they don't have in common
+/// at the start and the end is deleted and inserted again.
+pub fn diff(old: &str, new: &str) -> Vec<Op> {
+    let old: Vec<_> = old.chars().collect();
+    let new: Vec<_> = new.chars().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut change = Vec::new();
+    push(&mut change, Op::Retain(prefix));
+    push(&mut change, Op::Delete(old.len() - prefix - suffix));
+    push(
+        &mut change,
+        Op::Insert(new[prefix..new.len() - suffix].iter().collect()),
+    );
+    push(&mut change, Op::Retain(suffix));
+    change
+}
+
+/// Applies `change` to `text`, which it has to cover exactly.
+pub fn apply(text: &str, change: &[Op]) -> Result<String, String> {
+    let mut chars = text.chars();
+    let mut result = String::with_capacity(text.len());
+
+    for op in change {
+        match op {
+            Op::Retain(n) => {
+                let kept: String = chars.by_ref().take(*n).collect();
+                if kept.chars().count() < *n {
+                    return Err("The change is longer than the text.".to_string());
+                }
+                result.push_str(&kept);
+            }
+            Op::Insert(text) => result.push_str(text),
+            Op::Delete(n) => {
+                if chars.by_ref().take(*n).count() < *n {
+                    return Err("The change is longer than the text.".to_string());
+                }
+            }
+        }
+    }
+
+    match chars.next() {
+        Some(_) => Err("The change is shorter than the text.".to_string()),
+        None => Ok(result),
+    }
+}
+
+/// Given `a` and `b` made to the same text, returns `(a', b')` such that applying `a` then `b'` gives
+/// the same text as `b` then `a'`. When both insert at the same spot, `a`'s text goes first.
+pub fn transform(a: &[Op], b: &[Op]) -> Result<(Vec<Op>, Vec<Op>), String> {
+    if base_len(a) != base_len(b) {
+        return Err("Both changes have to be made to the same text.".to_string());
+    }
+
+    let mut a_prime = Vec::new();
+    let mut b_prime = Vec::new();
+    let mut a_ops = a.iter().cloned();
+    let mut b_ops = b.iter().cloned();
+    let mut next_a = a_ops.next();
+    let mut next_b = b_ops.next();
+
+    loop {
+        match (next_a.take(), next_b.take()) {
+            (None, None) => break,
+            (Some(Op::Insert(text)), op) => {
+                push(&mut b_prime, Op::Retain(text.chars().count()));
+                push(&mut a_prime, Op::Insert(text));
+                next_a = a_ops.next();
+                next_b = op;
+            }
+            (op, Some(Op::Insert(text))) => {
+                push(&mut a_prime, Op::Retain(text.chars().count()));
+                push(&mut b_prime, Op::Insert(text));
+                next_a = op;
+                next_b = b_ops.next();
+            }
+            (Some(op_a), Some(op_b)) => {
+                let (len_a, len_b) = (len(&op_a), len(&op_b));
+                let n = len_a.min(len_b);
+
+                match (&op_a, &op_b) {
+                    (Op::Retain(_), Op::Retain(_)) => {
+                        push(&mut a_prime, Op::Retain(n));
+                        push(&mut b_prime, Op::Retain(n));
+                    }
+                    // Deleted by both, so neither has to do it anymore
+                    (Op::Delete(_), Op::Delete(_)) => (),
+                    (Op::Delete(_), _) => push(&mut a_prime, Op::Delete(n)),
+                    (_, Op::Delete(_)) => push(&mut b_prime, Op::Delete(n)),
+                    _ => unreachable!("Inserts are handled above"),
+                }
+
+                (next_a, next_b) = match len_a.cmp(&len_b) {
+                    Ordering::Less => (a_ops.next(), Some(shorten(op_b, n))),
+                    Ordering::Equal => (a_ops.next(), b_ops.next()),
+                    Ordering::Greater => (Some(shorten(op_a, n)), b_ops.next()),
+                };
+            }
+            // Only possible if the lengths didn't match, which was checked above
+            _ => return Err("Both changes have to be made to the same text.".to_string()),
+        }
+    }
+
+    Ok((a_prime, b_prime))
+}
+
+fn len(op: &Op) -> usize {
+    match op {
+        Op::Retain(n) | Op::Delete(n) => *n,
+        Op::Insert(text) => text.chars().count(),
+    }
+}
+
+fn shorten(op: Op, by: usize) -> Op {
+    match op {
+        Op::Retain(n) => Op::Retain(n - by),
+        Op::Delete(n) => Op::Delete(n - by),
+        op => op,
+    }
+}
+
+/// Moves a position past `change`. Text inserted right at it pushes it forward.
+fn transform_position(position: usize, change: &[Op]) -> usize {
+    let mut new_position = position;
+    // How much of the original text is left before the position
+    let mut left = position;
+
+    for op in change {
+        match op {
+            Op::Retain(n) => {
+                if *n > left {
+                    break;
+                }
+                left -= n;
+            }
+            Op::Insert(text) => new_position += text.chars().count(),
+            Op::Delete(n) => {
+                let n = (*n).min(left);
+                new_position -= n;
+                left -= n;
+            }
+        }
+    }
+
+    new_position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retain(n: usize) -> Op {
+        Op::Retain(n)
+    }
+
+    fn insert(text: &str) -> Op {
+        Op::Insert(text.to_string())
+    }
+
+    fn delete(n: usize) -> Op {
+        Op::Delete(n)
+    }
+
+    /// Both orders end up with the same text, which is returned.
+    fn converge(text: &str, a: &[Op], b: &[Op]) -> String {
+        let (a_prime, b_prime) = transform(a, b).unwrap();
+        let ab = apply(&apply(text, a).unwrap(), &b_prime).unwrap();
+        let ba = apply(&apply(text, b).unwrap(), &a_prime).unwrap();
+        assert_eq!(ab, ba);
+        ab
+    }
+
+    #[test]
+    fn applies_changes() {
+        let change = [retain(4), delete(5), insert("rent"), retain(1)];
+        assert_eq!(apply("Pay bills!", &change).unwrap(), "Pay rent!");
+        assert_eq!(
+            apply("héllo", &[retain(1), delete(1), insert("e"), retain(3)]).unwrap(),
+            "hello"
+        );
+
+        assert!(apply("short", &[retain(6)]).is_err());
+        assert!(apply("long text", &[retain(4)]).is_err());
+        assert_eq!(
+            complete(vec![retain(4), insert("X")], 9).unwrap(),
+            [retain(4), insert("X"), retain(5)]
+        );
+        assert!(complete(vec![delete(10)], 9).is_err());
+    }
+
+    #[test]
+    fn diffs() {
+        assert_eq!(
+            diff("Pay bills!", "Pay rent!"),
+            [retain(4), delete(5), insert("rent"), retain(1)]
+        );
+        assert_eq!(diff("aaa", "aaaa"), [retain(3), insert("a")]);
+        assert_eq!(
+            diff("héllo", "hello"),
+            [retain(1), delete(1), insert("e"), retain(3)]
+        );
+        assert_eq!(diff("same", "same"), [retain(4)]);
+        assert_eq!(diff("", ""), []);
+    }
+
+    #[test]
+    fn merges_concurrent_changes() {
+        let text = "Buy milk";
+
+        // Typing in different places
+        let a = [retain(4), insert("oat "), retain(4)];
+        let b = [retain(8), insert(" and eggs")];
+        assert_eq!(converge(text, &a, &b), "Buy oat milk and eggs");
+
+        // Typing at the same spot, the first change's text goes first
+        let a = [retain(8), insert("!")];
+        let b = [retain(8), insert("?")];
+        assert_eq!(converge(text, &a, &b), "Buy milk!?");
+        assert_eq!(converge(text, &b, &a), "Buy milk?!");
+
+        // Overlapping deletes only delete once
+        let a = [retain(2), delete(4), retain(2)];
+        let b = [retain(4), delete(4)];
+        assert_eq!(converge(text, &a, &b), "Bu");
+
+        // Typing inside text someone else deleted keeps the typing
+        let a = [retain(6), insert("i"), retain(2)];
+        let b = [retain(3), delete(5)];
+        assert_eq!(converge(text, &a, &b), "Buyi");
+
+        assert!(transform(&[retain(3)], &[retain(4)]).is_err());
+    }
+
+    #[test]
+    fn moves_cursors() {
+        let cursor = Cursor {
+            position: 4,
+            anchor: Some(8),
+        };
+
+        let typed_before = [retain(2), insert("ab"), retain(6)];
+        assert_eq!(
+            cursor.transform(&typed_before),
+            Cursor {
+                position: 6,
+                anchor: Some(10)
+            }
+        );
+
+        let typed_after = [retain(9), insert("ab"), retain(1)];
+        assert_eq!(cursor.transform(&typed_after), cursor);
+
+        // Text typed right at the cursor pushes it along
+        assert_eq!(
+            transform_position(4, &[retain(4), insert("ab"), retain(4)]),
+            6
+        );
+
+        // Deleting around the cursor pulls it back to where the deletion was
+        let deleted = [retain(2), delete(4), retain(2)];
+        assert_eq!(
+            cursor.transform(&deleted),
+            Cursor {
+                position: 2,
+                anchor: Some(4)
+            }
+        );
+    }
+
+    /// Some random change to `text`.
+    fn random_change(rng: &mut impl rand::Rng, text: &str) -> Vec<Op> {
+        let mut change = Vec::new();
+        let mut left = text.chars().count();
+        while left > 0 {
+            let n = rng.gen_range(1..=left);
+            match rng.gen_range(0..3) {
+                0 => push(&mut change, Op::Retain(n)),
+                1 => push(&mut change, Op::Delete(n)),
+                _ => push(
+                    &mut change,
+                    insert(["a", "bc", "é", "🦀"][rng.gen_range(0..4)]),
+                ),
+            }
+            if !matches!(change.last(), Some(Op::Insert(_))) {
+                left -= n;
+            }
+        }
+        if rng.gen_bool(0.5) {
+            push(&mut change, insert("z"));
+        }
+        change
+    }
+
+    #[test]
+    fn random_changes_converge() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(25);
+        let text = "héllo wörld 🦀";
+        for _ in 0..1000 {
+            let a = random_change(&mut rng, text);
+            let b = random_change(&mut rng, text);
+            converge(text, &a, &b);
+
+            // Cursors stay inside the text
+            let (_, b_prime) = transform(&a, &b).unwrap();
+            let end = text.chars().count();
+            let cursor = Cursor {
+                position: end,
+                anchor: Some(0),
+            }
+            .transform(&a)
+            .transform(&b_prime);
+            let len = apply(&apply(text, &a).unwrap(), &b_prime)
+                .unwrap()
+                .chars()
+                .count();
+            assert!(cursor.position <= len && cursor.anchor.unwrap() <= len);
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Ordering tasks by what they wait on. A task waits on the tasks blocking it and on its own subtasks,
+//! given as `(waiting, on)` pairs of task IDs.
+
+use crate::database::tasks::Task;
+use serde::Serialize;
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+use utoipa::ToSchema;
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Step {
+    #[serde(flatten)]
+    pub task: Task,
+    /// Nothing it waits on is left to do
+    pub ready: bool,
+    /// The unfinished tasks it waits on
+    pub waiting_on: Vec<i64>,
+}
+
+/// Whether making `waiting` wait on `on` would end up with a task waiting on itself.
+pub fn creates_cycle(edges: &[(i64, i64)], waiting: i64, on: i64) -> bool {
+    let mut next: HashMap<i64, Vec<i64>> = HashMap::new();
+    for (from, to) in edges {
+        next.entry(*from).or_default().push(*to);
+    }
+
+    // Look for a way from `on` back to `waiting`
+    let mut seen = HashSet::new();
+    let mut stack = vec![on];
+    while let Some(id) = stack.pop() {
+        if id == waiting {
+            return true;
+        }
+        if seen.insert(id) {
+            stack.extend(next.get(&id).into_iter().flatten());
+        }
+    }
+
+    false
+}
+
+/// Puts unfinished `tasks` in an order they can be done in, soonest due first where there's a choice.
+/// Finished tasks and ones that aren't in `tasks` don't hold anything up.
+pub fn order(tasks: Vec<Task>, edges: &[(i64, i64)]) -> Vec<Step> {
+    let mut tasks: HashMap<i64, Task> = tasks
+        .into_iter()
+        .filter(|task| task.completed.is_none())
+        .map(|task| (task.id, task))
+        .collect();
+
+    let mut waiting_on: HashMap<i64, Vec<i64>> = HashMap::new();
+    let mut waited_on_by: HashMap<i64, Vec<i64>> = HashMap::new();
+    for (waiting, on) in edges {
+        if tasks.contains_key(waiting) && tasks.contains_key(on) {
+            waiting_on.entry(*waiting).or_default().push(*on);
+            waited_on_by.entry(*on).or_default().push(*waiting);
+        }
+    }
+
+    let key = |task: &Task| Reverse((task.due.is_none(), task.due, task.id));
+    let mut remaining: HashMap<i64, usize> = tasks
+        .keys()
+        .map(|id| (*id, waiting_on.get(id).map_or(0, Vec::len)))
+        .collect();
+    let mut available: BinaryHeap<_> = tasks
+        .values()
+        .filter(|task| remaining[&task.id] == 0)
+        .map(key)
+        .collect();
+
+    let mut steps = Vec::new();
+    while let Some(Reverse((_, _, id))) = available.pop() {
+        for waiting in waited_on_by.get(&id).into_iter().flatten() {
+            let count = remaining.entry(*waiting).or_default();
+            *count -= 1;
+            if *count == 0 {
+                available.push(key(&tasks[waiting]));
+            }
+        }
+
+        if let Some(task) = tasks.remove(&id) {
+            let mut waits = waiting_on.remove(&id).unwrap_or_default();
+            waits.sort();
+            waits.dedup();
+            steps.push(Step {
+                task,
+                ready: waits.is_empty(),
+                waiting_on: waits,
+            });
+        }
+    }
+
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tasks::from_timestamp;
+
+    fn task(id: i64, due: Option<i64>, done: bool) -> Task {
+        Task {
+            id,
+            title: format!("Task {id}"),
+            notes: String::new(),
+            category: None,
+            start: None,
+            due: due.map(from_timestamp),
+            completed: done.then(|| from_timestamp(0)),
+            created: from_timestamp(0),
+            updated: from_timestamp(0),
+            recurrence: None,
+            time_zone: "UTC".to_string(),
+            uid: format!("{id}@ztasks"),
+            parent_id: None,
+            list_id: None,
+        }
+    }
+
+    #[test]
+    fn cycles() {
+        // 1 waits on 2, which waits on 3
+        let edges = [(1, 2), (2, 3)];
+
+        assert!(creates_cycle(&edges, 3, 1));
+        assert!(creates_cycle(&edges, 2, 1));
+        assert!(creates_cycle(&edges, 4, 4));
+        assert!(!creates_cycle(&edges, 1, 3));
+        assert!(!creates_cycle(&edges, 3, 4));
+    }
+
+    #[test]
+    fn blockers_come_first() {
+        let tasks = vec![
+            task(1, Some(100), false),
+            task(2, Some(300), false),
+            task(3, None, false),
+            task(4, Some(200), false),
+            task(5, Some(50), true),
+        ];
+        // 1 waits on 2 and the finished 5, 4 waits on 1
+        let edges = [(1, 2), (1, 5), (4, 1)];
+
+        let steps = order(tasks, &edges);
+        let ids: Vec<_> = steps.iter().map(|step| step.task.id).collect();
+        assert_eq!(ids, [2, 1, 4, 3]);
+
+        let ready: Vec<_> = steps.iter().map(|step| step.ready).collect();
+        assert_eq!(ready, [true, false, false, true]);
+        assert_eq!(steps[1].waiting_on, [2]);
+    }
+}
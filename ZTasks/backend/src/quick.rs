@@ -0,0 +1,727 @@
+//! Turns a line like "pay rent tomorrow 9am #home every month !high" into a task, for adding tasks
+//! quickly. Dates and times are in the user's time zone. Whatever isn't understood ends up in the title,
+//! and so does anything in double quotes.
+//!
+//! - Dates: `today`, `tonight`, `tomorrow`, weekdays (`fri`, `next friday`), `next week`, `next month`,
+//!   `in 3 days`, `in 2 hours`, `march 15`, `15th mar 2024`, `2023-03-15`, optionally after `on`
+//! - Times: `9am`, `9:30 pm`, `21:00`, `noon`, `midnight`, optionally after `at`
+//! - Repeats: `daily`, `weekly`, `monthly`, `yearly`, `every day`, `every other week`, `every 3 months`,
+//!   `every weekday`, `every mon, wed and fri`
+//! - `#category` and `!high`, `!medium` or `!low` (or `!1` to `!3`)
+//!
+//! Tasks with only a date are due at the end of that day. Tasks with only a time, or that repeat without a
+//! date, are due the first time that fits and is still ahead.
+
+use crate::calendar::recurrence::{ByDay, Frequency, Rule};
+use chrono::{
+    DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike,
+    Utc, Weekday,
+};
+use chrono_tz::Tz;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// When tasks that only have a date are due.
+const END_OF_DAY: (u32, u32) = (23, 59);
+/// What `tonight` means.
+const TONIGHT: (u32, u32) = (20, 0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    Medium,
+    High,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Parsed {
+    pub title: String,
+    pub category: Option<String>,
+    pub due: Option<DateTime<Utc>>,
+    /// RRULE, see `calendar::recurrence`
+    pub recurrence: Option<String>,
+    pub priority: Option<Priority>,
+}
+
+struct Word {
+    text: String,
+    /// Quoted, so it's always part of the title
+    literal: bool,
+}
+
+/// Something recognized in the text.
+enum Part {
+    Date(NaiveDate),
+    Time(NaiveTime),
+    /// `tonight` and `in 2 hours`
+    DateTime(NaiveDate, NaiveTime),
+    Repeat(Repeat),
+    Category(String),
+    Priority(Priority),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Repeat {
+    frequency: Frequency,
+    interval: u32,
+    days: Vec<Weekday>,
+}
+
+impl Repeat {
+    fn new(frequency: Frequency, interval: u32) -> Self {
+        Self {
+            frequency,
+            interval,
+            days: Vec::new(),
+        }
+    }
+
+    fn rule(&self) -> String {
+        Rule {
+            frequency: self.frequency,
+            interval: self.interval,
+            by_day: self
+                .days
+                .iter()
+                .map(|weekday| ByDay {
+                    ordinal: None,
+                    weekday: *weekday,
+                })
+                .collect(),
+            count: None,
+            until: None,
+        }
+        .to_string()
+    }
+}
+
+pub fn parse(text: &str, now: DateTime<Tz>) -> Parsed {
+    let words = split(text);
+    let today = now.date_naive();
+    let mut title = Vec::new();
+    let mut date = None;
+    let mut time = None;
+    let mut repeat: Option<Repeat> = None;
+    let mut category = None;
+    let mut priority = None;
+
+    let mut index = 0;
+    while index < words.len() {
+        // Each kind of thing is only picked up once, later ones stay in the title
+        let part = recognize(&words[index..], now).filter(|(part, _)| match part {
+            Part::Date(_) => date.is_none(),
+            Part::Time(_) => time.is_none(),
+            Part::DateTime(..) => date.is_none() && time.is_none(),
+            Part::Repeat(_) => repeat.is_none(),
+            Part::Category(_) => category.is_none(),
+            Part::Priority(_) => priority.is_none(),
+        });
+
+        let Some((part, length)) = part else {
+            title.push(words[index].text.as_str());
+            index += 1;
+            continue;
+        };
+
+        match part {
+            Part::Date(found) => date = Some(found),
+            Part::Time(found) => time = Some(found),
+            Part::DateTime(found_date, found_time) => {
+                date = Some(found_date);
+                time = Some(found_time);
+            }
+            Part::Repeat(found) => repeat = Some(found),
+            Part::Category(found) => category = Some(found),
+            Part::Priority(found) => priority = Some(found),
+        }
+        index += length;
+    }
+
+    // The first day that fits the repeat and is still ahead, if there's no date to go on
+    let fits = |day: NaiveDate| {
+        let weekday_fits = repeat
+            .as_ref()
+            .is_none_or(|repeat| repeat.days.is_empty() || repeat.days.contains(&day.weekday()));
+        weekday_fits && time.is_none_or(|time| day.and_time(time) > now.naive_local())
+    };
+    let date = match date {
+        Some(date) => Some(date),
+        None if time.is_none() && repeat.is_none() => None,
+        None => today.iter_days().take(8).find(|day| fits(*day)),
+    };
+
+    let end_of_day = NaiveTime::from_hms_opt(END_OF_DAY.0, END_OF_DAY.1, 0).unwrap_or_default();
+    let due =
+        date.and_then(|date| local(now.timezone(), date.and_time(time.unwrap_or(end_of_day))));
+
+    Parsed {
+        title: title.join(" "),
+        category,
+        due,
+        recurrence: repeat.map(|repeat| repeat.rule()),
+        priority,
+    }
+}
+
+/// Splits on whitespace, keeping quoted text together.
+fn split(text: &str) -> Vec<Word> {
+    let mut words = Vec::new();
+
+    for (index, part) in text.split('"').enumerate() {
+        if index % 2 == 1 {
+            words.push(Word {
+                text: part.to_string(),
+                literal: true,
+            });
+            continue;
+        }
+
+        words.extend(part.split_whitespace().map(|word| Word {
+            text: word.to_string(),
+            literal: false,
+        }));
+    }
+
+    words.retain(|word| !word.text.is_empty());
+    words
+}
+
+/// Local time in `tz` to UTC. Times skipped by DST move an hour ahead.
+fn local(tz: Tz, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&time)
+        .earliest()
+        .or_else(|| {
+            tz.from_local_datetime(&(time + Duration::hours(1)))
+                .earliest()
+        })
+        .map(|time| time.with_timezone(&Utc))
+}
+
+/// What the words at the start of `words` mean, if anything.
+fn recognize(words: &[Word], now: DateTime<Tz>) -> Option<(Part, usize)> {
+    let lower: Vec<String> = words
+        .iter()
+        .take(6)
+        .take_while(|word| !word.literal)
+        .map(|word| {
+            word.text
+                .to_lowercase()
+                .trim_end_matches([',', '.', ';'])
+                .to_string()
+        })
+        .collect();
+    let lower: Vec<&str> = lower.iter().map(String::as_str).collect();
+    let today = now.date_naive();
+
+    let first = *lower.first()?;
+    let rest = &lower[1..];
+
+    if first.len() > 1 && first.starts_with('#') {
+        // Keep whatever case it was typed in, validation takes care of that
+        let tag = words[0].text.trim_end_matches([',', '.', ';']);
+        return Some((Part::Category(tag[1..].to_string()), 1));
+    }
+    if let Some(priority) = first.strip_prefix('!').and_then(priority) {
+        return Some((Part::Priority(priority), 1));
+    }
+    if let Some(repeat) = frequency_word(first) {
+        return Some((Part::Repeat(repeat), 1));
+    }
+    if first == "every" || first == "each" {
+        let (repeat, length) = every(rest)?;
+        return Some((Part::Repeat(repeat), length + 1));
+    }
+    if first == "at" {
+        let (time, length) = time(rest)?;
+        return Some((Part::Time(time), length + 1));
+    }
+    if first == "on" {
+        let (date, length) = date(rest, today)?;
+        return Some((Part::Date(date), length + 1));
+    }
+    if first == "tonight" {
+        let tonight = NaiveTime::from_hms_opt(TONIGHT.0, TONIGHT.1, 0)?;
+        return Some((Part::DateTime(today, tonight), 1));
+    }
+    if first == "in" {
+        return relative(rest, now).map(|(part, length)| (part, length + 1));
+    }
+    if let Some((time, length)) = time(&lower) {
+        return Some((Part::Time(time), length));
+    }
+    date(&lower, today).map(|(date, length)| (Part::Date(date), length))
+}
+
+fn priority(word: &str) -> Option<Priority> {
+    match word {
+        "high" | "h" | "1" | "!!" => Some(Priority::High),
+        "medium" | "med" | "m" | "2" | "!" => Some(Priority::Medium),
+        "low" | "l" | "3" => Some(Priority::Low),
+        _ => None,
+    }
+}
+
+fn weekday(word: &str) -> Option<Weekday> {
+    match word {
+        "mon" | "monday" => Some(Weekday::Mon),
+        "tue" | "tues" | "tuesday" => Some(Weekday::Tue),
+        "wed" | "wednesday" => Some(Weekday::Wed),
+        "thu" | "thur" | "thurs" | "thursday" => Some(Weekday::Thu),
+        "fri" | "friday" => Some(Weekday::Fri),
+        "sat" | "saturday" => Some(Weekday::Sat),
+        "sun" | "sunday" => Some(Weekday::Sun),
+        _ => None,
+    }
+}
+
+fn month(word: &str) -> Option<u32> {
+    let months = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let full = [
+        "january",
+        "february",
+        "march",
+        "april",
+        "may",
+        "june",
+        "july",
+        "august",
+        "september",
+        "october",
+        "november",
+        "december",
+    ];
+
+    let index = months
+        .iter()
+        .position(|month| *month == word)
+        .or_else(|| full.iter().position(|month| *month == word))
+        .or_else(|| (word == "sept").then_some(8))?;
+    Some(index as u32 + 1)
+}
+
+/// `15`, `15th`, `1st`, ...
+fn day_of_month(word: &str) -> Option<u32> {
+    let digits = ["st", "nd", "rd", "th"]
+        .iter()
+        .find_map(|suffix| word.strip_suffix(suffix))
+        .unwrap_or(word);
+    digits.parse().ok().filter(|day| (1..=31).contains(day))
+}
+
+/// A count like `3`, or `a` / `an` for one.
+fn count(word: &str) -> Option<u32> {
+    match word {
+        "a" | "an" | "one" => Some(1),
+        "two" => Some(2),
+        _ => word.parse().ok().filter(|count| *count > 0),
+    }
+}
+
+/// The first `weekday` from `from` on, `from` included.
+fn upcoming(from: NaiveDate, weekday: Weekday) -> NaiveDate {
+    let days = (7 + weekday.num_days_from_monday() - from.weekday().num_days_from_monday()) % 7;
+    from + Duration::days(days.into())
+}
+
+fn date(words: &[&str], today: NaiveDate) -> Option<(NaiveDate, usize)> {
+    let first = *words.first()?;
+    let second = words.get(1).copied();
+
+    match first {
+        "today" => return Some((today, 1)),
+        "tomorrow" | "tmrw" | "tmr" => return Some((today.succ_opt()?, 1)),
+        "next" => {
+            let second = second?;
+            let next = match second {
+                "week" => upcoming(today.succ_opt()?, Weekday::Mon),
+                "month" => today.with_day(1)?.checked_add_months(Months::new(1))?,
+                _ => upcoming(today.succ_opt()?, weekday(second)?),
+            };
+            return Some((next, 2));
+        }
+        _ => {}
+    }
+
+    if let Some(weekday) = weekday(first) {
+        return Some((upcoming(today, weekday), 1));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(first, "%Y-%m-%d") {
+        return Some((date, 1));
+    }
+
+    // `march 15` or `15 march`, with an optional year after
+    let (month, day) = match (month(first), second.and_then(day_of_month)) {
+        (Some(month), Some(day)) => (month, day),
+        _ => (second.and_then(month)?, day_of_month(first)?),
+    };
+    let year = words
+        .get(2)
+        .filter(|year| year.len() == 4)
+        .and_then(|year| year.parse::<i32>().ok());
+
+    match year {
+        Some(year) => Some((NaiveDate::from_ymd_opt(year, month, day)?, 3)),
+        None => {
+            // Dates that already went by this year are next year's
+            let date = NaiveDate::from_ymd_opt(today.year(), month, day)
+                .filter(|date| *date >= today)
+                .or_else(|| NaiveDate::from_ymd_opt(today.year() + 1, month, day))?;
+            Some((date, 2))
+        }
+    }
+}
+
+fn time(words: &[&str]) -> Option<(NaiveTime, usize)> {
+    let first = *words.first()?;
+
+    match first {
+        "noon" => return Some((NaiveTime::from_hms_opt(12, 0, 0)?, 1)),
+        "midnight" => return Some((NaiveTime::from_hms_opt(0, 0, 0)?, 1)),
+        _ => {}
+    }
+
+    // `9am`, or `9 am` over two words
+    let (clock, suffix, length) = match ["am", "pm"]
+        .iter()
+        .find_map(|suffix| Some((first.strip_suffix(suffix)?, *suffix)))
+    {
+        Some((clock, suffix)) if !clock.is_empty() => (clock, Some(suffix), 1),
+        _ => match words.get(1) {
+            Some(&suffix @ ("am" | "pm")) => (first, Some(suffix), 2),
+            _ => (first, None, 1),
+        },
+    };
+
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) if minute.len() == 2 => (hour.parse().ok()?, minute.parse().ok()?),
+        // Plain numbers are too likely to mean something else without am or pm
+        None if suffix.is_some() => (clock.parse::<u32>().ok()?, 0),
+        _ => return None,
+    };
+
+    let hour = match suffix {
+        Some(_) if !(1..=12).contains(&hour) => return None,
+        Some("am") => hour % 12,
+        Some(_) => hour % 12 + 12,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0).map(|time| (time, length))
+}
+
+/// The part after `in`, like `3 days` or `an hour`.
+fn relative(words: &[&str], now: DateTime<Tz>) -> Option<(Part, usize)> {
+    let amount = count(words.first()?)?;
+    let unit = words.get(1)?;
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    let today = now.date_naive();
+
+    let part = match unit {
+        "day" => Part::Date(today + Duration::days(amount.into())),
+        "week" => Part::Date(today + Duration::weeks(amount.into())),
+        "month" => Part::Date(today.checked_add_months(Months::new(amount))?),
+        "hour" | "hr" | "h" | "minute" | "min" | "m" => {
+            let duration = match unit {
+                "hour" | "hr" | "h" => Duration::hours(amount.into()),
+                _ => Duration::minutes(amount.into()),
+            };
+            let later = now + duration;
+            Part::DateTime(later.date_naive(), later.time().with_nanosecond(0)?)
+        }
+        _ => return None,
+    };
+
+    Some((part, 2))
+}
+
+fn frequency_word(word: &str) -> Option<Repeat> {
+    match word {
+        "daily" => Some(Repeat::new(Frequency::Daily, 1)),
+        "weekly" => Some(Repeat::new(Frequency::Weekly, 1)),
+        "monthly" => Some(Repeat::new(Frequency::Monthly, 1)),
+        // There's no yearly frequency, but twelve months comes out the same
+        "yearly" | "annually" => Some(Repeat::new(Frequency::Monthly, 12)),
+        _ => None,
+    }
+}
+
+/// The part after `every`.
+fn every(words: &[&str]) -> Option<(Repeat, usize)> {
+    let first = *words.first()?;
+
+    match first {
+        "weekday" => {
+            let mut repeat = Repeat::new(Frequency::Weekly, 1);
+            repeat.days = vec![
+                Weekday::Mon,
+                Weekday::Tue,
+                Weekday::Wed,
+                Weekday::Thu,
+                Weekday::Fri,
+            ];
+            return Some((repeat, 1));
+        }
+        "weekend" => {
+            let mut repeat = Repeat::new(Frequency::Weekly, 1);
+            repeat.days = vec![Weekday::Sat, Weekday::Sun];
+            return Some((repeat, 1));
+        }
+        _ => {}
+    }
+
+    // `every mon, wed and fri`
+    if weekday(first).is_some() {
+        let mut repeat = Repeat::new(Frequency::Weekly, 1);
+        let mut length = 0;
+        while let Some(day) = words.get(length).and_then(|word| weekday(word)) {
+            if !repeat.days.contains(&day) {
+                repeat.days.push(day);
+            }
+            length += 1;
+            if words.get(length) == Some(&"and")
+                && words
+                    .get(length + 1)
+                    .and_then(|word| weekday(word))
+                    .is_some()
+            {
+                length += 1;
+            }
+        }
+        repeat.days.sort_by_key(Weekday::num_days_from_monday);
+        return Some((repeat, length));
+    }
+
+    let (interval, length) = match first {
+        "other" => (2, 2),
+        _ => match count(first) {
+            Some(count) if first != "a" && first != "an" => (count, 2),
+            _ => (1, 1),
+        },
+    };
+    let unit = words.get(length - 1)?;
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    let repeat = match unit {
+        "day" => Repeat::new(Frequency::Daily, interval),
+        "week" => Repeat::new(Frequency::Weekly, interval),
+        "month" => Repeat::new(Frequency::Monthly, interval),
+        "year" => Repeat::new(Frequency::Monthly, interval.checked_mul(12)?),
+        _ => return None,
+    };
+    Some((repeat, length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_york() -> Tz {
+        "America/New_York".parse().unwrap()
+    }
+
+    /// Parses as of a Wednesday morning in New York.
+    fn parse_then(input: &str) -> Parsed {
+        parse(
+            input,
+            new_york().with_ymd_and_hms(2023, 3, 1, 10, 0, 0).unwrap(),
+        )
+    }
+
+    /// When it's due in New York, or an empty string.
+    fn due(parsed: &Parsed) -> String {
+        parsed
+            .due
+            .map(|due| {
+                due.with_timezone(&new_york())
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn everything_at_once() {
+        let parsed = parse_then("pay rent tomorrow 9am #home every month !high");
+        assert_eq!(parsed.title, "pay rent");
+        assert_eq!(due(&parsed), "2023-03-02 09:00");
+        assert_eq!(parsed.category.as_deref(), Some("home"));
+        assert_eq!(parsed.recurrence.as_deref(), Some("FREQ=MONTHLY"));
+        assert_eq!(parsed.priority, Some(Priority::High));
+    }
+
+    #[test]
+    fn dates_and_times() {
+        for (input, title, due_at) in [
+            ("Buy milk", "Buy milk", ""),
+            ("buy milk today", "buy milk", "2023-03-01 23:59"),
+            ("Call mom tomorrow", "Call mom", "2023-03-02 23:59"),
+            ("call mom tmrw at 6pm", "call mom", "2023-03-02 18:00"),
+            (
+                "take out trash tonight",
+                "take out trash",
+                "2023-03-01 20:00",
+            ),
+            // Weekdays count from today, `next` skips today
+            ("standup wed", "standup", "2023-03-01 23:59"),
+            ("standup next wednesday", "standup", "2023-03-08 23:59"),
+            ("report friday", "report", "2023-03-03 23:59"),
+            ("report on Monday", "report", "2023-03-06 23:59"),
+            ("plan next week", "plan", "2023-03-06 23:59"),
+            ("taxes next month", "taxes", "2023-04-01 23:59"),
+            ("dentist in 3 days", "dentist", "2023-03-04 23:59"),
+            ("dentist in a week", "dentist", "2023-03-08 23:59"),
+            ("renew in 2 months", "renew", "2023-05-01 23:59"),
+            ("check oven in 20 minutes", "check oven", "2023-03-01 10:20"),
+            ("call back in an hour", "call back", "2023-03-01 11:00"),
+            ("in 2 hours", "", "2023-03-01 12:00"),
+            // Dates that already went by this year are next year's
+            ("birthday march 15", "birthday", "2023-03-15 23:59"),
+            ("birthday 15th mar", "birthday", "2023-03-15 23:59"),
+            ("new year jan 1", "new year", "2024-01-01 23:59"),
+            ("trip sep 3 2024", "trip", "2024-09-03 23:59"),
+            ("trip 2023-06-30", "trip", "2023-06-30 23:59"),
+            // Times on their own are the next time that comes around
+            ("lunch at noon", "lunch", "2023-03-01 12:00"),
+            ("breakfast 8am", "breakfast", "2023-03-02 08:00"),
+            ("meeting 2:30 pm", "meeting", "2023-03-01 14:30"),
+            ("deploy 21:00", "deploy", "2023-03-01 21:00"),
+            ("backup at midnight", "backup", "2023-03-02 00:00"),
+            ("meeting friday 9:15am", "meeting", "2023-03-03 09:15"),
+            ("meeting 9:15AM Friday", "meeting", "2023-03-03 09:15"),
+            ("tomorrow, call bank", "call bank", "2023-03-02 23:59"),
+            // Only the first date counts
+            (
+                "move it from monday to tuesday",
+                "move it from to tuesday",
+                "2023-03-06 23:59",
+            ),
+            // Things that only look like dates stay in the title
+            (
+                "read \"next friday\" tomorrow",
+                "read next friday",
+                "2023-03-02 23:59",
+            ),
+            ("buy 2 apples", "buy 2 apples", ""),
+            ("meet at home", "meet at home", ""),
+            ("go on holiday", "go on holiday", ""),
+            ("in the morning", "in the morning", ""),
+            ("ask may about it", "ask may about it", ""),
+            ("the next chapter", "the next chapter", ""),
+            ("release 2.5", "release 2.5", ""),
+            ("25:00 is not a time", "25:00 is not a time", ""),
+            ("13pm", "13pm", ""),
+            ("feb 30", "feb 30", ""),
+            ("  lots   of   space  ", "lots of space", ""),
+        ] {
+            let parsed = parse_then(input);
+            assert_eq!(parsed.title, title, "{input}");
+            assert_eq!(due(&parsed), due_at, "{input}");
+        }
+    }
+
+    #[test]
+    fn repeats() {
+        for (input, due_at, recurrence) in [
+            ("water plants every day", "2023-03-01 23:59", "FREQ=DAILY"),
+            ("water plants daily 8am", "2023-03-02 08:00", "FREQ=DAILY"),
+            (
+                "gym every other day",
+                "2023-03-01 23:59",
+                "FREQ=DAILY;INTERVAL=2",
+            ),
+            (
+                "review every 2 weeks",
+                "2023-03-01 23:59",
+                "FREQ=WEEKLY;INTERVAL=2",
+            ),
+            ("review weekly", "2023-03-01 23:59", "FREQ=WEEKLY"),
+            (
+                "invoice every 3 months",
+                "2023-03-01 23:59",
+                "FREQ=MONTHLY;INTERVAL=3",
+            ),
+            (
+                "renew domain yearly",
+                "2023-03-01 23:59",
+                "FREQ=MONTHLY;INTERVAL=12",
+            ),
+            (
+                "anniversary every year jun 1",
+                "2023-06-01 23:59",
+                "FREQ=MONTHLY;INTERVAL=12",
+            ),
+            (
+                "standup every weekday 9am",
+                "2023-03-02 09:00",
+                "FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR",
+            ),
+            (
+                "chores every weekend",
+                "2023-03-04 23:59",
+                "FREQ=WEEKLY;BYDAY=SA,SU",
+            ),
+            (
+                "yoga every tue and thu 7pm",
+                "2023-03-02 19:00",
+                "FREQ=WEEKLY;BYDAY=TU,TH",
+            ),
+            (
+                "run every fri, mon and wed",
+                "2023-03-01 23:59",
+                "FREQ=WEEKLY;BYDAY=MO,WE,FR",
+            ),
+            (
+                "bins each monday",
+                "2023-03-06 23:59",
+                "FREQ=WEEKLY;BYDAY=MO",
+            ),
+            ("every now and then", "", ""),
+        ] {
+            let parsed = parse_then(input);
+            assert_eq!(due(&parsed), due_at, "{input}");
+            assert_eq!(parsed.recurrence.unwrap_or_default(), recurrence, "{input}");
+        }
+    }
+
+    #[test]
+    fn categories_and_priorities() {
+        for (input, title, category, priority) in [
+            ("#Work write report", "write report", Some("Work"), None),
+            (
+                "write report #work #urgent",
+                "write report #urgent",
+                Some("work"),
+                None,
+            ),
+            ("fix bug !1", "fix bug", None, Some(Priority::High)),
+            ("fix bug !med", "fix bug", None, Some(Priority::Medium)),
+            (
+                "fix bug !low !high",
+                "fix bug !high",
+                None,
+                Some(Priority::Low),
+            ),
+            ("fix bug !!!", "fix bug", None, Some(Priority::High)),
+            ("wow!", "wow!", None, None),
+            ("# and !", "# and !", None, None),
+        ] {
+            let parsed = parse_then(input);
+            assert_eq!(parsed.title, title, "{input}");
+            assert_eq!(parsed.category.as_deref(), category, "{input}");
+            assert_eq!(parsed.priority, priority, "{input}");
+        }
+    }
+
+    #[test]
+    fn daylight_saving() {
+        // Clocks in New York skip from 2:00 to 3:00 that night
+        let now = new_york().with_ymd_and_hms(2023, 3, 11, 12, 0, 0).unwrap();
+
+        let parsed = parse("feed cat tomorrow 2:30am", now);
+        assert_eq!(parsed.due, Some("2023-03-12T07:30:00Z".parse().unwrap()));
+
+        let parsed = parse("feed cat tomorrow 9am", now);
+        assert_eq!(parsed.due, Some("2023-03-12T13:00:00Z".parse().unwrap()));
+    }
+}
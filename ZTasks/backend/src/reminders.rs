@@ -0,0 +1,320 @@
+//! Pushes a `reminder` event a while before each task is due, including every repeat of recurring tasks.
+//! Sent reminders are kept in the database, so restarting doesn't send them again.
+
+use crate::{
+    calendar,
+    database::{
+        lists, occurrences, reminders,
+        tasks::{self, Task},
+        Database,
+    },
+    gateway::{
+        broker::{Broker, Publish},
+        protocol::Event,
+    },
+};
+use actix::{Actor, Addr, AsyncContext, Context, Handler, Message};
+use actix_web::web;
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::Connection;
+
+/// How often the scheduler looks for reminders to send.
+pub const TICK: std::time::Duration = std::time::Duration::from_secs(30);
+/// Reminders that were missed while the server was down still go out if the task was due this recently.
+const GRACE_MINUTES: i64 = 60;
+/// Sent reminders are remembered for this long after their task was due.
+const KEEP_DAYS: i64 = 7;
+
+/// Where the scheduler gets the time from, so tests can move it along.
+pub trait Clock: 'static {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Sends whatever reminders are due right away, without waiting for the next tick.
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Tick;
+
+pub struct Scheduler {
+    db: web::Data<Database>,
+    broker: Addr<Broker>,
+    clock: Box<dyn Clock>,
+    /// How long before a task is due its reminder goes out
+    lead: Duration,
+}
+
+impl Scheduler {
+    pub fn new(
+        db: web::Data<Database>,
+        broker: Addr<Broker>,
+        clock: impl Clock,
+        lead_minutes: u32,
+    ) -> Self {
+        Self {
+            db,
+            broker,
+            clock: Box::new(clock),
+            lead: Duration::minutes(lead_minutes.into()),
+        }
+    }
+
+    fn tick(&self) {
+        if let Err(error) = self.send_due() {
+            log::error!("Couldn't send reminders: {error}");
+        }
+    }
+
+    fn send_due(&self) -> rusqlite::Result<()> {
+        let now = self.clock.now();
+        // Everything due before `to` gets reminded about
+        let from = now - Duration::minutes(GRACE_MINUTES);
+        let to = now + self.lead;
+        let conn = self.db.lock();
+
+        for (owner_id, task) in tasks::upcoming(&conn, from.timestamp(), to.timestamp())? {
+            // One broken task doesn't hold up everyone else's reminders
+            if let Err(error) = self.remind(&conn, owner_id, &task, from, to, now) {
+                log::error!("Couldn't send reminders for task {}: {error}", task.id);
+            }
+        }
+
+        reminders::forget(&conn, (now - Duration::days(KEEP_DAYS)).timestamp())
+    }
+
+    /// Sends the reminders for the repeats of `task` due in `[from, to)`, and for recurring tasks notes when
+    /// the next one could be, so it isn't looked at again until then.
+    fn remind(
+        &self,
+        conn: &Connection,
+        owner_id: i64,
+        task: &Task,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> rusqlite::Result<()> {
+        let overrides = match task.recurrence {
+            Some(_) => occurrences::list(conn, task.id)?,
+            None => Vec::new(),
+        };
+
+        for occurrence in calendar::occurrences(task, &overrides, from, to) {
+            let Some(due) = occurrence.due else {
+                continue;
+            };
+            if occurrence.completed.is_some() || due < from || due >= to {
+                continue;
+            }
+            if !reminders::mark_sent(conn, task.id, due.timestamp(), now.timestamp())? {
+                continue;
+            }
+
+            let event = Event::Reminder {
+                occurrence: Box::new(occurrence),
+                list_id: task.list_id,
+            };
+            for user_id in lists::audience(conn, owner_id, task.list_id)? {
+                self.broker.do_send(Publish {
+                    user_id,
+                    origin: None,
+                    event: event.clone(),
+                });
+            }
+        }
+
+        if task.recurrence.is_some() {
+            let next = calendar::next_due(task, &overrides, to);
+            reminders::set_next(conn, task.id, next.map(|next| next.timestamp()))?;
+        }
+        Ok(())
+    }
+}
+
+impl Actor for Scheduler {
+    type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        // Catch up on anything missed while the server was down
+        self.tick();
+        ctx.run_interval(TICK, |scheduler, _| scheduler.tick());
+    }
+}
+
+impl Handler<Tick> for Scheduler {
+    type Result = ();
+
+    fn handle(&mut self, _: Tick, _: &mut Self::Context) {
+        self.tick();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{tasks::TaskFields, users},
+        gateway::{
+            broker::{Connect, Subscribe},
+            protocol::{ServerMessage, Topic},
+        },
+        testing::{events, received, Collector},
+    };
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone)]
+    struct ManualClock(Arc<Mutex<DateTime<Utc>>>);
+
+    impl ManualClock {
+        fn set(&self, time: &str) {
+            *self.0.lock().unwrap() = time.parse().unwrap();
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    fn fields(title: &str, due: &str, recurrence: Option<&str>) -> TaskFields {
+        TaskFields {
+            title: title.to_string(),
+            notes: String::new(),
+            category: None,
+            start: None,
+            due: Some(due.parse().unwrap()),
+            recurrence: recurrence.map(str::to_string),
+            time_zone: "UTC".to_string(),
+        }
+    }
+
+    /// The title and due time of every reminder in `messages`.
+    fn reminders(messages: Vec<ServerMessage>) -> Vec<(String, String)> {
+        events(messages)
+            .into_iter()
+            .filter_map(|message| match message {
+                ServerMessage::Event {
+                    event: Event::Reminder { occurrence, .. },
+                    ..
+                } => Some((occurrence.title, occurrence.due?.to_rfc3339())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn reminding() {
+        let db = web::Data::new(Database::open_in_memory().unwrap());
+        {
+            let conn = db.lock();
+            let user = users::create(&conn, "steven", "", 0).unwrap().unwrap();
+            for (title, due, recurrence) in [
+                ("Pay rent", "2023-03-01T09:00:00Z", None),
+                ("Stand-up", "2023-02-27T10:00:00Z", Some("FREQ=DAILY")),
+                ("Long gone", "2023-02-01T09:00:00Z", None),
+            ] {
+                tasks::insert(
+                    &conn,
+                    user.id,
+                    &fields(title, due, recurrence),
+                    None,
+                    None,
+                    0,
+                )
+                .unwrap();
+            }
+        }
+
+        let broker = Broker::default().start();
+        let collector = Collector::default().start();
+        broker.do_send(Connect {
+            session_id: "steven".to_string(),
+            user_id: 1,
+            recipient: collector.clone().recipient(),
+            resume_from: None,
+        });
+        broker.do_send(Subscribe {
+            session_id: "steven".to_string(),
+            topics: vec![Topic::Tasks],
+            enabled: true,
+        });
+
+        let clock = ManualClock(Arc::new(Mutex::new(DateTime::default())));
+        clock.set("2023-03-01T08:00:00Z");
+        let scheduler = Scheduler::new(db.clone(), broker.clone(), clock.clone(), 15).start();
+        scheduler.send(Tick).await.unwrap();
+        assert!(reminders(received(&broker, &collector).await).is_empty());
+
+        clock.set("2023-03-01T08:50:00Z");
+        scheduler.send(Tick).await.unwrap();
+        scheduler.send(Tick).await.unwrap();
+        assert_eq!(
+            reminders(received(&broker, &collector).await),
+            [(
+                "Pay rent".to_string(),
+                "2023-03-01T09:00:00+00:00".to_string()
+            )]
+        );
+
+        // Down for the stand-up reminder, which still goes out once, even after another restart
+        drop(scheduler);
+        clock.set("2023-03-01T10:30:00Z");
+        for _ in 0..2 {
+            let scheduler = Scheduler::new(db.clone(), broker.clone(), clock.clone(), 15).start();
+            scheduler.send(Tick).await.unwrap();
+        }
+        assert_eq!(
+            reminders(received(&broker, &collector).await),
+            [(
+                "Stand-up".to_string(),
+                "2023-03-01T10:00:00+00:00".to_string()
+            )]
+        );
+
+        // The next day's repeat gets its own
+        clock.set("2023-03-02T09:46:00Z");
+        let scheduler = Scheduler::new(db.clone(), broker.clone(), clock, 15).start();
+        scheduler.send(Tick).await.unwrap();
+        assert_eq!(
+            reminders(received(&broker, &collector).await),
+            [(
+                "Stand-up".to_string(),
+                "2023-03-02T10:00:00+00:00".to_string()
+            )]
+        );
+
+        // The series isn't looked at again until its next repeat comes up, or it's rescheduled
+        let conn = db.lock();
+        let window = |from: &str, to: &str| {
+            let upcoming = tasks::upcoming(
+                &conn,
+                from.parse::<DateTime<Utc>>().unwrap().timestamp(),
+                to.parse::<DateTime<Utc>>().unwrap().timestamp(),
+            )
+            .unwrap();
+            upcoming
+                .into_iter()
+                .map(|(_, task)| task.title)
+                .collect::<Vec<_>>()
+        };
+        assert!(window("2023-03-02T09:00:00Z", "2023-03-03T09:00:00Z").is_empty());
+        assert_eq!(
+            window("2023-03-02T09:00:00Z", "2023-03-03T10:01:00Z"),
+            ["Stand-up"]
+        );
+        let stand_up = tasks::get(&conn, 1, 2).unwrap().unwrap();
+        let fields = fields("Stand-up", "2023-02-27T09:00:00Z", Some("FREQ=DAILY"));
+        tasks::update(&conn, 1, stand_up.id, &fields, 0).unwrap();
+        assert_eq!(
+            window("2023-03-02T09:00:00Z", "2023-03-03T09:00:00Z"),
+            ["Stand-up"]
+        );
+    }
+}
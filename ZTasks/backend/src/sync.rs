@@ -0,0 +1,340 @@
+//! Merging edits that devices made while offline. Every field remembers the stamp of its last write,
+//! and a write only lands if its stamp is bigger, so applying the same writes in any order ends up the same.
+//! Fields that clash (like a start after the due date) are settled by stamp too, see `resolve()`.
+
+use crate::database::tasks::{Task, TaskFields};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// A logical timestamp. Clocks are compared first, and the client ID breaks ties.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Stamp {
+    pub clock: i64,
+    pub client_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    Title,
+    Notes,
+    Category,
+    Start,
+    Due,
+    Completed,
+    Recurrence,
+    TimeZone,
+}
+
+impl Field {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Title => "title",
+            Self::Notes => "notes",
+            Self::Category => "category",
+            Self::Start => "start",
+            Self::Due => "due",
+            Self::Completed => "completed",
+            Self::Recurrence => "recurrence",
+            Self::TimeZone => "time_zone",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        serde_json::from_value(Value::String(name.to_string())).ok()
+    }
+}
+
+/// The part of a task that operations can write to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub fields: TaskFields,
+    pub completed: Option<DateTime<Utc>>,
+}
+
+impl Snapshot {
+    /// What a task created through sync starts out as, before its fields are set.
+    pub fn blank() -> Self {
+        Self {
+            fields: TaskFields {
+                title: String::new(),
+                notes: String::new(),
+                category: None,
+                start: None,
+                due: None,
+                recurrence: None,
+                time_zone: "UTC".to_string(),
+            },
+            completed: None,
+        }
+    }
+
+    pub fn of(task: &Task) -> Self {
+        Self {
+            fields: task.fields(),
+            completed: task.completed,
+        }
+    }
+
+    pub fn get(&self, field: Field) -> Value {
+        let fields = &self.fields;
+        match field {
+            Field::Title => json!(fields.title),
+            Field::Notes => json!(fields.notes),
+            Field::Category => json!(fields.category),
+            Field::Start => json!(fields.start),
+            Field::Due => json!(fields.due),
+            Field::Completed => json!(self.completed),
+            Field::Recurrence => json!(fields.recurrence),
+            Field::TimeZone => json!(fields.time_zone),
+        }
+    }
+
+    /// Puts back the values that sync last wrote (`written`), which may have been changed by
+    /// `resolve()` before they were saved. Fields that have been changed some other way since (like
+    /// over REST) keep what they are now.
+    pub fn recall(&self, stamps: &HashMap<Field, Stamp>, written: &HashMap<Field, Value>) -> Self {
+        let mut recalled = self.clone();
+        for (field, value) in written {
+            // Values are checked before they're saved, so this only skips ones that don't parse anymore
+            let _ = recalled.set(*field, value);
+        }
+
+        let resolved = resolve(&recalled, stamps);
+        for field in written.keys() {
+            if resolved.get(*field) != self.get(*field) {
+                let _ = recalled.set(*field, &self.get(*field));
+            }
+        }
+        recalled
+    }
+
+    fn set(&mut self, field: Field, value: &Value) -> Result<(), String> {
+        fn parse<T: serde::de::DeserializeOwned>(field: Field, value: &Value) -> Result<T, String> {
+            T::deserialize(value).map_err(|_| format!("Invalid value for {}.", field.name()))
+        }
+
+        let fields = &mut self.fields;
+        match field {
+            Field::Title => fields.title = parse(field, value)?,
+            Field::Notes => fields.notes = parse(field, value)?,
+            Field::Category => fields.category = parse(field, value)?,
+            Field::Start => fields.start = parse(field, value)?,
+            Field::Due => fields.due = parse(field, value)?,
+            Field::Completed => self.completed = parse(field, value)?,
+            Field::Recurrence => fields.recurrence = parse(field, value)?,
+            Field::TimeZone => fields.time_zone = parse(field, value)?,
+        }
+
+        Ok(())
+    }
+}
+
+/// Applies the writes in `changes` that are newer than what `stamps` says was last written, and returns
+/// which fields changed. Fields without a stamp (like ones edited over REST) lose to any write.
+pub fn merge(
+    snapshot: &mut Snapshot,
+    stamps: &mut HashMap<Field, Stamp>,
+    changes: &[(Field, Value)],
+    stamp: &Stamp,
+) -> Result<Vec<Field>, String> {
+    let mut merged = snapshot.clone();
+    let mut won = Vec::new();
+
+    for (field, value) in changes {
+        if stamps.get(field).is_some_and(|last| last >= stamp) {
+            continue;
+        }
+        merged.set(*field, value)?;
+        won.push(*field);
+    }
+
+    // Nothing changes unless every value made sense
+    *snapshot = merged;
+    for field in &won {
+        stamps.insert(*field, stamp.clone());
+    }
+
+    Ok(won)
+}
+
+/// Settles clashes between fields that are each fine on their own by which was written last, so
+/// what a task ends up as doesn't depend on the order the writes came in. A start after the due date
+/// moves to the due date if it was written before it, and the due date moves to the start otherwise.
+/// Repeating is left out while there's neither a start nor a due date.
+pub fn resolve(snapshot: &Snapshot, stamps: &HashMap<Field, Stamp>) -> Snapshot {
+    let mut resolved = snapshot.clone();
+    let fields = &mut resolved.fields;
+
+    if let (Some(start), Some(due)) = (fields.start, fields.due) {
+        // Fields without a stamp lose, like they do in `merge()`
+        if start > due && stamps.get(&Field::Start) < stamps.get(&Field::Due) {
+            fields.start = Some(due);
+        } else if start > due {
+            fields.due = Some(start);
+        }
+    }
+    if fields.start.is_none() && fields.due.is_none() {
+        fields.recurrence = None;
+    }
+
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn stamp(clock: i64, client_id: &str) -> Stamp {
+        Stamp {
+            clock,
+            client_id: client_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn any_order_converges() {
+        let writes = [
+            (
+                stamp(1, "laptop"),
+                vec![
+                    (Field::Title, json!("Pay rent")),
+                    (Field::Notes, json!("Checks")),
+                ],
+            ),
+            (
+                stamp(2, "phone"),
+                vec![(Field::Title, json!("Pay the rent"))],
+            ),
+            // Same clock as the phone, but "tablet" sorts after "phone"
+            (
+                stamp(2, "tablet"),
+                vec![
+                    (Field::Title, json!("Rent!")),
+                    (Field::Due, json!("2023-03-01T09:00:00Z")),
+                ],
+            ),
+            (
+                stamp(3, "laptop"),
+                vec![
+                    (Field::Due, json!(null)),
+                    (Field::Completed, json!("2023-03-02T00:00:00Z")),
+                ],
+            ),
+        ];
+
+        let orders = [[0, 1, 2, 3], [3, 2, 1, 0], [2, 0, 3, 1], [1, 3, 0, 2]];
+        let results: Vec<_> = orders
+            .iter()
+            .map(|order| {
+                let mut snapshot = Snapshot::blank();
+                let mut stamps = HashMap::new();
+                for index in order {
+                    let (stamp, changes) = &writes[*index];
+                    merge(&mut snapshot, &mut stamps, changes, stamp).unwrap();
+                }
+                snapshot
+            })
+            .collect();
+
+        for result in &results {
+            assert_eq!(result, &results[0]);
+        }
+        assert_eq!(results[0].fields.title, "Rent!");
+        assert_eq!(results[0].fields.notes, "Checks");
+        assert_eq!(results[0].fields.due, None);
+        assert!(results[0].completed.is_some());
+    }
+
+    #[test]
+    fn replays_and_bad_values() {
+        let mut snapshot = Snapshot::blank();
+        let mut stamps = HashMap::new();
+        let changes = [(Field::Title, json!("Pay rent"))];
+
+        assert_eq!(
+            merge(&mut snapshot, &mut stamps, &changes, &stamp(1, "laptop")),
+            Ok(vec![Field::Title])
+        );
+        // Sending the same batch twice is harmless
+        assert_eq!(
+            merge(&mut snapshot, &mut stamps, &changes, &stamp(1, "laptop")),
+            Ok(vec![])
+        );
+
+        let changes = [(Field::Notes, json!("Fine")), (Field::Start, json!("soon"))];
+        assert_eq!(
+            merge(&mut snapshot, &mut stamps, &changes, &stamp(2, "laptop")),
+            Err("Invalid value for start.".to_string())
+        );
+        assert_eq!(snapshot.fields.notes, "");
+        assert!(!stamps.contains_key(&Field::Notes));
+    }
+
+    #[test]
+    fn clashes_settle_by_stamp() {
+        let writes = [
+            (
+                stamp(1, "laptop"),
+                (Field::Start, json!("2023-03-10T00:00:00Z")),
+            ),
+            (
+                stamp(2, "phone"),
+                (Field::Due, json!("2023-03-05T00:00:00Z")),
+            ),
+            (
+                stamp(3, "laptop"),
+                (Field::Due, json!("2023-03-20T00:00:00Z")),
+            ),
+            (stamp(4, "phone"), (Field::Recurrence, json!("FREQ=WEEKLY"))),
+        ];
+
+        let results: Vec<_> = [[0, 1], [1, 0]]
+            .iter()
+            .map(|order| {
+                let mut snapshot = Snapshot::blank();
+                let mut stamps = HashMap::new();
+                for index in order {
+                    let (stamp, change) = &writes[*index];
+                    merge(
+                        &mut snapshot,
+                        &mut stamps,
+                        std::slice::from_ref(change),
+                        stamp,
+                    )
+                    .unwrap();
+                }
+                resolve(&snapshot, &stamps)
+            })
+            .collect();
+        // The start was written first, so it gives way to the due date
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0].fields.start, results[0].fields.due);
+        assert_eq!(results[0].get(Field::Due), json!("2023-03-05T00:00:00Z"));
+
+        // Once the due date moves past it, the start is back to what was written
+        let mut snapshot = Snapshot::blank();
+        let mut stamps = HashMap::new();
+        for (stamp, change) in &writes {
+            merge(
+                &mut snapshot,
+                &mut stamps,
+                std::slice::from_ref(change),
+                stamp,
+            )
+            .unwrap();
+        }
+        let resolved = resolve(&snapshot, &stamps);
+        assert_eq!(resolved.get(Field::Start), json!("2023-03-10T00:00:00Z"));
+        assert_eq!(resolved.get(Field::Recurrence), json!("FREQ=WEEKLY"));
+
+        // Repeating needs a date to go by
+        snapshot.fields.start = None;
+        snapshot.fields.due = None;
+        assert_eq!(resolve(&snapshot, &stamps).fields.recurrence, None);
+    }
+}
@@ -0,0 +1,229 @@
+//! Helpers shared by the handler tests.
+
+use crate::{
+    api,
+    auth::tokens::TokenKey,
+    config::RateLimits,
+    database::Database,
+    dav::{self, Logins},
+    gateway::{
+        broker::{Broker, Close, Push},
+        protocol::ServerMessage,
+    },
+    health,
+    limiter::Limiter,
+    metrics::{self, Metrics},
+};
+use actix::{Actor, Addr, Context, Handler, Message, MessageResult, StreamHandler};
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{Codec, Frame};
+use actix_web::{
+    dev::{Service, ServiceFactory, ServiceRequest, ServiceResponse},
+    http::{header, Method, StatusCode},
+    middleware, test, web, App, Error,
+};
+use actix_web_actors::ws;
+use bytes::{Bytes, BytesMut};
+use futures_util::{stream, Stream, StreamExt};
+use serde_json::{json, Value};
+use std::pin::Pin;
+use tokio::sync::mpsc;
+
+/// The API with a fresh in-memory database behind it.
+pub fn app() -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    app_with(Broker::default().start())
+}
+
+/// Like `app()`, but pushing gateway events through `broker`.
+pub fn app_with(
+    broker: Addr<Broker>,
+) -> App<
+    impl ServiceFactory<
+        ServiceRequest,
+        Config = (),
+        Response = ServiceResponse,
+        Error = Error,
+        InitError = (),
+    >,
+> {
+    App::new()
+        .app_data(web::Data::new(Database::open_in_memory().unwrap()))
+        .app_data(web::Data::new(TokenKey::new("test")))
+        .app_data(web::Data::new(broker))
+        .app_data(web::Data::new(Limiter::new(
+            RateLimits::default(),
+            Vec::new(),
+        )))
+        .app_data(web::Data::new(Logins::default()))
+        .app_data(web::Data::new(Metrics::default()))
+        .wrap(middleware::from_fn(metrics::middleware))
+        .configure(api::config)
+        .configure(dav::config)
+        .configure(health::config)
+        .configure(metrics::config)
+}
+
+/// Sends a request and returns the status along with the JSON body (`Value::Null` if there isn't one).
+pub async fn call(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+    req: test::TestRequest,
+) -> (StatusCode, Value) {
+    let res = test::call_service(app, req.to_request()).await;
+    let status = res.status();
+    let body = test::read_body(res).await;
+    (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+}
+
+/// Registers and logs in a user, returning an access token for them.
+pub async fn sign_up(
+    app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+    username: &str,
+) -> String {
+    let credentials = json!({ "username": username, "password": "correct horse" });
+    let register = test::TestRequest::post()
+        .uri("/api/register")
+        .set_json(&credentials);
+    let login = test::TestRequest::post()
+        .uri("/api/login")
+        .set_json(&credentials);
+
+    call(app, register).await;
+    let (_, body) = call(app, login).await;
+    body["access_token"].as_str().unwrap().to_string()
+}
+
+/// Request authenticated with an access token from `sign_up()`.
+pub fn request(method: Method, uri: &str, token: &str) -> test::TestRequest {
+    test::TestRequest::default()
+        .method(method)
+        .uri(uri)
+        .insert_header((header::AUTHORIZATION, format!("Bearer {token}")))
+}
+
+/// Stands in for a websocket session, keeping whatever the broker pushes to it.
+#[derive(Default)]
+pub struct Collector {
+    messages: Vec<ServerMessage>,
+}
+
+impl Actor for Collector {
+    type Context = Context<Self>;
+}
+
+impl Handler<Push> for Collector {
+    type Result = ();
+
+    fn handle(&mut self, msg: Push, _: &mut Self::Context) {
+        self.messages.push(msg.0);
+    }
+}
+
+impl Handler<Close> for Collector {
+    type Result = ();
+
+    fn handle(&mut self, _: Close, _: &mut Self::Context) {}
+}
+
+/// Returns and forgets everything collected so far.
+#[derive(Message)]
+#[rtype(result = "Vec<ServerMessage>")]
+pub struct Take;
+
+impl Handler<Take> for Collector {
+    type Result = MessageResult<Take>;
+
+    fn handle(&mut self, _: Take, _: &mut Self::Context) -> Self::Result {
+        MessageResult(std::mem::take(&mut self.messages))
+    }
+}
+
+/// Only the events, which is what most tests care about.
+pub fn events(messages: Vec<ServerMessage>) -> Vec<ServerMessage> {
+    messages
+        .into_iter()
+        .filter(|message| matches!(message, ServerMessage::Event { .. }))
+        .collect()
+}
+
+/// Waits for the broker to get through its mailbox, then takes what `collector` received.
+pub async fn received(broker: &Addr<Broker>, collector: &Addr<Collector>) -> Vec<ServerMessage> {
+    // Mailboxes are FIFO, so once this is handled everything sent before it has been too
+    broker
+        .send(crate::gateway::broker::Disconnect {
+            session_id: String::new(),
+        })
+        .await
+        .unwrap();
+    collector.send(Take).await.unwrap()
+}
+
+type Output = Pin<Box<dyn Stream<Item = Result<Bytes, Error>>>>;
+
+/// A websocket actor hooked up to an in-memory client, without going through the network.
+pub struct Socket {
+    input: mpsc::UnboundedSender<Result<Bytes, actix_http::error::PayloadError>>,
+    output: Output,
+    buffer: BytesMut,
+    codec: Codec,
+}
+
+impl Socket {
+    pub fn open<A>(actor: A) -> Self
+    where
+        A: Actor<Context = ws::WebsocketContext<A>>
+            + StreamHandler<Result<ws::Message, ws::ProtocolError>>,
+    {
+        let (input, receiver) = mpsc::unbounded_channel();
+        let receiver = stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        });
+
+        Self {
+            input,
+            output: Box::pin(ws::WebsocketContext::create(actor, receiver)),
+            buffer: BytesMut::new(),
+            codec: Codec::new().client_mode(),
+        }
+    }
+
+    pub fn send(&mut self, message: ws::Message) {
+        let mut frame = BytesMut::new();
+        self.codec.encode(message, &mut frame).unwrap();
+        // The actor is gone if this fails, which tests notice when reading
+        let _ = self.input.send(Ok(frame.freeze()));
+    }
+
+    pub fn send_json(&mut self, value: Value) {
+        self.send(ws::Message::Text(value.to_string().into()));
+    }
+
+    /// The next frame from the server, or `None` once it hung up.
+    pub async fn recv(&mut self) -> Option<Frame> {
+        loop {
+            if let Some(frame) = self.codec.decode(&mut self.buffer).unwrap() {
+                return Some(frame);
+            }
+            let bytes = self.output.next().await?.unwrap();
+            self.buffer.extend_from_slice(&bytes);
+        }
+    }
+
+    /// The next text frame as JSON, skipping over pings.
+    pub async fn recv_json(&mut self) -> Value {
+        loop {
+            match self.recv().await {
+                Some(Frame::Text(text)) => return serde_json::from_slice(&text).unwrap(),
+                Some(Frame::Ping(_)) => continue,
+                frame => panic!("Expected a text frame, got {frame:?}"),
+            }
+        }
+    }
+}
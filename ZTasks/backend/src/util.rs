@@ -1,17 +1,9 @@
-use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn port() -> u16 {
-    // Check if it exists first
-    match env::var("PORT") {
-        Ok(port) => {
-            let port = str::parse::<u16>(&port);
-
-            // Then check if it's a correctly formatted u16
-            match port {
-                Ok(port) => port,
-                Err(_) => 3000,
-            }
-        }
-        Err(_) => 3000,
-    }
+/// Current UNIX timestamp in seconds.
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or(0)
 }
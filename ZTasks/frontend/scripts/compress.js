@@ -0,0 +1,32 @@
+// Writes .br and .gz copies of the build output next to the originals, which the backend serves to
+// browsers that accept them instead of compressing on every request.
+const fs = require("fs");
+const path = require("path");
+const zlib = require("zlib");
+
+const build = path.join(__dirname, "..", "build");
+const compressible = /\.(html|js|css|json|map|svg|txt|ico)$/;
+
+function walk(dir) {
+  for (const entry of fs.readdirSync(dir, { withFileTypes: true })) {
+    const file = path.join(dir, entry.name);
+
+    if (entry.isDirectory()) {
+      walk(file);
+    } else if (compressible.test(entry.name)) {
+      const data = fs.readFileSync(file);
+      // Small files aren't worth it
+      if (data.length < 1024) continue;
+
+      fs.writeFileSync(
+        file + ".br",
+        zlib.brotliCompressSync(data, {
+          params: { [zlib.constants.BROTLI_PARAM_QUALITY]: 11 },
+        })
+      );
+      fs.writeFileSync(file + ".gz", zlib.gzipSync(data, { level: 9 }));
+    }
+  }
+}
+
+walk(build);